@@ -0,0 +1,66 @@
+//! Compiles the GLSL shaders under `shaders/` to SPIR-V with `glslangValidator`, which must be in
+//! `$PATH`. Each shader is written to the same path under `OUT_DIR`, with `.spv` appended, where
+//! the `include_shader!` macro finds it.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SHADER_COMPILER: &str = "glslangValidator";
+const SHADER_DIR: &str = "shaders";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR is not set"));
+
+    let mut sources = Vec::new();
+    find_shaders(Path::new(SHADER_DIR), &mut sources);
+    for source in &sources {
+        compile(source, &spirv_path(&out_dir, source), &[]);
+    }
+    // The instanced vertex shader reading octahedral normals, for the `oct-normals` feature.
+    compile(
+        Path::new("shaders/instanced.vert"),
+        &out_dir.join("shaders/instanced.oct.vert.spv"),
+        &["-DOCT_NORMALS"],
+    );
+}
+
+/// Adds every vertex, fragment, and compute shader in `dir` and its subdirectories to `sources`.
+fn find_shaders(dir: &Path, sources: &mut Vec<PathBuf>) {
+    let entries = std::fs::read_dir(dir).unwrap_or_else(|e| panic!("Cannot read {}: {}", dir.display(), e));
+    for entry in entries {
+        let path = entry.expect("Cannot read the shader directory").path();
+        if path.is_dir() {
+            find_shaders(&path, sources);
+        } else if let Some("vert") | Some("frag") | Some("comp") = path.extension().and_then(|e| e.to_str()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+            sources.push(path);
+        }
+    }
+}
+
+/// Where the SPIR-V compiled from `source` is written: `source` under `out_dir`, with `.spv`
+/// appended.
+fn spirv_path(out_dir: &Path, source: &Path) -> PathBuf {
+    let mut file_name = source.file_name().unwrap().to_os_string();
+    file_name.push(".spv");
+    out_dir.join(source.with_file_name(file_name))
+}
+
+fn compile(source: &Path, output: &Path, defines: &[&str]) {
+    std::fs::create_dir_all(output.parent().unwrap())
+        .unwrap_or_else(|e| panic!("Cannot create {}: {}", output.parent().unwrap().display(), e));
+    let status = Command::new(SHADER_COMPILER)
+        .arg("-V")
+        .args(defines)
+        .arg(source)
+        .arg("-o")
+        .arg(output)
+        .status()
+        .unwrap_or_else(|e| {
+            panic!("Cannot run {}, which must be in $PATH to build the shaders: {}", SHADER_COMPILER, e)
+        });
+    if !status.success() {
+        panic!("{} failed to compile {}", SHADER_COMPILER, source.display());
+    }
+}
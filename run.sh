@@ -1,3 +1,2 @@
 export RUST_LOG=info/TIME
-make
 cargo run
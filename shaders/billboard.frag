@@ -0,0 +1,21 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+layout(location = 1) flat in uint v_layer;
+
+layout(location = 0) out vec4 o_target;
+
+layout(set = 0, binding = 1) uniform texture2DArray t_sprites;
+layout(set = 0, binding = 2) uniform sampler s_sprites;
+
+// Texels less opaque than this are discarded outright, which avoids needing to sort billboards for
+// blending.
+const float ALPHA_CUTOFF = 0.1;
+
+void main() {
+    vec4 tex = texture(sampler2DArray(t_sprites, s_sprites), vec3(v_tex_coord, float(v_layer)));
+    if (tex.a < ALPHA_CUTOFF) {
+        discard;
+    }
+    o_target = tex;
+}
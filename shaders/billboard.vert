@@ -0,0 +1,36 @@
+#version 450
+
+layout(location = 0) in vec3 i_world_pos;
+layout(location = 1) in float i_size;
+layout(location = 2) in uvec2 i_texture_index;
+
+layout(location = 0) out vec2 o_tex_coord;
+layout(location = 1) flat out uint o_layer;
+
+layout(set = 0, binding = 0) uniform Billboard {
+    mat4 c_transform;
+    vec4 c_right;
+    vec4 c_up;
+};
+
+// The corners of a quad, as two triangles, in units of the billboard's size. The quad is anchored
+// at its bottom edge so that sprites stand on the position they are placed at.
+const vec2 CORNERS[6] = vec2[6](
+    vec2(-0.5, 0.0),
+    vec2( 0.5, 0.0),
+    vec2( 0.5, 1.0),
+    vec2( 0.5, 1.0),
+    vec2(-0.5, 1.0),
+    vec2(-0.5, 0.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+
+    o_tex_coord = vec2(corner.x + 0.5, 1.0 - corner.y);
+    // Only the low 16 bits hold the texture index; the rest is padding.
+    o_layer = i_texture_index.x;
+
+    vec3 pos = i_world_pos + (c_right.xyz * corner.x + c_up.xyz * corner.y) * i_size;
+    gl_Position = c_transform * vec4(pos, 1.0);
+}
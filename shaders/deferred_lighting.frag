@@ -0,0 +1,143 @@
+#version 450
+
+// The lighting pass of the deferred path. Each pixel is shaded from the G-buffer exactly as the
+// forward shaders would have shaded it: terrain as in `shader.frag`, and instanced meshes as in
+// `instanced.frag`. Pixels no geometry was drawn into are discarded, leaving the sky.
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 o_target;
+
+layout(set = 0, binding = 0) uniform texture2D t_position;
+layout(set = 0, binding = 1) uniform texture2D t_normal;
+layout(set = 0, binding = 2) uniform texture2D t_albedo;
+layout(set = 0, binding = 3) uniform texture2D t_material;
+layout(set = 0, binding = 4) uniform sampler s_gbuffer;
+
+layout(set = 0, binding = 5) uniform Camera {
+    mat4 c_view;
+    mat4 c_proj;
+    mat4 c_view_proj;
+    mat4 c_inv_view;
+    mat4 c_inv_proj;
+    vec4 c_pos;
+};
+
+// Matches `lights::MAX_POINT_LIGHTS`.
+const uint MAX_POINT_LIGHTS = 8;
+
+struct PointLight {
+    vec4 position;
+    vec4 color;
+    float radius;
+    float intensity;
+};
+
+layout(set = 0, binding = 6) uniform Lights {
+    PointLight u_lights[MAX_POINT_LIGHTS];
+    // The direction toward the sun, and the color of its light.
+    vec4 u_sun_direction;
+    vec4 u_sun_color;
+    uint u_light_count;
+};
+
+layout(set = 0, binding = 7) uniform textureCube t_environment;
+layout(set = 0, binding = 8) uniform sampler s_environment;
+
+// The shading models, stored in the w component of the G-buffer normal.
+const float SHADING_TERRAIN = 0.0;
+const float SHADING_PBR = 1.0;
+
+const float PI = 3.14159265359;
+const vec3 DIELECTRIC_F0 = vec3(0.04);
+const float SKY_AMBIENT = 0.3;
+const float MIN_ROUGHNESS = 0.04;
+
+// The falloff of point light `i` at `dist` units away, as in the forward shaders.
+float light_falloff(uint i, float dist) {
+    float falloff = clamp(1.0 - dist / u_lights[i].radius, 0.0, 1.0);
+    return falloff * falloff;
+}
+
+// The diffuse point lighting of the terrain, matching `point_lighting` in `shader.frag`.
+vec3 point_lighting(vec3 world_pos, vec3 normal) {
+    vec3 total = vec3(0.0);
+    for (uint i = 0; i < min(u_light_count, MAX_POINT_LIGHTS); i++) {
+        vec3 to_light = u_lights[i].position.xyz - world_pos;
+        float dist = length(to_light);
+        float diffuse = max(dot(normal, to_light / max(dist, 1e-4)), 0.0);
+        total += u_lights[i].color.rgb * u_lights[i].intensity * diffuse * light_falloff(i, dist);
+    }
+    return total;
+}
+
+float D_GGX(float n_dot_h, float roughness) {
+    float a2 = roughness * roughness * roughness * roughness;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float G_Smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+vec3 F_Schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Matches `brdf` in `instanced.frag`, with the metalness passed in rather than read from the
+// material uniform.
+vec3 brdf(vec3 n, vec3 v, vec3 l, vec3 radiance, vec3 albedo, vec3 f0, float metallic, float roughness) {
+    float n_dot_l = max(dot(n, l), 0.0);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+    vec3 h = normalize(v + l);
+    float n_dot_v = max(dot(n, v), 1e-4);
+    vec3 f = F_Schlick(max(dot(h, v), 0.0), f0);
+    vec3 specular = D_GGX(max(dot(n, h), 0.0), roughness) * G_Smith(n_dot_v, n_dot_l, roughness) * f
+        / (4.0 * n_dot_v * n_dot_l);
+    vec3 k_d = (1.0 - f) * (1.0 - metallic);
+    return (k_d * albedo / PI + specular) * radiance * n_dot_l;
+}
+
+// Matches `main` in `instanced.frag`, with the ambient occlusion already scaled by its strength.
+vec3 shade_pbr(vec3 world_pos, vec3 n, vec3 albedo, float metallic, float roughness, float ao) {
+    roughness = max(roughness, MIN_ROUGHNESS);
+    vec3 f0 = mix(DIELECTRIC_F0, albedo, metallic);
+    vec3 v = normalize(c_pos.xyz - world_pos);
+
+    vec3 direct = brdf(n, v, u_sun_direction.xyz, u_sun_color.rgb * PI, albedo, f0, metallic, roughness);
+    for (uint i = 0; i < min(u_light_count, MAX_POINT_LIGHTS); i++) {
+        vec3 to_light = u_lights[i].position.xyz - world_pos;
+        float dist = length(to_light);
+        vec3 radiance = u_lights[i].color.rgb * u_lights[i].intensity * light_falloff(i, dist) * PI;
+        direct += brdf(n, v, to_light / max(dist, 1e-4), radiance, albedo, f0, metallic, roughness);
+    }
+
+    vec3 f = F_Schlick(max(dot(n, v), 0.0), f0);
+    vec3 irradiance = texture(samplerCube(t_environment, s_environment), n).rgb + u_sun_color.rgb * SKY_AMBIENT;
+    vec3 reflection = texture(samplerCube(t_environment, s_environment), reflect(-v, n)).rgb;
+    vec3 ambient = (1.0 - f) * (1.0 - metallic) * albedo * irradiance + f * reflection * (1.0 - roughness);
+    return direct + ambient * ao;
+}
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec4 position = texelFetch(sampler2D(t_position, s_gbuffer), texel, 0);
+    if (position.w == 0.0) {
+        discard;
+    }
+    vec4 normal = texelFetch(sampler2D(t_normal, s_gbuffer), texel, 0);
+    vec4 albedo = texelFetch(sampler2D(t_albedo, s_gbuffer), texel, 0);
+    vec4 material = texelFetch(sampler2D(t_material, s_gbuffer), texel, 0);
+
+    if (normal.w == SHADING_TERRAIN) {
+        o_target = vec4(albedo.rgb * (u_sun_color.rgb + point_lighting(position.xyz, normal.xyz)), albedo.a);
+    } else {
+        o_target = vec4(shade_pbr(position.xyz, normal.xyz, albedo.rgb, material.x, material.y, material.z), albedo.a);
+    }
+}
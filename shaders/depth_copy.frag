@@ -0,0 +1,13 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 o_target;
+
+layout(set = 0, binding = 0) uniform texture2D t_depth;
+layout(set = 0, binding = 1) uniform sampler s_depth;
+
+void main() {
+    // The copy is the size of the depth buffer, so each pixel reads exactly one texel.
+    o_target = vec4(texelFetch(sampler2D(t_depth, s_depth), ivec2(gl_FragCoord.xy), 0).r);
+}
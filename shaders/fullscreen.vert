@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec2 a_pos;
+
+layout(location = 0) out vec2 o_tex_coord;
+
+void main() {
+    // Texture coordinates start at the top of the image, while clip space starts at the bottom.
+    o_tex_coord = vec2(a_pos.x * 0.5 + 0.5, 0.5 - a_pos.y * 0.5);
+
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+}
@@ -0,0 +1,65 @@
+#version 450
+
+// Shows a single channel of the G-buffer, for `DeferredRenderer::capture_debug_view`. The target is
+// sRGB, so every channel except albedo is decoded first, leaving its raw values in the image.
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 o_target;
+
+layout(set = 0, binding = 0) uniform texture2D t_position;
+layout(set = 0, binding = 1) uniform texture2D t_normal;
+layout(set = 0, binding = 2) uniform texture2D t_albedo;
+layout(set = 0, binding = 3) uniform texture2D t_material;
+layout(set = 0, binding = 4) uniform texture2D t_depth;
+layout(set = 0, binding = 5) uniform sampler s_gbuffer;
+
+layout(set = 0, binding = 6) uniform Debug {
+    uint u_channel;
+    // Maps world space positions into [0, 1].
+    float u_position_scale;
+};
+
+// These match `GBufferChannel::index`.
+const uint CHANNEL_ALBEDO    = 0;
+const uint CHANNEL_NORMAL    = 1;
+const uint CHANNEL_POSITION  = 2;
+const uint CHANNEL_DEPTH     = 3;
+const uint CHANNEL_ROUGHNESS = 4;
+const uint CHANNEL_METALLIC  = 5;
+
+vec3 srgb_to_linear(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(color, vec3(0.04045)));
+}
+
+void main() {
+    // The target is the size of the G-buffer, so each pixel reads exactly one texel.
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec3 value;
+    switch (u_channel) {
+        case CHANNEL_ALBEDO:
+            o_target = vec4(texelFetch(sampler2D(t_albedo, s_gbuffer), texel, 0).rgb, 1.0);
+            return;
+        case CHANNEL_NORMAL:
+            value = texelFetch(sampler2D(t_normal, s_gbuffer), texel, 0).xyz * 0.5 + 0.5;
+            break;
+        case CHANNEL_POSITION:
+            value = texelFetch(sampler2D(t_position, s_gbuffer), texel, 0).xyz * u_position_scale;
+            break;
+        case CHANNEL_DEPTH:
+            value = vec3(texelFetch(sampler2D(t_depth, s_gbuffer), texel, 0).r);
+            break;
+        case CHANNEL_ROUGHNESS:
+            value = vec3(texelFetch(sampler2D(t_material, s_gbuffer), texel, 0).g);
+            break;
+        case CHANNEL_METALLIC:
+            value = vec3(texelFetch(sampler2D(t_material, s_gbuffer), texel, 0).r);
+            break;
+        default:
+            value = vec3(0.0);
+            break;
+    }
+    o_target = vec4(srgb_to_linear(clamp(value, 0.0, 1.0)), 1.0);
+}
@@ -0,0 +1,126 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) in vec4 v_color;
+// The ambient occlusion brightness, interpolated across the face.
+layout(location = 3) in float v_ao;
+layout(location = 4) in vec3 v_world_pos;
+
+layout(location = 0) out vec4 o_target;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 c_view;
+    mat4 c_proj;
+    mat4 c_view_proj;
+    mat4 c_inv_view;
+    mat4 c_inv_proj;
+    vec4 c_pos;
+};
+
+layout(set = 0, binding = 1) uniform texture2D t_color;
+layout(set = 0, binding = 2) uniform sampler s_color;
+
+// Matches `lights::MAX_POINT_LIGHTS`.
+const uint MAX_POINT_LIGHTS = 8;
+
+struct PointLight {
+    vec4 position;
+    vec4 color;
+    float radius;
+    float intensity;
+};
+
+layout(set = 0, binding = 5) uniform Lights {
+    PointLight u_lights[MAX_POINT_LIGHTS];
+    // The direction toward the sun, and the color of its light.
+    vec4 u_sun_direction;
+    vec4 u_sun_color;
+    uint u_light_count;
+};
+
+layout(set = 0, binding = 6) uniform textureCube t_environment;
+layout(set = 0, binding = 7) uniform sampler s_environment;
+
+layout(set = 0, binding = 8) uniform Material {
+    vec4 u_base_color;
+    float u_metallic;
+    float u_roughness;
+    float u_ao_strength;
+};
+
+const float PI = 3.14159265359;
+// The reflectance of dielectrics seen head-on.
+const vec3 DIELECTRIC_F0 = vec3(0.04);
+// Ambient light standing in for the sky, as a fraction of the sunlight, on top of the environment.
+const float SKY_AMBIENT = 0.3;
+// Perfectly smooth surfaces make the highlights of point lights infinitely small, so roughness is
+// kept above this.
+const float MIN_ROUGHNESS = 0.04;
+
+// The GGX (Trowbridge-Reitz) distribution of microfacet normals.
+float D_GGX(float n_dot_h, float roughness) {
+    float a2 = roughness * roughness * roughness * roughness;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's shadowing-masking term, with the Schlick-GGX approximation for each direction.
+float G_Smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+// Schlick's approximation of the Fresnel reflectance.
+vec3 F_Schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// The light reflected toward `v` from light of `radiance` arriving from direction `l`.
+vec3 brdf(vec3 n, vec3 v, vec3 l, vec3 radiance, vec3 albedo, vec3 f0, float roughness) {
+    float n_dot_l = max(dot(n, l), 0.0);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+    vec3 h = normalize(v + l);
+    float n_dot_v = max(dot(n, v), 1e-4);
+    vec3 f = F_Schlick(max(dot(h, v), 0.0), f0);
+    vec3 specular = D_GGX(max(dot(n, h), 0.0), roughness) * G_Smith(n_dot_v, n_dot_l, roughness) * f
+        / (4.0 * n_dot_v * n_dot_l);
+    // Light reflected at the surface does not enter it, and metals absorb what does.
+    vec3 k_d = (1.0 - f) * (1.0 - u_metallic);
+    return (k_d * albedo / PI + specular) * radiance * n_dot_l;
+}
+
+void main() {
+    vec4 base_color = texture(sampler2D(t_color, s_color), v_tex_coord) * v_color * u_base_color;
+    vec3 albedo = base_color.rgb;
+    float roughness = max(u_roughness, MIN_ROUGHNESS);
+    vec3 f0 = mix(DIELECTRIC_F0, albedo, u_metallic);
+
+    vec3 n = normalize(v_normal);
+    vec3 v = normalize(c_pos.xyz - v_world_pos);
+
+    // Lights are scaled by pi so that a white, rough surface facing one reflects all of its light.
+    vec3 direct = brdf(n, v, u_sun_direction.xyz, u_sun_color.rgb * PI, albedo, f0, roughness);
+    for (uint i = 0; i < min(u_light_count, MAX_POINT_LIGHTS); i++) {
+        vec3 to_light = u_lights[i].position.xyz - v_world_pos;
+        float dist = length(to_light);
+        // Each light fades out quadratically, reaching zero at its radius.
+        float falloff = clamp(1.0 - dist / u_lights[i].radius, 0.0, 1.0);
+        vec3 radiance = u_lights[i].color.rgb * u_lights[i].intensity * falloff * falloff * PI;
+        direct += brdf(n, v, to_light / max(dist, 1e-4), radiance, albedo, f0, roughness);
+    }
+
+    // Image based lighting: the environment in the direction of the normal stands in for the
+    // irradiance, and in the direction of the reflection for the specular lobe.
+    vec3 f = F_Schlick(max(dot(n, v), 0.0), f0);
+    vec3 irradiance = texture(samplerCube(t_environment, s_environment), n).rgb + u_sun_color.rgb * SKY_AMBIENT;
+    vec3 reflection = texture(samplerCube(t_environment, s_environment), reflect(-v, n)).rgb;
+    vec3 ambient = (1.0 - f) * (1.0 - u_metallic) * albedo * irradiance + f * reflection * (1.0 - roughness);
+    ambient *= mix(1.0, v_ao, u_ao_strength);
+
+    o_target = vec4(direct + ambient, base_color.a);
+}
@@ -0,0 +1,55 @@
+#version 450
+
+layout(location = 0) in vec4 a_pos;
+#ifdef OCT_NORMALS
+layout(location = 1) in vec2 a_normal_oct;
+#else
+layout(location = 1) in vec3 a_normal;
+#endif
+layout(location = 2) in vec2 a_tex_coord;
+// Unpacked from the `u32` color of `Vertex` by the vertex format.
+layout(location = 3) in vec4 a_color;
+layout(location = 4) in float a_ao;
+// A mat4 attribute occupies four consecutive locations, one per column.
+layout(location = 5) in mat4 i_model;
+
+layout(location = 0) out vec2 o_tex_coord;
+layout(location = 1) out vec3 o_normal;
+layout(location = 2) out vec4 o_color;
+layout(location = 3) out float o_ao;
+layout(location = 4) out vec3 o_world_pos;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 c_view;
+    mat4 c_proj;
+    mat4 c_view_proj;
+    mat4 c_inv_view;
+    mat4 c_inv_proj;
+    vec4 c_pos;
+};
+
+#ifdef OCT_NORMALS
+// Matches `utils::unpack_normal_oct`.
+vec3 unpack_normal_oct(vec2 e) {
+    vec3 n = vec3(e, 1.0 - abs(e.x) - abs(e.y));
+    if (n.z < 0.0) {
+        vec2 sign_not_zero = mix(vec2(-1.0), vec2(1.0), greaterThanEqual(n.xy, vec2(0.0)));
+        n.xy = (1.0 - abs(n.yx)) * sign_not_zero;
+    }
+    return normalize(n);
+}
+#endif
+
+void main() {
+#ifdef OCT_NORMALS
+    vec3 a_normal = unpack_normal_oct(a_normal_oct);
+#endif
+    o_tex_coord = a_tex_coord;
+    o_color = a_color;
+    o_ao = a_ao;
+    o_normal = mat3(i_model) * a_normal;
+
+    vec4 world_pos = i_model * a_pos;
+    o_world_pos = world_pos.xyz;
+    gl_Position = c_view_proj * world_pos;
+}
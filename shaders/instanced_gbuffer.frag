@@ -0,0 +1,38 @@
+#version 450
+
+// The instanced mesh shader of the deferred path: rather than shading, it writes the surface and
+// its material into the G-buffer for `deferred_lighting.frag`.
+
+layout(location = 0) in vec2 v_tex_coord;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) in vec4 v_color;
+// The ambient occlusion brightness, interpolated across the face.
+layout(location = 3) in float v_ao;
+layout(location = 4) in vec3 v_world_pos;
+
+// The G-buffer, matching `deferred::GBUFFER_FORMATS`.
+layout(location = 0) out vec4 o_position;
+layout(location = 1) out vec4 o_normal;
+layout(location = 2) out vec4 o_albedo;
+layout(location = 3) out vec4 o_material;
+
+layout(set = 0, binding = 1) uniform texture2D t_color;
+layout(set = 0, binding = 2) uniform sampler s_color;
+
+layout(set = 0, binding = 8) uniform Material {
+    vec4 u_base_color;
+    float u_metallic;
+    float u_roughness;
+    float u_ao_strength;
+};
+
+// Matches `SHADING_PBR` in `deferred_lighting.frag`.
+const float SHADING_PBR = 1.0;
+
+void main() {
+    o_position = vec4(v_world_pos, 1.0);
+    o_normal = vec4(normalize(v_normal), SHADING_PBR);
+    o_albedo = texture(sampler2D(t_color, s_color), v_tex_coord) * v_color * u_base_color;
+    // The occlusion is stored with its strength already applied.
+    o_material = vec4(u_metallic, u_roughness, mix(1.0, v_ao, u_ao_strength), 0.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coord;
+
+layout(location = 0) out vec4 o_target;
+
+layout(set = 0, binding = 0) uniform texture2D t_minimap;
+layout(set = 0, binding = 1) uniform sampler s_minimap;
+
+void main() {
+    o_target = texture(sampler2D(t_minimap, s_minimap), v_tex_coord);
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) out vec4 o_target;
+
+// Matches `OutlineConstants` in `outline.rs`.
+layout(push_constant) uniform Outline {
+    vec4 u_color;
+    vec4 u_center;
+    float u_scale;
+};
+
+void main() {
+    o_target = u_color;
+}
@@ -0,0 +1,32 @@
+#version 450
+
+// Draws the selected terrain patch, scaled about its center, for the stencil outline. Only the
+// position of each vertex is used.
+
+layout(location = 0) in ivec3 v_pos;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 c_view;
+    mat4 c_proj;
+    mat4 c_view_proj;
+    mat4 c_inv_view;
+    mat4 c_inv_proj;
+    vec4 c_pos;
+};
+
+layout(set = 0, binding = 3) uniform Transform {
+    mat4 u_transform;
+};
+
+// Matches `OutlineConstants` in `outline.rs`.
+layout(push_constant) uniform Outline {
+    vec4 u_color;
+    vec4 u_center;
+    float u_scale;
+};
+
+void main() {
+    vec4 world_pos = u_transform * vec4(v_pos, 1.0);
+    world_pos.xyz = u_center.xyz + (world_pos.xyz - u_center.xyz) * u_scale;
+    gl_Position = c_view_proj * world_pos;
+}
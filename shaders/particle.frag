@@ -0,0 +1,13 @@
+#version 450
+
+// The fraction of the particle's lifetime remaining, in [0, 1].
+layout(location = 0) in float v_life;
+
+layout(location = 0) out vec4 o_target;
+
+const vec3 PARTICLE_COLOR = vec3(0.9, 0.85, 0.7);
+
+void main() {
+    // Fade particles out as they approach the end of their lifetime.
+    o_target = vec4(PARTICLE_COLOR, v_life);
+}
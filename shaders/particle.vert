@@ -0,0 +1,33 @@
+#version 450
+
+layout(location = 0) in vec3 i_pos;
+layout(location = 1) in float i_life;
+
+layout(location = 0) out float o_life;
+
+layout(set = 0, binding = 0) uniform Particles {
+    mat4 c_transform;
+    vec4 c_right;
+    vec4 c_up;
+    float u_size;
+};
+
+// The corners of a quad, as two triangles, in units of the particle's size. Unlike billboards,
+// particles are centred on their position.
+const vec2 CORNERS[6] = vec2[6](
+    vec2(-0.5, -0.5),
+    vec2( 0.5, -0.5),
+    vec2( 0.5,  0.5),
+    vec2( 0.5,  0.5),
+    vec2(-0.5,  0.5),
+    vec2(-0.5, -0.5)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+
+    o_life = i_life;
+
+    vec3 pos = i_pos + (c_right.xyz * corner.x + c_up.xyz * corner.y) * u_size;
+    gl_Position = c_transform * vec4(pos, 1.0);
+}
@@ -1,36 +1,128 @@
 #version 450
 
-layout(location = 0) in vec2 v_tex_coord_raw;
-layout(location = 1) flat in ivec3 b_pos;
-layout(location = 2) flat in uint face;
+layout(location = 0) in vec3 v_world_pos;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) flat in uvec2 v_tile;
 
 layout(location = 0) out vec4 o_target;
 
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 c_view;
+    mat4 c_proj;
+    mat4 c_view_proj;
+    mat4 c_inv_view;
+    mat4 c_inv_proj;
+    vec4 c_pos;
+};
+
 layout(set = 0, binding = 1) uniform texture2D t_color;
 layout(set = 0, binding = 2) uniform sampler s_color;
 
-const float _2_PI = 6.283185307179586;
-const float PI = 3.1415926535897932384626433832795;
-const float PI_2 = 1.57079632679489661923;
-const float PI_4 = 0.785398163397448309616;
+layout(set = 0, binding = 4) uniform Terrain {
+    vec2 u_atlas_dims;
+    float u_blend_sharpness;
+    // How many times each tile repeats per world unit.
+    float u_tiling_scale;
+    // The height of the water surface. Upward faces no higher than this are water.
+    float u_sea_level;
+};
+
+// Matches `lights::MAX_POINT_LIGHTS`.
+const uint MAX_POINT_LIGHTS = 8;
+
+struct PointLight {
+    vec4 position;
+    vec4 color;
+    float radius;
+    float intensity;
+};
+
+layout(set = 0, binding = 5) uniform Lights {
+    PointLight u_lights[MAX_POINT_LIGHTS];
+    // The direction toward the sun, and the color of its light.
+    vec4 u_sun_direction;
+    vec4 u_sun_color;
+    uint u_light_count;
+};
+
+layout(set = 0, binding = 6) uniform textureCube t_environment;
+layout(set = 0, binding = 7) uniform sampler s_environment;
+
+// The reflectance of water seen straight on.
+const float WATER_F0 = 0.02;
+
+layout(set = 1, binding = 0) uniform Time {
+    float u_time;
+    float u_delta_time;
+    // The low and high halves of the frame index.
+    uint u_frame_index;
+    uint u_frame_index_hi;
+};
+
+// Returns the weight of each of the three axis-aligned projections for a surface with the given
+// normal. Higher sharpness narrows the regions where projections are blended together.
+vec3 triplanar_blend(vec3 normal, float blend_sharpness) {
+    vec3 weights = pow(abs(normal), vec3(blend_sharpness));
+    return weights / (weights.x + weights.y + weights.z);
+}
+
+// Sums the diffuse light reaching a surface at `world_pos` facing along `normal` from every point
+// light. Each light fades out quadratically, reaching zero at its radius.
+vec3 point_lighting(vec3 world_pos, vec3 normal) {
+    vec3 total = vec3(0.0);
+    for (uint i = 0; i < min(u_light_count, MAX_POINT_LIGHTS); i++) {
+        vec3 to_light = u_lights[i].position.xyz - world_pos;
+        float dist = length(to_light);
+        float falloff = clamp(1.0 - dist / u_lights[i].radius, 0.0, 1.0);
+        float diffuse = max(dot(normal, to_light / max(dist, 1e-4)), 0.0);
+        total += u_lights[i].color.rgb * u_lights[i].intensity * diffuse * falloff * falloff;
+    }
+    return total;
+}
+
+// Samples the current atlas tile, repeating it `u_tiling_scale` times per world unit.
+vec4 sample_tile(vec2 coord) {
+    vec2 repeat_coord = coord * u_tiling_scale;
+    // Take derivatives of the unwrapped coordinate, since `fract` jumps at every tile edge, which
+    // would otherwise select the smallest mip level there.
+    vec2 dx = dFdx(repeat_coord) / u_atlas_dims;
+    vec2 dy = dFdy(repeat_coord) / u_atlas_dims;
+
+    // Keep samples half a texel inside the tile at the mip level being read, so that filtering
+    // never reaches into the neighbouring tile.
+    vec2 atlas_size = vec2(textureSize(sampler2D(t_color, s_color), 0));
+    vec2 dx_texels = dx * atlas_size;
+    vec2 dy_texels = dy * atlas_size;
+    float lod = max(0.5 * log2(max(dot(dx_texels, dx_texels), dot(dy_texels, dy_texels))), 0.0);
+    vec2 inset = 0.5 * exp2(lod) * u_atlas_dims / atlas_size;
+    vec2 in_tile = clamp(fract(repeat_coord), inset, 1.0 - inset);
+
+    vec2 tile_coord = (vec2(v_tile) + in_tile) / u_atlas_dims;
+    return textureGrad(sampler2D(t_color, s_color), tile_coord, dx, dy);
+}
 
 void main() {
-    vec2 v_tex_coord = v_tex_coord_raw / 2.0;
-
-    if (b_pos.z >= 15) {
-        vec4 tex = texture(sampler2D(t_color, s_color), v_tex_coord);
-        o_target = tex;
-    } else if (b_pos.z >= 0) {
-        // If we are on the top face, use the grass texture.
-        if (face == 32) {
-            vec4 tex = texture(sampler2D(t_color, s_color), v_tex_coord + vec2(0.5));
-            o_target = tex;
-        } else {
-            vec4 tex = texture(sampler2D(t_color, s_color), v_tex_coord + vec2(0.0, 0.5));
-            o_target = tex;
-        }
-    } else {
-        vec4 tex = texture(sampler2D(t_color, s_color), v_tex_coord + vec2(0.5, 0.0));
-        o_target = tex;
+    vec3 weights = triplanar_blend(v_normal, u_blend_sharpness);
+
+    // Project along each axis in turn. Texture v runs down the image, so world z is negated to keep
+    // the sides of the terrain upright.
+    vec4 x_proj = sample_tile(vec2(v_world_pos.y, -v_world_pos.z));
+    vec4 y_proj = sample_tile(vec2(v_world_pos.x, -v_world_pos.z));
+    vec4 z_proj = sample_tile(v_world_pos.xy);
+
+    vec4 albedo = x_proj * weights.x + y_proj * weights.y + z_proj * weights.z;
+    // The terrain is not shaded by the sun, only tinted with its color, so that it is shown as is
+    // by day.
+    vec3 color = albedo.rgb * (u_sun_color.rgb + point_lighting(v_world_pos, v_normal));
+
+    // Water reflects the environment, more so at grazing angles, by Schlick's approximation of the
+    // Fresnel reflectance. The top of the water is the only upward face at sea level; land is at
+    // least a block higher.
+    if (v_normal.z > 0.5 && v_world_pos.z < u_sea_level + 0.5) {
+        vec3 v = normalize(c_pos.xyz - v_world_pos);
+        float fresnel = WATER_F0 + (1.0 - WATER_F0) * pow(1.0 - max(v.z, 0.0), 5.0);
+        vec3 reflection = texture(samplerCube(t_environment, s_environment), reflect(-v, v_normal)).rgb;
+        color = mix(color, reflection, fresnel);
     }
+    o_target = vec4(color, albedo.a);
 }
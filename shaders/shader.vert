@@ -2,27 +2,51 @@
 
 layout(location = 0) in ivec3 v_pos;
 layout(location = 1) in ivec3 b_pos;
-layout(location = 2) in vec2 v_tex_coord;
-layout(location = 3) in uint data;
+layout(location = 2) in uint data;
 
-layout(location = 0) out vec2 o_v_tex_coord;
-layout(location = 1) out ivec3 o_b_pos;
-layout(location = 2) out uint face;
+layout(location = 0) out vec3 o_world_pos;
+layout(location = 1) out vec3 o_normal;
+layout(location = 2) out uvec2 o_tile;
 
 layout(set = 0, binding = 0) uniform Camera {
-    mat4 c_transform;
+    mat4 c_view;
+    mat4 c_proj;
+    mat4 c_view_proj;
+    mat4 c_inv_view;
+    mat4 c_inv_proj;
+    vec4 c_pos;
 };
 
 layout(set = 0, binding = 3) uniform Transform {
     mat4 u_transform;
 };
 
+// These match the bits of `CuboidFaces`.
+const uint FACE_TOP    = 32;
+const uint FACE_BOTTOM = 16;
+const uint FACE_FRONT  = 8;
+const uint FACE_BACK   = 4;
+const uint FACE_LEFT   = 2;
+
+vec3 face_normal(uint face) {
+    switch (face) {
+        case FACE_TOP:    return vec3( 0.0,  0.0,  1.0);
+        case FACE_BOTTOM: return vec3( 0.0,  0.0, -1.0);
+        case FACE_FRONT:  return vec3( 0.0,  1.0,  0.0);
+        case FACE_BACK:   return vec3( 0.0, -1.0,  0.0);
+        case FACE_LEFT:   return vec3(-1.0,  0.0,  0.0);
+        default:          return vec3( 1.0,  0.0,  0.0);
+    }
+}
+
 void main() {
-    o_v_tex_coord = v_tex_coord;
-    o_b_pos = b_pos;
-    ivec3 clamped_pos = v_pos;
+    // `data` packs the face into its lowest byte, followed by the atlas tile column and row.
+    uint face = data & 0xFF;
+    o_tile = uvec2((data >> 8) & 0xFF, (data >> 16) & 0xFF);
+    o_normal = face_normal(face);
 
-    face = data;
+    vec4 world_pos = u_transform * vec4(v_pos, 1.0);
+    o_world_pos = world_pos.xyz;
 
-    gl_Position = c_transform * u_transform * vec4(clamped_pos, 1.0);
+    gl_Position = c_view_proj * world_pos;
 }
@@ -0,0 +1,67 @@
+#version 450
+
+// The terrain shader of the deferred path: rather than shading, it writes the surface into the
+// G-buffer for `deferred_lighting.frag`. The texturing matches `shader.frag`.
+
+layout(location = 0) in vec3 v_world_pos;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) flat in uvec2 v_tile;
+
+// The G-buffer, matching `deferred::GBUFFER_FORMATS`.
+layout(location = 0) out vec4 o_position;
+layout(location = 1) out vec4 o_normal;
+layout(location = 2) out vec4 o_albedo;
+layout(location = 3) out vec4 o_material;
+
+layout(set = 0, binding = 1) uniform texture2D t_color;
+layout(set = 0, binding = 2) uniform sampler s_color;
+
+layout(set = 0, binding = 4) uniform Terrain {
+    vec2 u_atlas_dims;
+    float u_blend_sharpness;
+    // How many times each tile repeats per world unit.
+    float u_tiling_scale;
+};
+
+// Matches `SHADING_TERRAIN` in `deferred_lighting.frag`.
+const float SHADING_TERRAIN = 0.0;
+
+// Returns the weight of each of the three axis-aligned projections for a surface with the given
+// normal. Higher sharpness narrows the regions where projections are blended together.
+vec3 triplanar_blend(vec3 normal, float blend_sharpness) {
+    vec3 weights = pow(abs(normal), vec3(blend_sharpness));
+    return weights / (weights.x + weights.y + weights.z);
+}
+
+// Samples the current atlas tile, repeating it `u_tiling_scale` times per world unit.
+vec4 sample_tile(vec2 coord) {
+    vec2 repeat_coord = coord * u_tiling_scale;
+    // Take derivatives of the unwrapped coordinate, since `fract` jumps at every tile edge, which
+    // would otherwise select the smallest mip level there.
+    vec2 dx = dFdx(repeat_coord) / u_atlas_dims;
+    vec2 dy = dFdy(repeat_coord) / u_atlas_dims;
+
+    // Keep samples half a texel inside the tile at the mip level being read, so that filtering
+    // never reaches into the neighbouring tile.
+    vec2 atlas_size = vec2(textureSize(sampler2D(t_color, s_color), 0));
+    vec2 dx_texels = dx * atlas_size;
+    vec2 dy_texels = dy * atlas_size;
+    float lod = max(0.5 * log2(max(dot(dx_texels, dx_texels), dot(dy_texels, dy_texels))), 0.0);
+    vec2 inset = 0.5 * exp2(lod) * u_atlas_dims / atlas_size;
+    vec2 in_tile = clamp(fract(repeat_coord), inset, 1.0 - inset);
+
+    vec2 tile_coord = (vec2(v_tile) + in_tile) / u_atlas_dims;
+    return textureGrad(sampler2D(t_color, s_color), tile_coord, dx, dy);
+}
+
+void main() {
+    vec3 weights = triplanar_blend(v_normal, u_blend_sharpness);
+    vec4 x_proj = sample_tile(vec2(v_world_pos.y, -v_world_pos.z));
+    vec4 y_proj = sample_tile(vec2(v_world_pos.x, -v_world_pos.z));
+    vec4 z_proj = sample_tile(v_world_pos.xy);
+
+    o_position = vec4(v_world_pos, 1.0);
+    o_normal = vec4(v_normal, SHADING_TERRAIN);
+    o_albedo = x_proj * weights.x + y_proj * weights.y + z_proj * weights.z;
+    o_material = vec4(0.0);
+}
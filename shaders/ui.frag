@@ -0,0 +1,11 @@
+#version 450
+
+// Unused until UI elements are textured.
+layout(location = 0) in vec2 v_tex_coord;
+layout(location = 1) in vec4 v_color;
+
+layout(location = 0) out vec4 o_target;
+
+void main() {
+    o_target = v_color;
+}
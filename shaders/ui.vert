@@ -0,0 +1,15 @@
+#version 450
+
+// Positions are already in normalized device coordinates.
+layout(location = 0) in vec2 a_pos;
+layout(location = 1) in vec2 a_tex_coord;
+layout(location = 2) in vec4 a_color;
+
+layout(location = 0) out vec2 o_tex_coord;
+layout(location = 1) out vec4 o_color;
+
+void main() {
+    o_tex_coord = a_tex_coord;
+    o_color = a_color;
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+}
@@ -0,0 +1,81 @@
+//! Texture atlases: single images divided into a regular grid of tiles, so that many materials can
+//! be drawn from one texture in one draw call.
+
+use crate::mesh::CuboidFaces;
+
+/// Chooses the atlas tile for one face of a terrain block, given the block's position in world
+/// space, with `z` measured from sea level. Returns the `(col, row)` of the tile.
+pub type TileSelector = fn(x: i32, y: i32, z: i32, face: CuboidFaces) -> (u32, u32);
+
+/// The grid an atlas is divided into. This is kept separate from the image so that meshes can be
+/// generated against an atlas without holding onto its pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasLayout {
+    pub tile_cols: u32,
+    pub tile_rows: u32,
+}
+
+pub struct TextureAtlas {
+    pub image: image::RgbaImage,
+    pub layout: AtlasLayout,
+}
+
+impl TextureAtlas {
+    /// Loads the image at `image_path` as an atlas of `tile_cols` by `tile_rows` tiles. The image
+    /// is converted to RGBA8, whatever its original format.
+    pub fn load(
+        image_path: &std::path::Path,
+        tile_cols: u32,
+        tile_rows: u32,
+    ) -> image::ImageResult<Self> {
+        let image = image::open(image_path)?.to_rgba();
+        Ok(Self {
+            image,
+            layout: AtlasLayout {
+                tile_cols,
+                tile_rows,
+            },
+        })
+    }
+
+    /// Generates an atlas of `tile_cols` by `tile_rows` tiles, each a `tile_size` square colored by
+    /// `gradient` with `utils::create_texels`. This stands in for a missing atlas image.
+    pub fn from_gradient(
+        tile_size: u32,
+        tile_cols: u32,
+        tile_rows: u32,
+        gradient: &crate::utils::HeightGradient,
+    ) -> Self {
+        let tile = image::RgbaImage::from_raw(tile_size, tile_size, crate::utils::create_texels(tile_size, gradient))
+            .expect("create_texels returned a tile of the wrong size");
+        let mut image = image::RgbaImage::new(tile_size * tile_cols, tile_size * tile_rows);
+        for row in 0..tile_rows {
+            for col in 0..tile_cols {
+                image::imageops::replace(&mut image, &tile, col * tile_size, row * tile_size);
+            }
+        }
+        Self {
+            image,
+            layout: AtlasLayout {
+                tile_cols,
+                tile_rows,
+            },
+        }
+    }
+}
+
+/// The tile selection used by the bundled `texture.png`: snow on the peaks, grass on top of the
+/// ground, gravel on its sides, and water below sea level.
+pub fn default_tile_selector(_x: i32, _y: i32, z: i32, face: CuboidFaces) -> (u32, u32) {
+    if z >= 15 {
+        (0, 0)
+    } else if z >= 0 {
+        if face == CuboidFaces::TOP {
+            (1, 1)
+        } else {
+            (0, 1)
+        }
+    } else {
+        (1, 0)
+    }
+}
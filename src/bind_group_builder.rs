@@ -0,0 +1,189 @@
+//! Builds a bind group layout and a bind group matching it from a single list of bindings, so the
+//! two can never disagree about which resource lives in which slot.
+
+#[derive(Default)]
+pub struct BindGroupBuilder<'a> {
+    layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    bindings: Vec<wgpu::Binding<'a>>,
+}
+
+impl<'a> BindGroupBuilder<'a> {
+    pub fn new() -> Self {
+        Self {
+            layout_entries: Vec::new(),
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Binds `buffer` as a uniform buffer of at least `binding_size` bytes.
+    pub fn add_uniform_buffer(
+        self,
+        binding: u32,
+        stages: wgpu::ShaderStage,
+        buffer: wgpu::BufferSlice<'a>,
+        binding_size: u64,
+    ) -> Self {
+        self.add_buffer(binding, stages, buffer, binding_size, false)
+    }
+
+    /// Binds `buffer` as a uniform buffer viewed `binding_size` bytes at a time, starting at the
+    /// dynamic offset given when the bind group is set.
+    pub fn add_dynamic_uniform_buffer(
+        self,
+        binding: u32,
+        stages: wgpu::ShaderStage,
+        buffer: wgpu::BufferSlice<'a>,
+        binding_size: u64,
+    ) -> Self {
+        self.add_buffer(binding, stages, buffer, binding_size, true)
+    }
+
+    /// Binds `buffer` as a storage buffer of at least `binding_size` bytes, which shaders may only
+    /// read from if `readonly` is set.
+    pub fn add_storage_buffer(
+        mut self,
+        binding: u32,
+        stages: wgpu::ShaderStage,
+        buffer: wgpu::BufferSlice<'a>,
+        binding_size: u64,
+        readonly: bool,
+    ) -> Self {
+        self.layout_entries.push(wgpu::BindGroupLayoutEntry::new(
+            binding,
+            stages,
+            wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                min_binding_size: wgpu::BufferSize::new(binding_size),
+                readonly,
+            },
+        ));
+        self.bindings.push(wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Buffer(buffer),
+        });
+        self
+    }
+
+    /// Binds `view` as a two-dimensional storage image of `format`, which shaders may only write to.
+    pub fn add_storage_texture(
+        mut self,
+        binding: u32,
+        stages: wgpu::ShaderStage,
+        view: &'a wgpu::TextureView,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        self.layout_entries.push(wgpu::BindGroupLayoutEntry::new(
+            binding,
+            stages,
+            wgpu::BindingType::StorageTexture {
+                dimension: wgpu::TextureViewDimension::D2,
+                format,
+                readonly: false,
+            },
+        ));
+        self.bindings.push(wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        });
+        self
+    }
+
+    /// Binds `view` as a two-dimensional, single-sampled float texture.
+    pub fn add_texture(self, binding: u32, stages: wgpu::ShaderStage, view: &'a wgpu::TextureView) -> Self {
+        self.add_texture_view(binding, stages, view, wgpu::TextureViewDimension::D2)
+    }
+
+    /// Binds `view` as a single-sampled float two-dimensional texture array.
+    pub fn add_texture_array(self, binding: u32, stages: wgpu::ShaderStage, view: &'a wgpu::TextureView) -> Self {
+        self.add_texture_view(binding, stages, view, wgpu::TextureViewDimension::D2Array)
+    }
+
+    /// Binds `view` as a single-sampled float cube map.
+    pub fn add_cube_texture(self, binding: u32, stages: wgpu::ShaderStage, view: &'a wgpu::TextureView) -> Self {
+        self.add_texture_view(binding, stages, view, wgpu::TextureViewDimension::Cube)
+    }
+
+    fn add_texture_view(
+        mut self,
+        binding: u32,
+        stages: wgpu::ShaderStage,
+        view: &'a wgpu::TextureView,
+        dimension: wgpu::TextureViewDimension,
+    ) -> Self {
+        self.layout_entries.push(wgpu::BindGroupLayoutEntry::new(
+            binding,
+            stages,
+            wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                component_type: wgpu::TextureComponentType::Float,
+                dimension,
+            },
+        ));
+        self.bindings.push(wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        });
+        self
+    }
+
+    /// Binds `sampler` as a non-comparison sampler.
+    pub fn add_sampler(mut self, binding: u32, stages: wgpu::ShaderStage, sampler: &'a wgpu::Sampler) -> Self {
+        self.layout_entries.push(wgpu::BindGroupLayoutEntry::new(
+            binding,
+            stages,
+            wgpu::BindingType::Sampler { comparison: false },
+        ));
+        self.bindings.push(wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Sampler(sampler),
+        });
+        self
+    }
+
+    fn add_buffer(
+        mut self,
+        binding: u32,
+        stages: wgpu::ShaderStage,
+        buffer: wgpu::BufferSlice<'a>,
+        binding_size: u64,
+        dynamic: bool,
+    ) -> Self {
+        self.layout_entries.push(wgpu::BindGroupLayoutEntry::new(
+            binding,
+            stages,
+            wgpu::BindingType::UniformBuffer {
+                dynamic,
+                min_binding_size: wgpu::BufferSize::new(binding_size),
+            },
+        ));
+        self.bindings.push(wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Buffer(buffer),
+        });
+        self
+    }
+
+    /// Creates the bind group layout described by the added bindings, and a bind group using it.
+    pub fn build(self, gpu_context: &crate::gpu::GpuContext) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+        let layout = gpu_context.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            bindings: &self.layout_entries,
+        });
+        let bind_group = self.build_with_layout(gpu_context, &layout);
+        (layout, bind_group)
+    }
+
+    /// Creates a bind group from the added bindings using an existing `layout`, which must have been
+    /// built from the same kinds of bindings. This lets several bind groups share one layout.
+    pub fn build_with_layout(
+        &self,
+        gpu_context: &crate::gpu::GpuContext,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        gpu_context.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &self.bindings,
+            label: None,
+        })
+    }
+}
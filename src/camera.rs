@@ -1,6 +1,77 @@
 use crate::utils;
 use cgmath::prelude::*;
 
+/// The projection used to map the camera's view volume onto the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProjectionMode {
+    /// A standard perspective projection using the camera's vertical field of view.
+    Perspective,
+    /// A parallel projection, as used for a top-down minimap. The view volume extends `half_height` world units
+    /// above and below the view direction; the horizontal extent is derived from the aspect ratio.
+    Orthographic { half_height: f32 },
+}
+
+/// The placement of a camera orbiting a fixed point, as an alternative to flying freely.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrbitMode {
+    /// The point the camera circles, and always looks at.
+    pub target: cgmath::Point3<f32>,
+    /// The distance from the target to the camera.
+    pub distance: f32,
+    /// The angle of the camera around the target's z-axis, measured from the x-axis, in radians.
+    pub azimuth: f32,
+    /// The angle of the camera above the target's xy plane, in radians.
+    pub elevation: f32,
+}
+
+/// Where a camera is and which way it looks, apart from its projection. See `Camera::pose`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraPose {
+    pub position: cgmath::Point3<f32>,
+    /// The normalized view direction.
+    pub view: cgmath::Vector3<f32>,
+    pub pitch: cgmath::Rad<f32>,
+}
+
+impl CameraPose {
+    /// Blends from `self`, at a `t` of 0, to `other`, at 1. The view direction is interpolated
+    /// linearly and then normalized, which is close enough for the small turns made in one update.
+    pub fn lerp(&self, other: &CameraPose, t: f32) -> CameraPose {
+        let view = self.view.lerp(other.view, t);
+        CameraPose {
+            position: self.position + (other.position - self.position) * t,
+            view: if view.magnitude2() > 0.0 { view.normalize() } else { other.view },
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+        }
+    }
+}
+
+/// Orbiting cameras never come closer to their target than this.
+const MIN_ORBIT_DISTANCE: f32 = 1.0;
+
+/// Orbiting cameras stay this far short of looking straight up or down, where the view would spin
+/// about the target.
+const ORBIT_ELEVATION_MARGIN: f32 = 0.01;
+
+/// How much each line of scrolling scales the orbit distance by.
+const ORBIT_ZOOM_PER_LINE: f32 = 0.9;
+
+/// The camera data made available to shaders. Matches the `Camera` uniform block in the shaders.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CameraUniforms {
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+    pub view_proj: [[f32; 4]; 4],
+    pub inv_view: [[f32; 4]; 4],
+    pub inv_proj: [[f32; 4]; 4],
+    /// The camera's world position. The w component is always 1.
+    pub camera_pos: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for CameraUniforms {}
+unsafe impl bytemuck::Zeroable for CameraUniforms {}
+
 pub struct Camera {
     position: cgmath::Point3<f32>,
     view: cgmath::Vector3<f32>,
@@ -11,6 +82,10 @@ pub struct Camera {
     // which wraps the more general `Camera` type.
     pitch: cgmath::Rad<f32>,
 
+    /// Set while the camera orbits a target, rather than flying freely. See `orbit`.
+    orbit: Option<OrbitMode>,
+
+    projection_mode: ProjectionMode,
     aspect_ratio: f32,
     fovy: f32,
     z_near: f32,
@@ -36,12 +111,11 @@ impl Camera {
         let up = up.normalize();
 
         // Calculate the current pitch of the camera, given our view vector. This is just the angle
-        // between the view vector and the xy plane.
-        let mut pitch = view.angle(view - (view.project_on(cgmath::Vector3::unit_z())));
-        // If we were looking down, invert the calculated angle.
-        if view.z < 0.0 {
-            pitch = -pitch;
-        }
+        // between the view vector and the xy plane. Since the view vector is normalized, this is the
+        // arcsine of its z component, which (unlike measuring the angle against the view vector's
+        // projection onto the xy plane) is well defined when looking straight up or down. Rounding
+        // can leave the normalized z just past 1, where the arcsine is NaN, so it is clamped.
+        let pitch = cgmath::Rad(view.z.max(-1.0).min(1.0).asin());
 
         Self {
             position,
@@ -50,6 +124,9 @@ impl Camera {
 
             pitch,
 
+            orbit: None,
+
+            projection_mode: ProjectionMode::Perspective,
             aspect_ratio,
             fovy,
             z_near,
@@ -60,23 +137,206 @@ impl Camera {
         }
     }
 
+    /// The combined view-projection matrix, mapping world space to clip space.
     // Requires a mutable reference since this function caches its results. This might be okay.
     pub fn matrix(&mut self) -> cgmath::Matrix4<f32> {
         if let Some(cached_matrix) = self.cached_matrix {
             cached_matrix
         } else {
-            let m =
-                utils::OPENGL_TO_WGPU_MATRIX *
-                cgmath::perspective(cgmath::Deg(self.fovy), self.aspect_ratio, self.z_near, self.z_far) *
-                cgmath::Matrix4::look_at_dir(self.position, self.view, self.up);
-
+            let m = self.projection_matrix() * self.view_matrix();
             self.cached_matrix = Some(m);
             m
         }
     }
 
+    /// The view matrix, mapping world space to view space.
+    pub fn view_matrix(&self) -> cgmath::Matrix4<f32> {
+        utils::view_matrix(self.position, self.position + self.view, self.up)
+    }
+
+    /// The projection matrix, mapping view space to clip space.
+    pub fn projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        match self.projection_mode {
+            ProjectionMode::Perspective =>
+                utils::projection_matrix(self.aspect_ratio, self.fovy, self.z_near, self.z_far),
+            ProjectionMode::Orthographic { half_height } => {
+                let half_width = half_height * self.aspect_ratio;
+                utils::OPENGL_TO_WGPU_MATRIX *
+                    cgmath::ortho(-half_width, half_width, -half_height, half_height, self.z_near, self.z_far)
+            },
+        }
+    }
+
+    /// Computes every matrix the shaders may need at once.
+    pub fn uniforms(&mut self) -> CameraUniforms {
+        let view = self.view_matrix();
+        let proj = self.projection_matrix();
+        // Both matrices are invertible for any sensible camera; fall back to the identity rather
+        // than fail for a degenerate one.
+        let inv_view = view.invert().unwrap_or_else(cgmath::Matrix4::identity);
+        let inv_proj = proj.invert().unwrap_or_else(cgmath::Matrix4::identity);
+        CameraUniforms {
+            view: view.into(),
+            proj: proj.into(),
+            view_proj: self.matrix().into(),
+            inv_view: inv_view.into(),
+            inv_proj: inv_proj.into(),
+            camera_pos: [self.position.x, self.position.y, self.position.z, 1.0],
+        }
+    }
+
+    pub fn position(&self) -> cgmath::Point3<f32> {
+        self.position
+    }
+
+    /// Moves the camera to `position`. This leaves orbit mode.
+    pub fn set_position(&mut self, position: cgmath::Point3<f32>) {
+        self.invalidate_cache();
+
+        self.orbit = None;
+        self.position = position;
+    }
+
+    /// The camera's position and view direction, to be restored later with `set_pose`.
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            position: self.position,
+            view: self.view,
+            pitch: self.pitch,
+        }
+    }
+
+    /// Places the camera at `pose`. Unlike `set_position` and `set_yaw_pitch`, this stays in orbit
+    /// mode, so that a pose returned by `pose` is restored exactly.
+    pub fn set_pose(&mut self, pose: CameraPose) {
+        self.invalidate_cache();
+
+        self.position = pose.position;
+        self.view = pose.view;
+        self.pitch = pose.pitch;
+    }
+
+    /// The angle of the view direction around the z-axis, measured from the x-axis.
+    pub fn yaw(&self) -> cgmath::Rad<f32> {
+        cgmath::Rad(self.view.y.atan2(self.view.x))
+    }
+
+    /// The angle between the view direction and the xy plane.
+    pub fn pitch(&self) -> cgmath::Rad<f32> {
+        self.pitch
+    }
+
+    /// Points the camera in the direction given by `yaw` and `pitch`, as returned by `yaw()` and
+    /// `pitch()`. The pitch is clamped to [-pi/2, pi/2]. This leaves orbit mode.
+    pub fn set_yaw_pitch(&mut self, yaw: cgmath::Rad<f32>, pitch: cgmath::Rad<f32>) {
+        self.orbit = None;
+        self.point(yaw, pitch);
+    }
+
+    /// Like `set_yaw_pitch`, but stays in orbit mode.
+    fn point(&mut self, yaw: cgmath::Rad<f32>, pitch: cgmath::Rad<f32>) {
+        self.invalidate_cache();
+
+        let half_pi = std::f32::consts::PI * 0.5;
+        self.pitch = cgmath::Rad(pitch.0.max(-half_pi).min(half_pi));
+        let (sin_yaw, cos_yaw) = yaw.0.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
+        self.view = cgmath::Vector3::new(cos_pitch * cos_yaw, cos_pitch * sin_yaw, sin_pitch);
+    }
+
+    /// Points the camera at `target`. Does nothing if `target` is the camera's position. This leaves
+    /// orbit mode.
+    pub fn look_at(&mut self, target: cgmath::Point3<f32>) {
+        self.orbit = None;
+        self.point_at(target);
+    }
+
+    /// Like `look_at`, but stays in orbit mode.
+    fn point_at(&mut self, target: cgmath::Point3<f32>) {
+        let direction = target - self.position;
+        if direction.magnitude2() == 0.0 {
+            return;
+        }
+        let direction = direction.normalize();
+        self.point(
+            cgmath::Rad(direction.y.atan2(direction.x)),
+            // Clamped as in `new`, since rounding can leave it just past 1.
+            cgmath::Rad(direction.z.max(-1.0).min(1.0).asin()),
+        );
+    }
+
+    /// Starts circling `target` at `distance`, from the direction the camera is in now, looking at
+    /// the target. While orbiting, mouse movement swings the camera around the target, `zoom`
+    /// changes the distance, and the movement functions move the target along with the camera.
+    /// Placing the camera directly, with `set_position`, `set_yaw_pitch`, or `look_at`, returns it
+    /// to flying freely.
+    pub fn orbit(&mut self, target: cgmath::Point3<f32>, distance: f32) {
+        // From the target itself, keep looking the same way.
+        let offset = self.position - target;
+        let direction = if offset.magnitude2() > 0.0 { offset.normalize() } else { -self.view };
+        self.orbit = Some(OrbitMode {
+            target,
+            distance,
+            azimuth: direction.y.atan2(direction.x),
+            elevation: direction.z.max(-1.0).min(1.0).asin(),
+        });
+        self.update_orbit();
+    }
+
+    /// Stops orbiting, leaving the camera where it is, free to fly again.
+    pub fn free_fly(&mut self) {
+        self.orbit = None;
+    }
+
+    /// The camera's orbit, if it is orbiting rather than flying freely.
+    pub fn orbit_mode(&self) -> Option<OrbitMode> {
+        self.orbit
+    }
+
+    /// Moves an orbiting camera `lines` lines of scrolling closer to its target, or further away if
+    /// negative. Does nothing while flying freely.
+    pub fn zoom(&mut self, lines: f32) {
+        if let Some(orbit) = self.orbit.as_mut() {
+            orbit.distance *= ORBIT_ZOOM_PER_LINE.powf(lines);
+            self.update_orbit();
+        }
+    }
+
+    /// Places an orbiting camera according to its orbit, clamping the orbit to its limits first.
+    fn update_orbit(&mut self) {
+        let orbit = match self.orbit.as_mut() {
+            Some(orbit) => orbit,
+            None => return,
+        };
+        let max_elevation = std::f32::consts::PI * 0.5 - ORBIT_ELEVATION_MARGIN;
+        orbit.elevation = orbit.elevation.max(-max_elevation).min(max_elevation);
+        orbit.distance = orbit.distance.max(MIN_ORBIT_DISTANCE);
+        let (sin_azimuth, cos_azimuth) = orbit.azimuth.sin_cos();
+        let (sin_elevation, cos_elevation) = orbit.elevation.sin_cos();
+        let offset = cgmath::Vector3::new(cos_elevation * cos_azimuth, cos_elevation * sin_azimuth, sin_elevation);
+        let target = orbit.target;
+
+        self.position = target + offset * orbit.distance;
+        self.point_at(target);
+    }
+
+    /// Moves the camera by `offset`, taking the target of its orbit with it.
+    fn translate(&mut self, offset: cgmath::Vector3<f32>) {
+        self.invalidate_cache();
+
+        self.position += offset;
+        if let Some(orbit) = self.orbit.as_mut() {
+            orbit.target += offset;
+        }
+    }
+
+    /// The normalized direction the camera is looking in.
+    pub fn view(&self) -> cgmath::Vector3<f32> {
+        self.view
+    }
+
     // Requires a mutable reference since this function caches its results. This might be okay.
-    fn right(&mut self) -> cgmath::Vector3<f32> {
+    pub fn right(&mut self) -> cgmath::Vector3<f32> {
         if let Some(cached_cross) = self.cached_right {
             cached_cross
         } else {
@@ -98,44 +358,66 @@ impl Camera {
         self.aspect_ratio = aspect_ratio;
     }
 
-    pub fn move_forward(&mut self, mag: f32) {
+    /// The vertical field of view, in degrees.
+    pub fn fovy(&self) -> f32 {
+        self.fovy
+    }
+
+    pub fn set_fovy(&mut self, fovy: f32) {
         self.invalidate_cache();
 
-        self.position += self.view * mag;
+        self.fovy = fovy;
     }
 
-    pub fn move_backward(&mut self, mag: f32) {
+    /// The distances to the near and far clip planes.
+    #[allow(dead_code)]
+    pub fn clip_planes(&self) -> (f32, f32) {
+        (self.z_near, self.z_far)
+    }
+
+    /// Sets the distances to the near and far clip planes. `z_near` must be positive, and less than
+    /// `z_far`.
+    pub fn set_clip_planes(&mut self, z_near: f32, z_far: f32) {
         self.invalidate_cache();
 
-        self.position -= self.view * mag;
+        self.z_near = z_near;
+        self.z_far = z_far;
     }
 
-    pub fn move_up(&mut self, mag: f32) {
+    pub fn set_projection_mode(&mut self, projection_mode: ProjectionMode) {
         self.invalidate_cache();
 
-        self.position += self.up * mag;
+        self.projection_mode = projection_mode;
     }
 
-    pub fn move_down(&mut self, mag: f32) {
-        self.invalidate_cache();
+    pub fn move_forward(&mut self, mag: f32) {
+        self.translate(self.view * mag);
+    }
+
+    pub fn move_backward(&mut self, mag: f32) {
+        self.translate(-self.view * mag);
+    }
 
-        self.position -= self.up * mag;
+    pub fn move_up(&mut self, mag: f32) {
+        self.translate(self.up * mag);
     }
 
-    pub fn move_right(&mut self, mag: f32) {
-        self.invalidate_cache();
+    pub fn move_down(&mut self, mag: f32) {
+        self.translate(-self.up * mag);
+    }
 
+    pub fn move_right(&mut self, mag: f32) {
         let r = self.right();
-        self.position += r * mag;
+        self.translate(r * mag);
     }
 
     pub fn move_left(&mut self, mag: f32) {
-        self.invalidate_cache();
-
         let r = self.right();
-        self.position -= r * mag;
+        self.translate(-r * mag);
     }
 
+    /// Turns the camera by a mouse movement of `delta`, or swings it around its target while
+    /// orbiting. Larger sensitivities turn the camera less for the same movement.
     pub fn rotate_by_x_y(
         &mut self,
         delta: cgmath::Vector2<f32>,
@@ -144,6 +426,14 @@ impl Camera {
     ) {
         self.invalidate_cache();
 
+        // Dragging the view one way swings the camera around the other, as if turning the scene.
+        if let Some(orbit) = self.orbit.as_mut() {
+            orbit.azimuth += delta.x / x_sensitivity;
+            orbit.elevation -= delta.y / y_sensitivity;
+            self.update_orbit();
+            return;
+        }
+
         // Left and right rotation, so rotate around the z-axis, *not* the up axis.
         let rot_axis_x = cgmath::Vector3::new(0.0, 0.0, 1.0);
         let x_theta = cgmath::Rad(delta.x / x_sensitivity);
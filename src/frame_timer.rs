@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+/// Measures the time between frames, and keeps the most recent measurements for statistics.
+pub struct FrameTimer {
+    last_frame: std::time::Instant,
+    /// The most recent frame times, in seconds, oldest first.
+    frame_times: VecDeque<f32>,
+    /// The most frame times kept in `frame_times`.
+    capacity: usize,
+}
+
+#[allow(dead_code)]
+impl FrameTimer {
+    /// Creates a `FrameTimer` which keeps the last `capacity` frame times. Time is measured from
+    /// the moment it is created.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            last_frame: std::time::Instant::now(),
+            frame_times: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Marks the start of a frame. Returns the seconds since the previous call (or since the timer
+    /// was created), which is also recorded as a frame time.
+    pub fn tick(&mut self) -> f32 {
+        let now = std::time::Instant::now();
+        let frame_time = (now - self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        if self.frame_times.len() == self.capacity {
+            self.frame_times.pop_front();
+        }
+        if self.capacity > 0 {
+            self.frame_times.push_back(frame_time);
+        }
+        frame_time
+    }
+
+    /// The mean of the recorded frame times, in seconds, or 0 if none are recorded.
+    pub fn avg_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    /// The frame rate implied by `avg_frame_time`, or 0 if no frame times are recorded.
+    pub fn fps(&self) -> f32 {
+        let avg_frame_time = self.avg_frame_time();
+        if avg_frame_time > 0.0 { 1.0 / avg_frame_time } else { 0.0 }
+    }
+
+    /// The frame time which 99% of the recorded frame times do not exceed, in seconds, or 0 if
+    /// none are recorded.
+    pub fn p99_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let mut sorted: Vec<f32> = self.frame_times.iter().copied().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let index = ((sorted.len() as f32 * 0.99).ceil() as usize).saturating_sub(1);
+        sorted[index.min(sorted.len() - 1)]
+    }
+}
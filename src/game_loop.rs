@@ -0,0 +1,206 @@
+use winit::event::{DeviceEvent, VirtualKeyCode, WindowEvent};
+use winit::event_loop::ControlFlow;
+
+use crate::camera::CameraPose;
+use crate::input;
+use crate::render_context::RenderContext;
+
+/// How many pixels of scrolling, as reported by touchpads, make up one line.
+const PIXELS_PER_SCROLL_LINE: f64 = 20.0;
+
+/// The number of simulation updates per second.
+const UPDATE_RATE: u32 = 60;
+
+/// The most updates run to catch up before a single frame. If updates fall further behind than
+/// this (e.g. after the process was suspended), the backlog is dropped rather than letting the
+/// simulation fall ever further behind.
+const MAX_UPDATES_PER_FRAME: u32 = 8;
+
+/// Separates the three phases of the application. Events only record what happened; the
+/// simulation advances in fixed steps of `1 / UPDATE_RATE` seconds in `on_update`, however often
+/// frames are drawn; and the `RenderContext` draws only from `on_render`.
+pub struct GameLoop {
+    render_context: RenderContext,
+    input_context: input::InputContext,
+
+    /// Start focused by default, assuming the application was executed with the intention of using
+    /// it straight away.
+    window_focused: bool,
+    /// Input received since the last update, applied at the start of the next one.
+    pending_keys: Vec<VirtualKeyCode>,
+    pending_mouse_delta: (f64, f64),
+    /// Lines scrolled since the last update, positive away from the user.
+    pending_scroll: f32,
+    /// The most recent window size, applied before the next frame is rendered.
+    pending_resize: Option<winit::dpi::PhysicalSize<u32>>,
+
+    /// The camera as it was before the last update. Frames are drawn between it and the camera's
+    /// current pose, so that its motion looks smooth at any frame rate.
+    previous_camera_pose: CameraPose,
+
+    fixed_timestep: std::time::Duration,
+    previous_tick: std::time::Instant,
+    /// Time which has passed but has not yet been simulated.
+    accumulator: std::time::Duration,
+}
+
+impl GameLoop {
+    pub fn new(render_context: RenderContext) -> Self {
+        let previous_camera_pose = render_context.camera().pose();
+        Self {
+            render_context,
+            input_context: input::InputContext::new(),
+            window_focused: true,
+            pending_keys: Vec::new(),
+            pending_mouse_delta: (0.0, 0.0),
+            pending_scroll: 0.0,
+            pending_resize: None,
+            previous_camera_pose,
+            fixed_timestep: std::time::Duration::from_secs(1) / UPDATE_RATE,
+            previous_tick: std::time::Instant::now(),
+            accumulator: std::time::Duration::from_secs(0),
+        }
+    }
+
+    /// Records a window event. Nothing is acted on until the next update or frame.
+    pub fn on_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::Resized(size) => self.pending_resize = Some(*size),
+            WindowEvent::KeyboardInput {
+                input: winit::event::KeyboardInput {
+                    virtual_keycode: Some(keycode),
+                    state: winit::event::ElementState::Pressed, ..
+                }, ..
+            } => self.pending_keys.push(*keycode),
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.pending_scroll += match delta {
+                    winit::event::MouseScrollDelta::LineDelta(_, y) => *y,
+                    winit::event::MouseScrollDelta::PixelDelta(position) =>
+                        (position.y / PIXELS_PER_SCROLL_LINE) as f32,
+                };
+            },
+            // The window's physical size changes with its scale factor, which recreates the depth
+            // buffer and updates the camera just like a resize.
+            WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                self.render_context.on_dpi_scale_change(*scale_factor);
+                self.pending_resize = Some(**new_inner_size);
+            },
+            // We track if the window has focus so that we can ignore device events when focus is
+            // lost. The render context throttles rendering while unfocused.
+            WindowEvent::Focused(focused) => {
+                self.window_focused = *focused;
+                self.render_context.on_window_focus_change(*focused);
+            },
+            _ => {},
+        }
+    }
+
+    /// Records a device event. Device events are ignored while the window does not have focus.
+    pub fn on_device_event(&mut self, event: &DeviceEvent) {
+        if !self.window_focused {
+            return;
+        }
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = event {
+            self.pending_mouse_delta.0 += x;
+            self.pending_mouse_delta.1 += y;
+        }
+    }
+
+    /// Runs as many fixed updates as the time since the previous call calls for. This should be
+    /// called once per iteration of the event loop, before the frame is requested.
+    pub fn tick(&mut self) {
+        let now = std::time::Instant::now();
+        self.accumulator += now - self.previous_tick;
+        self.previous_tick = now;
+
+        let mut updates = 0;
+        while self.accumulator >= self.fixed_timestep {
+            if updates == MAX_UPDATES_PER_FRAME {
+                self.accumulator = std::time::Duration::from_secs(0);
+                break;
+            }
+            self.accumulator -= self.fixed_timestep;
+            self.on_update(self.fixed_timestep.as_secs_f32());
+            updates += 1;
+        }
+    }
+
+    /// How the event loop should continue after this iteration. With event-driven rendering, it
+    /// sleeps until the next event once all input has been applied and nothing is left to draw;
+    /// otherwise it never waits.
+    pub fn control_flow(&self) -> ControlFlow {
+        let idle = self.pending_keys.is_empty()
+            && self.pending_mouse_delta == (0.0, 0.0)
+            && self.pending_scroll == 0.0
+            && self.pending_resize.is_none()
+            && !self.render_context.needs_redraw();
+        if self.render_context.event_driven_rendering() && idle {
+            ControlFlow::Wait
+        } else {
+            ControlFlow::Poll
+        }
+    }
+
+    /// Advances the simulation by `dt` seconds, applying any input received since the last update.
+    pub fn on_update(&mut self, dt: f32) {
+        self.previous_camera_pose = self.render_context.camera().pose();
+        for keycode in self.pending_keys.drain(..) {
+            self.input_context.handle_key(&mut self.render_context, keycode);
+        }
+        let mouse_delta = std::mem::replace(&mut self.pending_mouse_delta, (0.0, 0.0));
+        if mouse_delta != (0.0, 0.0) {
+            self.input_context.handle_cursor_moved(&mut self.render_context, mouse_delta);
+        }
+        let scroll = std::mem::replace(&mut self.pending_scroll, 0.0);
+        if scroll != 0.0 {
+            self.input_context.handle_scroll(&mut self.render_context, scroll);
+        }
+        self.render_context.update(dt);
+    }
+
+    /// Renders a frame. `alpha` is how far the current time lies between the last update and the
+    /// next, in [0, 1). The camera is drawn that far from its pose before the last update to its
+    /// current one; the rest of the scene shows the state of the last update.
+    pub fn on_render(&mut self, alpha: f32) {
+        if let Some(size) = self.pending_resize.take() {
+            self.render_context.resize(size);
+        }
+        if !self.render_context.is_ready() {
+            return;
+        }
+        let current_pose = self.render_context.camera().pose();
+        if self.previous_camera_pose == current_pose {
+            // Taking the camera mutably would mark it dirty, so a still camera is left alone.
+            self.render_context.render();
+        } else {
+            // The simulation carries on from the current pose, so it is restored after the frame.
+            let interpolated_pose = self.previous_camera_pose.lerp(&current_pose, alpha);
+            self.render_context.camera_mut().set_pose(interpolated_pose);
+            self.render_context.render();
+            self.render_context.camera_mut().set_pose(current_pose);
+        }
+    }
+
+    /// Renders a frame at the current point between updates.
+    pub fn render(&mut self) {
+        let alpha = self.accumulator.as_secs_f32() / self.fixed_timestep.as_secs_f32();
+        self.on_render(alpha);
+    }
+
+    /// Saves the scene, along with the key bindings, if it asked to be saved. Called once, as the
+    /// application exits.
+    pub fn on_exit(&mut self, scene_path: &std::path::Path) {
+        if self.render_context.autosave() {
+            let mut scene_state = self.render_context.scene_state();
+            scene_state.key_bindings = Some(*self.input_context.key_bindings());
+            if let Err(e) = scene_state.write(scene_path) {
+                log::error!("Failed to save the scene: {}", e);
+            }
+        }
+    }
+
+    /// Replaces the controls with `key_bindings`, as saved with a scene.
+    pub fn set_key_bindings(&mut self, key_bindings: input::KeyBindings) {
+        self.input_context.set_key_bindings(key_bindings);
+    }
+}
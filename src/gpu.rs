@@ -1,26 +1,129 @@
 /// This struct is the base level abstraction to the GPU. It is responsible for managing the render
-/// surface, the swap chain, the device, and the device queue.
+/// surface, the swap chain, the device, and the device queue. A headless context, made with
+/// `create_headless`, has neither a surface nor a swap chain, and can only render offscreen.
 pub struct GpuContext {
     // TODO: does this field need to be here?
     #[allow(dead_code)]
     instance: wgpu::Instance,
 
-    surface: wgpu::Surface,
-    // TODO: does this field need to be here?
-    #[allow(dead_code)]
+    surface: Option<wgpu::Surface>,
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
 
+    /// Describes the swap chain, or for a headless context the size and format it would have.
     swap_chain_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
+    swap_chain: Option<wgpu::SwapChain>,
+
+    /// Staging memory for buffer uploads, reused from frame to frame. See `write_buffer`.
+    staging_belt: wgpu::util::StagingBelt,
+    /// Drives the futures returned by `staging_belt.recall()`, which resolve once the GPU is done
+    /// with the staging chunks of a frame.
+    recall_pool: futures::executor::LocalPool,
+}
+
+/// The size of each chunk of staging memory. Uploads larger than this get a chunk of their own.
+const STAGING_BELT_CHUNK_SIZE: wgpu::BufferAddress = 1 << 16;
+
+/// An offscreen texture to render into, with an optional depth buffer of the same size. The color
+/// texture can be sampled or copied from once rendered.
+pub struct RenderTarget {
+    pub color_texture: wgpu::Texture,
+    pub color_view: wgpu::TextureView,
+    pub depth_texture: Option<wgpu::Texture>,
+    pub depth_view: Option<wgpu::TextureView>,
+
+    format: wgpu::TextureFormat,
+}
+
+/// The format of every depth buffer, matching the depth state of the main pipelines. It has a
+/// stencil aspect for the outline of the selected terrain patch.
+pub const RENDER_TARGET_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+
+impl RenderTarget {
+    /// Recreates the textures at `width` by `height` pixels, keeping the format and whether there is
+    /// a depth buffer. The old contents are discarded, and any bind groups referencing the old views
+    /// must be rebuilt.
+    #[allow(dead_code)]
+    pub fn resize(&mut self, gpu_context: &GpuContext, width: u32, height: u32) {
+        *self = gpu_context.create_render_target(width, height, self.format, self.depth_texture.is_some());
+    }
+}
+
+/// A uniform buffer holding a single `T`. Unlike a `ManagedBuffer`, it has no CPU-side copy to
+/// flush: `update` writes through the queue immediately, and the write lands before the next
+/// submitted command buffer executes.
+pub struct UniformBuffer<T: bytemuck::Pod> {
+    buffer: wgpu::Buffer,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> UniformBuffer<T> {
+    pub fn new(gpu_context: &GpuContext, data: &T) -> Self {
+        Self {
+            buffer: gpu_context.create_buffer_init(
+                None,
+                bytemuck::bytes_of(data),
+                wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            ),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, data: &T) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(data));
+    }
+
+    #[allow(dead_code)]
+    pub fn binding_resource(&self) -> wgpu::BindingResource {
+        wgpu::BindingResource::Buffer(self.slice())
+    }
+
+    pub fn slice(&self) -> wgpu::BufferSlice {
+        self.buffer.slice(..)
+    }
+}
+
+/// Describes the device a `GpuContext` should request.
+pub struct GpuContextDescriptor {
+    /// Features we can make use of if present. These are intersected with the adapter's features,
+    /// so they are never strictly required.
+    pub features: wgpu::Features,
+    /// Limits the device must meet. Unlike features, these are required: `GpuContext::create` fails
+    /// if the device provides less than any of them.
+    pub limits: wgpu::Limits,
+    /// The number of bytes of push constants to request if the device supports
+    /// `wgpu::Features::PUSH_CONSTANTS` and it is among `features`. This raises
+    /// `limits.max_push_constant_size`, and is ignored otherwise, so push constants stay optional.
+    pub push_constant_size: u32,
+}
+
+/// The number of bytes of push constants requested by default: enough for one 4x4 float matrix and
+/// then some.
+pub const DEFAULT_PUSH_CONSTANT_SIZE: u32 = 128;
+
+impl Default for GpuContextDescriptor {
+    fn default() -> Self {
+        Self {
+            features: wgpu::Features::SAMPLER_ANISOTROPY | wgpu::Features::PUSH_CONSTANTS,
+            limits: wgpu::Limits::default(),
+            push_constant_size: DEFAULT_PUSH_CONSTANT_SIZE,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum GpuContextError {
     RequestAdapterError,
     RequestDeviceError(wgpu::RequestDeviceError),
+    /// The device provides less than the requested limits. Each entry names a limit, and gives the
+    /// requested and provided values.
+    UnsupportedLimitsError(Vec<(&'static str, u32, u32)>),
     SwapChainError(wgpu::SwapChainError),
+    /// A texture was requested with a zero width or height, given as `(width, height)`.
+    InvalidTextureSizeError(u32, u32),
+    /// A frame was requested from a headless context, which has no swap chain.
+    HeadlessError,
 }
 
 impl std::fmt::Display for GpuContextError {
@@ -29,65 +132,126 @@ impl std::fmt::Display for GpuContextError {
             GpuContextError::RequestAdapterError =>
                 write!(f, "Adapter request failed! `wgpu` returned `None`."),
             GpuContextError::RequestDeviceError(wgpu_err) =>
-                write!(f, "Device request failed! `wgpu` error is: {:?}", wgpu_err),
+                write!(f, "Device request failed! `wgpu` error is: {}", wgpu_err),
+            GpuContextError::UnsupportedLimitsError(limits) => {
+                write!(f, "Device does not support the requested limits!")?;
+                for (name, requested, provided) in limits {
+                    write!(f, " `{}`: requested {}, provided {}.", name, requested, provided)?;
+                }
+                Ok(())
+            },
             GpuContextError::SwapChainError(wgpu_err) =>
-                write!(f, "Swap chain operation failed! `wgpu` error is: {:?}", wgpu_err),
+                write!(f, "Swap chain operation failed! `wgpu` error is: {}", wgpu_err),
+            GpuContextError::InvalidTextureSizeError(width, height) =>
+                write!(f, "Cannot create a texture of {}x{} pixels!", width, height),
+            GpuContextError::HeadlessError =>
+                write!(f, "A headless context has no swap chain to present to!"),
         }
     }
 }
 
-impl std::error::Error for GpuContextError {}
+impl std::error::Error for GpuContextError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GpuContextError::RequestAdapterError => None,
+            GpuContextError::RequestDeviceError(wgpu_err) => Some(wgpu_err),
+            GpuContextError::UnsupportedLimitsError(_) => None,
+            GpuContextError::SwapChainError(wgpu_err) => Some(wgpu_err),
+            GpuContextError::InvalidTextureSizeError(..) => None,
+            GpuContextError::HeadlessError => None,
+        }
+    }
+}
 
 impl GpuContext {
-    /// Create a new `GpuContext` on the provided window.
-    pub async fn create(window: &winit::window::Window) -> Result<GpuContext, GpuContextError> {
+    /// Create a new `GpuContext` on the provided window, with a device meeting `desc`.
+    pub async fn create(
+        window: &winit::window::Window,
+        desc: &GpuContextDescriptor,
+    ) -> Result<GpuContext, GpuContextError> {
         let size = window.inner_size();
 
-        // TODO: These features will eventually need to be parameters.
-        let unsafe_features = wgpu::UnsafeFeatures::disallow();
-        let required_features = wgpu::Features::empty();
-
         // Create the wgpu instance.
         let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
 
         // Create the wgpu surface.
         let surface = unsafe { instance.create_surface(window) };
 
+        let mut context = Self::create_with_surface(instance, Some(surface), size.width, size.height, desc).await?;
+        context.recover_swap_chain();
+        Ok(context)
+    }
+
+    /// Create a new `GpuContext` with no window, with a device meeting `desc`. Frames can only be
+    /// rendered offscreen; `size` reports `width` by `height`, as the size offscreen frames should
+    /// have. This suits tests and tools run without a display.
+    #[allow(dead_code)]
+    pub async fn create_headless(
+        desc: &GpuContextDescriptor,
+        width: u32,
+        height: u32,
+    ) -> Result<GpuContext, GpuContextError> {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        Self::create_with_surface(instance, None, width, height, desc).await
+    }
+
+    /// Picks an adapter compatible with `surface`, if any, and creates the device. The swap chain is
+    /// left to the caller.
+    async fn create_with_surface(
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface>,
+        width: u32,
+        height: u32,
+        desc: &GpuContextDescriptor,
+    ) -> Result<GpuContext, GpuContextError> {
+        // TODO: These features will eventually need to be parameters.
+        let unsafe_features = wgpu::UnsafeFeatures::disallow();
+
         // Create the wgpu adapter.
         let adapter = instance
             .request_adapter(
                 &wgpu::RequestAdapterOptions {
                     power_preference: wgpu::PowerPreference::HighPerformance,
-                    compatible_surface: Some(&surface),
+                    compatible_surface: surface.as_ref(),
                 },
                 unsafe_features,
             )
             .await
             .ok_or(GpuContextError::RequestAdapterError)?;
 
-        let adapter_features = adapter.features();
+        let features = adapter.features() & desc.features;
+        // Devices without push constants only allow a push constant size of zero.
+        let mut limits = desc.limits.clone();
+        if features.contains(wgpu::Features::PUSH_CONSTANTS) {
+            limits.max_push_constant_size = limits.max_push_constant_size.max(desc.push_constant_size);
+        }
 
         // Create the device handle and the command queue handle for that device.
         let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
-            features: adapter_features & required_features,
-            limits: wgpu::Limits::default(),
+            features,
+            limits: limits.clone(),
             shader_validation: true,
         }, None)
         .await
         .map_err(|e| GpuContextError::RequestDeviceError(e))?;
 
-        // Create our swapchain. The swapchain is an abstraction over a buffered pixel array which corresponds directly
-        // to the image which is rendered onto the display.
+        // Fail now if the device fell short of the requested limits, rather than when some later
+        // operation exceeds them.
+        let unsupported_limits = unsupported_limits(&limits, &device.limits());
+        if !unsupported_limits.is_empty() {
+            return Err(GpuContextError::UnsupportedLimitsError(unsupported_limits));
+        }
+
+        // Describe our swapchain. The swapchain is an abstraction over a buffered pixel array which corresponds
+        // directly to the image which is rendered onto the display.
         let swap_chain_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
             format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            width: size.width,
-            height: size.height,
+            width,
+            height,
             present_mode: wgpu::PresentMode::Mailbox,
         };
 
-        let swap_chain = device.create_swap_chain(&surface, &swap_chain_desc);
-
         Ok(Self {
             instance,
             surface,
@@ -95,7 +259,9 @@ impl GpuContext {
             device,
             queue,
             swap_chain_desc,
-            swap_chain,
+            swap_chain: None,
+            staging_belt: wgpu::util::StagingBelt::new(STAGING_BELT_CHUNK_SIZE),
+            recall_pool: futures::executor::LocalPool::new(),
         })
     }
 
@@ -106,7 +272,14 @@ impl GpuContext {
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
         self.swap_chain_desc.width = size.width;
         self.swap_chain_desc.height = size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.swap_chain_desc);
+        self.recover_swap_chain();
+    }
+
+    /// Recreates the swap chain from the last-known descriptor. The old swap chain is dropped as
+    /// the new one replaces it, so this can be called repeatedly without accumulating swap chains.
+    /// A headless context has no swap chain to recreate.
+    pub fn recover_swap_chain(&mut self) {
+        recreate_swap_chain(self);
     }
 
     pub fn create_command_encoder(&self) -> wgpu::CommandEncoder {
@@ -119,14 +292,168 @@ impl GpuContext {
         self.device.create_shader_module(spirv)
     }
 
+    /// Gets the next frame from the swap chain. If the swap chain has been lost, for instance because
+    /// the surface changed between a resize and this call, it is recreated once and the frame is
+    /// requested again. A headless context has no frames to give.
     pub fn get_next_frame(&mut self) -> Result<wgpu::SwapChainFrame, GpuContextError> {
-        self.swap_chain.get_next_frame().map_err(|e| GpuContextError::SwapChainError(e))
+        acquire_with_recovery(self)
+    }
+
+    /// Creates a `width` by `height` depth texture in `RENDER_TARGET_DEPTH_FORMAT`, with a view of
+    /// it. Besides being rendered into, its depth aspect can be sampled, for post-processing; the
+    /// format cannot be copied from. Fails if either dimension is zero, rather than letting wgpu
+    /// panic.
+    pub fn create_depth_texture(
+        &self,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Result<(wgpu::Texture, wgpu::TextureView), GpuContextError> {
+        if width == 0 || height == 0 {
+            return Err(GpuContextError::InvalidTextureSizeError(width, height));
+        }
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: RENDER_TARGET_DEPTH_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+            label: Some("depth texture"),
+        });
+        let view = texture.create_default_view();
+        Ok((texture, view))
+    }
+
+    /// Creates a `RenderTarget` of `width` by `height` pixels, with a color texture in `format`, and
+    /// a depth buffer if `with_depth` is set.
+    pub fn create_render_target(
+        &self,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        with_depth: bool,
+    ) -> RenderTarget {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        };
+        let color_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_SRC,
+            label: None,
+        });
+        let color_view = color_texture.create_default_view();
+
+        let depth_texture = if with_depth {
+            Some(self.device.create_texture(&wgpu::TextureDescriptor {
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: RENDER_TARGET_DEPTH_FORMAT,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+                label: None,
+            }))
+        } else {
+            None
+        };
+        let depth_view = depth_texture.as_ref().map(|texture| texture.create_default_view());
+
+        RenderTarget {
+            color_texture,
+            color_view,
+            depth_texture,
+            depth_view,
+            format,
+        }
+    }
+
+    /// Sets push constants for the following draws in `render_pass` to the bytes of `data`.
+    /// `offset` and the size of `T` must be multiples of 4 bytes, and lie within the current
+    /// pipeline's push constant range for `stages`. The device must support
+    /// `wgpu::Features::PUSH_CONSTANTS`.
+    pub fn set_push_constants<T: bytemuck::Pod>(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        stages: wgpu::ShaderStage,
+        offset: u32,
+        data: &T,
+    ) {
+        debug_assert!(self.features().contains(wgpu::Features::PUSH_CONSTANTS));
+        debug_assert_eq!(std::mem::size_of::<T>() % 4, 0);
+        // `wgpu` takes push constants as 32-bit words. `data` need not be aligned to 4 bytes, so it
+        // is copied into words rather than cast.
+        let words: Vec<u32> = bytemuck::bytes_of(data)
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        render_pass.set_push_constants(stages, offset, &words);
+    }
+
+    /// Records a compute pass onto `encoder` dispatching `pipeline` over an `x` by `y` by `z` grid
+    /// of work groups, with `bind_group` bound at index 0.
+    pub fn dispatch_compute_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        bind_group: &wgpu::BindGroup,
+        x: u32,
+        y: u32,
+        z: u32,
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass();
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.dispatch(x, y, z);
     }
 
     pub fn submit_command_encoder(&self, encoder: wgpu::CommandEncoder) {
         self.queue.submit(Some(encoder.finish()));
     }
 
+    /// Submits the command buffers making up a frame. Any encoder passed to `write_buffer` since the
+    /// last call must be among them, since the staging memory is closed off here and reclaimed
+    /// once the GPU has consumed it.
+    pub fn finish_frame<I: IntoIterator<Item = wgpu::CommandBuffer>>(&mut self, command_buffers: I) {
+        self.staging_belt.finish();
+        self.queue.submit(command_buffers);
+
+        use futures::task::SpawnExt;
+        if let Err(e) = self.recall_pool.spawner().spawn(self.staging_belt.recall()) {
+            log::error!("Failed to reclaim staging memory: {}", e);
+        }
+        self.device.poll(wgpu::Maintain::Poll);
+        self.recall_pool.run_until_stalled();
+    }
+
+    /// Records a copy of `data` into `target` at `offset` on `encoder`, staging it in memory which is
+    /// reused across frames. The encoder must be submitted through `finish_frame`.
+    pub fn write_buffer(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[u8],
+    ) {
+        let size = match wgpu::BufferSize::new(data.len() as u64) {
+            Some(size) => size,
+            None => return,
+        };
+        self.staging_belt
+            .write_buffer(encoder, target, offset, size, &self.device)
+            .copy_from_slice(data);
+    }
+
     //
     // Forwarding functions.
     //
@@ -155,20 +482,64 @@ impl GpuContext {
         self.device.create_render_pipeline(desc)
     }
 
-    pub fn create_buffer_with_data(&self, data: &[u8], usage: wgpu::BufferUsage) -> wgpu::Buffer {
-        self.device.create_buffer_with_data(data, usage)
+    pub fn create_compute_pipeline(&self, desc: &wgpu::ComputePipelineDescriptor) -> wgpu::ComputePipeline {
+        self.device.create_compute_pipeline(desc)
+    }
+
+    pub fn create_buffer(&self, desc: &wgpu::BufferDescriptor) -> wgpu::Buffer {
+        self.device.create_buffer(desc)
+    }
+
+    pub fn create_buffer_init(&self, label: Option<&str>, contents: &[u8], usage: wgpu::BufferUsage) -> wgpu::Buffer {
+        use wgpu::util::DeviceExt;
+        self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label,
+            contents,
+            usage,
+        })
+    }
+
+    pub fn poll(&self, maintain: wgpu::Maintain) {
+        self.device.poll(maintain)
     }
 
     //
     // Unknown.
     //
 
+    /// Gets the raw queue. This is intentionally always available: writes to buffers and textures
+    /// go straight through the queue, and wrapping each of them would add nothing.
     // TODO: Determine what functionality is needed from Queue and provide interfaces here, rather
     // than provide raw queue access.
     pub fn queue(&self) -> &wgpu::Queue {
         &self.queue
     }
 
+    /// Gets the raw device, for integrations which need functionality `GpuContext` does not wrap.
+    /// This is only available with the `raw-device-access` feature, so that code in the default
+    /// configuration goes through the `GpuContext` API.
+    #[cfg(feature = "raw-device-access")]
+    #[allow(dead_code)]
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    /// Gets the features enabled on the device. Optional functionality should check these before
+    /// being used.
+    pub fn features(&self) -> wgpu::Features {
+        self.device.features()
+    }
+
+    /// Logs, at `info` level, which adapter was selected and what it supports. The adapter info of
+    /// this wgpu does not include a driver version.
+    pub fn log_info(&self) {
+        let info = self.adapter.get_info();
+        log::info!("Adapter: {} ({:?}, {:?} backend)", info.name, info.device_type, info.backend);
+        log::info!("Vendor ID: {:#06x}, device ID: {:#06x}", info.vendor, info.device);
+        log::info!("Adapter features: {:?}", self.adapter.features());
+        log::info!("Enabled features: {:?}", self.features());
+    }
+
     /// Gets the aspect ratio of the current swap chain.
     // TODO: This feels weird to have here...
     pub fn aspect_ratio(&self) -> f32 {
@@ -181,3 +552,234 @@ impl GpuContext {
         (self.swap_chain_desc.width, self.swap_chain_desc.height)
     }
 }
+
+// Most of the fields are wgpu handles whose `Debug` output says little, so only the state that
+// identifies this context is printed.
+impl std::fmt::Debug for GpuContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let info = self.adapter.get_info();
+        f.debug_struct("GpuContext")
+            .field("adapter", &info.name)
+            .field("backend", &info.backend)
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+/// Returns the name, requested value, and provided value of every limit in `requested` which exceeds
+/// the corresponding limit in `provided`.
+fn unsupported_limits(requested: &wgpu::Limits, provided: &wgpu::Limits) -> Vec<(&'static str, u32, u32)> {
+    let limits = [
+        ("max_bind_groups", requested.max_bind_groups, provided.max_bind_groups),
+        (
+            "max_dynamic_uniform_buffers_per_pipeline_layout",
+            requested.max_dynamic_uniform_buffers_per_pipeline_layout,
+            provided.max_dynamic_uniform_buffers_per_pipeline_layout,
+        ),
+        (
+            "max_dynamic_storage_buffers_per_pipeline_layout",
+            requested.max_dynamic_storage_buffers_per_pipeline_layout,
+            provided.max_dynamic_storage_buffers_per_pipeline_layout,
+        ),
+        (
+            "max_sampled_textures_per_shader_stage",
+            requested.max_sampled_textures_per_shader_stage,
+            provided.max_sampled_textures_per_shader_stage,
+        ),
+        (
+            "max_samplers_per_shader_stage",
+            requested.max_samplers_per_shader_stage,
+            provided.max_samplers_per_shader_stage,
+        ),
+        (
+            "max_storage_buffers_per_shader_stage",
+            requested.max_storage_buffers_per_shader_stage,
+            provided.max_storage_buffers_per_shader_stage,
+        ),
+        (
+            "max_storage_textures_per_shader_stage",
+            requested.max_storage_textures_per_shader_stage,
+            provided.max_storage_textures_per_shader_stage,
+        ),
+        (
+            "max_uniform_buffers_per_shader_stage",
+            requested.max_uniform_buffers_per_shader_stage,
+            provided.max_uniform_buffers_per_shader_stage,
+        ),
+        ("max_push_constant_size", requested.max_push_constant_size, provided.max_push_constant_size),
+    ];
+    limits.iter().copied().filter(|&(_, requested, provided)| requested > provided).collect()
+}
+
+/// What swap chain recovery needs from the owner of a swap chain. `GpuContext` is the only real
+/// owner; the trait lets the bookkeeping in `recreate_swap_chain` and `acquire_with_recovery` be
+/// tested without a GPU.
+trait SwapChainOwner {
+    type SwapChain;
+    type Frame;
+
+    /// Creates a swap chain from the last-known descriptor, or returns `None` if there is no
+    /// surface to create one for.
+    fn create_swap_chain(&self) -> Option<Self::SwapChain>;
+
+    /// The current swap chain, if any.
+    fn swap_chain_mut(&mut self) -> &mut Option<Self::SwapChain>;
+
+    /// Gets the next frame from `swap_chain`.
+    fn next_frame(swap_chain: &mut Self::SwapChain) -> Result<Self::Frame, wgpu::SwapChainError>;
+}
+
+impl SwapChainOwner for GpuContext {
+    type SwapChain = wgpu::SwapChain;
+    type Frame = wgpu::SwapChainFrame;
+
+    fn create_swap_chain(&self) -> Option<wgpu::SwapChain> {
+        let surface = self.surface.as_ref()?;
+        Some(self.device.create_swap_chain(surface, &self.swap_chain_desc))
+    }
+
+    fn swap_chain_mut(&mut self) -> &mut Option<wgpu::SwapChain> {
+        &mut self.swap_chain
+    }
+
+    fn next_frame(swap_chain: &mut wgpu::SwapChain) -> Result<wgpu::SwapChainFrame, wgpu::SwapChainError> {
+        swap_chain.get_next_frame()
+    }
+}
+
+/// Replaces the swap chain of `owner` with a new one. The old swap chain is dropped as the new one
+/// replaces it, so this can be called repeatedly without accumulating swap chains. An owner with no
+/// surface keeps having no swap chain.
+fn recreate_swap_chain<O: SwapChainOwner>(owner: &mut O) {
+    if let Some(swap_chain) = owner.create_swap_chain() {
+        *owner.swap_chain_mut() = Some(swap_chain);
+    }
+}
+
+/// Acquires a frame from the swap chain of `owner`. If the swap chain has been lost, it is
+/// recreated once and the frame is requested again; any other error is returned as is. An owner
+/// with no swap chain gives `GpuContextError::HeadlessError`.
+fn acquire_with_recovery<O: SwapChainOwner>(owner: &mut O) -> Result<O::Frame, GpuContextError> {
+    let swap_chain = owner.swap_chain_mut().as_mut().ok_or(GpuContextError::HeadlessError)?;
+    let frame = match O::next_frame(swap_chain) {
+        Err(wgpu::SwapChainError::Lost) => {
+            recreate_swap_chain(owner);
+            // Recreating replaces the swap chain rather than removing it, so it is still there.
+            O::next_frame(owner.swap_chain_mut().as_mut().unwrap())
+        },
+        result => result,
+    };
+    frame.map_err(GpuContextError::SwapChainError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Stands in for a `GpuContext`. Each swap chain holds a clone of `live`, so the strong count
+    /// of `live` is one more than the number of swap chains alive.
+    struct MockOwner {
+        live: Rc<()>,
+        has_surface: bool,
+        swap_chain: Option<MockSwapChain>,
+        /// How many more times acquiring a frame reports the swap chain as lost.
+        losses: Rc<Cell<u32>>,
+        created: Cell<u32>,
+    }
+
+    struct MockSwapChain {
+        _live: Rc<()>,
+        losses: Rc<Cell<u32>>,
+    }
+
+    impl MockOwner {
+        /// An owner whose first `losses` frames report the swap chain as lost. It starts with a
+        /// swap chain if it has a surface, as `GpuContext::create` does.
+        fn new(has_surface: bool, losses: u32) -> Self {
+            let mut owner = Self {
+                live: Rc::new(()),
+                has_surface,
+                swap_chain: None,
+                losses: Rc::new(Cell::new(losses)),
+                created: Cell::new(0),
+            };
+            recreate_swap_chain(&mut owner);
+            owner
+        }
+    }
+
+    impl SwapChainOwner for MockOwner {
+        type SwapChain = MockSwapChain;
+        type Frame = ();
+
+        fn create_swap_chain(&self) -> Option<MockSwapChain> {
+            if !self.has_surface {
+                return None;
+            }
+            self.created.set(self.created.get() + 1);
+            Some(MockSwapChain {
+                _live: self.live.clone(),
+                losses: self.losses.clone(),
+            })
+        }
+
+        fn swap_chain_mut(&mut self) -> &mut Option<MockSwapChain> {
+            &mut self.swap_chain
+        }
+
+        fn next_frame(swap_chain: &mut MockSwapChain) -> Result<(), wgpu::SwapChainError> {
+            match swap_chain.losses.get() {
+                0 => Ok(()),
+                losses => {
+                    swap_chain.losses.set(losses - 1);
+                    Err(wgpu::SwapChainError::Lost)
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn consecutive_recoveries_keep_one_swap_chain() {
+        let mut owner = MockOwner::new(true, 0);
+        // Two resizes without a frame in between.
+        recreate_swap_chain(&mut owner);
+        recreate_swap_chain(&mut owner);
+        assert_eq!(owner.created.get(), 3);
+        assert_eq!(Rc::strong_count(&owner.live), 2);
+    }
+
+    #[test]
+    fn lost_swap_chain_is_recovered_once() {
+        let mut owner = MockOwner::new(true, 1);
+        assert!(acquire_with_recovery(&mut owner).is_ok());
+        assert_eq!(owner.created.get(), 2);
+        assert_eq!(Rc::strong_count(&owner.live), 2);
+    }
+
+    #[test]
+    fn swap_chain_lost_twice_fails() {
+        let mut owner = MockOwner::new(true, 2);
+        let result = acquire_with_recovery(&mut owner);
+        assert!(matches!(result, Err(GpuContextError::SwapChainError(wgpu::SwapChainError::Lost))));
+        assert_eq!(owner.created.get(), 2);
+    }
+
+    #[test]
+    fn headless_owner_has_no_frames() {
+        let mut owner = MockOwner::new(false, 0);
+        recreate_swap_chain(&mut owner);
+        assert!(owner.swap_chain.is_none());
+        assert!(matches!(acquire_with_recovery(&mut owner), Err(GpuContextError::HeadlessError)));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn log_info_on_a_headless_context() {
+        let desc = GpuContextDescriptor::default();
+        futures::executor::block_on(GpuContext::create_headless(&desc, 1, 1))
+            .expect("this test needs a GPU adapter")
+            .log_info();
+    }
+}
@@ -1,10 +1,88 @@
+use serde::{Deserialize, Serialize};
 use winit::event::VirtualKeyCode;
 
 use crate::render_context;
 
+/// The key bound to each action. These can be set from the `[key_bindings]` table of the scene
+/// file; any action left out keeps its default key. The arrow keys move the camera as well, unless
+/// they are bound to something else.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub move_forward: VirtualKeyCode,
+    pub move_back: VirtualKeyCode,
+    pub move_left: VirtualKeyCode,
+    pub move_right: VirtualKeyCode,
+    pub move_up: VirtualKeyCode,
+    pub move_down: VirtualKeyCode,
+    pub toggle_wireframe: VirtualKeyCode,
+    pub toggle_minimap: VirtualKeyCode,
+    /// Switches between flying freely and orbiting the center of the terrain.
+    pub toggle_orbit: VirtualKeyCode,
+}
+
+impl Default for KeyBindings {
+    /// Movement is on `FRST` rather than `WASD`, for Colemak.
+    fn default() -> Self {
+        Self {
+            move_forward: VirtualKeyCode::F,
+            move_back: VirtualKeyCode::S,
+            move_left: VirtualKeyCode::R,
+            move_right: VirtualKeyCode::T,
+            move_up: VirtualKeyCode::W,
+            move_down: VirtualKeyCode::P,
+            toggle_wireframe: VirtualKeyCode::L,
+            toggle_minimap: VirtualKeyCode::M,
+            toggle_orbit: VirtualKeyCode::O,
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Each action's name, as in the scene file, and its key.
+    fn actions(&self) -> [(&'static str, VirtualKeyCode); 9] {
+        [
+            ("move_forward", self.move_forward),
+            ("move_back", self.move_back),
+            ("move_left", self.move_left),
+            ("move_right", self.move_right),
+            ("move_up", self.move_up),
+            ("move_down", self.move_down),
+            ("toggle_wireframe", self.toggle_wireframe),
+            ("toggle_minimap", self.toggle_minimap),
+            ("toggle_orbit", self.toggle_orbit),
+        ]
+    }
+
+    /// The names of the first two actions which share a key, if any do. Only the first of those
+    /// actions could ever be triggered.
+    pub fn find_duplicate(&self) -> Option<(&'static str, &'static str)> {
+        let actions = self.actions();
+        actions.iter().enumerate().find_map(|(i, &(name, key))| {
+            actions[i + 1..].iter().find(|&&(_, other_key)| other_key == key).map(|&(other_name, _)| (name, other_name))
+        })
+    }
+
+    /// The key `keycode` acts as: the arrow keys are secondary movement keys, unless they are
+    /// bound to an action of their own.
+    fn resolve(&self, keycode: VirtualKeyCode) -> VirtualKeyCode {
+        if self.actions().iter().any(|&(_, key)| key == keycode) {
+            return keycode;
+        }
+        match keycode {
+            VirtualKeyCode::Up => self.move_forward,
+            VirtualKeyCode::Down => self.move_back,
+            VirtualKeyCode::Left => self.move_left,
+            VirtualKeyCode::Right => self.move_right,
+            _ => keycode,
+        }
+    }
+}
+
 pub struct InputContext {
     x_sensitivity: f32,
     y_sensitivity: f32,
+    key_bindings: KeyBindings,
 }
 
 impl InputContext {
@@ -12,32 +90,43 @@ impl InputContext {
         Self {
             x_sensitivity: 500.0,
             y_sensitivity: 500.0,
+            key_bindings: KeyBindings::default(),
         }
     }
 
+    pub fn key_bindings(&self) -> &KeyBindings {
+        &self.key_bindings
+    }
+
+    pub fn set_key_bindings(&mut self, key_bindings: KeyBindings) {
+        self.key_bindings = key_bindings;
+    }
+
     pub fn handle_key(&self, render_context: &mut render_context::RenderContext, keycode: VirtualKeyCode) {
-        match keycode {
-            VirtualKeyCode::F | VirtualKeyCode::Up => {
-                render_context.camera_mut().move_forward(0.5);
-            },
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                render_context.camera_mut().move_backward(0.5);
-            },
-            VirtualKeyCode::R | VirtualKeyCode::Left => {
-                render_context.camera_mut().move_left(0.5);
-            },
-            VirtualKeyCode::T | VirtualKeyCode::Right => {
-                render_context.camera_mut().move_right(0.5);
-            },
-            VirtualKeyCode::W => {
-                render_context.camera_mut().move_up(0.5);
-            },
-            VirtualKeyCode::P => {
-                render_context.camera_mut().move_down(0.5);
-            },
-            // Ignore other keys.
-            _ => {},
+        let bindings = &self.key_bindings;
+        let keycode = bindings.resolve(keycode);
+        if keycode == bindings.move_forward {
+            render_context.camera_mut().move_forward(0.5);
+        } else if keycode == bindings.move_back {
+            render_context.camera_mut().move_backward(0.5);
+        } else if keycode == bindings.move_left {
+            render_context.camera_mut().move_left(0.5);
+        } else if keycode == bindings.move_right {
+            render_context.camera_mut().move_right(0.5);
+        } else if keycode == bindings.move_up {
+            render_context.camera_mut().move_up(0.5);
+        } else if keycode == bindings.move_down {
+            render_context.camera_mut().move_down(0.5);
+        } else if keycode == bindings.toggle_minimap {
+            let enabled = render_context.minimap_enabled();
+            render_context.set_minimap_enabled(!enabled);
+        } else if keycode == bindings.toggle_wireframe {
+            let wireframe = render_context.wireframe();
+            render_context.set_wireframe(!wireframe);
+        } else if keycode == bindings.toggle_orbit {
+            render_context.toggle_orbit_mode(None);
         }
+        // Ignore other keys.
     }
 
     pub fn handle_cursor_moved(
@@ -48,4 +137,49 @@ impl InputContext {
         let delta = cgmath::Vector2::new(-delta_x as f32, -delta_y as f32);
         render_context.camera_mut().rotate_by_x_y(delta, self.x_sensitivity, self.y_sensitivity);
     }
+
+    /// Zooms an orbiting camera by `lines` lines of scrolling. Scrolling does nothing while flying.
+    pub fn handle_scroll(&mut self, render_context: &mut render_context::RenderContext, lines: f32) {
+        if render_context.camera().orbit_mode().is_some() {
+            render_context.camera_mut().zoom(lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings_have_no_duplicates() {
+        assert_eq!(KeyBindings::default().find_duplicate(), None);
+    }
+
+    #[test]
+    fn rebinding_onto_a_default_key_is_a_duplicate() {
+        let bindings: KeyBindings = toml::from_str(r#"move_forward = "W""#).unwrap();
+        assert_eq!(bindings.find_duplicate(), Some(("move_forward", "move_up")));
+
+        let bindings: KeyBindings = toml::from_str(
+            r#"
+            move_forward = "W"
+            move_left = "A"
+            move_right = "D"
+            move_up = "Space"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(bindings.find_duplicate(), None);
+    }
+
+    #[test]
+    fn arrow_keys_move_unless_rebound() {
+        let mut bindings = KeyBindings::default();
+        assert_eq!(bindings.resolve(VirtualKeyCode::Up), bindings.move_forward);
+        assert_eq!(bindings.resolve(VirtualKeyCode::Right), bindings.move_right);
+        assert_eq!(bindings.resolve(VirtualKeyCode::F), VirtualKeyCode::F);
+
+        bindings.toggle_wireframe = VirtualKeyCode::Up;
+        assert_eq!(bindings.resolve(VirtualKeyCode::Up), VirtualKeyCode::Up);
+    }
 }
@@ -1,48 +1,96 @@
 use winit::{
     event,
-    event::{Event, DeviceEvent, WindowEvent},
+    event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::Window
 };
 
+/// The SPIR-V which the build script compiled from the shader at `path`, relative to `shaders/`.
+macro_rules! include_shader {
+    ($path:literal) => {
+        include_bytes!(concat!(env!("OUT_DIR"), "/shaders/", $path, ".spv"))
+    };
+}
+
+mod atlas;
+mod bind_group_builder;
 mod camera;
+mod frame_timer;
+mod game_loop;
 mod gpu;
 mod input;
 mod managed_buffer;
 mod mesh;
+mod particles;
+mod postprocess;
 mod render_context;
-mod simplex;
 #[allow(dead_code)]
 mod utils;
+mod voronoi;
+mod voxel;
 mod world_geometry;
 
 use render_context::RenderContext;
 
 async fn run(event_loop: EventLoop<()>, window: Window) {
     env_logger::init();
-    // Initialize the render context.
-    let mut render_context = RenderContext::create(&window).await.unwrap();
-    let mut input_context = input::InputContext::new();
+    let benchmark = std::env::args().any(|arg| arg == "--benchmark");
 
-    // Start focused by default, assuming the application was executed with the intention of using it straight away.
-    let mut window_focused: bool = true;
-    let six_ms = std::time::Duration::from_millis(6);
-    // Move our starting time back by the time between frame requests so that we request the first frame right away.
-    let mut prev_frame = std::time::Instant::now() - six_ms;
+    // The saved scene is only restored if it asked to be, but key bindings in the file are always
+    // used. Benchmarks always run on the default scene, so that runs are comparable.
+    let scene_path = std::path::Path::new(render_context::DEFAULT_SCENE_PATH);
+    let saved_scene = render_context::SceneState::read(scene_path).ok();
+    let initial_scene = saved_scene
+        .as_ref()
+        .filter(|scene| scene.autosave && !benchmark)
+        .cloned()
+        .unwrap_or_default();
+
+    // Initialize the render context.
+    let gpu_context = match gpu::GpuContext::create(&window, &gpu::GpuContextDescriptor::default()).await {
+        Ok(gpu_context) => gpu_context,
+        Err(e) => {
+            log::error!("Failed to create the GPU context: {}", e);
+            return;
+        },
+    };
+    let mut render_context = match RenderContext::from_scene_state(&initial_scene, gpu_context) {
+        Ok(render_context) => render_context,
+        Err(e) => {
+            log::error!("Failed to create the render context: {}", e);
+            return;
+        },
+    };
+    render_context.on_dpi_scale_change(window.scale_factor());
+    // In benchmark mode, report the timings and exit without entering the event loop.
+    if benchmark {
+        let result = render_context.run_benchmark(render_context::DEFAULT_BENCHMARK_FRAMES);
+        println!("{}", result);
+        return;
+    }
+    let mut game_loop = game_loop::GameLoop::new(render_context);
+    if let Some(key_bindings) = saved_scene.and_then(|scene| scene.key_bindings) {
+        match key_bindings.find_duplicate() {
+            Some((first, second)) => log::warn!(
+                "Ignoring the saved key bindings, since {} and {} are bound to the same key.",
+                first,
+                second
+            ),
+            None => game_loop.set_key_bindings(key_bindings),
+        }
+    }
 
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
         match event {
-            Event::MainEventsCleared => window.request_redraw(),
-            Event::RedrawRequested(_) => {
-                let now = std::time::Instant::now();
-                if now - prev_frame > six_ms {
-                    prev_frame = now;
-                }
-                render_context.render();
+            // Catch the simulation up, then ask for a frame. The control flow is decided here, once
+            // per iteration, since it depends on what is left to do after the update.
+            Event::MainEventsCleared => {
+                game_loop.tick();
+                *control_flow = game_loop.control_flow();
+                window.request_redraw();
             },
+            Event::RedrawRequested(_) => game_loop.render(),
 
-            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => render_context.resize(size),
             // Handle requests to close the window...
             Event::WindowEvent { event: WindowEvent::CloseRequested, .. } |
             Event::WindowEvent { event: WindowEvent::KeyboardInput { input: event::KeyboardInput {
@@ -51,19 +99,12 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
             }, .. }, .. } => {
                 *control_flow = ControlFlow::Exit;
 
+                game_loop.on_exit(scene_path);
+
                 window.set_cursor_grab(false).unwrap();
                 window.set_cursor_visible(true);
             },
 
-            // Other keypresses go to the input handler.
-            Event::WindowEvent { event: WindowEvent::KeyboardInput { input: event::KeyboardInput {
-                virtual_keycode: Some(keycode),
-                state: event::ElementState::Pressed, ..
-            }, .. }, .. } => input_context.handle_key(&mut render_context, keycode),
-
-            // We track if the window has focus so that we can ignore device events when focus is lost.
-            Event::WindowEvent { event: WindowEvent::Focused(b), .. } => window_focused = b,
-
             Event::WindowEvent { event: WindowEvent::CursorEntered { .. }, .. } => {
                 window.set_cursor_grab(true).unwrap();
                 window.set_cursor_visible(false);
@@ -73,13 +114,9 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                 window.set_cursor_visible(true);
             },
 
-            // Ignore all device events if the window does not have focus.
-            Event::DeviceEvent { .. } if !window_focused => {}
-
-            // Handle mouse motion.
-            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta, .. }, .. } => {
-                input_context.handle_cursor_moved(&mut render_context, delta);
-            }
+            // Everything else goes to the game loop.
+            Event::WindowEvent { event: window_event, .. } => game_loop.on_event(&window_event),
+            Event::DeviceEvent { event: device_event, .. } => game_loop.on_device_event(&device_event),
             _ => {}
         }
     });
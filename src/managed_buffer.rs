@@ -44,6 +44,19 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         }
     }
 
+    /// Overwrites the elements starting at `offset` with `data`, both in CPU memory and, through
+    /// `queue`, on the GPU. The write is issued right away rather than through a command encoder,
+    /// so the dirty flag is left alone. Returns `None`, writing nothing, if `data` would run past
+    /// the end of the buffer.
+    pub fn write_range(&mut self, queue: &wgpu::Queue, offset: usize, data: &[T]) -> Option<()>
+        where Own: AsMut<[T]>
+    {
+        let end = offset.checked_add(data.len())?;
+        self.host_data.as_mut().get_mut(offset..end)?.copy_from_slice(data);
+        queue.write_buffer(&self.raw, (offset * self.t_size()) as wgpu::BufferAddress, bytemuck::cast_slice(data));
+        Some(())
+    }
+
     /// Create a new vertex buffer with some provided input data. This object manages the data on
     /// both the CPU and the GPU. This buffer is `COPY_DST`, so it can be written to. If the desired
     /// buffer is immutable, this is not the function to use.
@@ -51,7 +64,8 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         gpu_context: &crate::gpu::GpuContext,
         host_data: Own,
     ) -> Result<ManagedBuffer<T, Own>, ManagedBufferError> {
-        let raw = gpu_context.create_buffer_with_data(
+        let raw = gpu_context.create_buffer_init(
+            None,
             bytemuck::cast_slice(host_data.as_ref()),
             wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
         );
@@ -70,7 +84,8 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         gpu_context: &crate::gpu::GpuContext,
         host_data: Own,
     ) -> Result<ManagedBuffer<T, Own>, ManagedBufferError> {
-        let raw = gpu_context.create_buffer_with_data(
+        let raw = gpu_context.create_buffer_init(
+            None,
             bytemuck::cast_slice(host_data.as_ref()),
             wgpu::BufferUsage::INDEX | wgpu::BufferUsage::COPY_DST,
         );
@@ -89,7 +104,8 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         gpu_context: &crate::gpu::GpuContext,
         host_data: Own,
     ) -> Result<ManagedBuffer<T, Own>, ManagedBufferError> {
-        let raw = gpu_context.create_buffer_with_data(
+        let raw = gpu_context.create_buffer_init(
+            None,
             bytemuck::cast_slice(host_data.as_ref()),
             wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
         );
@@ -117,6 +133,13 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
         self.host_data.as_ref().len()
     }
 
+    /// Returns the data in CPU memory. This is what the GPU buffer holds once any pending copy has
+    /// been flushed.
+    #[allow(dead_code)]
+    pub fn host_data(&self) -> &[T] {
+        self.host_data.as_ref()
+    }
+
     /// Returns true if the buffer is dirty and needs to be flushed to GPU.
     pub fn dirty(&self) -> bool {
         self.dirty
@@ -126,24 +149,15 @@ impl<'cpu, Own: AsRef<[T]>, T: bytemuck::Pod + bytemuck::Zeroable> ManagedBuffer
     /// a write unless the buffer is dirty, and so can safely be called in a render loop.
     ///
     /// Calling this function will reset the dirty flag. Be sure that you finish the command encoder
-    /// and submit it on a queue.
-    // TODO: Should this functionality actually be provided by `wgpu::queue::write_buffer`, which
-    // may be able to avoid a memcpy?
+    /// and submit it through `GpuContext::finish_frame`.
     pub fn enqueue_copy_command(
         &mut self,
-        gpu_context: &crate::gpu::GpuContext,
+        gpu_context: &mut crate::gpu::GpuContext,
         encoder: &mut wgpu::CommandEncoder,
     ) {
         if !self.dirty { return }
 
-        let stage_buffer = gpu_context.create_buffer_with_data(
-            bytemuck::cast_slice(self.host_data.as_ref()),
-            wgpu::BufferUsage::COPY_SRC
-        );
-
-        encoder.copy_buffer_to_buffer(
-            &stage_buffer, 0, &self.raw, 0, (self.host_data.as_ref().len() * self.t_size()) as u64
-        );
+        gpu_context.write_buffer(encoder, &self.raw, 0, bytemuck::cast_slice(self.host_data.as_ref()));
 
         // We are setting the dirty flag to false here trusting that the caller will actually
         // finish the command encoder and submit it on the queue!
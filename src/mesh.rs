@@ -22,6 +22,12 @@ bitflags! {
     }
 }
 
+/// A finished mesh, ready to be uploaded to the GPU.
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
 // TODO: This will need to be generic over vertex type, index type, and index offset type
 // eventually.
 pub struct MeshAccumulator {
@@ -52,10 +58,14 @@ impl MeshAccumulator {
         }
     }
 
-    // TODO: Return new `Mesh` type.
-    /// Consumes the `MeshAccumulator` and returns the vertex and index buffers.
-    pub fn report(self) -> (Vec<Vertex>, Vec<u32>) {
-        (self.vertex_accum, self.index_accum)
+    /// Consumes the `MeshAccumulator` and returns the accumulated `Mesh`, with its triangles
+    /// reordered for the vertex cache (see `utils::optimize_vertex_cache`).
+    pub fn report(mut self) -> Mesh {
+        crate::utils::optimize_vertex_cache(&mut self.vertex_accum, &mut self.index_accum);
+        Mesh {
+            vertices: self.vertex_accum,
+            indices: self.index_accum,
+        }
     }
 
     /// Add an isolated cuboid to the mesh. The parameter names are self-describing.
@@ -103,35 +113,54 @@ impl MeshAccumulator {
         length_2: f32,
         height_2: f32,
     ) {
+        self.add_cuboid_quat_faces_ao(center, orientation, faces, width_2, length_2, height_2, |_| [1.0; 4]);
+    }
+
+    /// Add an isolated cuboid to the mesh, like `add_cuboid_quat_faces`. `ao` returns the ambient
+    /// occlusion of each face's four vertices, in the order they are emitted (see
+    /// `utils::compute_voxel_ao`).
+    ///
+    /// Up is assumed to be the z-axis.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_cuboid_quat_faces_ao<F>(
+        &mut self,
+        center: Point3<f32>,
+        orientation: Quaternion<f32>,
+        faces: CuboidFaces,
+        width_2: f32,
+        length_2: f32,
+        height_2: f32,
+        ao: F,
+    ) where F: Fn(CuboidFaces) -> [f32; 4] {
         if faces.contains(CuboidFaces::TOP) {
             let top_center = center + orientation.rotate_vector(Vector3::new(0., 0., height_2));
             let top = orientation;
-            self.add_quad_quat(top_center, top, width_2, length_2);
+            self.add_quad_quat_ao(top_center, top, width_2, length_2, ao(CuboidFaces::TOP));
         }
         if faces.contains(CuboidFaces::BOTTOM) {
             let bottom_center = center - orientation.rotate_vector(Vector3::new(0., 0., height_2));
             let bottom = orientation * Quaternion::from_angle_x(Rad::turn_div_2());
-            self.add_quad_quat(bottom_center, bottom, width_2, length_2);
+            self.add_quad_quat_ao(bottom_center, bottom, width_2, length_2, ao(CuboidFaces::BOTTOM));
         }
         if faces.contains(CuboidFaces::RIGHT) {
             let right_center = center + orientation.rotate_vector(Vector3::new(width_2, 0., 0.));
             let right = orientation * Quaternion::from_angle_y(Rad::turn_div_4());
-            self.add_quad_quat(right_center, right, height_2, length_2);
+            self.add_quad_quat_ao(right_center, right, height_2, length_2, ao(CuboidFaces::RIGHT));
         }
         if faces.contains(CuboidFaces::LEFT) {
             let left_center = center - orientation.rotate_vector(Vector3::new(width_2, 0., 0.));
             let left = orientation * Quaternion::from_angle_y(-Rad::turn_div_4());
-            self.add_quad_quat(left_center, left, height_2, length_2);
+            self.add_quad_quat_ao(left_center, left, height_2, length_2, ao(CuboidFaces::LEFT));
         }
         if faces.contains(CuboidFaces::FRONT) {
             let front_center = center + orientation.rotate_vector(Vector3::new(0., length_2, 0.));
             let front = orientation * Quaternion::from_angle_x(-Rad::turn_div_4());
-            self.add_quad_quat(front_center, front, width_2, height_2);
+            self.add_quad_quat_ao(front_center, front, width_2, height_2, ao(CuboidFaces::FRONT));
         }
         if faces.contains(CuboidFaces::BACK) {
             let back_center = center - orientation.rotate_vector(Vector3::new(0., length_2, 0.));
             let back = orientation * Quaternion::from_angle_x(Rad::turn_div_4());
-            self.add_quad_quat(back_center, back, width_2, height_2);
+            self.add_quad_quat_ao(back_center, back, width_2, height_2, ao(CuboidFaces::BACK));
         }
     }
 
@@ -144,6 +173,21 @@ impl MeshAccumulator {
         orientation: Quaternion<f32>,
         width_2: f32,
         length_2: f32,
+    ) {
+        self.add_quad_quat_ao(center, orientation, width_2, length_2, [1.0; 4]);
+    }
+
+    /// Add an isolated quad to the mesh, like `add_quad_quat`, with the ambient occlusion `ao` at
+    /// each of its vertices.
+    ///
+    /// Up is assumed to be the z-axis.
+    pub fn add_quad_quat_ao(
+        &mut self,
+        center: Point3<f32>,
+        orientation: Quaternion<f32>,
+        width_2: f32,
+        length_2: f32,
+        ao: [f32; 4],
     ) {
         let i = self.index_offset;
 
@@ -161,10 +205,10 @@ impl MeshAccumulator {
         let _1_n: [f32; 3] = orientation.rotate_vector(Vector3::unit_z()).normalize().into();
 
         self.vertex_accum.extend(&[
-            Vertex::new([_1.x, _1.y, _1.z], _1_n, [0.0, 0.0]),
-            Vertex::new([_2.x, _2.y, _2.z], _1_n, [1.0, 0.0]),
-            Vertex::new([_3.x, _3.y, _3.z], _1_n, [1.0, 1.0]),
-            Vertex::new([_4.x, _4.y, _4.z], _1_n, [0.0, 1.0]),
+            Vertex::new([_1.x, _1.y, _1.z], _1_n, [0.0, 0.0]).with_ao(ao[0]),
+            Vertex::new([_2.x, _2.y, _2.z], _1_n, [1.0, 0.0]).with_ao(ao[1]),
+            Vertex::new([_3.x, _3.y, _3.z], _1_n, [1.0, 1.0]).with_ao(ao[2]),
+            Vertex::new([_4.x, _4.y, _4.z], _1_n, [0.0, 1.0]).with_ao(ao[3]),
         ]);
         self.index_accum.extend(&[0+i, 1+i, 2+i, 2+i, 3+i, 0+i]);
         self.index_offset += 4;
@@ -205,89 +249,118 @@ impl IMeshAccumulator {
         (self.vertex_accum, self.index_accum)
     }
 
-    /// Add an isolated cuboid to the mesh. The parameter names are self-describing.
+    /// Add an isolated cuboid to the mesh. The parameter names are self-describing. `tile` returns
+    /// the `(col, row)` of the atlas tile to texture each face with.
     ///
     /// Up is assumed to be the z-axis.
-    pub fn add_cuboid_faces(
+    pub fn add_cuboid_faces<F>(
         &mut self,
         pos: Point3<i32>,
         faces: CuboidFaces,
-    ) {
+        tile: F,
+    ) where F: Fn(CuboidFaces) -> (u8, u8) {
+        self.add_cuboid_faces_sized(pos, (1, 1), faces, tile);
+    }
+
+    /// Like `add_cuboid_faces`, but the cuboid is `size.0` blocks wide along the x-axis and `size.1`
+    /// along the y-axis, while still one block tall.
+    pub fn add_cuboid_faces_sized<F>(
+        &mut self,
+        pos: Point3<i32>,
+        size: (i32, i32),
+        faces: CuboidFaces,
+        tile: F,
+    ) where F: Fn(CuboidFaces) -> (u8, u8) {
         if faces.contains(CuboidFaces::TOP) {
-            self.add_face(pos, CuboidFaces::TOP);
+            self.add_face_sized(pos, size, CuboidFaces::TOP, tile(CuboidFaces::TOP));
         }
         if faces.contains(CuboidFaces::BOTTOM) {
-            self.add_face(pos, CuboidFaces::BOTTOM);
+            self.add_face_sized(pos, size, CuboidFaces::BOTTOM, tile(CuboidFaces::BOTTOM));
         }
         if faces.contains(CuboidFaces::FRONT) {
-            self.add_face(pos, CuboidFaces::FRONT);
+            self.add_face_sized(pos, size, CuboidFaces::FRONT, tile(CuboidFaces::FRONT));
         }
         if faces.contains(CuboidFaces::BACK) {
-            self.add_face(pos, CuboidFaces::BACK);
+            self.add_face_sized(pos, size, CuboidFaces::BACK, tile(CuboidFaces::BACK));
         }
         if faces.contains(CuboidFaces::LEFT) {
-            self.add_face(pos, CuboidFaces::LEFT);
+            self.add_face_sized(pos, size, CuboidFaces::LEFT, tile(CuboidFaces::LEFT));
         }
         if faces.contains(CuboidFaces::RIGHT) {
-            self.add_face(pos, CuboidFaces::RIGHT);
+            self.add_face_sized(pos, size, CuboidFaces::RIGHT, tile(CuboidFaces::RIGHT));
         }
     }
 
-    /// Add an isolated quad to the mesh. The parameter names are self-describing.
+    /// Add an isolated quad to the mesh. The parameter names are self-describing. The quad is
+    /// textured with the atlas tile at `(col, row)`; texture coordinates within the tile are
+    /// derived from world position in the shader.
     ///
     /// Up is assumed to be the z-axis.
     pub fn add_face(
         &mut self,
         pos: Point3<i32>,
         face: CuboidFaces,
+        tile: (u8, u8),
+    ) {
+        self.add_face_sized(pos, (1, 1), face, tile);
+    }
+
+    /// Like `add_face`, but the face belongs to a cuboid `size.0` blocks wide along the x-axis and
+    /// `size.1` along the y-axis. See `add_cuboid_faces_sized`.
+    pub fn add_face_sized(
+        &mut self,
+        pos: Point3<i32>,
+        (w, d): (i32, i32),
+        face: CuboidFaces,
+        tile: (u8, u8),
     ) {
         let i = self.index_offset;
 
         let [v1, v2, v3, v4] = match face {
             CuboidFaces::TOP => [
-                Vector3::new(pos.x+1, pos.y+1, pos.z+1),
-                Vector3::new(pos.x  , pos.y+1, pos.z+1),
+                Vector3::new(pos.x+w, pos.y+d, pos.z+1),
+                Vector3::new(pos.x  , pos.y+d, pos.z+1),
                 Vector3::new(pos.x  , pos.y  , pos.z+1),
-                Vector3::new(pos.x+1, pos.y  , pos.z+1),
+                Vector3::new(pos.x+w, pos.y  , pos.z+1),
             ],
             CuboidFaces::BOTTOM => [
-                Vector3::new(pos.x+1, pos.y  , pos.z),
+                Vector3::new(pos.x+w, pos.y  , pos.z),
                 Vector3::new(pos.x  , pos.y  , pos.z),
-                Vector3::new(pos.x  , pos.y+1, pos.z),
-                Vector3::new(pos.x+1, pos.y+1, pos.z),
+                Vector3::new(pos.x  , pos.y+d, pos.z),
+                Vector3::new(pos.x+w, pos.y+d, pos.z),
             ],
             CuboidFaces::FRONT => [
-                Vector3::new(pos.x+1, pos.y+1, pos.z),
-                Vector3::new(pos.x  , pos.y+1, pos.z),
-                Vector3::new(pos.x  , pos.y+1, pos.z+1),
-                Vector3::new(pos.x+1, pos.y+1, pos.z+1),
+                Vector3::new(pos.x+w, pos.y+d, pos.z),
+                Vector3::new(pos.x  , pos.y+d, pos.z),
+                Vector3::new(pos.x  , pos.y+d, pos.z+1),
+                Vector3::new(pos.x+w, pos.y+d, pos.z+1),
             ],
             CuboidFaces::BACK => [
                 Vector3::new(pos.x,   pos.y, pos.z),
-                Vector3::new(pos.x+1, pos.y, pos.z),
-                Vector3::new(pos.x+1, pos.y, pos.z+1),
+                Vector3::new(pos.x+w, pos.y, pos.z),
+                Vector3::new(pos.x+w, pos.y, pos.z+1),
                 Vector3::new(pos.x,   pos.y, pos.z+1),
             ],
             CuboidFaces::LEFT => [
-                Vector3::new(pos.x, pos.y+1, pos.z),
+                Vector3::new(pos.x, pos.y+d, pos.z),
                 Vector3::new(pos.x, pos.y,   pos.z),
                 Vector3::new(pos.x, pos.y,   pos.z+1),
-                Vector3::new(pos.x, pos.y+1, pos.z+1),
+                Vector3::new(pos.x, pos.y+d, pos.z+1),
             ],
             CuboidFaces::RIGHT => [
-                Vector3::new(pos.x+1, pos.y,   pos.z),
-                Vector3::new(pos.x+1, pos.y+1, pos.z),
-                Vector3::new(pos.x+1, pos.y+1, pos.z+1),
-                Vector3::new(pos.x+1, pos.y,   pos.z+1),
+                Vector3::new(pos.x+w, pos.y,   pos.z),
+                Vector3::new(pos.x+w, pos.y+d, pos.z),
+                Vector3::new(pos.x+w, pos.y+d, pos.z+1),
+                Vector3::new(pos.x+w, pos.y,   pos.z+1),
             ],
             _ => panic!("bad faces"),
         };
 
         self.vertex_accum.extend(&[
-            IVertex::new(v1, pos, [0.0, 0.0], face.bits()),
-            IVertex::new(v2, pos, [1.0, 0.0], face.bits()),
-            IVertex::new(v3, pos, [1.0, 1.0], face.bits()),
-            IVertex::new(v4, pos, [0.0, 1.0], face.bits()),
+            IVertex::new(v1, pos, face.bits(), tile),
+            IVertex::new(v2, pos, face.bits(), tile),
+            IVertex::new(v3, pos, face.bits(), tile),
+            IVertex::new(v4, pos, face.bits(), tile),
         ]);
         self.index_accum.extend(&[0+i, 1+i, 2+i, 2+i, 3+i, 0+i]);
         self.index_offset += 4;
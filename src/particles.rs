@@ -0,0 +1,141 @@
+//! A simple CPU-simulated particle system, for effects like dust, sparks, or rain. Particles are
+//! spawned at a fixed rate from a single emitter, fall under gravity, and die once they reach the
+//! end of their lifetime.
+
+use cgmath::prelude::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub pos: [f32; 3],
+    pub vel: [f32; 3],
+    /// Seconds since this particle was spawned.
+    pub age: f32,
+}
+
+/// What happens to a particle when it falls below the ground.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroundCollision {
+    /// Particles pass through the ground.
+    Ignore,
+    /// Particles are reflected off the ground, losing some of their speed.
+    Bounce { restitution: f32 },
+    /// Particles are removed as soon as they touch the ground.
+    Kill,
+}
+
+pub struct ParticleSystem {
+    pub particles: Vec<Particle>,
+    pub emitter_pos: cgmath::Point3<f32>,
+    /// Downward acceleration applied to every particle, in units per second squared.
+    pub gravity: f32,
+    /// The age, in seconds, at which particles die.
+    pub lifetime: f32,
+
+    /// The number of particles spawned per second.
+    pub spawn_rate: f32,
+    /// The speed at which particles leave the emitter.
+    pub spawn_speed: f32,
+    pub ground_collision: GroundCollision,
+
+    /// Fractional particles carried over between ticks, so that low spawn rates are honoured.
+    spawn_accumulator: f32,
+    /// State for the pseudo-random spawn directions. This only needs to look random, so a tiny
+    /// xorshift generator is plenty.
+    rng_state: u32,
+}
+
+#[allow(dead_code)]
+impl ParticleSystem {
+    /// Create a new `ParticleSystem` with no live particles.
+    pub fn new(
+        emitter_pos: cgmath::Point3<f32>,
+        gravity: f32,
+        lifetime: f32,
+        spawn_rate: f32,
+        spawn_speed: f32,
+    ) -> Self {
+        Self {
+            particles: Vec::new(),
+            emitter_pos,
+            gravity,
+            lifetime,
+            spawn_rate,
+            spawn_speed,
+            ground_collision: GroundCollision::Ignore,
+            spawn_accumulator: 0.0,
+            rng_state: 0x9E37_79B9,
+        }
+    }
+
+    /// Advances the simulation by `delta_secs`, ignoring the ground.
+    pub fn tick(&mut self, delta_secs: f32) {
+        self.tick_with_ground(delta_secs, |_, _| None);
+    }
+
+    /// Advances the simulation by `delta_secs`: spawns new particles, integrates their motion, ages
+    /// them, and removes dead ones. `ground_height` returns the height of the ground at a given
+    /// x and y, or `None` where there is no ground; it is used according to `ground_collision`.
+    pub fn tick_with_ground<F>(&mut self, delta_secs: f32, ground_height: F)
+        where F: Fn(f32, f32) -> Option<f32>
+    {
+        // Spawn this tick's share of new particles.
+        self.spawn_accumulator += self.spawn_rate * delta_secs;
+        while self.spawn_accumulator >= 1.0 {
+            self.spawn_accumulator -= 1.0;
+            let vel = self.random_direction() * self.spawn_speed;
+            self.particles.push(Particle {
+                pos: self.emitter_pos.into(),
+                vel: vel.into(),
+                age: 0.0,
+            });
+        }
+
+        let gravity = self.gravity;
+        let ground_collision = self.ground_collision;
+        for p in self.particles.iter_mut() {
+            p.age += delta_secs;
+            p.vel[2] -= gravity * delta_secs;
+            for i in 0..3 {
+                p.pos[i] += p.vel[i] * delta_secs;
+            }
+
+            if let GroundCollision::Bounce { restitution } = ground_collision {
+                if let Some(ground) = ground_height(p.pos[0], p.pos[1]) {
+                    if p.pos[2] < ground && p.vel[2] < 0.0 {
+                        p.pos[2] = ground;
+                        p.vel[2] = -p.vel[2] * restitution;
+                    }
+                }
+            }
+        }
+
+        let lifetime = self.lifetime;
+        self.particles.retain(|p| {
+            if p.age >= lifetime {
+                return false;
+            }
+            if ground_collision == GroundCollision::Kill {
+                if let Some(ground) = ground_height(p.pos[0], p.pos[1]) {
+                    return p.pos[2] >= ground;
+                }
+            }
+            true
+        });
+    }
+
+    /// Returns a pseudo-random unit vector in the upper hemisphere.
+    fn random_direction(&mut self) -> cgmath::Vector3<f32> {
+        let theta = self.next_random() * std::f32::consts::PI * 2.0;
+        let z = self.next_random();
+        let r = (1.0 - z * z).sqrt();
+        cgmath::Vector3::new(r * theta.cos(), r * theta.sin(), z).normalize()
+    }
+
+    /// Returns a pseudo-random value in `[0, 1)`.
+    fn next_random(&mut self) -> f32 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 17;
+        self.rng_state ^= self.rng_state << 5;
+        (self.rng_state >> 8) as f32 / (1 << 24) as f32
+    }
+}
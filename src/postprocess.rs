@@ -0,0 +1,250 @@
+//! Shared infrastructure for passes which shade every pixel of a target, such as compositing,
+//! blitting, and post-processing effects.
+
+/// Two triangles covering all of normalized device coordinates.
+const QUAD_VERTICES: [[f32; 2]; 6] = [
+    [-1.0, -1.0],
+    [ 1.0, -1.0],
+    [ 1.0,  1.0],
+    [ 1.0,  1.0],
+    [-1.0,  1.0],
+    [-1.0, -1.0],
+];
+
+/// A quad covering the whole render target, drawn with a caller-provided fragment shader. The
+/// vertex shader passes texture coordinates spanning `[0, 1]` to the fragment shader at location
+/// 0, with `(0, 0)` at the top-left of the target.
+///
+/// Each `FullscreenQuad` owns one pipeline, so each post-processing effect should create its own.
+pub struct FullscreenQuad {
+    vertex_buf: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl FullscreenQuad {
+    /// Create a new `FullscreenQuad` which shades with `fs_module`, reading its inputs from a bind
+    /// group matching `bind_group_layout`, and writing to targets of the given `format`.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        fs_module: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let vertex_buf = gpu_context.create_buffer_init(
+            Some("fullscreen quad vertices"),
+            bytemuck::cast_slice(&QUAD_VERTICES),
+            wgpu::BufferUsage::VERTEX,
+        );
+
+        let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = gpu_context.create_shader_module_from_bytes(include_shader!("fullscreen.vert"));
+
+        let pipeline = gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                    ],
+                }],
+            },
+
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Self {
+            vertex_buf,
+            pipeline,
+        }
+    }
+
+    /// Records a render pass shading all of `target`.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        self.record(encoder, target, bind_group, None);
+    }
+
+    /// Records a render pass shading only the `(x, y, width, height)` region of `target`, in
+    /// pixels. The rest of `target` is left untouched.
+    pub fn render_viewport(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        bind_group: &wgpu::BindGroup,
+        viewport: (f32, f32, f32, f32),
+    ) {
+        self.record(encoder, target, bind_group, Some(viewport));
+    }
+
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        bind_group: &wgpu::BindGroup,
+        viewport: Option<(f32, f32, f32, f32)>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Load rather than clear, so that a viewport can draw over existing contents.
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }
+            }],
+            depth_stencil_attachment: None,
+        });
+        if let Some((x, y, width, height)) = viewport {
+            render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        render_pass.draw(0..QUAD_VERTICES.len() as u32, 0..1);
+    }
+}
+
+/// Returns the number of mip levels in a full mip chain for a texture of the given size, i.e.
+/// `floor(log2(max(width, height))) + 1`.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).leading_zeros()
+}
+
+/// Fills the mip chains of textures by repeatedly downsampling each level into the next. The blit
+/// pipeline for each texture format is created the first time it is needed and reused afterwards.
+pub struct MipmapGenerator {
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    fs_module: wgpu::ShaderModule,
+    blit_quads: std::collections::HashMap<wgpu::TextureFormat, FullscreenQuad>,
+}
+
+impl MipmapGenerator {
+    pub fn new(gpu_context: &crate::gpu::GpuContext) -> Self {
+        let bind_group_layout = gpu_context.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            bindings: &[
+                wgpu::BindGroupLayoutEntry::new(
+                    0,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        component_type: wgpu::TextureComponentType::Float,
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    1,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::Sampler { comparison: false },
+                ),
+            ],
+        });
+
+        let sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("blit.frag"));
+
+        Self {
+            bind_group_layout,
+            sampler,
+            fs_module,
+            blit_quads: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Records passes filling mip levels `1..mip_count` of `texture`, each by linearly downsampling
+    /// the level before it. Level 0 must already hold the image. `texture` must have been created
+    /// with `OUTPUT_ATTACHMENT | SAMPLED` usage and at least `mip_count` mip levels.
+    pub fn generate(
+        &mut self,
+        gpu_context: &crate::gpu::GpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        mip_count: u32,
+    ) {
+        if mip_count <= 1 {
+            return;
+        }
+
+        let bind_group_layout = &self.bind_group_layout;
+        let fs_module = &self.fs_module;
+        let blit_quad = self.blit_quads
+            .entry(format)
+            .or_insert_with(|| FullscreenQuad::new(gpu_context, bind_group_layout, fs_module, format));
+
+        // Each level gets its own view, so that the blit reads from exactly one level and writes to
+        // exactly one other.
+        let views: Vec<wgpu::TextureView> = (0..mip_count)
+            .map(|mip_level| texture.create_view(&wgpu::TextureViewDescriptor {
+                label: None,
+                format,
+                dimension: wgpu::TextureViewDimension::D2,
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level: mip_level,
+                level_count: 1,
+                base_array_layer: 0,
+                array_layer_count: 1,
+            }))
+            .collect();
+
+        for target_level in 1..mip_count as usize {
+            let bind_group = crate::bind_group_builder::BindGroupBuilder::new()
+                .add_texture(0, wgpu::ShaderStage::FRAGMENT, &views[target_level - 1])
+                .add_sampler(1, wgpu::ShaderStage::FRAGMENT, &self.sampler)
+                .build_with_layout(gpu_context, &self.bind_group_layout);
+            blit_quad.render(encoder, &views[target_level], &bind_group);
+        }
+    }
+}
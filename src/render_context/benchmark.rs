@@ -0,0 +1,57 @@
+/// The number of frames rendered by the `--benchmark` flag.
+pub const DEFAULT_BENCHMARK_FRAMES: u32 = 600;
+
+/// The height above the terrain from which the benchmark camera looks down on it.
+pub const ORBIT_ELEVATION: f32 = 100.0;
+
+/// Timings collected by `RenderContext::run_benchmark`. Frame times are wall-clock times of a whole
+/// call to `RenderContext::render`, so they include any time spent waiting on the swap chain.
+#[derive(Clone, Copy, Debug)]
+pub struct BenchmarkResult {
+    /// The time taken to generate the terrain mesh on the CPU.
+    pub mesh_generation_ms: f32,
+    pub avg_frame_ms: f32,
+    pub p99_frame_ms: f32,
+    pub min_frame_ms: f32,
+    pub max_frame_ms: f32,
+    /// The number of triangles drawn each frame.
+    pub total_triangles: u64,
+}
+
+impl BenchmarkResult {
+    /// Summarizes the per-frame times in `frame_ms`. If no frames were rendered, every frame time
+    /// is zero.
+    pub fn from_frame_times(mut frame_ms: Vec<f32>, mesh_generation_ms: f32, total_triangles: u64) -> Self {
+        if frame_ms.is_empty() {
+            return Self {
+                mesh_generation_ms,
+                avg_frame_ms: 0.0,
+                p99_frame_ms: 0.0,
+                min_frame_ms: 0.0,
+                max_frame_ms: 0.0,
+                total_triangles,
+            };
+        }
+        frame_ms.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let p99_index = ((frame_ms.len() as f32 * 0.99).ceil() as usize).max(1) - 1;
+        Self {
+            mesh_generation_ms,
+            avg_frame_ms: frame_ms.iter().sum::<f32>() / frame_ms.len() as f32,
+            p99_frame_ms: frame_ms[p99_index],
+            min_frame_ms: frame_ms[0],
+            max_frame_ms: frame_ms[frame_ms.len() - 1],
+            total_triangles,
+        }
+    }
+}
+
+impl std::fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "mesh generation: {:.2} ms", self.mesh_generation_ms)?;
+        writeln!(f, "triangles:       {}", self.total_triangles)?;
+        writeln!(f, "avg frame:       {:.2} ms", self.avg_frame_ms)?;
+        writeln!(f, "p99 frame:       {:.2} ms", self.p99_frame_ms)?;
+        writeln!(f, "min frame:       {:.2} ms", self.min_frame_ms)?;
+        write!(f, "max frame:       {:.2} ms", self.max_frame_ms)
+    }
+}
@@ -0,0 +1,346 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::prelude::*;
+
+use crate::camera;
+use crate::managed_buffer::ManagedBuffer;
+
+/// The width and height every sprite is resampled to, so that they fit in one texture array.
+const SPRITE_SIZE: u32 = 64;
+
+/// A single camera-facing sprite. Billboards are anchored at the bottom edge, so `world_pos` is the
+/// point the sprite stands on.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BillboardInstance {
+    pub world_pos: [f32; 3],
+    pub size: f32,
+    /// The layer of the sprite texture array to draw.
+    pub texture_index: u16,
+    _pad: u16,
+}
+
+impl BillboardInstance {
+    #[allow(dead_code)]
+    pub fn new(world_pos: [f32; 3], size: f32, texture_index: u16) -> Self {
+        Self {
+            world_pos,
+            size,
+            texture_index,
+            _pad: 0,
+        }
+    }
+}
+
+unsafe impl Pod for BillboardInstance {}
+unsafe impl Zeroable for BillboardInstance {}
+
+/// The camera data needed to expand each billboard into a quad facing the viewer.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BillboardUniforms {
+    transform: [[f32; 4]; 4],
+    right: [f32; 4],
+    up: [f32; 4],
+}
+
+unsafe impl Pod for BillboardUniforms {}
+unsafe impl Zeroable for BillboardUniforms {}
+
+impl BillboardUniforms {
+    fn from_camera(camera: &mut camera::Camera) -> Self {
+        let right = camera.right().normalize();
+        // The true up vector of the view, rather than the world's up, so that billboards stay
+        // parallel to the screen when looking up or down.
+        let up = right.cross(camera.view()).normalize();
+        Self {
+            transform: camera.matrix().into(),
+            right: right.extend(0.0).into(),
+            up: up.extend(0.0).into(),
+        }
+    }
+}
+
+/// Draws camera-facing sprites, such as vegetation and particle effects. Each billboard is a single
+/// instance; its quad is generated entirely in the vertex shader. Texels below an alpha threshold
+/// are discarded rather than blended, so billboards do not need to be sorted.
+pub struct BillboardRenderer {
+    instances: Vec<BillboardInstance>,
+    /// Set whenever `instances` changes, so that the instance buffer is re-uploaded.
+    dirty: bool,
+    /// `None` while there are no billboards, since an empty buffer cannot be bound.
+    instance_buf: Option<ManagedBuffer<BillboardInstance, Vec<BillboardInstance>>>,
+
+    uniform_buf: ManagedBuffer<BillboardUniforms, [BillboardUniforms; 1]>,
+
+    #[allow(dead_code)]
+    sprite_texture: wgpu::Texture,
+    #[allow(dead_code)]
+    sprite_view: wgpu::TextureView,
+    #[allow(dead_code)]
+    sprite_sampler: wgpu::Sampler,
+
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl BillboardRenderer {
+    /// Create a new `BillboardRenderer`, loading each image in `sprite_paths` into a layer of the
+    /// sprite texture array. The index of a path in `sprite_paths` is the `texture_index` used to
+    /// draw it.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        camera: &mut camera::Camera,
+        sprite_paths: &[&str],
+    ) -> Option<Self> {
+        let uniform_buf = ManagedBuffer::new_uniform_buf_with_data(
+            gpu_context,
+            [BillboardUniforms::from_camera(camera)],
+        ).ok()?;
+
+        // Load every sprite into one layer of a texture array, resampling them to a common size.
+        let layer_count = sprite_paths.len() as u32;
+        let layer_extent = wgpu::Extent3d {
+            width: SPRITE_SIZE,
+            height: SPRITE_SIZE,
+            depth: 1,
+        };
+        let sprite_texture = gpu_context.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                depth: layer_count,
+                ..layer_extent
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+            label: None,
+        });
+        for (layer, path) in sprite_paths.iter().enumerate() {
+            let texels = image::open(path)
+                .ok()?
+                .resize_exact(SPRITE_SIZE, SPRITE_SIZE, image::imageops::FilterType::Triangle)
+                .to_rgba()
+                .into_raw();
+            gpu_context.queue().write_texture(
+                wgpu::TextureCopyView {
+                    texture: &sprite_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                },
+                &texels,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: 4 * SPRITE_SIZE,
+                    rows_per_image: 0,
+                },
+                layer_extent,
+            );
+        }
+        let sprite_view = sprite_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: None,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            dimension: wgpu::TextureViewDimension::D2Array,
+            aspect: wgpu::TextureAspect::All,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            array_layer_count: layer_count,
+        });
+
+        let sprite_sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let (bind_group_layout, bind_group) = crate::bind_group_builder::BindGroupBuilder::new()
+            .add_uniform_buffer(
+                0,
+                wgpu::ShaderStage::VERTEX,
+                uniform_buf.slice(..),
+                std::mem::size_of::<BillboardUniforms>() as u64,
+            )
+            .add_texture_array(1, wgpu::ShaderStage::FRAGMENT, &sprite_view)
+            .add_sampler(2, wgpu::ShaderStage::FRAGMENT, &sprite_sampler)
+            .build(gpu_context);
+
+        let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = gpu_context.create_shader_module_from_bytes(include_shader!("billboard.vert"));
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("billboard.frag"));
+
+        let pipeline = gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            // Billboards always face the camera, so there is nothing to cull.
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: wgpu::TextureFormat::Bgra8UnormSrgb,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: crate::gpu::RENDER_TARGET_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<BillboardInstance>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Instance,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float3,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float,
+                            offset: 4*3,
+                            shader_location: 1,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Ushort2,
+                            offset: 4*3 + 4,
+                            shader_location: 2,
+                        },
+                    ],
+                }],
+            },
+
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Some(Self {
+            instances: Vec::new(),
+            dirty: false,
+            instance_buf: None,
+            uniform_buf,
+            sprite_texture,
+            sprite_view,
+            sprite_sampler,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn add(&mut self, instance: BillboardInstance) {
+        self.instances.push(instance);
+        self.dirty = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty = true;
+    }
+
+    /// Updates the camera data used to orient the billboards. This should be called whenever the
+    /// camera moves.
+    pub fn update_camera(&mut self, camera: &mut camera::Camera) {
+        self.uniform_buf.replace_data([BillboardUniforms::from_camera(camera)]);
+    }
+
+    /// Flushes the instance and uniform buffers to the GPU if they have changed. The instance
+    /// buffer is recreated if the number of billboards has changed.
+    pub fn enqueue_copy_commands(
+        &mut self,
+        gpu_context: &mut crate::gpu::GpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.dirty {
+            self.dirty = false;
+            let same_len = self.instance_buf
+                .as_ref()
+                .map_or(false, |instance_buf| instance_buf.len() == self.instances.len());
+            if self.instances.is_empty() {
+                self.instance_buf = None;
+            } else if same_len {
+                if let Some(instance_buf) = self.instance_buf.as_mut() {
+                    instance_buf.replace_data(self.instances.clone());
+                }
+            } else {
+                self.instance_buf =
+                    ManagedBuffer::new_vertex_buf_with_data(gpu_context, self.instances.clone()).ok();
+            }
+        }
+
+        if let Some(instance_buf) = self.instance_buf.as_mut() {
+            if instance_buf.dirty() {
+                instance_buf.enqueue_copy_command(gpu_context, encoder);
+            }
+        }
+        if self.uniform_buf.dirty() {
+            self.uniform_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+    }
+
+    /// Records a render pass drawing every billboard on top of the contents of `region` of
+    /// `color_view`, depth-tested against `depth_view`.
+    pub fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        region: &super::scene::ViewRegion,
+    ) {
+        let instance_buf = match self.instance_buf.as_ref() {
+            Some(instance_buf) => instance_buf,
+            None => return,
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        region.apply(&mut render_pass);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instance_buf.slice(..));
+        render_pass.draw(0..6, 0..instance_buf.len() as u32);
+    }
+}
@@ -0,0 +1,61 @@
+/// How a pipeline combines what it draws with what is already in the color target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Overwrite the target. Opaque draws also write depth.
+    Opaque,
+    /// Blend by the source alpha, for colors which are not premultiplied.
+    AlphaBlend,
+    /// Blend colors which have already been multiplied by their alpha.
+    PremultipliedAlpha,
+    /// Add the source to the target, for glows and other light-like effects.
+    Additive,
+}
+
+impl BlendMode {
+    /// Whether draws in this mode hide what is behind them, and so should write depth and can be
+    /// drawn in any order.
+    pub fn is_opaque(self) -> bool {
+        self == BlendMode::Opaque
+    }
+
+    /// The color state for a target of `format` drawn with this blend mode.
+    pub fn color_state(self, format: wgpu::TextureFormat) -> wgpu::ColorStateDescriptor {
+        let (color_blend, alpha_blend) = match self {
+            BlendMode::Opaque => (wgpu::BlendDescriptor::REPLACE, wgpu::BlendDescriptor::REPLACE),
+            BlendMode::AlphaBlend => (
+                wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            ),
+            BlendMode::PremultipliedAlpha => {
+                let blend = wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                };
+                (blend.clone(), blend)
+            },
+            BlendMode::Additive => {
+                let blend = wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                };
+                (blend.clone(), blend)
+            },
+        };
+        wgpu::ColorStateDescriptor {
+            format,
+            color_blend,
+            alpha_blend,
+            write_mask: wgpu::ColorWrite::ALL,
+        }
+    }
+}
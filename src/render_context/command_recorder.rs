@@ -0,0 +1,58 @@
+/// Records the commands for one part of a frame into its own command encoder. Recorders are
+/// independent of one another, so several can be filled at once (e.g. on different threads) and
+/// then submitted together, in order, with `RenderContext::submit_recorders`.
+pub struct CommandRecorder {
+    encoder: wgpu::CommandEncoder,
+}
+
+impl CommandRecorder {
+    pub fn new(gpu_context: &crate::gpu::GpuContext) -> Self {
+        Self {
+            encoder: gpu_context.create_command_encoder(),
+        }
+    }
+
+    /// The underlying encoder, for recording commands which have no dedicated method here.
+    pub fn encoder(&mut self) -> &mut wgpu::CommandEncoder {
+        &mut self.encoder
+    }
+
+    /// Records the pass drawing the terrain and instanced objects of `scene`. See
+    /// `Scene::record_commands`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn terrain_pass(
+        &mut self,
+        scene: &super::scene::Scene,
+        pipelines: &super::scene::ScenePipelines,
+        bind_group: &wgpu::BindGroup,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        region: &super::scene::ViewRegion,
+    ) {
+        scene.record_commands(
+            &mut self.encoder,
+            pipelines,
+            bind_group,
+            color_view,
+            depth_view,
+            region,
+        );
+    }
+
+    /// Records the passes drawing overlays on top of the finished scene in `target`, which is
+    /// `target_size` pixels large. Currently the only overlay is the minimap.
+    pub fn ui_pass(
+        &mut self,
+        minimap: &super::minimap::Minimap,
+        scene: &super::scene::Scene,
+        pipelines: &super::scene::ScenePipelines,
+        target: &wgpu::TextureView,
+        target_size: (u32, u32),
+    ) {
+        minimap.record(&mut self.encoder, scene, pipelines, target, target_size);
+    }
+
+    pub fn finish(self) -> wgpu::CommandBuffer {
+        self.encoder.finish()
+    }
+}
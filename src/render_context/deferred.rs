@@ -0,0 +1,328 @@
+//! The deferred rendering path. The opaque geometry is drawn once into a G-buffer holding each
+//! pixel's position, normal, albedo, and material; a fullscreen lighting pass then shades every
+//! pixel from the G-buffer, so the cost of the lights no longer scales with the geometry drawn.
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::bind_group_builder::BindGroupBuilder;
+use crate::postprocess::FullscreenQuad;
+
+use super::frame_graph::ResourceId;
+
+/// Selects how the scene is shaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RenderPath {
+    /// Each object is lit as it is drawn.
+    Forward,
+    /// Opaque objects are drawn into a G-buffer, and lit in a separate pass. Transparent objects,
+    /// billboards, and particles are still drawn forward, over the lit result.
+    Deferred,
+}
+
+impl Default for RenderPath {
+    fn default() -> Self {
+        RenderPath::Forward
+    }
+}
+
+/// The formats of the G-buffer's targets, in the order of the geometry shaders' outputs: world
+/// space position, normal (with the shading model in `w`), albedo, and material (metallic,
+/// roughness, ambient occlusion). Positions need full floats, since half floats are only accurate
+/// to a quarter of a block across most of the world.
+pub const GBUFFER_FORMATS: [wgpu::TextureFormat; 4] = [
+    wgpu::TextureFormat::Rgba32Float,
+    wgpu::TextureFormat::Rgba16Float,
+    wgpu::TextureFormat::Rgba8UnormSrgb,
+    wgpu::TextureFormat::Rgba8Unorm,
+];
+
+/// The G-buffer's targets, as frame graph resources.
+pub const GBUFFER_RESOURCES: [ResourceId; 4] = [
+    ResourceId("gbuffer position"),
+    ResourceId("gbuffer normal"),
+    ResourceId("gbuffer albedo"),
+    ResourceId("gbuffer material"),
+];
+
+/// The color states of pipelines drawing into the G-buffer. Every target is overwritten.
+pub fn gbuffer_color_states() -> Vec<wgpu::ColorStateDescriptor> {
+    GBUFFER_FORMATS
+        .iter()
+        .map(|&format| wgpu::ColorStateDescriptor {
+            format,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        })
+        .collect()
+}
+
+/// A single channel of the G-buffer, as shown by `DeferredRenderer::capture_debug_view`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GBufferChannel {
+    /// The surface color, as shaded.
+    Albedo,
+    /// The world space normal, mapped from `[-1, 1]` into `[0, 1]`.
+    Normal,
+    /// The world space position, scaled so that the world spans `[0, 1]`.
+    Position,
+    /// The depth buffer, as is: nearer surfaces are darker.
+    Depth,
+    Roughness,
+    Metallic,
+}
+
+impl GBufferChannel {
+    /// The index of the channel, matching the `CHANNEL_` constants in `gbuffer_debug.frag`.
+    fn index(self) -> u32 {
+        match self {
+            GBufferChannel::Albedo => 0,
+            GBufferChannel::Normal => 1,
+            GBufferChannel::Position => 2,
+            GBufferChannel::Depth => 3,
+            GBufferChannel::Roughness => 4,
+            GBufferChannel::Metallic => 5,
+        }
+    }
+}
+
+/// The `Debug` block of `gbuffer_debug.frag`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DebugUniforms {
+    channel: u32,
+    position_scale: f32,
+    _pad: [u32; 2],
+}
+
+unsafe impl Pod for DebugUniforms {}
+unsafe impl Zeroable for DebugUniforms {}
+
+/// The render targets opaque geometry is drawn into on the deferred path. The depth buffer is the
+/// render context's own, so the G-buffer must be the same size.
+pub struct GBuffer {
+    pub targets: [crate::gpu::RenderTarget; 4],
+}
+
+impl GBuffer {
+    pub fn new(gpu_context: &crate::gpu::GpuContext, width: u32, height: u32) -> Self {
+        let create = |format| gpu_context.create_render_target(width, height, format, false);
+        Self {
+            targets: [
+                create(GBUFFER_FORMATS[0]),
+                create(GBUFFER_FORMATS[1]),
+                create(GBUFFER_FORMATS[2]),
+                create(GBUFFER_FORMATS[3]),
+            ],
+        }
+    }
+
+    /// Recreates the targets at `width` by `height` pixels. The old contents are discarded.
+    pub fn resize(&mut self, gpu_context: &crate::gpu::GpuContext, width: u32, height: u32) {
+        for target in self.targets.iter_mut() {
+            target.resize(gpu_context, width, height);
+        }
+    }
+
+    /// The views of the targets, in the order of `GBUFFER_FORMATS`.
+    pub fn views(&self) -> [&wgpu::TextureView; 4] {
+        [
+            &self.targets[0].color_view,
+            &self.targets[1].color_view,
+            &self.targets[2].color_view,
+            &self.targets[3].color_view,
+        ]
+    }
+}
+
+/// The resources shared with the forward path that the lighting pass reads.
+pub struct LightingInputs<'a> {
+    pub camera: wgpu::BufferSlice<'a>,
+    pub lights: wgpu::BufferSlice<'a>,
+    pub environment_map_view: &'a wgpu::TextureView,
+    pub environment_sampler: &'a wgpu::Sampler,
+}
+
+/// Everything the deferred path needs beyond the forward path: the G-buffer, the pipelines drawing
+/// instanced meshes into it, and the lighting pass.
+pub struct DeferredRenderer {
+    pub gbuffer: GBuffer,
+    /// Draws opaque `InstancedMesh`es into the G-buffer.
+    pub instanced_pipelines: super::instanced::InstancedPipelines,
+
+    sampler: wgpu::Sampler,
+    lighting_bind_group_layout: wgpu::BindGroupLayout,
+    lighting_bind_group: wgpu::BindGroup,
+    lighting_quad: FullscreenQuad,
+}
+
+impl DeferredRenderer {
+    /// Create a new `DeferredRenderer` with a G-buffer of `width` by `height` pixels. Instanced
+    /// meshes are drawn with the main bind group layout, `bind_group_layout`.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+        inputs: &LightingInputs,
+    ) -> Self {
+        let gbuffer = GBuffer::new(gpu_context, width, height);
+        let instanced_pipelines = super::instanced::InstancedPipelines::new_gbuffer(gpu_context, bind_group_layout);
+
+        // The G-buffer is read a texel at a time, so it is never filtered.
+        let sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let (lighting_bind_group_layout, lighting_bind_group) =
+            lighting_bind_group_builder(&gbuffer, &sampler, inputs).build(gpu_context);
+
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("deferred_lighting.frag"));
+        let lighting_quad = FullscreenQuad::new(
+            gpu_context,
+            &lighting_bind_group_layout,
+            &fs_module,
+            wgpu::TextureFormat::Bgra8UnormSrgb,
+        );
+
+        Self {
+            gbuffer,
+            instanced_pipelines,
+            sampler,
+            lighting_bind_group_layout,
+            lighting_bind_group,
+            lighting_quad,
+        }
+    }
+
+    /// Recreates the G-buffer at `width` by `height` pixels.
+    pub fn resize(&mut self, gpu_context: &crate::gpu::GpuContext, width: u32, height: u32, inputs: &LightingInputs) {
+        self.gbuffer.resize(gpu_context, width, height);
+        self.rebuild_bind_group(gpu_context, inputs);
+    }
+
+    /// Rebinds the lighting pass's inputs, e.g. after the environment map has been replaced.
+    pub fn rebuild_bind_group(&mut self, gpu_context: &crate::gpu::GpuContext, inputs: &LightingInputs) {
+        self.lighting_bind_group = lighting_bind_group_builder(&self.gbuffer, &self.sampler, inputs)
+            .build_with_layout(gpu_context, &self.lighting_bind_group_layout);
+    }
+
+    /// Records the lighting pass, which clears `color_view` to `clear_color` and then shades every
+    /// pixel of it that geometry was drawn into. `color_view` must be the size of the G-buffer.
+    pub fn record_lighting(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        clear_color: wgpu::Color,
+    ) {
+        // The lighting pass keeps what is already in the target where it discards, so the sky is
+        // cleared in first.
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        self.lighting_quad.render(encoder, color_view, &self.lighting_bind_group);
+    }
+
+    /// Renders `channel` of the G-buffer, as the last frame left it, into an RGBA image the size of
+    /// the G-buffer, `width` by `height` pixels, and returns its pixels row by row. `depth_buffer`
+    /// is the depth buffer the G-buffer was drawn with, and positions are multiplied by
+    /// `position_scale`. This submits its own commands and blocks until the GPU has finished them.
+    pub fn capture_debug_view(
+        &self,
+        gpu_context: &crate::gpu::GpuContext,
+        depth_buffer: &wgpu::Texture,
+        width: u32,
+        height: u32,
+        channel: GBufferChannel,
+        position_scale: f32,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        // Only the depth aspect of a depth-stencil texture can be sampled.
+        let depth_view = depth_buffer.create_view(&wgpu::TextureViewDescriptor {
+            label: None,
+            format: crate::gpu::RENDER_TARGET_DEPTH_FORMAT,
+            dimension: wgpu::TextureViewDimension::D2,
+            aspect: wgpu::TextureAspect::DepthOnly,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            array_layer_count: 1,
+        });
+        let uniforms = DebugUniforms {
+            channel: channel.index(),
+            position_scale,
+            _pad: [0; 2],
+        };
+        let uniform_buf = gpu_context.create_buffer_init(
+            Some("G-buffer debug uniforms"),
+            bytemuck::bytes_of(&uniforms),
+            wgpu::BufferUsage::UNIFORM,
+        );
+        let [position, normal, albedo, material] = self.gbuffer.views();
+        let (bind_group_layout, bind_group) = BindGroupBuilder::new()
+            .add_texture(0, wgpu::ShaderStage::FRAGMENT, position)
+            .add_texture(1, wgpu::ShaderStage::FRAGMENT, normal)
+            .add_texture(2, wgpu::ShaderStage::FRAGMENT, albedo)
+            .add_texture(3, wgpu::ShaderStage::FRAGMENT, material)
+            .add_texture(4, wgpu::ShaderStage::FRAGMENT, &depth_view)
+            .add_sampler(5, wgpu::ShaderStage::FRAGMENT, &self.sampler)
+            .add_uniform_buffer(
+                6,
+                wgpu::ShaderStage::FRAGMENT,
+                uniform_buf.slice(..),
+                std::mem::size_of::<DebugUniforms>() as u64,
+            )
+            .build(gpu_context);
+
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("gbuffer_debug.frag"));
+        let debug_quad = FullscreenQuad::new(
+            gpu_context,
+            &bind_group_layout,
+            &fs_module,
+            super::screenshot::CAPTURE_FORMAT,
+        );
+        let target = gpu_context.create_render_target(width, height, super::screenshot::CAPTURE_FORMAT, false);
+        let mut encoder = gpu_context.create_command_encoder();
+        debug_quad.render(&mut encoder, &target.color_view, &bind_group);
+        gpu_context.submit_command_encoder(encoder);
+
+        let image = super::screenshot::read_texture(gpu_context, &target.color_texture, width, height)?;
+        Ok(image.into_raw())
+    }
+}
+
+fn lighting_bind_group_builder<'a>(
+    gbuffer: &'a GBuffer,
+    sampler: &'a wgpu::Sampler,
+    inputs: &LightingInputs<'a>,
+) -> BindGroupBuilder<'a> {
+    let [position, normal, albedo, material] = gbuffer.views();
+    BindGroupBuilder::new()
+        .add_texture(0, wgpu::ShaderStage::FRAGMENT, position)
+        .add_texture(1, wgpu::ShaderStage::FRAGMENT, normal)
+        .add_texture(2, wgpu::ShaderStage::FRAGMENT, albedo)
+        .add_texture(3, wgpu::ShaderStage::FRAGMENT, material)
+        .add_sampler(4, wgpu::ShaderStage::FRAGMENT, sampler)
+        .add_uniform_buffer(
+            5,
+            wgpu::ShaderStage::FRAGMENT,
+            inputs.camera,
+            std::mem::size_of::<crate::camera::CameraUniforms>() as u64,
+        )
+        .add_uniform_buffer(6, wgpu::ShaderStage::FRAGMENT, inputs.lights, super::lights::LIGHTS_UNIFORM_SIZE)
+        .add_cube_texture(7, wgpu::ShaderStage::FRAGMENT, inputs.environment_map_view)
+        .add_sampler(8, wgpu::ShaderStage::FRAGMENT, inputs.environment_sampler)
+}
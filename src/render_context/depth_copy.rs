@@ -0,0 +1,67 @@
+//! Copies of the depth buffer which can be sampled while the depth buffer itself is being rendered
+//! into. The depth buffer has a stencil aspect, so its format cannot be copied from directly; the
+//! depth is drawn into a float color target instead.
+
+use crate::bind_group_builder::BindGroupBuilder;
+use crate::postprocess::FullscreenQuad;
+
+/// The format of the copy. Depth is stored as is, in `[0, 1]`.
+pub const DEPTH_COPY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+/// A copy of a depth buffer, along with the pass filling it.
+pub struct DepthCopy {
+    target: crate::gpu::RenderTarget,
+    bind_group: wgpu::BindGroup,
+    copy_quad: FullscreenQuad,
+}
+
+impl DepthCopy {
+    /// Create a new `DepthCopy` of `depth_buffer`, which must be a `width` by `height` texture in
+    /// `RENDER_TARGET_DEPTH_FORMAT`. Nothing is copied until `record` is called.
+    pub fn new(gpu_context: &crate::gpu::GpuContext, depth_buffer: &wgpu::Texture, width: u32, height: u32) -> Self {
+        // Only the depth aspect of a depth-stencil texture can be sampled.
+        let depth_view = depth_buffer.create_view(&wgpu::TextureViewDescriptor {
+            label: None,
+            format: crate::gpu::RENDER_TARGET_DEPTH_FORMAT,
+            dimension: wgpu::TextureViewDimension::D2,
+            aspect: wgpu::TextureAspect::DepthOnly,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            array_layer_count: 1,
+        });
+        let sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let (bind_group_layout, bind_group) = BindGroupBuilder::new()
+            .add_texture(0, wgpu::ShaderStage::FRAGMENT, &depth_view)
+            .add_sampler(1, wgpu::ShaderStage::FRAGMENT, &sampler)
+            .build(gpu_context);
+
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("depth_copy.frag"));
+        let copy_quad = FullscreenQuad::new(gpu_context, &bind_group_layout, &fs_module, DEPTH_COPY_FORMAT);
+
+        Self {
+            target: gpu_context.create_render_target(width, height, DEPTH_COPY_FORMAT, false),
+            bind_group,
+            copy_quad,
+        }
+    }
+
+    /// The view of the copy, in `DEPTH_COPY_FORMAT`.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.target.color_view
+    }
+
+    /// Records a pass copying the depth buffer into the copy.
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.copy_quad.render(encoder, &self.target.color_view, &self.bind_group);
+    }
+}
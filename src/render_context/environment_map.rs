@@ -0,0 +1,243 @@
+//! Environment maps: cube maps of the scene's surroundings, for reflections. They are loaded from
+//! equirectangular (latitude-longitude) HDR images. The water in `shader.frag` reflects them, and
+//! the instanced meshes are lit by them.
+
+/// The format of every environment map. Half floats keep the range of an HDR image at half the size
+/// of full floats.
+pub const ENVIRONMENT_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// The number of faces of a cube map, stored as that many array layers.
+const CUBE_FACES: u32 = 6;
+
+/// Loads the equirectangular Radiance HDR (`.hdr`) image at `path` and converts it into a cube map
+/// of `ENVIRONMENT_MAP_FORMAT`, with one array layer per face in the order +x, -x, +y, -y, +z, -z.
+/// Each face is a quarter of the image's width on a side. The cube map is indexed by world space
+/// directions, so +z is up. See `create_cube_view`.
+///
+/// OpenEXR (`.exr`) images are not supported, since the version of the `image` crate this uses
+/// cannot decode them. Convert them to Radiance HDR first.
+pub fn load_environment_map_hdr(
+    gpu_context: &crate::gpu::GpuContext,
+    path: &std::path::Path,
+) -> Result<wgpu::Texture, Box<dyn std::error::Error>> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let decoder = image::hdr::HdrDecoder::new(reader)?;
+    let metadata = decoder.metadata();
+    let (width, height) = (metadata.width, metadata.height);
+    let pixels = decoder.read_image_hdr()?;
+
+    let face_size = (width / 4).max(1);
+    let texels = equirectangular_to_cube(&pixels, width, height, face_size);
+    Ok(create_cube_texture(gpu_context, face_size, &texels))
+}
+
+/// Creates a one texel cube map which is black in every direction, to bind until an environment
+/// map is loaded.
+pub fn create_default_environment_map(gpu_context: &crate::gpu::GpuContext) -> wgpu::Texture {
+    create_cube_texture(gpu_context, 1, &[0; 4 * CUBE_FACES as usize])
+}
+
+/// Creates a cube view of all six faces of an environment map.
+pub fn create_cube_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: None,
+        format: ENVIRONMENT_MAP_FORMAT,
+        dimension: wgpu::TextureViewDimension::Cube,
+        aspect: wgpu::TextureAspect::All,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        array_layer_count: CUBE_FACES,
+    })
+}
+
+/// Creates a cube map of `face_size` texels on a side, filled with `texels`: each face in turn, as
+/// rows of RGBA half floats.
+fn create_cube_texture(gpu_context: &crate::gpu::GpuContext, face_size: u32, texels: &[u16]) -> wgpu::Texture {
+    let face_extent = wgpu::Extent3d {
+        width: face_size,
+        height: face_size,
+        depth: 1,
+    };
+    let texture = gpu_context.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            depth: CUBE_FACES,
+            ..face_extent
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ENVIRONMENT_MAP_FORMAT,
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        label: Some("environment map"),
+    });
+    let face_len = (4 * face_size * face_size) as usize;
+    for (face, face_texels) in texels.chunks(face_len).enumerate() {
+        gpu_context.queue().write_texture(
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: face as u32 },
+            },
+            bytemuck::cast_slice(face_texels),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 4 * std::mem::size_of::<u16>() as u32 * face_size,
+                rows_per_image: 0,
+            },
+            face_extent,
+        );
+    }
+    texture
+}
+
+/// Resamples an equirectangular image into six cube faces of `face_size` texels on a side, as RGBA
+/// half floats. The image's rows run from straight up to straight down, and its columns once around
+/// the horizon, starting from -x. Each texel takes the nearest pixel in its direction.
+fn equirectangular_to_cube(pixels: &[image::Rgb<f32>], width: u32, height: u32, face_size: u32) -> Vec<u16> {
+    use std::f32::consts::PI;
+
+    let mut texels = Vec::with_capacity((4 * CUBE_FACES * face_size * face_size) as usize);
+    for face in 0..CUBE_FACES {
+        for y in 0..face_size {
+            for x in 0..face_size {
+                // Texel centers, from -1 to 1 across the face. t runs down the face.
+                let s = (x as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                let t = (y as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                // The direction through each texel, following the cube map conventions of Vulkan.
+                let [dx, dy, dz] = match face {
+                    0 => [1.0, -t, -s],
+                    1 => [-1.0, -t, s],
+                    2 => [s, 1.0, t],
+                    3 => [s, -1.0, -t],
+                    4 => [s, -t, 1.0],
+                    _ => [-s, -t, -1.0],
+                };
+                let length = (dx * dx + dy * dy + dz * dz).sqrt();
+                let longitude = dy.atan2(dx);
+                let latitude = (dz / length).asin();
+
+                let u = 0.5 + longitude / (2.0 * PI);
+                let v = 0.5 - latitude / PI;
+                let px = ((u * width as f32) as u32).min(width - 1);
+                let py = ((v * height as f32) as u32).min(height - 1);
+                let image::Rgb([r, g, b]) = pixels[(py * width + px) as usize];
+                texels.extend([r, g, b, 1.0].iter().map(|&channel| f32_to_f16_bits(channel)));
+            }
+        }
+    }
+    texels
+}
+
+/// Converts `value` to the bits of the nearest smaller half float, saturating to infinity.
+fn f32_to_f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+    if exponent == 0xff {
+        // Infinity stays infinity, and NaN stays NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x0200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        sign | 0x7c00
+    } else if exponent <= 0 {
+        // Too small for a normal half float. Shift in the implicit leading bit to make a subnormal,
+        // or flush to zero if even that is too small.
+        if exponent < -10 {
+            return sign;
+        }
+        sign | ((mantissa | 0x0080_0000) >> (14 - exponent)) as u16
+    } else {
+        sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An equirectangular image of `width` by `height` pixels in which each pixel's red channel is
+    /// its column and its green channel its row.
+    fn coordinate_image(width: u32, height: u32) -> Vec<image::Rgb<f32>> {
+        (0..height)
+            .flat_map(|row| (0..width).map(move |column| image::Rgb([column as f32, row as f32, 0.0])))
+            .collect()
+    }
+
+    #[test]
+    fn f32_to_f16_bits_converts_exactly_representable_values() {
+        assert_eq!(f32_to_f16_bits(0.0), 0x0000);
+        assert_eq!(f32_to_f16_bits(1.0), 0x3c00);
+        assert_eq!(f32_to_f16_bits(0.5), 0x3800);
+        assert_eq!(f32_to_f16_bits(-2.0), 0xc000);
+        assert_eq!(f32_to_f16_bits(65504.0), 0x7bff);
+        // The smallest subnormal half float.
+        assert_eq!(f32_to_f16_bits(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16_bits(2f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_f16_bits(1.0e6), 0x7c00);
+        assert_eq!(f32_to_f16_bits(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_f16_bits(f32::NAN) & 0x7e00, 0x7e00);
+    }
+
+    #[test]
+    fn cube_faces_sample_their_directions() {
+        let (width, height, face_size) = (8, 4, 2);
+        let texels = equirectangular_to_cube(&coordinate_image(width, height), width, height, face_size);
+        let face_len = (4 * face_size * face_size) as usize;
+        assert_eq!(texels.len(), CUBE_FACES as usize * face_len);
+
+        // The column and row of the pixel each texel of each face was taken from.
+        let to_pixel = |texel: &[u16]| {
+            let find = |bits: u16, count: u32| (0..count).find(|&i| f32_to_f16_bits(i as f32) == bits).unwrap();
+            (find(texel[0], width), find(texel[1], height))
+        };
+        let faces: Vec<Vec<(u32, u32)>> =
+            texels.chunks(face_len).map(|face| face.chunks(4).map(to_pixel).collect()).collect();
+
+        // Columns run once around the horizon from -x, so +x is in the middle of the image and +y
+        // three quarters of the way across.
+        assert!(faces[0].iter().all(|&(column, _)| column == 3 || column == 4));
+        assert!(faces[1].iter().all(|&(column, _)| column == 0 || column == 7));
+        assert!(faces[2].iter().all(|&(column, _)| column == 5 || column == 6));
+        assert!(faces[3].iter().all(|&(column, _)| column == 1 || column == 2));
+        // Rows run from straight up to straight down.
+        assert!(faces[4].iter().all(|&(_, row)| row == 0));
+        assert!(faces[5].iter().all(|&(_, row)| row == height - 1));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn loaded_map_has_six_faces() {
+        let desc = crate::gpu::GpuContextDescriptor::default();
+        let gpu_context = futures::executor::block_on(crate::gpu::GpuContext::create_headless(&desc, 1, 1))
+            .expect("this test needs a GPU adapter");
+        let (width, height) = (16, 8);
+        let path = std::env::temp_dir().join(format!("jvox_environment_test_{}.hdr", std::process::id()));
+        let file = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+        image::hdr::HdrEncoder::new(file)
+            .encode(&coordinate_image(width, height), width as usize, height as usize)
+            .unwrap();
+        let texture = load_environment_map_hdr(&gpu_context, &path);
+        std::fs::remove_file(&path).unwrap();
+        let texture = texture.unwrap();
+
+        // wgpu panics on validation errors, so viewing a layer the texture does not have would fail
+        // the test.
+        for face in 0..CUBE_FACES {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: None,
+                format: ENVIRONMENT_MAP_FORMAT,
+                dimension: wgpu::TextureViewDimension::D2,
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: face,
+                array_layer_count: 1,
+            });
+        }
+        create_cube_view(&texture);
+    }
+}
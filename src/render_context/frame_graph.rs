@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+/// Names a texture read or written by the passes of a `FrameGraph`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(pub &'static str);
+
+/// The color target a frame is drawn into.
+pub const COLOR: ResourceId = ResourceId("color");
+/// The depth buffer shared by the passes drawing into `COLOR`.
+pub const DEPTH: ResourceId = ResourceId("depth");
+
+/// The textures available to the passes of a `FrameGraph`, by `ResourceId`.
+#[derive(Default)]
+pub struct FrameResources<'a> {
+    views: HashMap<ResourceId, &'a wgpu::TextureView>,
+}
+
+impl<'a> FrameResources<'a> {
+    /// The view of `id`. Panics if `id` was never imported, which `FrameGraph::compile` rules out
+    /// for every resource a pass declares as an input or output.
+    pub fn view(&self, id: ResourceId) -> &'a wgpu::TextureView {
+        self.views[&id]
+    }
+}
+
+/// A single pass of a `FrameGraph`. `execute` must only touch the resources declared in `inputs`
+/// and `outputs`, since those are all its position in the schedule accounts for.
+pub struct PassNode<'a> {
+    pub name: &'static str,
+    pub inputs: Vec<ResourceId>,
+    pub outputs: Vec<ResourceId>,
+    pub execute: Box<dyn FnOnce(&mut wgpu::CommandEncoder, &FrameResources<'a>) + 'a>,
+}
+
+#[derive(Debug)]
+pub enum FrameGraphError {
+    /// A pass uses a resource that was never imported into the graph.
+    MissingResource { pass: &'static str, resource: ResourceId },
+    /// The passes depend on each other in a cycle. The names are those of the passes involved.
+    Cycle(Vec<&'static str>),
+}
+
+impl std::fmt::Display for FrameGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FrameGraphError::MissingResource { pass, resource } =>
+                write!(f, "pass `{}` uses resource `{}`, which was never imported", pass, resource.0),
+            FrameGraphError::Cycle(passes) =>
+                write!(f, "passes {:?} depend on each other in a cycle", passes),
+        }
+    }
+}
+
+impl std::error::Error for FrameGraphError {}
+
+/// The order in which the passes of a `FrameGraph` run, as found by `FrameGraph::compile`. A
+/// schedule stays valid for any graph built with the same passes, in the same order, so it can be
+/// kept for as long as the render configuration does not change.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    order: Vec<usize>,
+}
+
+/// Runs a frame's passes in an order derived from the resources they read and write, rather than
+/// the order they happen to be recorded in.
+///
+/// Passes are added in the order they would run if nothing else were known about them; this is
+/// the order used to decide which of several writers of a resource a reader sees, and to break
+/// ties between independent passes.
+#[derive(Default)]
+pub struct FrameGraph<'a> {
+    nodes: Vec<PassNode<'a>>,
+    resources: FrameResources<'a>,
+}
+
+impl<'a> FrameGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `view` available to the passes of the graph as `id`.
+    pub fn import(&mut self, id: ResourceId, view: &'a wgpu::TextureView) {
+        self.resources.views.insert(id, view);
+    }
+
+    pub fn add_pass(&mut self, node: PassNode<'a>) {
+        self.nodes.push(node);
+    }
+
+    /// Topologically sorts the passes of the graph. A pass runs after the most recent earlier
+    /// writer of each of its inputs, and after every earlier reader or writer of each of its
+    /// outputs. wgpu tracks resource states itself and transitions them between passes, so no
+    /// explicit barriers have to be inserted.
+    pub fn compile(&self) -> Result<Schedule, FrameGraphError> {
+        for node in &self.nodes {
+            for resource in node.inputs.iter().chain(node.outputs.iter()) {
+                if !self.resources.views.contains_key(resource) {
+                    return Err(FrameGraphError::MissingResource {
+                        pass: node.name,
+                        resource: *resource,
+                    });
+                }
+            }
+        }
+
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        let mut dependency_counts = vec![0usize; self.nodes.len()];
+        let mut add_edge = |from: usize, to: usize| {
+            if !dependents[from].contains(&to) {
+                dependents[from].push(to);
+                dependency_counts[to] += 1;
+            }
+        };
+
+        let mut last_writer: HashMap<ResourceId, usize> = HashMap::new();
+        let mut readers_since_write: HashMap<ResourceId, Vec<usize>> = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            for resource in &node.inputs {
+                if let Some(&writer) = last_writer.get(resource) {
+                    add_edge(writer, i);
+                }
+            }
+            for resource in &node.outputs {
+                if let Some(&writer) = last_writer.get(resource) {
+                    if writer != i {
+                        add_edge(writer, i);
+                    }
+                }
+                for &reader in readers_since_write.get(resource).into_iter().flatten() {
+                    if reader != i {
+                        add_edge(reader, i);
+                    }
+                }
+            }
+            for resource in &node.inputs {
+                readers_since_write.entry(*resource).or_insert_with(Vec::new).push(i);
+            }
+            for resource in &node.outputs {
+                last_writer.insert(*resource, i);
+                readers_since_write.remove(resource);
+            }
+        }
+
+        // Kahn's algorithm, always taking the earliest added pass that is ready so that independent
+        // passes keep the order they were added in.
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut ready: std::collections::BTreeSet<usize> = (0..self.nodes.len())
+            .filter(|&i| dependency_counts[i] == 0)
+            .collect();
+        while let Some(&i) = ready.iter().next() {
+            ready.remove(&i);
+            order.push(i);
+            for &dependent in &dependents[i] {
+                dependency_counts[dependent] -= 1;
+                if dependency_counts[dependent] == 0 {
+                    ready.insert(dependent);
+                }
+            }
+        }
+
+        if order.len() < self.nodes.len() {
+            let cycle = (0..self.nodes.len())
+                .filter(|&i| dependency_counts[i] > 0)
+                .map(|i| self.nodes[i].name)
+                .collect();
+            return Err(FrameGraphError::Cycle(cycle));
+        }
+
+        Ok(Schedule { order })
+    }
+
+    /// Records every pass into `encoder` in the order given by `schedule`, which must have been
+    /// compiled from a graph with the same passes as this one.
+    pub fn execute(self, encoder: &mut wgpu::CommandEncoder, schedule: &Schedule) {
+        debug_assert_eq!(schedule.order.len(), self.nodes.len());
+        let resources = self.resources;
+        let mut nodes: Vec<Option<PassNode<'a>>> = self.nodes.into_iter().map(Some).collect();
+        for &i in &schedule.order {
+            if let Some(node) = nodes[i].take() {
+                (node.execute)(encoder, &resources);
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Occlusion testing against a hierarchical depth buffer (HZB): a mip chain of the depth buffer in
+//! which each texel holds the farthest depth of the texels it covers. A chunk's bounding box is
+//! occluded if its nearest point is farther than the HZB over the screen area the box covers, and
+//! the level at which that area is about one texel answers that with four reads.
+//!
+//! The depth buffer is cleared to 1 and tested with `Less`, so farther is larger, and the reduction
+//! keeps the maximum.
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::bind_group_builder::BindGroupBuilder;
+
+/// The format of every level of the HZB.
+const HZB_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+/// The work group sizes of the HZB shaders, matching their `local_size` declarations.
+const DOWNSAMPLE_WORK_GROUP_SIZE: u32 = 8;
+const CULL_WORK_GROUP_SIZE: u32 = 64;
+
+/// The world space bounding box of a chunk, laid out as in the `Bounds` buffer of `cull.comp`. The
+/// `w` components are unused.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChunkBounds {
+    pub min: [f32; 4],
+    pub max: [f32; 4],
+}
+
+unsafe impl Pod for ChunkBounds {}
+unsafe impl Zeroable for ChunkBounds {}
+
+/// Matches the `Params` block of `cull.comp`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OcclusionParams {
+    chunk_count: u32,
+    level_count: u32,
+    hzb_size: [f32; 2],
+}
+
+unsafe impl Pod for OcclusionParams {}
+unsafe impl Zeroable for OcclusionParams {}
+
+/// The HZB built from a depth buffer, and the per-chunk occlusion test reading it.
+pub struct HzbOcclusion {
+    /// Every level of the HZB, for the occlusion test.
+    hzb_view: wgpu::TextureView,
+    size: (u32, u32),
+    level_count: u32,
+
+    init_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+    cull_pipeline: wgpu::ComputePipeline,
+    /// Copies the depth buffer into level 0.
+    init_bind_group: wgpu::BindGroup,
+    /// Builds each level after the first from the one before it.
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    cull_bind_group_layout: wgpu::BindGroupLayout,
+    cull_bind_group: wgpu::BindGroup,
+    sampler: wgpu::Sampler,
+
+    params_buf: crate::gpu::UniformBuffer<OcclusionParams>,
+    bounds_buf: wgpu::Buffer,
+    /// One `u32` per chunk, written by the occlusion test.
+    visibility_buf: wgpu::Buffer,
+    /// The CPU's copy of `visibility_buf`.
+    readback_buf: wgpu::Buffer,
+    chunk_count: usize,
+}
+
+impl HzbOcclusion {
+    /// Create a new `HzbOcclusion` for `depth_buffer`, a `width` by `height` texture in
+    /// `RENDER_TARGET_DEPTH_FORMAT`, testing the chunks bounded by `bounds` as seen by the camera in
+    /// `camera`.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        depth_buffer: &wgpu::Texture,
+        width: u32,
+        height: u32,
+        camera: wgpu::BufferSlice,
+        bounds: &[ChunkBounds],
+    ) -> Self {
+        let level_count = crate::postprocess::mip_level_count(width, height);
+        let hzb = gpu_context.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HZB_FORMAT,
+            usage: wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::SAMPLED,
+            label: Some("hierarchical depth buffer"),
+        });
+        let level_view = |base_mip_level, level_count| hzb.create_view(&wgpu::TextureViewDescriptor {
+            label: None,
+            format: HZB_FORMAT,
+            dimension: wgpu::TextureViewDimension::D2,
+            aspect: wgpu::TextureAspect::All,
+            base_mip_level,
+            level_count,
+            base_array_layer: 0,
+            array_layer_count: 1,
+        });
+        let level_views: Vec<wgpu::TextureView> = (0..level_count).map(|level| level_view(level, 1)).collect();
+        let hzb_view = level_view(0, level_count);
+
+        // Only the depth aspect of a depth-stencil texture can be sampled.
+        let depth_view = depth_buffer.create_view(&wgpu::TextureViewDescriptor {
+            label: None,
+            format: crate::gpu::RENDER_TARGET_DEPTH_FORMAT,
+            dimension: wgpu::TextureViewDimension::D2,
+            aspect: wgpu::TextureAspect::DepthOnly,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            array_layer_count: 1,
+        });
+        // Every read is a `texelFetch`, so the sampler never filters.
+        let sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        // Every level is built by reading one texture and writing another, so the copy from the
+        // depth buffer and the downsampling passes share a bind group layout.
+        let level_builder = |source, destination| {
+            BindGroupBuilder::new()
+                .add_texture(0, wgpu::ShaderStage::COMPUTE, source)
+                .add_sampler(1, wgpu::ShaderStage::COMPUTE, &sampler)
+                .add_storage_texture(2, wgpu::ShaderStage::COMPUTE, destination, HZB_FORMAT)
+        };
+        let (level_bind_group_layout, init_bind_group) = level_builder(&depth_view, &level_views[0]).build(gpu_context);
+        let downsample_bind_groups = level_views
+            .windows(2)
+            .map(|pair| level_builder(&pair[0], &pair[1]).build_with_layout(gpu_context, &level_bind_group_layout))
+            .collect();
+
+        let params_buf = crate::gpu::UniformBuffer::new(gpu_context, &OcclusionParams {
+            chunk_count: bounds.len() as u32,
+            level_count,
+            hzb_size: [width as f32, height as f32],
+        });
+        let (bounds_buf, visibility_buf, readback_buf) = create_chunk_buffers(gpu_context, bounds);
+        let (cull_bind_group_layout, cull_bind_group) =
+            cull_bind_group_builder(camera, &params_buf, &bounds_buf, &visibility_buf, &hzb_view, &sampler)
+                .build(gpu_context);
+
+        let create_pipeline = |layout: &wgpu::BindGroupLayout, spirv: &[u8]| {
+            let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            gpu_context.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                layout: &pipeline_layout,
+                compute_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &gpu_context.create_shader_module_from_bytes(spirv),
+                    entry_point: "main",
+                },
+            })
+        };
+
+        Self {
+            init_pipeline: create_pipeline(&level_bind_group_layout, include_shader!("hzb/init.comp")),
+            downsample_pipeline: create_pipeline(
+                &level_bind_group_layout,
+                include_shader!("hzb/downsample.comp"),
+            ),
+            cull_pipeline: create_pipeline(&cull_bind_group_layout, include_shader!("hzb/cull.comp")),
+            hzb_view,
+            size: (width, height),
+            level_count,
+            init_bind_group,
+            downsample_bind_groups,
+            cull_bind_group_layout,
+            cull_bind_group,
+            sampler,
+            params_buf,
+            bounds_buf,
+            visibility_buf,
+            readback_buf,
+            chunk_count: bounds.len(),
+        }
+    }
+
+    /// Replaces the chunks tested with those bounded by `bounds`, e.g. after the terrain has been
+    /// regenerated.
+    pub fn set_chunk_bounds(
+        &mut self,
+        gpu_context: &crate::gpu::GpuContext,
+        camera: wgpu::BufferSlice,
+        bounds: &[ChunkBounds],
+    ) {
+        let (bounds_buf, visibility_buf, readback_buf) = create_chunk_buffers(gpu_context, bounds);
+        self.bounds_buf = bounds_buf;
+        self.visibility_buf = visibility_buf;
+        self.readback_buf = readback_buf;
+        self.chunk_count = bounds.len();
+        self.params_buf.update(gpu_context.queue(), &OcclusionParams {
+            chunk_count: bounds.len() as u32,
+            level_count: self.level_count,
+            hzb_size: [self.size.0 as f32, self.size.1 as f32],
+        });
+        self.cull_bind_group = cull_bind_group_builder(
+            camera,
+            &self.params_buf,
+            &self.bounds_buf,
+            &self.visibility_buf,
+            &self.hzb_view,
+            &self.sampler,
+        )
+        .build_with_layout(gpu_context, &self.cull_bind_group_layout);
+    }
+
+    /// Records the passes building the HZB from the depth buffer, testing every chunk against it,
+    /// and copying the results where `read_visibility` can reach them. The depth buffer must hold
+    /// the frame's depth by the time these run.
+    pub fn record(&self, gpu_context: &crate::gpu::GpuContext, encoder: &mut wgpu::CommandEncoder) {
+        let groups = |texels: u32| (texels + DOWNSAMPLE_WORK_GROUP_SIZE - 1) / DOWNSAMPLE_WORK_GROUP_SIZE;
+        let (width, height) = self.size;
+        let levels = std::iter::once((&self.init_pipeline, &self.init_bind_group))
+            .chain(self.downsample_bind_groups.iter().map(|bind_group| (&self.downsample_pipeline, bind_group)));
+        for (level, (pipeline, bind_group)) in levels.enumerate() {
+            let (level_width, level_height) = ((width >> level).max(1), (height >> level).max(1));
+            let (x, y) = (groups(level_width), groups(level_height));
+            gpu_context.dispatch_compute_pass(encoder, pipeline, bind_group, x, y, 1);
+        }
+
+        if self.chunk_count == 0 {
+            return;
+        }
+        let chunk_groups = (self.chunk_count as u32 + CULL_WORK_GROUP_SIZE - 1) / CULL_WORK_GROUP_SIZE;
+        gpu_context.dispatch_compute_pass(encoder, &self.cull_pipeline, &self.cull_bind_group, chunk_groups, 1, 1);
+        let size = visibility_size(self.chunk_count);
+        encoder.copy_buffer_to_buffer(&self.visibility_buf, 0, &self.readback_buf, 0, size);
+    }
+
+    /// Reads back the results of the last occlusion test recorded with `record`, in the order the
+    /// bounds were given: `false` for each occluded chunk. This blocks until the GPU has finished.
+    /// Returns `None` if the results could not be read.
+    pub fn read_visibility(&self, gpu_context: &crate::gpu::GpuContext) -> Option<Vec<bool>> {
+        if self.chunk_count == 0 {
+            return Some(Vec::new());
+        }
+        let slice = self.readback_buf.slice(..visibility_size(self.chunk_count));
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        gpu_context.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapped).ok()?;
+        let visible = {
+            let data = slice.get_mapped_range();
+            let words: &[u32] = bytemuck::cast_slice(&data);
+            words.iter().map(|&word| word != 0).collect()
+        };
+        self.readback_buf.unmap();
+        Some(visible)
+    }
+}
+
+/// The size in bytes of the visibility results of `chunk_count` chunks.
+fn visibility_size(chunk_count: usize) -> wgpu::BufferAddress {
+    (chunk_count * std::mem::size_of::<u32>()) as wgpu::BufferAddress
+}
+
+/// Creates the buffers of `bounds`, of the visibility of each chunk, and of the CPU's copy of that.
+/// None of them is ever empty, since wgpu rejects empty bindings.
+fn create_chunk_buffers(
+    gpu_context: &crate::gpu::GpuContext,
+    bounds: &[ChunkBounds],
+) -> (wgpu::Buffer, wgpu::Buffer, wgpu::Buffer) {
+    let padded_bounds = if bounds.is_empty() { &[ChunkBounds::default()][..] } else { bounds };
+    let bounds_buf = gpu_context.create_buffer_init(
+        Some("chunk bounds"),
+        bytemuck::cast_slice(padded_bounds),
+        wgpu::BufferUsage::STORAGE,
+    );
+    let size = visibility_size(padded_bounds.len());
+    let visibility_buf = gpu_context.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("chunk visibility"),
+        size,
+        usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback_buf = gpu_context.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("chunk visibility readback"),
+        size,
+        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+    (bounds_buf, visibility_buf, readback_buf)
+}
+
+fn cull_bind_group_builder<'a>(
+    camera: wgpu::BufferSlice<'a>,
+    params_buf: &'a crate::gpu::UniformBuffer<OcclusionParams>,
+    bounds_buf: &'a wgpu::Buffer,
+    visibility_buf: &'a wgpu::Buffer,
+    hzb_view: &'a wgpu::TextureView,
+    sampler: &'a wgpu::Sampler,
+) -> BindGroupBuilder<'a> {
+    BindGroupBuilder::new()
+        .add_uniform_buffer(
+            0,
+            wgpu::ShaderStage::COMPUTE,
+            camera,
+            std::mem::size_of::<crate::camera::CameraUniforms>() as u64,
+        )
+        .add_uniform_buffer(
+            1,
+            wgpu::ShaderStage::COMPUTE,
+            params_buf.slice(),
+            std::mem::size_of::<OcclusionParams>() as u64,
+        )
+        .add_storage_buffer(
+            2,
+            wgpu::ShaderStage::COMPUTE,
+            bounds_buf.slice(..),
+            std::mem::size_of::<ChunkBounds>() as u64,
+            true,
+        )
+        .add_storage_buffer(
+            3,
+            wgpu::ShaderStage::COMPUTE,
+            visibility_buf.slice(..),
+            std::mem::size_of::<u32>() as u64,
+            false,
+        )
+        .add_texture(4, wgpu::ShaderStage::COMPUTE, hzb_view)
+        .add_sampler(5, wgpu::ShaderStage::COMPUTE, sampler)
+}
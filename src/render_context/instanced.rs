@@ -0,0 +1,362 @@
+use crate::managed_buffer::ManagedBuffer;
+use crate::mesh;
+use crate::utils;
+
+/// The per-instance data uploaded to the GPU: a column-major model-to-world matrix.
+type InstanceTransform = [f32; 16];
+
+/// An index buffer in either of the formats of `utils::IndexData`.
+enum IndexBuffer {
+    U16(ManagedBuffer<u16, Vec<u16>>),
+    U32(ManagedBuffer<u32, Vec<u32>>),
+}
+
+impl IndexBuffer {
+    fn new(gpu_context: &crate::gpu::GpuContext, indices: utils::IndexData) -> Option<Self> {
+        Some(match indices {
+            utils::IndexData::U16(indices) =>
+                IndexBuffer::U16(ManagedBuffer::new_index_buf_with_data(gpu_context, indices).ok()?),
+            utils::IndexData::U32(indices) =>
+                IndexBuffer::U32(ManagedBuffer::new_index_buf_with_data(gpu_context, indices).ok()?),
+        })
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            IndexBuffer::U16(buf) => buf.len(),
+            IndexBuffer::U32(buf) => buf.len(),
+        }
+    }
+
+    fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            IndexBuffer::U16(_) => wgpu::IndexFormat::Uint16,
+            IndexBuffer::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+
+    fn slice(&self) -> wgpu::BufferSlice {
+        match self {
+            IndexBuffer::U16(buf) => buf.slice(..),
+            IndexBuffer::U32(buf) => buf.slice(..),
+        }
+    }
+
+    fn enqueue_copy_commands(&mut self, gpu_context: &mut crate::gpu::GpuContext, encoder: &mut wgpu::CommandEncoder) {
+        match self {
+            IndexBuffer::U16(buf) if buf.dirty() => buf.enqueue_copy_command(gpu_context, encoder),
+            IndexBuffer::U32(buf) if buf.dirty() => buf.enqueue_copy_command(gpu_context, encoder),
+            _ => {},
+        }
+    }
+}
+
+/// A single mesh drawn many times in one draw call, once per model matrix in its instance buffer.
+/// This is used for objects which are repeated across the world, such as trees and rocks.
+pub struct InstancedMesh {
+    vertex_buf: ManagedBuffer<utils::Vertex, Vec<utils::Vertex>>,
+    /// 16-bit if the mesh is small enough, and 32-bit otherwise.
+    index_buf: IndexBuffer,
+    instance_buf: ManagedBuffer<InstanceTransform, Vec<InstanceTransform>>,
+    /// Transparent meshes are drawn after everything opaque, with the transparent pipeline, and
+    /// sorted back to front.
+    transparent: bool,
+}
+
+impl InstancedMesh {
+    /// Uploads `mesh` and one model matrix per instance to the GPU. Returns `None` if `mesh` has no
+    /// indices or `instances` is empty, since there would be nothing to draw.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        mesh: &mesh::Mesh,
+        instances: &[cgmath::Matrix4<f32>],
+    ) -> Option<Self> {
+        if mesh.indices.is_empty() || instances.is_empty() {
+            return None;
+        }
+
+        let vertex_buf = ManagedBuffer::new_vertex_buf_with_data(gpu_context, mesh.vertices.clone()).ok()?;
+        let index_buf = IndexBuffer::new(
+            gpu_context,
+            utils::IndexData::new(mesh.indices.clone(), mesh.vertices.len()),
+        )?;
+        // Instance data is consumed as a vertex attribute, so it lives in a vertex buffer.
+        let instance_buf = ManagedBuffer::new_vertex_buf_with_data(gpu_context, to_instance_transforms(instances))
+            .ok()?;
+
+        Some(Self {
+            vertex_buf,
+            index_buf,
+            instance_buf,
+            transparent: false,
+        })
+    }
+
+    /// Replaces the model matrices of every instance. If the number of instances is unchanged the
+    /// existing buffer is reused and flushed on the next frame; otherwise a new buffer is created.
+    /// Returns `None` if `instances` is empty or the buffer could not be created.
+    pub fn update_instances(
+        &mut self,
+        gpu_context: &crate::gpu::GpuContext,
+        instances: &[cgmath::Matrix4<f32>],
+    ) -> Option<()> {
+        if instances.is_empty() {
+            return None;
+        }
+
+        let transforms = to_instance_transforms(instances);
+        if transforms.len() == self.instance_buf.len() {
+            self.instance_buf.replace_data(transforms);
+        } else {
+            self.instance_buf = ManagedBuffer::new_vertex_buf_with_data(gpu_context, transforms).ok()?;
+        }
+        Some(())
+    }
+
+    pub fn transparent(&self) -> bool {
+        self.transparent
+    }
+
+    pub fn set_transparent(&mut self, transparent: bool) {
+        self.transparent = transparent;
+    }
+
+    /// The number of instances of the mesh.
+    pub fn instance_count(&self) -> usize {
+        self.instance_buf.len()
+    }
+
+    /// The world position of the origin of instance `index`.
+    pub fn instance_position(&self, index: usize) -> cgmath::Point3<f32> {
+        let transform = &self.instance_buf.host_data()[index];
+        // The translation is the last column of the column-major matrix.
+        cgmath::Point3::new(transform[12], transform[13], transform[14])
+    }
+
+    /// The index format the pipeline drawing this mesh must be created with.
+    pub fn index_format(&self) -> wgpu::IndexFormat {
+        self.index_buf.format()
+    }
+
+    /// The number of triangles drawn by `draw`, across every instance.
+    pub fn triangle_count(&self) -> u64 {
+        (self.index_buf.len() / 3 * self.instance_buf.len()) as u64
+    }
+
+    /// Flushes any dirty buffers to the GPU.
+    pub fn enqueue_copy_commands(
+        &mut self,
+        gpu_context: &mut crate::gpu::GpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.vertex_buf.dirty() {
+            self.vertex_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+        self.index_buf.enqueue_copy_commands(gpu_context, encoder);
+        if self.instance_buf.dirty() {
+            self.instance_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+    }
+
+    /// Draws every instance of the mesh in a single draw call. The instanced pipeline for
+    /// `index_format` and its bind group must already be set on `render_pass`.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        self.set_buffers(render_pass);
+        render_pass.draw_indexed(0..self.index_buf.len() as u32, 0, 0..self.instance_buf.len() as u32);
+    }
+
+    /// Binds the mesh's vertex, index, and instance buffers, ready for `draw_instance`.
+    pub fn set_buffers<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_index_buffer(self.index_buf.slice());
+        render_pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buf.slice(..));
+    }
+
+    /// Draws instance `index` alone, for when instances must be ordered individually. The mesh's
+    /// buffers must already be bound with `set_buffers`.
+    pub fn draw_instance<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize) {
+        let index = index as u32;
+        render_pass.draw_indexed(0..self.index_buf.len() as u32, 0, index..index + 1);
+    }
+}
+
+fn to_instance_transforms(instances: &[cgmath::Matrix4<f32>]) -> Vec<InstanceTransform> {
+    instances.iter().map(|m| *AsRef::<[f32; 16]>::as_ref(m)).collect()
+}
+
+/// The pipelines drawing `InstancedMesh`es with one blend mode. The index format is part of a
+/// pipeline's state, so there is one pipeline for each.
+pub struct InstancedPipelines {
+    uint16: wgpu::RenderPipeline,
+    uint32: wgpu::RenderPipeline,
+}
+
+impl InstancedPipelines {
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        blend_mode: super::BlendMode,
+    ) -> Self {
+        let fs_spirv = include_shader!("instanced.frag");
+        let color_states = [blend_mode.color_state(wgpu::TextureFormat::Bgra8UnormSrgb)];
+        // Transparent objects are tested against the depth buffer, but do not hide what is drawn
+        // behind them afterwards.
+        let depth_write_enabled = blend_mode.is_opaque();
+        let create = |index_format| {
+            create_instanced_pipeline(
+                gpu_context,
+                bind_group_layout,
+                fs_spirv,
+                &color_states,
+                depth_write_enabled,
+                index_format,
+            )
+        };
+        Self {
+            uint16: create(wgpu::IndexFormat::Uint16),
+            uint32: create(wgpu::IndexFormat::Uint32),
+        }
+    }
+
+    /// Creates the pipelines drawing opaque `InstancedMesh`es into the G-buffer of the deferred
+    /// path, rather than shading them.
+    pub fn new_gbuffer(gpu_context: &crate::gpu::GpuContext, bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let fs_spirv = include_shader!("instanced_gbuffer.frag");
+        let color_states = super::deferred::gbuffer_color_states();
+        let create = |index_format| {
+            create_instanced_pipeline(gpu_context, bind_group_layout, fs_spirv, &color_states, true, index_format)
+        };
+        Self {
+            uint16: create(wgpu::IndexFormat::Uint16),
+            uint32: create(wgpu::IndexFormat::Uint32),
+        }
+    }
+
+    /// The pipeline for meshes whose indices are in `index_format`.
+    pub fn get(&self, index_format: wgpu::IndexFormat) -> &wgpu::RenderPipeline {
+        match index_format {
+            wgpu::IndexFormat::Uint16 => &self.uint16,
+            wgpu::IndexFormat::Uint32 => &self.uint32,
+        }
+    }
+}
+
+/// Creates a render pipeline drawing `InstancedMesh`es with the fragment shader `fs_spirv` into
+/// targets described by `color_states`. It uses the terrain's bind group layout, so the main bind
+/// group (camera, texture, and sampler) can be reused as is.
+fn create_instanced_pipeline(
+    gpu_context: &crate::gpu::GpuContext,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    fs_spirv: &[u8],
+    color_states: &[wgpu::ColorStateDescriptor],
+    depth_write_enabled: bool,
+    index_format: wgpu::IndexFormat,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    #[cfg(not(feature = "oct-normals"))]
+    let vs_module = gpu_context.create_shader_module_from_bytes(include_shader!("instanced.vert"));
+    #[cfg(feature = "oct-normals")]
+    let vs_module = gpu_context.create_shader_module_from_bytes(include_shader!("instanced.oct.vert"));
+    let fs_module = gpu_context.create_shader_module_from_bytes(fs_spirv);
+
+    gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        layout: &pipeline_layout,
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: &vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: &fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::Back,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states,
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: crate::gpu::RENDER_TARGET_DEPTH_FORMAT,
+            depth_write_enabled,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format,
+            vertex_buffers: &[
+                wgpu::VertexBufferDescriptor {
+                    stride: utils::VERTEX_SIZE as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: utils::VERTEX_NORMAL_FORMAT,
+                            offset: 4*4,
+                            shader_location: 1,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: utils::VERTEX_TC_OFFSET as wgpu::BufferAddress,
+                            shader_location: 2,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Uchar4Norm,
+                            offset: utils::VERTEX_COLOR_OFFSET as wgpu::BufferAddress,
+                            shader_location: 3,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float,
+                            offset: utils::VERTEX_AO_OFFSET as wgpu::BufferAddress,
+                            shader_location: 4,
+                        },
+                    ],
+                },
+                // The model matrix is passed one column per attribute, advancing once per instance.
+                wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<InstanceTransform>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Instance,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 0,
+                            shader_location: 5,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 4*4,
+                            shader_location: 6,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 4*4*2,
+                            shader_location: 7,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 4*4*3,
+                            shader_location: 8,
+                        },
+                    ],
+                },
+            ],
+        },
+
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
@@ -0,0 +1,215 @@
+use bytemuck::{Pod, Zeroable};
+
+/// Where the sun is by default, as a compass bearing and a height above the horizon, in degrees.
+/// This is roughly where the instanced meshes' fixed light direction used to point.
+pub const DEFAULT_SUN_AZIMUTH: f32 = 63.4;
+pub const DEFAULT_SUN_ELEVATION: f32 = 65.9;
+
+/// Below this elevation, in degrees, the sky and sunlight start turning toward dusk colors.
+const DUSK_ELEVATION: f32 = 15.0;
+/// How far below the horizon, in degrees, the sun sets before it is fully night.
+const TWILIGHT_DEGREES: f32 = 6.0;
+
+/// The linear RGB colors of the sky and of sunlight by day, at sunset, and by night.
+const DAY_SKY: [f32; 3] = [0.1, 0.2, 0.3];
+const DUSK_SKY: [f32; 3] = [0.8, 0.4, 0.45];
+const NIGHT_SKY: [f32; 3] = [0.01, 0.01, 0.04];
+const DAY_SUNLIGHT: [f32; 3] = [1.0, 1.0, 1.0];
+const DUSK_SUNLIGHT: [f32; 3] = [1.0, 0.7, 0.55];
+const NIGHT_SUNLIGHT: [f32; 3] = [0.1, 0.1, 0.2];
+
+/// The most point lights that can be in the scene at once, matching `MAX_POINT_LIGHTS` in the
+/// terrain and instanced fragment shaders.
+pub const MAX_POINT_LIGHTS: usize = 8;
+
+/// A light shining equally in all directions from a point, fading out to nothing at `radius` world
+/// units away.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight {
+    /// The position of the light in world space. The `w` component is ignored.
+    pub position: [f32; 4],
+    /// The linear RGB color of the light. The alpha component is ignored.
+    pub color: [f32; 4],
+    pub radius: f32,
+    /// Scales the color, so that lights can be brighter than white.
+    pub intensity: f32,
+}
+
+/// Identifies a light added with `RenderContext::add_light`. Ids are never reused, so an id stays
+/// invalid once its light has been removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LightId(u64);
+
+/// A `PointLight` as laid out in the `Lights` block, where std140 rounds each array element up to
+/// a multiple of 16 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct PointLightUniform {
+    position: [f32; 4],
+    color: [f32; 4],
+    radius: f32,
+    intensity: f32,
+    _pad: [f32; 2],
+}
+
+unsafe impl Pod for PointLightUniform {}
+unsafe impl Zeroable for PointLightUniform {}
+
+impl From<PointLight> for PointLightUniform {
+    fn from(light: PointLight) -> Self {
+        Self {
+            position: light.position,
+            color: light.color,
+            radius: light.radius,
+            intensity: light.intensity,
+            _pad: [0.0; 2],
+        }
+    }
+}
+
+/// Every light in the scene, matching the `Lights` block in the fragment shaders. Only the first
+/// `count` point lights are used.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct LightArrayUniform {
+    lights: [PointLightUniform; MAX_POINT_LIGHTS],
+    /// The direction toward the sun. The `w` component is unused.
+    sun_direction: [f32; 4],
+    /// The linear RGB color of sunlight. The alpha component is unused.
+    sun_color: [f32; 4],
+    count: u32,
+    _pad: [u32; 3],
+}
+
+unsafe impl Pod for LightArrayUniform {}
+unsafe impl Zeroable for LightArrayUniform {}
+
+/// The size of the `Lights` block, for binding its buffer.
+pub const LIGHTS_UNIFORM_SIZE: u64 = std::mem::size_of::<LightArrayUniform>() as u64;
+
+/// Returns the unit vector pointing toward a sun at `azimuth_degrees` clockwise from north (the +y
+/// axis), and `elevation_degrees` above the horizon.
+pub fn sun_direction(azimuth_degrees: f32, elevation_degrees: f32) -> cgmath::Vector3<f32> {
+    let (azimuth, elevation) = (azimuth_degrees.to_radians(), elevation_degrees.to_radians());
+    cgmath::Vector3::new(
+        azimuth.sin() * elevation.cos(),
+        azimuth.cos() * elevation.cos(),
+        elevation.sin(),
+    )
+}
+
+/// Picks between the day, dusk, and night variants of a color for a sun `elevation_degrees` above
+/// the horizon. The day color fades to the dusk color as the sun approaches the horizon, and then
+/// to the night color over the twilight after it sets.
+fn time_of_day_color(elevation_degrees: f32, day: [f32; 3], dusk: [f32; 3], night: [f32; 3]) -> [f32; 3] {
+    let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ];
+    if elevation_degrees >= 0.0 {
+        lerp(dusk, day, (elevation_degrees / DUSK_ELEVATION).min(1.0))
+    } else {
+        lerp(dusk, night, (-elevation_degrees / TWILIGHT_DEGREES).min(1.0))
+    }
+}
+
+/// The color the sky is cleared to with the sun `elevation_degrees` above the horizon.
+pub fn sky_color(elevation_degrees: f32) -> wgpu::Color {
+    let [r, g, b] = time_of_day_color(elevation_degrees, DAY_SKY, DUSK_SKY, NIGHT_SKY);
+    wgpu::Color {
+        r: r as f64,
+        g: g as f64,
+        b: b as f64,
+        a: 1.0,
+    }
+}
+
+/// The lights in the scene: the sun, and any point lights. Every change is written through to the
+/// uniform buffer immediately.
+pub struct SceneLights {
+    lights: Vec<(LightId, PointLight)>,
+    next_id: u64,
+    /// The sun's compass bearing and height above the horizon, in degrees.
+    sun_position: (f32, f32),
+    uniform_buf: crate::gpu::UniformBuffer<LightArrayUniform>,
+}
+
+impl SceneLights {
+    /// Create a new `SceneLights` with the sun at its default position, and no point lights.
+    pub fn new(gpu_context: &crate::gpu::GpuContext) -> Self {
+        let scene_lights = Self {
+            lights: Vec::with_capacity(MAX_POINT_LIGHTS),
+            next_id: 0,
+            sun_position: (DEFAULT_SUN_AZIMUTH, DEFAULT_SUN_ELEVATION),
+            uniform_buf: crate::gpu::UniformBuffer::new(gpu_context, &LightArrayUniform::default()),
+        };
+        scene_lights.upload(gpu_context.queue());
+        scene_lights
+    }
+
+    /// The sun's compass bearing and height above the horizon, in degrees.
+    pub fn sun_position(&self) -> (f32, f32) {
+        self.sun_position
+    }
+
+    /// Moves the sun to `azimuth_degrees` and `elevation_degrees`. See `sun_direction`.
+    pub fn set_sun_position(&mut self, queue: &wgpu::Queue, azimuth_degrees: f32, elevation_degrees: f32) {
+        self.sun_position = (azimuth_degrees, elevation_degrees);
+        self.upload(queue);
+    }
+
+    /// The uniform buffer holding the `Lights` block.
+    pub fn slice(&self) -> wgpu::BufferSlice {
+        self.uniform_buf.slice()
+    }
+
+    /// Adds `light`, returning its id, or `None` if there are already `MAX_POINT_LIGHTS` lights.
+    pub fn add(&mut self, queue: &wgpu::Queue, light: PointLight) -> Option<LightId> {
+        if self.lights.len() >= MAX_POINT_LIGHTS {
+            return None;
+        }
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.lights.push((id, light));
+        self.upload(queue);
+        Some(id)
+    }
+
+    /// Removes the light with the given id, returning it, or `None` if there is no such light.
+    pub fn remove(&mut self, queue: &wgpu::Queue, id: LightId) -> Option<PointLight> {
+        let index = self.lights.iter().position(|(light_id, _)| *light_id == id)?;
+        let (_, light) = self.lights.remove(index);
+        self.upload(queue);
+        Some(light)
+    }
+
+    /// Replaces the light with the given id. Returns `None` if there is no such light.
+    pub fn update(&mut self, queue: &wgpu::Queue, id: LightId, light: PointLight) -> Option<()> {
+        let (_, existing) = self.lights.iter_mut().find(|(light_id, _)| *light_id == id)?;
+        *existing = light;
+        self.upload(queue);
+        Some(())
+    }
+
+    /// Returns the light with the given id, if any.
+    pub fn get(&self, id: LightId) -> Option<&PointLight> {
+        self.lights.iter().find(|(light_id, _)| *light_id == id).map(|(_, light)| light)
+    }
+
+    fn upload(&self, queue: &wgpu::Queue) {
+        let (azimuth, elevation) = self.sun_position;
+        let direction = sun_direction(azimuth, elevation);
+        let [r, g, b] = time_of_day_color(elevation, DAY_SUNLIGHT, DUSK_SUNLIGHT, NIGHT_SUNLIGHT);
+        let mut uniform = LightArrayUniform {
+            sun_direction: [direction.x, direction.y, direction.z, 0.0],
+            sun_color: [r, g, b, 1.0],
+            count: self.lights.len() as u32,
+            ..Default::default()
+        };
+        for (slot, (_, light)) in uniform.lights.iter_mut().zip(&self.lights) {
+            *slot = (*light).into();
+        }
+        self.uniform_buf.update(queue, &uniform);
+    }
+}
@@ -0,0 +1,52 @@
+use bytemuck::{Pod, Zeroable};
+
+/// The surface the instanced meshes are shaded as, in the metallic-roughness workflow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PbrMaterial {
+    /// The linear RGBA color multiplied with each mesh's texture and vertex colors.
+    pub base_color: [f32; 4],
+    /// 0 for a dielectric, like stone or plastic, and 1 for a bare metal.
+    pub metallic: f32,
+    /// 0 for a mirror finish, up to 1 for a fully rough one.
+    pub roughness: f32,
+    /// How much the vertices' ambient occlusion darkens the ambient light, from 0 (not at all) to 1.
+    pub ao_strength: f32,
+}
+
+impl Default for PbrMaterial {
+    /// A rough, white dielectric, fully occluded by the vertex ambient occlusion.
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 1.0,
+            ao_strength: 1.0,
+        }
+    }
+}
+
+/// A `PbrMaterial` as laid out in the `Material` block of `instanced.frag`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PbrUniforms {
+    base_color: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    ao_strength: f32,
+    _pad: f32,
+}
+
+unsafe impl Pod for PbrUniforms {}
+unsafe impl Zeroable for PbrUniforms {}
+
+impl From<PbrMaterial> for PbrUniforms {
+    fn from(material: PbrMaterial) -> Self {
+        Self {
+            base_color: material.base_color,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            ao_strength: material.ao_strength,
+            _pad: 0.0,
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::camera;
+
+/// The width and height of the minimap texture, in pixels.
+const MINIMAP_SIZE: u32 = 256;
+/// The gap between the minimap and the edges of the window, in pixels.
+const MINIMAP_MARGIN: f32 = 16.0;
+/// The height above the world origin from which the minimap camera looks down.
+const MINIMAP_ELEVATION: f32 = 500.0;
+
+/// A picture-in-picture, top-down view of the world. The world geometry is rendered into a small
+/// offscreen texture using an orthographic camera, and this texture is then composited into the
+/// lower-right corner of the main view.
+///
+/// The minimap owns its own camera uniform buffer and bind group, but it is drawn with the main
+/// render pipelines and shares the world's vertex and index buffers.
+pub struct Minimap {
+    enabled: bool,
+
+    camera: camera::Camera,
+    uniform_buf: crate::managed_buffer::ManagedBuffer<camera::CameraUniforms, [camera::CameraUniforms; 1]>,
+    /// A bind group matching the main bind group layout, but referencing the minimap's camera.
+    terrain_bind_group: wgpu::BindGroup,
+
+    /// The offscreen color and depth targets the world is drawn into.
+    target: crate::gpu::RenderTarget,
+
+    #[allow(dead_code)]
+    composite_sampler: wgpu::Sampler,
+    composite_bind_group: wgpu::BindGroup,
+    composite_quad: crate::postprocess::FullscreenQuad,
+}
+
+impl Minimap {
+    /// Create a new `Minimap` looking down over the center of a world of size `world_extent`. The
+    /// shared bindings are those used by the main view; they are needed to build a bind group
+    /// compatible with `terrain_bind_group_layout`.
+    pub fn new(
+        gpu_context: &crate::gpu::GpuContext,
+        world_extent: cgmath::Vector2<f32>,
+        terrain_bind_group_layout: &wgpu::BindGroupLayout,
+        shared_bindings: &super::SharedBindings,
+    ) -> Option<Self> {
+        // Fix the camera high above the center of the world, looking straight down. Since we are
+        // looking along the z-axis, the y-axis serves as up on the minimap.
+        let half_extent = world_extent / 2.0;
+        let mut camera = camera::Camera::new(
+            cgmath::Point3::new(half_extent.x, half_extent.y, MINIMAP_ELEVATION),
+            cgmath::Vector3::new(0.0, 0.0, -1.0),
+            cgmath::Vector3::new(0.0, 1.0, 0.0),
+            1.0,
+            70.0,
+            0.5,
+            MINIMAP_ELEVATION * 2.0,
+        );
+        camera.set_projection_mode(camera::ProjectionMode::Orthographic {
+            half_height: half_extent.x.max(half_extent.y),
+        });
+        let uniform_buf = crate::managed_buffer::ManagedBuffer::new_uniform_buf_with_data(
+            gpu_context,
+            [camera.uniforms()],
+        ).ok()?;
+
+        let terrain_bind_group = super::create_main_bind_group(
+            gpu_context,
+            terrain_bind_group_layout,
+            uniform_buf.slice(..),
+            shared_bindings,
+        );
+
+        // Create the offscreen targets. The color target uses the swap chain's format so that the
+        // main render pipeline can draw into it unchanged.
+        let target = gpu_context.create_render_target(
+            MINIMAP_SIZE,
+            MINIMAP_SIZE,
+            wgpu::TextureFormat::Bgra8UnormSrgb,
+            true,
+        );
+
+        // Set up the composite pass, which samples the minimap texture onto a quad in the corner of
+        // the main view.
+        let composite_sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let (composite_bind_group_layout, composite_bind_group) = crate::bind_group_builder::BindGroupBuilder::new()
+            .add_texture(0, wgpu::ShaderStage::FRAGMENT, &target.color_view)
+            .add_sampler(1, wgpu::ShaderStage::FRAGMENT, &composite_sampler)
+            .build(gpu_context);
+
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("minimap.frag"));
+        let composite_quad = crate::postprocess::FullscreenQuad::new(
+            gpu_context,
+            &composite_bind_group_layout,
+            &fs_module,
+            wgpu::TextureFormat::Bgra8UnormSrgb,
+        );
+
+        Some(Self {
+            enabled: true,
+            camera,
+            uniform_buf,
+            terrain_bind_group,
+            target,
+            composite_sampler,
+            composite_bind_group,
+            composite_quad,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Moves the minimap camera to look down over the center of a world of size `world_extent`,
+    /// zoomed out just far enough to fit the whole world.
+    pub fn set_world_extent(&mut self, world_extent: cgmath::Vector2<f32>) {
+        let half_extent = world_extent / 2.0;
+        self.camera.set_position(cgmath::Point3::new(half_extent.x, half_extent.y, MINIMAP_ELEVATION));
+        self.camera.set_projection_mode(camera::ProjectionMode::Orthographic {
+            half_height: half_extent.x.max(half_extent.y),
+        });
+        self.uniform_buf.replace_data([self.camera.uniforms()]);
+    }
+
+    /// Rebuilds the bind group used to draw the world into the minimap. This must be called whenever
+    /// any of the main view's shared bindings are replaced.
+    pub fn rebuild_terrain_bind_group(
+        &mut self,
+        gpu_context: &crate::gpu::GpuContext,
+        terrain_bind_group_layout: &wgpu::BindGroupLayout,
+        shared_bindings: &super::SharedBindings,
+    ) {
+        self.terrain_bind_group = super::create_main_bind_group(
+            gpu_context,
+            terrain_bind_group_layout,
+            self.uniform_buf.slice(..),
+            shared_bindings,
+        );
+    }
+
+    /// Flushes the minimap camera's uniform buffer to the GPU if it has changed.
+    pub fn enqueue_copy_commands(
+        &mut self,
+        gpu_context: &mut crate::gpu::GpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.uniform_buf.dirty() {
+            self.uniform_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+    }
+
+    /// Records the passes needed to draw the minimap: first the world is drawn into the minimap
+    /// texture, and then that texture is composited into the lower-right corner of `target`, which
+    /// is `target_width` by `target_height` pixels large.
+    pub fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &super::scene::Scene,
+        pipelines: &super::scene::ScenePipelines,
+        target: &wgpu::TextureView,
+        (target_width, target_height): (u32, u32),
+    ) {
+        // The target is always created with a depth buffer.
+        let depth_view = self.target.depth_view.as_ref().unwrap();
+        scene.record_commands(
+            encoder,
+            pipelines,
+            &self.terrain_bind_group,
+            &self.target.color_view,
+            depth_view,
+            &super::scene::ViewRegion::default(),
+        );
+
+        // Place the minimap in the lower-right corner. If the window is too small to fit the
+        // margin, we pin the minimap to the top-left instead of passing a negative offset.
+        let size = MINIMAP_SIZE as f32;
+        let x = (target_width as f32 - size - MINIMAP_MARGIN).max(0.0);
+        let y = (target_height as f32 - size - MINIMAP_MARGIN).max(0.0);
+        self.composite_quad.render_viewport(
+            encoder,
+            target,
+            &self.composite_bind_group,
+            (x, y, size, size),
+        );
+    }
+}
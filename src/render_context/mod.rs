@@ -1,10 +1,261 @@
 use winit::window::Window;
 
 use crate::camera;
+use crate::particles::ParticleSystem;
 use crate::utils;
 
+pub use benchmark::{BenchmarkResult, DEFAULT_BENCHMARK_FRAMES};
+pub use billboard::BillboardInstance;
+pub use blend_mode::BlendMode;
+pub use command_recorder::CommandRecorder;
+pub use deferred::{GBufferChannel, RenderPath};
+pub use lights::{LightId, PointLight};
+pub use material::PbrMaterial;
+pub use scene::SceneState;
+pub use terrain_mesh::{PatchRegion, TerrainPatch, TopologyMode};
+
+/// Where the scene is saved on exit and restored from on startup, if its `autosave` flag is set.
+pub const DEFAULT_SCENE_PATH: &str = "scene.toml";
+
+/// The images loaded into the billboard sprite array, indexed by `BillboardInstance::texture_index`.
+// TODO: There is no sprite art yet, so the terrain texture stands in as the only sprite.
+const BILLBOARD_SPRITES: &[&str] = &["texture.png"];
+
+mod benchmark;
+mod billboard;
+mod blend_mode;
+mod command_recorder;
+mod deferred;
+mod depth_copy;
+mod environment_map;
 #[cfg(debug_assertions)]
 mod debug_pass;
+mod frame_graph;
+mod hzb;
+mod instanced;
+mod lights;
+mod material;
+mod minimap;
+mod outline;
+mod particles;
+mod pipeline_cache;
+mod ply;
+mod render_scale;
+mod scene;
+mod screenshot;
+mod terrain_mesh;
+mod ui;
+mod voxel_terrain;
+
+#[derive(Debug)]
+pub enum RenderContextError {
+    GpuContextError(crate::gpu::GpuContextError),
+    TextureLoadError(image::ImageError),
+    /// A GPU resource could not be created. The string names the resource.
+    ResourceCreationError(&'static str),
+    /// The passes making up a frame could not be scheduled, so nothing was rendered.
+    FrameGraphError(frame_graph::FrameGraphError),
+    /// The near clip plane must lie in front of the camera, and before the far clip plane.
+    InvalidClipPlanesError { near: f32, far: f32 },
+    /// A viewport must have a positive width and height.
+    InvalidViewportError { width: f32, height: f32 },
+    /// A `TerrainPatch` could not be applied. The string says why.
+    InvalidTerrainPatchError(&'static str),
+}
+
+impl std::fmt::Display for RenderContextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderContextError::GpuContextError(err) =>
+                write!(f, "GPU context creation failed! Error is: {}", err),
+            RenderContextError::TextureLoadError(err) =>
+                write!(f, "Texture loading failed! `image` error is: {}", err),
+            RenderContextError::ResourceCreationError(resource) =>
+                write!(f, "Failed to create the {}!", resource),
+            RenderContextError::FrameGraphError(err) =>
+                write!(f, "Frame graph compilation failed! Error is: {}", err),
+            RenderContextError::InvalidClipPlanesError { near, far } =>
+                write!(f, "Invalid clip planes! Near is {} and far is {}.", near, far),
+            RenderContextError::InvalidViewportError { width, height } =>
+                write!(f, "Invalid viewport! Width is {} and height is {}.", width, height),
+            RenderContextError::InvalidTerrainPatchError(reason) =>
+                write!(f, "Invalid terrain patch: {}!", reason),
+        }
+    }
+}
+
+impl std::error::Error for RenderContextError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderContextError::GpuContextError(err) => Some(err),
+            RenderContextError::TextureLoadError(err) => Some(err),
+            RenderContextError::ResourceCreationError(_) => None,
+            RenderContextError::FrameGraphError(err) => Some(err),
+            RenderContextError::InvalidClipPlanesError { .. } => None,
+            RenderContextError::InvalidViewportError { .. } => None,
+            RenderContextError::InvalidTerrainPatchError(_) => None,
+        }
+    }
+}
+
+/// The terrain shaders.
+const TERRAIN_VS_SPIRV: &[u8] = include_shader!("shader.vert");
+const TERRAIN_FS_SPIRV: &[u8] = include_shader!("shader.frag");
+/// The terrain fragment shader of the deferred path, which writes the G-buffer.
+const TERRAIN_GBUFFER_FS_SPIRV: &[u8] = include_shader!("terrain_gbuffer.frag");
+
+/// The number of samples per pixel in the main view.
+const SAMPLE_COUNT: u32 = 1;
+
+/// Pipeline variants which go unused for this many frames are dropped from the cache.
+const PIPELINE_EVICTION_FRAMES: u64 = 600;
+
+/// How the terrain texture is filtered when sampled.
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerConfig {
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    /// The maximum anisotropy to filter with, or `None` to disable anisotropic filtering. This only
+    /// takes effect if the device supports `SAMPLER_ANISOTROPY`.
+    pub anisotropy_clamp: Option<std::num::NonZeroU8>,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            anisotropy_clamp: None,
+        }
+    }
+}
+
+/// A terrain parameter which can tell whether a new value would change the generated terrain.
+trait TerrainParameter {
+    fn differs_from(&self, other: &Self) -> bool;
+}
+
+impl TerrainParameter for f32 {
+    fn differs_from(&self, other: &Self) -> bool {
+        (self - other).abs() > std::f32::EPSILON
+    }
+}
+
+impl TerrainParameter for u32 {
+    fn differs_from(&self, other: &Self) -> bool {
+        self != other
+    }
+}
+
+impl TerrainParameter for bool {
+    fn differs_from(&self, other: &Self) -> bool {
+        self != other
+    }
+}
+
+/// Sets the field `$field` of the terrain configuration of `$self` to `$value`, marking the terrain
+/// dirty so that it is regenerated, but only if the value actually changed. Setting the same value
+/// repeatedly (e.g. from a slider which reports every frame) then costs nothing.
+macro_rules! set_field_dirty {
+    ($self:ident, $field:ident, $value:expr) => {{
+        let value = $value;
+        let mut config = $self.scene.world_geometry_manager.config();
+        if TerrainParameter::differs_from(&config.$field, &value) {
+            config.$field = value;
+            $self.scene.world_geometry_manager.set_config(config);
+            $self.terrain_dirty = true;
+        }
+    }};
+}
+
+/// The number of recent frame times kept for frame statistics: a few seconds' worth.
+const FRAME_TIMER_CAPACITY: usize = 300;
+
+/// The default `(constant, slope scale)` depth bias of the wireframe; see `set_wireframe_depth_bias`.
+/// Enough to keep edges in front of coplanar faces, without pulling them through nearby geometry, on
+/// the backends which offset lines at all.
+const DEFAULT_WIREFRAME_DEPTH_BIAS: (i32, f32) = (-2, -1.0);
+
+/// How long `render` sleeps after each frame while the window is unfocused, by default.
+const DEFAULT_UNFOCUSED_SLEEP_MS: u64 = 100;
+
+/// Once the frame index reaches this, a warning is logged that it will soon stop counting.
+const FRAME_INDEX_WARNING_THRESHOLD: u64 = std::u64::MAX - (1 << 32);
+
+/// The default exponent applied to the normal when blending triplanar projections.
+const DEFAULT_TRIPLANAR_BLEND_SHARPNESS: f32 = 4.0;
+
+/// The parameters used to texture the terrain, matching the `Terrain` block in `shader.frag`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TerrainUniforms {
+    /// The number of columns and rows of tiles in the terrain atlas.
+    atlas_dims: [f32; 2],
+    blend_sharpness: f32,
+    /// How many times each tile repeats per world unit.
+    tiling_scale: f32,
+    /// The height of the water surface, which reflects the environment map.
+    sea_level: f32,
+    _pad: [f32; 3],
+}
+
+unsafe impl bytemuck::Pod for TerrainUniforms {}
+unsafe impl bytemuck::Zeroable for TerrainUniforms {}
+
+/// Timing information for animating shaders, matching the `Time` block bound at group 1.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct TimeUniform {
+    /// Seconds since the render context was created.
+    time: f32,
+    /// Seconds since the previous frame.
+    delta_time: f32,
+    /// The low and high halves of `RenderContext::frame_index`, since shaders cannot be assumed to
+    /// support 64-bit integers.
+    frame_index: u32,
+    frame_index_hi: u32,
+}
+
+unsafe impl bytemuck::Pod for TimeUniform {}
+unsafe impl bytemuck::Zeroable for TimeUniform {}
+
+/// The range the field of view is clamped to, in degrees.
+const MIN_FOV: f32 = 10.0;
+const MAX_FOV: f32 = 170.0;
+
+/// A transition of the camera's vertical field of view, eased with smoothstep.
+#[derive(Clone, Copy, Debug)]
+struct FovTransition {
+    from: f32,
+    to: f32,
+    duration: f32,
+    elapsed: f32,
+}
+
+impl FovTransition {
+    /// Advances the transition by `delta_secs`, returning the field of view at the new point in
+    /// time and whether the transition has finished.
+    fn advance(&mut self, delta_secs: f32) -> (f32, bool) {
+        self.elapsed += delta_secs;
+        if self.elapsed >= self.duration {
+            return (self.to, true);
+        }
+        let t = self.elapsed / self.duration;
+        let eased = t * t * (3.0 - 2.0 * t);
+        (self.from + (self.to - self.from) * eased, false)
+    }
+}
+
+/// The settings which decide which passes make up the frame graph. The compiled schedule is reused
+/// until these change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FrameGraphConfig {
+    particles: bool,
+    deferred: bool,
+    outline: bool,
+}
 
 /// A `RenderContext` stores any state that is required for rendering a frame. This may include:
 ///
@@ -21,82 +272,216 @@ mod debug_pass;
 pub struct RenderContext {
     gpu_context: crate::gpu::GpuContext,
 
-    next_frame_encoder: wgpu::CommandEncoder,
+    /// Collects work recorded between frames, such as compute dispatches and buffer uploads. It is
+    /// submitted ahead of the next frame's passes.
+    next_frame_recorder: CommandRecorder,
 
-    world_geometry_manager: crate::world_geometry::WorldGeometryManager,
+    scene: scene::Scene,
 
     vs_module: wgpu::ShaderModule,
     fs_module: wgpu::ShaderModule,
+    gbuffer_fs_module: wgpu::ShaderModule,
 
     texture: wgpu::Texture,
     texture_view: wgpu::TextureView,
     texture_sampler: wgpu::Sampler,
+    texture_mip_count: u32,
+    sampler_config: SamplerConfig,
+    mipmap_generator: crate::postprocess::MipmapGenerator,
 
     depth_buffer: wgpu::Texture,
+    depth_buffer_size: wgpu::Extent3d,
     depth_buffer_view: wgpu::TextureView,
     depth_buffer_sampler: wgpu::Sampler,
+    /// A copy of the depth buffer made by `copy_depth_to_texture`, which can be sampled while the
+    /// depth buffer itself is being rendered into. Created on first use.
+    depth_copy: Option<depth_copy::DepthCopy>,
+    /// Tests the terrain's chunks for occlusion against each frame's depth buffer. See
+    /// `set_occlusion_culling`.
+    occlusion: Option<hzb::HzbOcclusion>,
+
+    /// The resolution the scene is rendered at, relative to the window. See `set_render_scale`.
+    render_scale: f32,
+    /// The target the scene is rendered into, at `render_scale` times the window's resolution,
+    /// before being stretched over the window. `None` while the scale is 1.
+    scaled_target: Option<render_scale::ScaledTarget>,
+
+    /// How the scene is shaded. See `set_render_path`.
+    render_path: RenderPath,
+    /// The G-buffer and lighting pass of the deferred path, at the render size. `None` while the
+    /// forward path is selected.
+    deferred: Option<deferred::DeferredRenderer>,
 
-    camera: camera::Camera,
     // For now, this only stores the camera's matrix.
-    uniform_buf: crate::managed_buffer::ManagedBuffer<f32, utils::Matrix4>,
+    uniform_buf: crate::gpu::UniformBuffer<camera::CameraUniforms>,
+    /// The most recently written terrain uniforms, kept so that they can be changed one at a time.
+    terrain_uniforms: TerrainUniforms,
+    terrain_uniform_buf: crate::managed_buffer::ManagedBuffer<TerrainUniforms, [TerrainUniforms; 1]>,
+    /// The point lights shading the scene. See `add_light`.
+    scene_lights: lights::SceneLights,
+    /// The cube map of the surroundings, reflected by shiny surfaces. See `load_environment_map`.
+    environment_map: wgpu::Texture,
+    environment_map_view: wgpu::TextureView,
+    environment_sampler: wgpu::Sampler,
+    /// The material the instanced meshes are shaded with. See `set_pbr_material`.
+    pbr_material: PbrMaterial,
+    pbr_uniform_buf: crate::gpu::UniformBuffer<material::PbrUniforms>,
 
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
 
     pipeline_layout: wgpu::PipelineLayout,
-    render_pipeline: wgpu::RenderPipeline,
+    /// Every variant of the terrain pipeline in use. The variant drawn is selected by `wireframe`,
+    /// `topology`, `cull_mode`, `wireframe_depth_bias`, `SAMPLE_COUNT`, and `shader_hash`.
+    pipeline_cache: pipeline_cache::PipelineCache,
+    wireframe: bool,
+    topology: TopologyMode,
+    /// The constant and slope-scaled depth bias of the wireframe. See `set_wireframe_depth_bias`.
+    wireframe_depth_bias: (i32, f32),
+    cull_mode: wgpu::CullMode,
+    /// A hash of the terrain shaders' SPIR-V.
+    shader_hash: u64,
+
+    instanced_pipeline: instanced::InstancedPipelines,
+    /// Draws the transparent instanced objects, blending with `transparent_blend_mode`.
+    transparent_instanced_pipeline: instanced::InstancedPipelines,
+    transparent_blend_mode: BlendMode,
+
+    /// The order of the scene's passes, along with the configuration it was compiled for.
+    frame_schedule: Option<(FrameGraphConfig, frame_graph::Schedule)>,
+
+    billboards: billboard::BillboardRenderer,
+
+    particle_renderer: particles::ParticleRenderer,
+
+    minimap: minimap::Minimap,
+
+    /// The outline of the selected terrain patch. See `set_selected_patch`.
+    outline: outline::OutlineRenderer,
+
+    /// Voxel terrain, drawn alongside the heightmap terrain as `scene.voxel_terrain`. See
+    /// `set_terrain_grid`.
+    terrain_grid: Option<crate::voxel::VoxelGrid<bool>>,
+    terrain_grid_extractor: crate::voxel::MeshExtractor,
+
+    /// 2D elements drawn over the main view. See `ui_draw_rect`.
+    ui: ui::UiRenderer,
 
     camera_dirty: bool,
+    /// Set when the terrain no longer matches its configuration, which is that of the scene's
+    /// `WorldGeometryManager`. The terrain is regenerated with the next update.
+    terrain_dirty: bool,
+    /// The level of detail each chunk was last generated at, given the camera's position and
+    /// `lod_bias`. See `set_lod_bias`.
+    chunk_lods: crate::world_geometry::ChunkLodMap,
+    lod_bias: f32,
+    /// When set, the terrain is split into this many Voronoi regions rather than a grid of chunks.
+    /// See `set_voronoi_regions`.
+    voronoi_regions: Option<u32>,
+    /// The noise the terrain's heights are sampled from, if not the default. See
+    /// `set_terrain_noise`.
+    terrain_noise: Option<crate::utils::NoiseCompositor>,
+    /// Set by `force_redraw_next_frame` to have the next frame rendered even if nothing in the
+    /// scene changed. Cleared once a frame has been rendered.
+    repaint_requested: bool,
+    /// When set, `render` skips frames in which nothing has changed since the last one. See
+    /// `set_event_driven_rendering`.
+    event_driven: bool,
+    /// While the window is unfocused, `render` sleeps for `unfocused_sleep_ms` after each frame so
+    /// that a window in the background does not keep a core busy.
+    window_focused: bool,
+    unfocused_sleep_ms: u64,
+    /// The ratio of physical to logical pixels of the display the window is on. Everything is
+    /// rendered in physical pixels; this is for sizing anything specified in logical ones.
+    scale_factor: f64,
+    /// Whether the scene should be saved to `DEFAULT_SCENE_PATH` on exit.
+    autosave: bool,
+
+    /// Measures the time between frames. The most recent measurement is `time_uniform.delta_time`.
+    frame_timer: crate::frame_timer::FrameTimer,
+    /// The time the render context was created, from which `TimeUniform::time` is measured.
+    start_time: std::time::Instant,
+    /// The number of frames rendered since creation, or since `reset_frame_index`.
+    frame_index: u64,
+    /// The most recently written time uniform, and the bind group (group 1) exposing it to shaders.
+    time_uniform: TimeUniform,
+    time_uniform_buf: crate::gpu::UniformBuffer<TimeUniform>,
+    time_bind_group: wgpu::BindGroup,
+
+    /// The field of view transition in progress, if any. See `set_fov_target`.
+    fov_transition: Option<FovTransition>,
+
+    /// The `(x, y, width, height)` rectangle of the window the scene is drawn into, in pixels, or
+    /// `None` to draw into the whole window.
+    viewport: Option<(f32, f32, f32, f32)>,
+    /// The `(x, y, width, height)` rectangle of the window, in pixels, that drawing in the next
+    /// frame is clipped to, or `None` to clip only to the viewport.
+    scissor_rect: Option<(u32, u32, u32, u32)>,
+
+    /// Set once initialization has completed. Frames should not be rendered before then.
+    is_ready: bool,
 }
 
 impl RenderContext {
-    // TODO: `Option` -> `Result`.
-    pub async fn create(window: &Window) -> Option<RenderContext> {
-        let gpu_context = crate::gpu::GpuContext::create(window).await.unwrap();
+    /// Creates a render context for `window` showing the default scene.
+    #[allow(dead_code)]
+    pub async fn create(window: &Window) -> Result<RenderContext, RenderContextError> {
+        let gpu_context = crate::gpu::GpuContext::create(window, &crate::gpu::GpuContextDescriptor::default())
+            .await
+            .map_err(RenderContextError::GpuContextError)?;
+        let mut render_context = Self::from_scene_state(&SceneState::default(), gpu_context)?;
+        render_context.scale_factor = window.scale_factor();
+        Ok(render_context)
+    }
 
+    /// Creates a render context on `gpu_context` showing the scene described by `state`: its
+    /// terrain is generated straight from the saved configuration and sea level, the voxel terrain
+    /// is restored, and the camera starts where the scene left it. Key bindings in `state` are left
+    /// to the caller. The window's scale factor is taken to be 1 until `on_dpi_scale_change` says
+    /// otherwise.
+    pub fn from_scene_state(
+        state: &SceneState,
+        gpu_context: crate::gpu::GpuContext,
+    ) -> Result<RenderContext, RenderContextError> {
         // Create the command encoder used during initialization.
-        let init_encoder = gpu_context.create_command_encoder();
+        let mut init_encoder = gpu_context.create_command_encoder();
 
-        let world_geometry_manager = crate::world_geometry::WorldGeometryManager::new(&gpu_context)?;
+        // The terrain texture is an atlas of materials. Each terrain face picks its tile when the
+        // mesh is generated, so that every material is drawn in the same draw call. Coordinates
+        // within the tile are derived from world position by triplanar projection in the shader.
+        let atlas_path = std::path::Path::new("texture.png");
+        let atlas = if atlas_path.exists() {
+            crate::atlas::TextureAtlas::load(atlas_path, 2, 2).map_err(RenderContextError::TextureLoadError)?
+        } else {
+            // Without an atlas, every tile is the same procedural height gradient.
+            log::warn!("{} not found, so the terrain is colored by a generated gradient.", atlas_path.display());
+            crate::atlas::TextureAtlas::from_gradient(64, 2, 2, &crate::utils::HeightGradient::default())
+        };
+        let world_geometry_manager = crate::world_geometry::WorldGeometryManager::new(
+            state.terrain_config,
+            state.sea_level as i32,
+            crate::atlas::default_tile_selector,
+        );
+        let mut chunk_lods = crate::world_geometry::ChunkLodMap::default();
+        chunk_lods.update_lod(&state.terrain_config, state.camera_position.into(), 0.0);
+        let terrain_mesh =
+            terrain_mesh::TerrainMesh::new(&gpu_context, world_geometry_manager.generate_with_lods(chunk_lods.lods()))
+                .ok_or(RenderContextError::ResourceCreationError("terrain mesh"))?;
 
         // Load the vertex and fragment shaders.
-        let vs_module = gpu_context.create_shader_module_from_bytes(include_bytes!("../../shaders/shader.vert.spv"));
-        let fs_module = gpu_context.create_shader_module_from_bytes(include_bytes!("../../shaders/shader.frag.spv"));
+        let vs_module = gpu_context.create_shader_module_from_bytes(TERRAIN_VS_SPIRV);
+        let fs_module = gpu_context.create_shader_module_from_bytes(TERRAIN_FS_SPIRV);
+        let gbuffer_fs_module = gpu_context.create_shader_module_from_bytes(TERRAIN_GBUFFER_FS_SPIRV);
 
-        // Create our texture and write it into a GPU buffer. Right now the texture is just a white image, but the
-        // infrastructure is already in place to make better use of this data.
-        let size = 64u32;
-        let texels = utils::load_image_bytes("texture.png");
-        let texture_extent = wgpu::Extent3d {
-            width: size,
-            height: size,
-            depth: 1,
-        };
-        let texture = gpu_context.create_texture(&wgpu::TextureDescriptor {
-            size: texture_extent,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            label: None,
-        });
-        let texture_view = texture.create_default_view();
-        // Place the texture data into a temporary copy buffer, and then immediately request a copy of it into a texture
-        // buffer on the GPU. We wrap this in a lexical scope to avoid reusing `temp_buf`.
-        gpu_context.queue().write_texture(
-            wgpu::TextureCopyView {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &texels,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * size,
-                rows_per_image: 0,
-            },
-            texture_extent,
+        // Create our texture and write the atlas into it.
+        let mut mipmap_generator = crate::postprocess::MipmapGenerator::new(&gpu_context);
+        let (texture, texture_view, mip_count) = create_terrain_texture(
+            &gpu_context,
+            &mut init_encoder,
+            &mut mipmap_generator,
+            &atlas.image,
+            atlas.layout,
+            true,
         );
 
         let (sc_width, sc_height) = gpu_context.size();
@@ -106,16 +491,9 @@ impl RenderContext {
             height: sc_height,
             depth: 1,
         };
-        let depth_buffer = gpu_context.create_texture(&wgpu::TextureDescriptor {
-            size: depth_buffer_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_SRC,
-            label: None,
-        });
-        let depth_buffer_view = depth_buffer.create_default_view();
+        let (depth_buffer, depth_buffer_view) = gpu_context
+            .create_depth_texture(sc_width, sc_height, SAMPLE_COUNT)
+            .map_err(RenderContextError::GpuContextError)?;
 
         // Create the samplers.
         let depth_buffer_sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
@@ -132,312 +510,2434 @@ impl RenderContext {
             ..Default::default()
         });
 
-        let texture_sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let sampler_config = SamplerConfig::default();
+        let texture_sampler = create_texture_sampler(&gpu_context, &sampler_config, mip_count);
 
         // Create the camera and initialize it with sane defaults.
         let aspect_ratio = gpu_context.aspect_ratio();
         // This needs to be mutable because the camera has a matrix cache.
         // TODO: Can this be fixed? RefCell? Do we need an Arc? :(
         let mut camera = camera::Camera::new(
-            // Start out at a nice vantage point looking toward the origin.
-            cgmath::Point3::new(32.0, 32.0, 32.0),
-            cgmath::Vector3::new(-1.0, -1.0, -1.0),
+            state.camera_position.into(),
+            cgmath::Vector3::new(1.0, 0.0, 0.0),
             cgmath::Vector3::new(0.0, 0.0, 1.0),
             aspect_ratio,
             70.0,
             0.5,
             1000.0,
         );
-        let camera_matrix: crate::utils::Matrix4 = camera.matrix().into();
-
+        camera.set_yaw_pitch(cgmath::Rad(state.camera_yaw), cgmath::Rad(state.camera_pitch));
         // Create the GPU buffer where we will store our shader uniforms.
-        let uniform_buf = crate::managed_buffer::ManagedBuffer::new_uniform_buf_with_data(
+        let uniform_buf = crate::gpu::UniformBuffer::new(&gpu_context, &camera.uniforms());
+
+        // Create the buffer holding the parameters used to texture the terrain.
+        let terrain_uniforms = TerrainUniforms {
+            atlas_dims: [atlas.layout.tile_cols as f32, atlas.layout.tile_rows as f32],
+            blend_sharpness: DEFAULT_TRIPLANAR_BLEND_SHARPNESS,
+            tiling_scale: 1.0,
+            sea_level: state.sea_level as i32 as f32,
+            _pad: [0.0; 3],
+        };
+        let terrain_uniform_buf = crate::managed_buffer::ManagedBuffer::new_uniform_buf_with_data(
             &gpu_context,
-            camera_matrix,
-        ).ok()?;
+            [terrain_uniforms],
+        ).map_err(|_| RenderContextError::ResourceCreationError("terrain uniform buffer"))?;
 
-        // Set up our bind groups; this binds our data to named locations which are referenced in the shaders.
-        let bind_group_layout = gpu_context.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: None,
-            bindings: &[
-                // Our 0th bind group is for small global data shared between all invocations of the shader. Currently,
-                // this is the camera matrix. We set this bind group only once per frame
-                wgpu::BindGroupLayoutEntry::new(
-                    0,
-                    wgpu::ShaderStage::VERTEX,
-                    wgpu::BindingType::UniformBuffer {
-                        dynamic: false,
-                        min_binding_size: wgpu::BufferSize::new(64),
-                    },
-                ),
-                // Our 1st bind group is for texture data, which will be passed as an atlas. This may change a few times
-                // per frame if we need to render from multiple atlases. TODO: are texture atlases the right way to do
-                // this?
-                wgpu::BindGroupLayoutEntry::new(
-                    1,
-                    wgpu::ShaderStage::FRAGMENT,
-                    wgpu::BindingType::SampledTexture {
-                        multisampled: false,
-                        component_type: wgpu::TextureComponentType::Float,
-                        dimension: wgpu::TextureViewDimension::D2,
-                    },
-                ),
-                // Our 2nd bind group is the sampler for the above texture. This is likely to change only when the
-                // texture changes. TODO: is this true?
-                wgpu::BindGroupLayoutEntry::new(
-                    2,
-                    wgpu::ShaderStage::FRAGMENT,
-                    wgpu::BindingType::Sampler { comparison: false },
-                ),
-                // Our last bind group is a per object buffer, holding any data needed for an individual abstract object
-                // being rendered. This might be a transform matrix, for instance.
-                wgpu::BindGroupLayoutEntry::new(
-                    3,
-                    wgpu::ShaderStage::VERTEX,
-                    wgpu::BindingType::UniformBuffer {
-                        dynamic: true,
-                        min_binding_size: wgpu::BufferSize::new(256),
-                    },
-                ),
-            ],
-        });
+        let scene_lights = lights::SceneLights::new(&gpu_context);
+
+        let pbr_material = PbrMaterial::default();
+        let pbr_uniform_buf = crate::gpu::UniformBuffer::<material::PbrUniforms>::new(&gpu_context, &pbr_material.into());
 
-        let bind_group = gpu_context.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            bindings: &[
-                wgpu::Binding {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(uniform_buf.slice(..)),
-                },
-                wgpu::Binding {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::Binding {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&texture_sampler),
-                },
-                wgpu::Binding {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Buffer(world_geometry_manager.transforms_buf.slice(..)),
-                },
-            ],
+        // Nothing is reflected until an environment map is loaded.
+        let environment_map = environment_map::create_default_environment_map(&gpu_context);
+        let environment_map_view = environment_map::create_cube_view(&environment_map);
+        let environment_sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
             label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
         });
 
+        let shared_bindings = SharedBindings {
+            texture_view: &texture_view,
+            texture_sampler: &texture_sampler,
+            transforms_buf: terrain_mesh.transforms_buf(),
+            terrain_uniform_buf: terrain_uniform_buf.slice(..),
+            light_buf: scene_lights.slice(),
+            environment_map_view: &environment_map_view,
+            environment_sampler: &environment_sampler,
+            pbr_uniform_buf: pbr_uniform_buf.slice(),
+        };
+        // Set up our bind groups; this binds our data to named locations which are referenced in the shaders.
+        let (bind_group_layout, bind_group) =
+            main_bind_group_builder(uniform_buf.slice(), &shared_bindings).build(&gpu_context);
+
+        // The time uniform lives in a bind group of its own, since it changes every frame regardless
+        // of what else does.
+        let time_uniform = TimeUniform::default();
+        let time_uniform_buf = crate::gpu::UniformBuffer::new(&gpu_context, &time_uniform);
+        let (time_bind_group_layout, time_bind_group) = crate::bind_group_builder::BindGroupBuilder::new()
+            .add_uniform_buffer(
+                0,
+                wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                time_uniform_buf.slice(),
+                std::mem::size_of::<TimeUniform>() as u64,
+            )
+            .build(&gpu_context);
+
         // Set up our central render pipeline.
         let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &time_bind_group_layout],
+            push_constant_ranges: &[],
         });
 
-        let render_pipeline = gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            layout: &pipeline_layout,
-            vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module,
-                entry_point: "main",
-            },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module,
-                entry_point: "main",
-            }),
-            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Ccw,
+        let shader_hash = hash_shaders(&[TERRAIN_VS_SPIRV, TERRAIN_FS_SPIRV, TERRAIN_GBUFFER_FS_SPIRV]);
+        let mut pipeline_cache = pipeline_cache::PipelineCache::new(PIPELINE_EVICTION_FRAMES);
+        // Create the default variant now, so that the first frame does not stall on it.
+        pipeline_cache.get_or_create(
+            pipeline_cache::PipelineKey {
+                wireframe: false,
+                topology: TopologyMode::List,
                 cull_mode: wgpu::CullMode::Back,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-            }),
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                color_blend: wgpu::BlendDescriptor {
-                    src_factor: wgpu::BlendFactor::SrcAlpha,
-                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                    operation: wgpu::BlendOperation::Add,
-                },
-                alpha_blend: wgpu::BlendDescriptor {
-                    src_factor: wgpu::BlendFactor::One,
-                    dst_factor: wgpu::BlendFactor::One,
-                    operation: wgpu::BlendOperation::Add,
-                },
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_read_mask: 0,
-                stencil_write_mask: 0,
-            }),
-            vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16,
-                vertex_buffers: &[wgpu::VertexBufferDescriptor {
-                    stride: utils::IVERTEX_SIZE as wgpu::BufferAddress,
-                    step_mode: wgpu::InputStepMode::Vertex,
-                    attributes: &[
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Int3,
-                            offset: 0,
-                            shader_location: 0,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Int3,
-                            offset: 4*3,
-                            shader_location: 1,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float2,
-                            offset: 4*3 + 4*3,
-                            shader_location: 2,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Uint,
-                            offset: 4*3 + 4*3 + 4*2,
-                            shader_location: 3,
-                        },
-                    ],
-                }],
+                wireframe_depth_bias: 0,
+                wireframe_depth_bias_slope_scale: 0,
+                sample_count: SAMPLE_COUNT,
+                deferred: false,
+                shader_hash,
             },
+            |key| {
+                create_terrain_pipeline(&gpu_context, &pipeline_layout, &vs_module, &fs_module, &gbuffer_fs_module, key)
+            },
+        );
 
-            sample_count: 1,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        });
+        let instanced_pipeline =
+            instanced::InstancedPipelines::new(&gpu_context, &bind_group_layout, BlendMode::Opaque);
+        let transparent_instanced_pipeline =
+            instanced::InstancedPipelines::new(&gpu_context, &bind_group_layout, BlendMode::AlphaBlend);
+
+        let billboards = billboard::BillboardRenderer::new(&gpu_context, &mut camera, BILLBOARD_SPRITES)
+            .ok_or(RenderContextError::ResourceCreationError("billboard renderer"))?;
+        let particle_renderer = particles::ParticleRenderer::new(&gpu_context, &mut camera)
+            .ok_or(RenderContextError::ResourceCreationError("particle renderer"))?;
+
+        let minimap = minimap::Minimap::new(
+            &gpu_context,
+            world_geometry_manager.extent(),
+            &bind_group_layout,
+            &shared_bindings,
+        ).ok_or(RenderContextError::ResourceCreationError("minimap"))?;
+
+        let outline = outline::OutlineRenderer::new(&gpu_context, &bind_group_layout);
+
+        let ui = ui::UiRenderer::new(&gpu_context);
+
+        Self::log_gpu_info(&gpu_context);
 
         // Flush the initialization commands on the command queue.
         gpu_context.queue().submit(Some(init_encoder.finish()));
 
-        let next_frame_encoder =
-            gpu_context.create_command_encoder();
+        let next_frame_recorder = CommandRecorder::new(&gpu_context);
 
-        Some(Self {
+        let mut render_context = Self {
             gpu_context,
-            next_frame_encoder,
-            world_geometry_manager,
+            next_frame_recorder,
+            scene: scene::Scene::new(camera, world_geometry_manager, terrain_mesh),
             vs_module,
             fs_module,
+            gbuffer_fs_module,
             texture,
             texture_view,
             texture_sampler,
+            texture_mip_count: mip_count,
+            sampler_config,
+            mipmap_generator,
             depth_buffer,
+            depth_buffer_size,
             depth_buffer_view,
             depth_buffer_sampler,
-            camera,
+            depth_copy: None,
+            occlusion: None,
+            render_scale: 1.0,
+            scaled_target: None,
+            render_path: RenderPath::default(),
+            deferred: None,
             uniform_buf,
+            terrain_uniforms,
+            terrain_uniform_buf,
+            scene_lights,
+            environment_map,
+            environment_map_view,
+            environment_sampler,
+            pbr_material,
+            pbr_uniform_buf,
             bind_group_layout,
             bind_group,
             pipeline_layout,
-            render_pipeline,
+            pipeline_cache,
+            wireframe: false,
+            topology: TopologyMode::List,
+            wireframe_depth_bias: DEFAULT_WIREFRAME_DEPTH_BIAS,
+            cull_mode: wgpu::CullMode::Back,
+            shader_hash,
+            instanced_pipeline,
+            transparent_instanced_pipeline,
+            transparent_blend_mode: BlendMode::AlphaBlend,
+
+            frame_schedule: None,
+            billboards,
+            particle_renderer,
+            minimap,
+            outline,
+            terrain_grid: state.terrain_grid.clone(),
+            terrain_grid_extractor: crate::voxel::MeshExtractor::default(),
+            ui,
             camera_dirty: false,
-        })
+            terrain_dirty: false,
+            chunk_lods,
+            lod_bias: 0.0,
+            voronoi_regions: None,
+            terrain_noise: None,
+            // Nothing has been rendered yet, so the first frame must not be skipped.
+            repaint_requested: true,
+            event_driven: false,
+            window_focused: true,
+            unfocused_sleep_ms: DEFAULT_UNFOCUSED_SLEEP_MS,
+            scale_factor: 1.0,
+            autosave: state.autosave,
+            frame_timer: crate::frame_timer::FrameTimer::new(FRAME_TIMER_CAPACITY),
+            start_time: std::time::Instant::now(),
+            frame_index: 0,
+            time_uniform,
+            time_uniform_buf,
+            time_bind_group,
+            fov_transition: None,
+            viewport: None,
+            scissor_rect: None,
+            // This is the last step of initialization.
+            is_ready: true,
+        };
+        render_context.remesh_terrain_grid();
+        Ok(render_context)
+    }
+
+    /// Whether initialization has completed, so that frames can be rendered. Callers driving
+    /// `create` from another thread should skip frames until this returns `true`.
+    pub fn is_ready(&self) -> bool {
+        self.is_ready
     }
 
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
         // Update our GPU context with the new width and height.
         self.gpu_context.resize(size);
+        self.resize_render_targets();
 
         // Our aspect ratio might have changed, so we update our camera.
-        self.camera.set_aspect_ratio(self.gpu_context.aspect_ratio());
+        self.update_aspect_ratio();
+        self.force_redraw_next_frame();
     }
 
-    pub fn render(&mut self) {
-        let frame = self.gpu_context.get_next_frame().unwrap();
+    /// The size the scene is rendered at: the size of the swap chain, scaled by `render_scale`.
+    fn render_size(&self) -> (u32, u32) {
+        let (width, height) = self.gpu_context.size();
+        render_scale::scaled_size(width, height, self.render_scale)
+    }
 
-        // If the camera moved, we have to write the camera's data into the uniform buffer. We write
-        // the data into the CPU side of our managed uniform buffer here.
-        if self.camera_dirty {
-            self.uniform_buf.replace_data(self.camera.matrix().into());
+    /// Recreates the depth buffer, the scaled target, and the G-buffer, if any, at `render_size`.
+    fn resize_render_targets(&mut self) {
+        // A minimized window has no area. The old targets are kept until it is restored.
+        let (window_width, window_height) = self.gpu_context.size();
+        if window_width == 0 || window_height == 0 {
+            return;
+        }
+        let (width, height) = self.render_size();
+        if let Some(scaled_target) = self.scaled_target.as_mut() {
+            scaled_target.resize(&self.gpu_context, width, height);
+        }
+        if let Some(deferred) = self.deferred.as_mut() {
+            let inputs = deferred::LightingInputs {
+                camera: self.uniform_buf.slice(),
+                lights: self.scene_lights.slice(),
+                environment_map_view: &self.environment_map_view,
+                environment_sampler: &self.environment_sampler,
+            };
+            deferred.resize(&self.gpu_context, width, height, &inputs);
         }
 
-        // This looks weird, but picture the future: a loop over some collection of buffers,
-        // potentially flushing each one.
-        if self.world_geometry_manager.vertex_buf.dirty() {
-            self.world_geometry_manager.vertex_buf.enqueue_copy_command(&self.gpu_context, &mut self.next_frame_encoder);
-        }
-        if self.world_geometry_manager.index_buf.dirty() {
-            self.world_geometry_manager.index_buf.enqueue_copy_command(&self.gpu_context, &mut self.next_frame_encoder);
-        }
-        if self.uniform_buf.dirty() {
-            self.uniform_buf.enqueue_copy_command(&self.gpu_context, &mut self.next_frame_encoder);
-        }
-
-
-        {
-            let mut render_pass = self.next_frame_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.output.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    }
-                }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: &self.depth_buffer_view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(0),
-                        store: true,
-                    }),
-                }),
+        // The depth buffer has to match the target the scene is rendered into.
+        match self.gpu_context.create_depth_texture(width, height, SAMPLE_COUNT) {
+            Ok((depth_buffer, depth_buffer_view)) => {
+                self.depth_buffer = depth_buffer;
+                self.depth_buffer_view = depth_buffer_view;
+                self.depth_buffer_size = wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                };
+                // A copy of the old depth buffer would no longer line up with the frame.
+                self.depth_copy = None;
+                if self.occlusion.is_some() {
+                    self.occlusion = Some(self.create_occlusion());
+                }
+            },
+            Err(e) => log::warn!("Keeping the previous depth buffer: {}", e),
+        }
+    }
+
+    /// Renders the scene at `factor` times the window's resolution, then stretches it over the
+    /// window with bilinear filtering: above 1 this supersamples, and below 1 it trades sharpness
+    /// for speed. `factor` is clamped to `0.25..=4.0`. The minimap and UI are always drawn at the
+    /// window's resolution.
+    #[allow(dead_code)]
+    pub fn set_render_scale(&mut self, factor: f32) {
+        let factor = factor.max(render_scale::MIN_RENDER_SCALE).min(render_scale::MAX_RENDER_SCALE);
+        if factor == self.render_scale {
+            return;
+        }
+        self.render_scale = factor;
+        if factor == 1.0 {
+            self.scaled_target = None;
+        } else if self.scaled_target.is_none() {
+            let (width, height) = self.render_size();
+            self.scaled_target = Some(render_scale::ScaledTarget::new(&self.gpu_context, width, height));
+        }
+        self.resize_render_targets();
+        self.force_redraw_next_frame();
+    }
+
+    /// The resolution the scene is rendered at, relative to the window. See `set_render_scale`.
+    #[allow(dead_code)]
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Selects how the scene is shaded. The deferred path draws opaque geometry into a G-buffer and
+    /// lights it in a separate fullscreen pass; transparent objects, billboards, and particles are
+    /// drawn forward over the result. The minimap, and frames rendered with a caller-provided depth
+    /// buffer, always use the forward path.
+    #[allow(dead_code)]
+    pub fn set_render_path(&mut self, path: RenderPath) {
+        if path == self.render_path {
+            return;
+        }
+        self.render_path = path;
+        self.deferred = match path {
+            RenderPath::Forward => None,
+            RenderPath::Deferred => {
+                let (width, height) = self.render_size();
+                let inputs = deferred::LightingInputs {
+                    camera: self.uniform_buf.slice(),
+                    lights: self.scene_lights.slice(),
+                    environment_map_view: &self.environment_map_view,
+                    environment_sampler: &self.environment_sampler,
+                };
+                Some(deferred::DeferredRenderer::new(
+                    &self.gpu_context,
+                    &self.bind_group_layout,
+                    width,
+                    height,
+                    &inputs,
+                ))
+            },
+        };
+        self.force_redraw_next_frame();
+    }
+
+    /// How the scene is shaded. See `set_render_path`.
+    #[allow(dead_code)]
+    pub fn render_path(&self) -> RenderPath {
+        self.render_path
+    }
+
+    /// Matches the camera's aspect ratio to the region of the window the scene is drawn into.
+    fn update_aspect_ratio(&mut self) {
+        let aspect_ratio = match self.viewport {
+            Some((_, _, width, height)) => width / height,
+            None => self.gpu_context.aspect_ratio(),
+        };
+        self.scene.camera.set_aspect_ratio(aspect_ratio);
+        self.set_camera_dirty();
+    }
+
+    /// Draws the scene into the `(x, y, width, height)` rectangle of the window, in pixels, rather
+    /// than the whole window. The camera's aspect ratio follows the rectangle, and drawing is
+    /// clipped to it. Fails, leaving the viewport unchanged, unless the rectangle has a positive
+    /// width and height.
+    #[allow(dead_code)]
+    pub fn set_viewport(&mut self, x: f32, y: f32, width: f32, height: f32) -> Result<(), RenderContextError> {
+        // Written so that NaNs are rejected too.
+        if !(width > 0.0 && height > 0.0) {
+            return Err(RenderContextError::InvalidViewportError { width, height });
+        }
+        self.viewport = Some((x, y, width, height));
+        self.update_aspect_ratio();
+        Ok(())
+    }
+
+    /// Moves the camera's near and far clip planes to `near` and `far` units away. Small terrain is
+    /// best served by tight planes (e.g. 0.1 and 100), which keep depth precision high; large
+    /// terrain may need a far plane of 10000 or more. Fails unless `0 < near < far`.
+    #[allow(dead_code)]
+    pub fn set_near_far_clip(&mut self, near: f32, far: f32) -> Result<(), RenderContextError> {
+        // Written so that NaNs are rejected too.
+        if !(near > 0.0 && far > near) {
+            return Err(RenderContextError::InvalidClipPlanesError { near, far });
+        }
+        self.camera_mut().set_clip_planes(near, far);
+        Ok(())
+    }
+
+    /// Sets the camera's vertical field of view to `degrees` at once, cancelling any transition in
+    /// progress. The field of view is clamped to [`MIN_FOV`, `MAX_FOV`].
+    #[allow(dead_code)]
+    pub fn set_fov(&mut self, degrees: f32) {
+        self.set_fov_target(degrees, 0.0);
+    }
+
+    /// Eases the camera's vertical field of view from its current value to `target_degrees` over
+    /// the next `duration_secs` seconds of frames, e.g. to zoom in smoothly. The target is clamped
+    /// to [`MIN_FOV`, `MAX_FOV`].
+    #[allow(dead_code)]
+    pub fn set_fov_target(&mut self, target_degrees: f32, duration_secs: f32) {
+        let target = target_degrees.max(MIN_FOV).min(MAX_FOV);
+        if duration_secs <= 0.0 {
+            self.fov_transition = None;
+            self.camera_mut().set_fovy(target);
+        } else {
+            self.fov_transition = Some(FovTransition {
+                from: self.scene.camera.fovy(),
+                to: target,
+                duration: duration_secs,
+                elapsed: 0.0,
             });
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_index_buffer(self.world_geometry_manager.index_buf.slice(..));
-            render_pass.set_vertex_buffer(0, self.world_geometry_manager.vertex_buf.slice(..));
-            for i in 0..self.world_geometry_manager.chunks.len() as u32 {
-                let chunk = &self.world_geometry_manager.chunks[i as usize];
-                render_pass.set_bind_group(
-                    0,
-                    &self.bind_group,
-                    &[(chunk.transform_index * self.world_geometry_manager.transforms_buf.t_size()) as u32],
-                );
-                render_pass.draw_indexed(chunk.index_offset as u32..(chunk.index_offset + chunk.index_count) as u32, chunk.vertex_offset as i32, 0..1);
+        }
+    }
+
+    /// Draws the scene into the whole window again.
+    #[allow(dead_code)]
+    pub fn clear_viewport(&mut self) {
+        self.viewport = None;
+        self.update_aspect_ratio();
+    }
+
+    /// Clips drawing in the next frame to the `(x, y, width, height)` rectangle of the window, in
+    /// pixels, independently of the viewport. The rectangle is reset after every frame, so it has to
+    /// be set again for each frame that should be clipped.
+    #[allow(dead_code)]
+    pub fn set_scissor_rect(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        self.scissor_rect = Some((x, y, width, height));
+    }
+
+    /// Stops clipping drawing in the next frame, other than to the viewport.
+    #[allow(dead_code)]
+    pub fn clear_scissor_rect(&mut self) {
+        self.scissor_rect = None;
+    }
+
+    /// The `(x, y, width, height)` rectangle of the window, in pixels, that the scene is drawn into:
+    /// the viewport if one is set, and otherwise the whole window.
+    fn viewport_rect(&self) -> (f32, f32, f32, f32) {
+        self.viewport.unwrap_or_else(|| {
+            let (width, height) = self.gpu_context.size();
+            (0.0, 0.0, width as f32, height as f32)
+        })
+    }
+
+    /// Projects `world_pos` into the window, returning its position in pixels from the top left
+    /// corner. The position may lie outside the window if the point is out of view to the side.
+    /// Returns `None` if the point is behind the camera.
+    #[allow(dead_code)]
+    pub fn world_to_screen(&self, world_pos: cgmath::Point3<f32>) -> Option<cgmath::Vector2<f32>> {
+        use cgmath::EuclideanSpace;
+
+        let camera = &self.scene.camera;
+        let clip = camera.projection_matrix() * camera.view_matrix() * world_pos.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let (ndc_x, ndc_y) = (clip.x / clip.w, clip.y / clip.w);
+        // Normalized device coordinates point y up, but pixel rows count down from the top.
+        let (x, y, width, height) = self.viewport_rect();
+        Some(cgmath::Vector2::new(
+            x + (ndc_x + 1.0) * 0.5 * width,
+            y + (1.0 - ndc_y) * 0.5 * height,
+        ))
+    }
+
+    /// The ray from the camera through `pixel`, measured in pixels from the top left corner of the
+    /// window, as its origin (the camera's position) and normalized direction. This inverts
+    /// `world_to_screen` for a perspective camera.
+    #[allow(dead_code)]
+    pub fn screen_to_world_ray(&self, pixel: cgmath::Vector2<f32>) -> (cgmath::Point3<f32>, cgmath::Vector3<f32>) {
+        use cgmath::{InnerSpace, SquareMatrix};
+
+        let camera = &self.scene.camera;
+        let (x, y, width, height) = self.viewport_rect();
+        let ndc_x = (pixel.x - x) / width * 2.0 - 1.0;
+        let ndc_y = 1.0 - (pixel.y - y) / height * 2.0;
+        let inverse = match (camera.projection_matrix() * camera.view_matrix()).invert() {
+            Some(inverse) => inverse,
+            None => return (camera.position(), camera.view()),
+        };
+        // Unproject the points on the near (z = 0) and far (z = 1) planes under the pixel.
+        let unproject = |z: f32| {
+            let world = inverse * cgmath::Vector4::new(ndc_x, ndc_y, z, 1.0);
+            world.truncate() / world.w
+        };
+        (camera.position(), (unproject(1.0) - unproject(0.0)).normalize())
+    }
+
+    /// The voxel terrain, if any. See `set_terrain_grid`.
+    #[allow(dead_code)]
+    pub fn terrain_grid(&self) -> Option<&crate::voxel::VoxelGrid<bool>> {
+        self.terrain_grid.as_ref()
+    }
+
+    /// Replaces the voxel terrain, which is drawn alongside the heightmap terrain with its
+    /// `(0, 0, 0)` corner at the world origin, and edited with `add_voxel` and `remove_voxel`.
+    /// `None` removes it.
+    pub fn set_terrain_grid(&mut self, grid: Option<crate::voxel::VoxelGrid<bool>>) {
+        self.terrain_grid = grid;
+        self.remesh_terrain_grid();
+    }
+
+    /// Fills the voxel at `pos` in the voxel terrain. Returns `None` if there is no voxel terrain or
+    /// `pos` lies outside it. Only the chunks of the voxel terrain around `pos` are remeshed; the
+    /// heightmap terrain is not regenerated.
+    #[allow(dead_code)]
+    pub fn add_voxel(&mut self, pos: (u32, u32, u32)) -> Option<()> {
+        self.set_voxel(pos, true)
+    }
+
+    /// Empties the voxel at `pos` in the voxel terrain. See `add_voxel`.
+    #[allow(dead_code)]
+    pub fn remove_voxel(&mut self, pos: (u32, u32, u32)) -> Option<()> {
+        self.set_voxel(pos, false)
+    }
+
+    fn set_voxel(&mut self, (x, y, z): (u32, u32, u32), filled: bool) -> Option<()> {
+        let grid = self.terrain_grid.as_mut()?;
+        if !grid.in_bounds(x, y, z) {
+            return None;
+        }
+        if grid.get(x, y, z) != filled {
+            grid.set(x, y, z, filled);
+            if let Some(voxel_terrain) = self.scene.voxel_terrain.as_mut() {
+                voxel_terrain.remesh_around(&self.gpu_context, grid, self.terrain_grid_extractor, (x, y, z));
             }
+            self.force_redraw_next_frame();
         }
+        Some(())
+    }
 
-        // Pull out the command encoder we have been using to build up this frame. We set up the next frame's encoder
-        // at the same time.
-        let final_encoder = std::mem::replace(
-            &mut self.next_frame_encoder,
-            self.gpu_context.create_command_encoder(),
-        );
+    #[allow(dead_code)]
+    pub fn terrain_grid_extractor(&self) -> crate::voxel::MeshExtractor {
+        self.terrain_grid_extractor
+    }
 
-        self.gpu_context.submit_command_encoder(final_encoder);
+    /// Selects how the voxel terrain is meshed, remeshing it if it changed.
+    #[allow(dead_code)]
+    pub fn set_terrain_grid_extractor(&mut self, extractor: crate::voxel::MeshExtractor) {
+        if self.terrain_grid_extractor != extractor {
+            self.terrain_grid_extractor = extractor;
+            self.remesh_terrain_grid();
+        }
     }
 
-    // Expose raw mutation for some of the basic state variables.
-    fn set_camera_dirty(&mut self) {
-        self.camera_dirty = true;
+    /// Rebuilds every chunk of `scene.voxel_terrain` from `terrain_grid`.
+    fn remesh_terrain_grid(&mut self) {
+        let extractor = self.terrain_grid_extractor;
+        let gpu_context = &self.gpu_context;
+        self.scene.voxel_terrain = self
+            .terrain_grid
+            .as_ref()
+            .map(|grid| voxel_terrain::VoxelTerrainMesh::new(gpu_context, grid, extractor));
+        self.force_redraw_next_frame();
     }
 
+    /// Finds the first filled voxel of `grid` along the ray from `ray_origin` in direction
+    /// `ray_dir`, along with the face it was hit on. The grid is taken to be meshed with its
+    /// `(0, 0, 0)` corner at the world origin and one world unit per voxel, as
+    /// `utils::create_vertices_from_voxel_grid` does given the origin as its `min`.
     #[allow(dead_code)]
-    pub fn camera(&self) -> &camera::Camera {
-        &self.camera
+    pub fn pick_voxel(
+        &self,
+        ray_origin: cgmath::Point3<f32>,
+        ray_dir: cgmath::Vector3<f32>,
+        grid: &crate::voxel::VoxelGrid<bool>,
+    ) -> Option<crate::voxel::VoxelHit> {
+        grid.raycast(ray_origin, ray_dir)
     }
-    pub fn camera_mut(&mut self) -> &mut camera::Camera {
-        // This is aggressive; we set the camera to dirty any time someone takes a mutable reference
-        // to the camera; they do not have to mutate the camera. This is a negligible performance
-        // hit.
-        self.set_camera_dirty();
-        &mut self.camera
+
+    /// Finds the first filled voxel of `grid` under the pixel `(px, py)`, measured from the top left
+    /// corner of the window. See `pick_voxel`.
+    #[allow(dead_code)]
+    pub fn pick_voxel_from_screen(
+        &self,
+        px: f32,
+        py: f32,
+        grid: &crate::voxel::VoxelGrid<bool>,
+    ) -> Option<crate::voxel::VoxelHit> {
+        let (origin, dir) = self.screen_to_world_ray(cgmath::Vector2::new(px, py));
+        self.pick_voxel(origin, dir, grid)
+    }
+
+    /// The viewport and scissor rectangle for a target `scale` times the size of the window. Both
+    /// are specified in window pixels, so they are scaled along with the target.
+    fn view_region(&self, scale: f32) -> scene::ViewRegion {
+        let (window_width, window_height) = self.gpu_context.size();
+        let (target_width, target_height) = render_scale::scaled_size(window_width, window_height, scale);
+        let viewport = self
+            .viewport
+            .map(|(x, y, width, height)| (x * scale, y * scale, width * scale, height * scale));
+        let scale_px = |px: u32| (px as f32 * scale) as u32;
+        let scissor_rect = self
+            .scissor_rect
+            .map(|(x, y, width, height)| (scale_px(x), scale_px(y), scale_px(width), scale_px(height)));
+        let scissor_rect = match (scissor_rect, viewport) {
+            (Some((x, y, width, height)), _) => {
+                let x0 = x.min(target_width);
+                let y0 = y.min(target_height);
+                let x1 = x.saturating_add(width).min(target_width);
+                let y1 = y.saturating_add(height).min(target_height);
+                Some((x0, y0, x1 - x0, y1 - y0))
+            },
+            (None, Some((x, y, width, height))) => {
+                let x0 = (x.max(0.0) as u32).min(target_width);
+                let y0 = (y.max(0.0) as u32).min(target_height);
+                let x1 = ((x + width).max(0.0).ceil() as u32).min(target_width);
+                let y1 = ((y + height).max(0.0).ceil() as u32).min(target_height);
+                Some((x0, y0, x1.saturating_sub(x0), y1.saturating_sub(y0)))
+            },
+            (None, None) => None,
+        };
+        scene::ViewRegion {
+            viewport,
+            scissor_rect,
+        }
+    }
+
+    /// A counter incremented at the start of every rendered frame, for temporal effects. It never
+    /// wraps; instead it stops at `u64::MAX`.
+    #[allow(dead_code)]
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+
+    /// Restarts the frame index from zero, e.g. so that benchmark runs are repeatable.
+    pub fn reset_frame_index(&mut self) {
+        self.frame_index = 0;
+    }
+
+    /// Timing statistics for recently rendered frames.
+    #[allow(dead_code)]
+    pub fn frame_timer(&self) -> &crate::frame_timer::FrameTimer {
+        &self.frame_timer
+    }
+
+    /// Advances the scene's simulations, such as the particle system, by `delta_secs` seconds.
+    pub fn update(&mut self, delta_secs: f32) {
+        // Voronoi regions are always generated at full detail.
+        let lod_changed_chunks = if self.voronoi_regions.is_none() {
+            self.chunk_lods.update_lod(
+                &self.scene.world_geometry_manager.config(),
+                self.scene.camera.position(),
+                self.lod_bias,
+            )
+        } else {
+            Vec::new()
+        };
+        if self.terrain_dirty {
+            self.terrain_dirty = false;
+            if self.regenerate_mesh().is_none() {
+                log::error!("Failed to upload the regenerated terrain.");
+            }
+            self.force_redraw_next_frame();
+        } else if !lod_changed_chunks.is_empty() {
+            if self.regenerate_chunks(&lod_changed_chunks).is_none() {
+                log::error!("Failed to upload the terrain chunks whose level of detail changed.");
+            }
+            self.force_redraw_next_frame();
+        }
+        self.scene.update(delta_secs);
+    }
+
+    /// Has the next frame rendered, even if event-driven rendering is enabled and nothing in the
+    /// scene has changed. Unlike marking the terrain dirty, this does not regenerate anything.
+    pub fn force_redraw_next_frame(&mut self) {
+        self.repaint_requested = true;
+    }
+
+    /// Only renders frames in which something may have changed: the camera moved, the terrain was
+    /// regenerated, particles are animating, the wireframe or minimap was toggled, or
+    /// `force_redraw_next_frame` was called. Other changes made from outside the render context
+    /// (e.g. to instanced objects) must be followed by `force_redraw_next_frame` to be shown.
+    /// `render` returns straight away for any other frame, without acquiring a swap chain frame.
+    /// Off by default, in which case every call to `render` renders a frame.
+    #[allow(dead_code)]
+    pub fn set_event_driven_rendering(&mut self, event_driven: bool) {
+        self.event_driven = event_driven;
+        self.force_redraw_next_frame();
+    }
+
+    pub fn event_driven_rendering(&self) -> bool {
+        self.event_driven
+    }
+
+    /// Whether the next frame would show anything different from the last.
+    pub fn needs_redraw(&self) -> bool {
+        self.repaint_requested
+            || self.camera_dirty
+            || self.terrain_dirty
+            || self.fov_transition.is_some()
+            || self.scene.particle_system.is_some()
+    }
+
+    pub fn render(&mut self) {
+        if self.event_driven && !self.needs_redraw() {
+            return;
+        }
+        let frame = match self.gpu_context.get_next_frame() {
+            Ok(frame) => frame,
+            Err(e) => {
+                // Even after recovering a lost swap chain, a frame may not be available (e.g. on a
+                // timeout). Skip this frame rather than crash; the next one will try again.
+                log::warn!("Skipping frame: {}", e);
+                return;
+            },
+        };
+        if let Err(e) = self.render_to_view(&frame.output.view, None, true) {
+            log::error!("Failed to render a frame: {}", e);
+        }
+
+        if !self.window_focused {
+            std::thread::sleep(std::time::Duration::from_millis(self.unfocused_sleep_ms));
+        }
+    }
+
+    /// Should be called whenever the window gains or loses focus. See `set_unfocused_sleep_ms`.
+    pub fn on_window_focus_change(&mut self, focused: bool) {
+        self.window_focused = focused;
+        if focused {
+            self.force_redraw_next_frame();
+        }
+    }
+
+    /// Should be called when the window moves to a display with a different scale factor. The
+    /// window's physical size changes along with it, which must be passed on to `resize`.
+    pub fn on_dpi_scale_change(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+        self.force_redraw_next_frame();
+    }
+
+    /// The ratio of physical to logical pixels of the display the window is on.
+    #[allow(dead_code)]
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// Sets how long `render` sleeps after each frame while the window is unfocused, throttling the
+    /// frame rate of a window in the background. Zero disables the throttling.
+    #[allow(dead_code)]
+    pub fn set_unfocused_sleep_ms(&mut self, unfocused_sleep_ms: u64) {
+        self.unfocused_sleep_ms = unfocused_sleep_ms;
+    }
+
+    /// Renders a frame offscreen into `target_view`, rather than into the swap chain, so nothing is
+    /// presented. The caller owns the target texture, which must be `OUTPUT_ATTACHMENT` with the
+    /// swap chain's format. `depth_view`, if given, must be a `Depth24PlusStencil8` view of the same size;
+    /// otherwise the render context's depth buffer is used, and the target must be the size of the
+    /// window. Neither the minimap nor the UI is drawn.
+    #[allow(dead_code)]
+    pub fn render_to_texture(
+        &mut self,
+        target_view: &wgpu::TextureView,
+        depth_view: Option<&wgpu::TextureView>,
+    ) -> Result<(), RenderContextError> {
+        self.render_to_view(target_view, depth_view, false)
+    }
+
+    /// Renders a frame into `color_view`, using `depth_view` as the depth buffer, or the render
+    /// context's own depth buffer if it is `None`; that requires `color_view` to be the size of the
+    /// swap chain, and applies the render scale. The minimap and UI are only overlaid if
+    /// `with_overlays` is set.
+    fn render_to_view(
+        &mut self,
+        color_view: &wgpu::TextureView,
+        depth_view: Option<&wgpu::TextureView>,
+        with_overlays: bool,
+    ) -> Result<(), RenderContextError> {
+        if self.frame_index == FRAME_INDEX_WARNING_THRESHOLD {
+            log::warn!("The frame index is nearing its maximum; it will stop increasing once reached.");
+        }
+        self.frame_index = self.frame_index.saturating_add(1);
+
+        let delta_time = self.frame_timer.tick();
+        self.time_uniform = TimeUniform {
+            time: self.start_time.elapsed().as_secs_f32(),
+            delta_time,
+            frame_index: self.frame_index as u32,
+            frame_index_hi: (self.frame_index >> 32) as u32,
+        };
+        self.time_uniform_buf.update(self.gpu_context.queue(), &self.time_uniform);
+
+        if let Some(transition) = self.fov_transition.as_mut() {
+            let (fov, finished) = transition.advance(delta_time);
+            if finished {
+                self.fov_transition = None;
+            }
+            self.camera_mut().set_fovy(fov);
+        }
+
+        // If the camera moved, we have to write the camera's data into the uniform buffer.
+        if self.camera_dirty {
+            self.uniform_buf.update(self.gpu_context.queue(), &self.scene.camera.uniforms());
+            self.billboards.update_camera(&mut self.scene.camera);
+            self.particle_renderer.update_camera(&mut self.scene.camera);
+        }
+
+
+        // This looks weird, but picture the future: a loop over some collection of buffers,
+        // potentially flushing each one.
+        let uploads = self.next_frame_recorder.encoder();
+        self.scene.enqueue_copy_commands(&mut self.gpu_context, uploads);
+        if self.terrain_uniform_buf.dirty() {
+            self.terrain_uniform_buf.enqueue_copy_command(&mut self.gpu_context, uploads);
+        }
+
+        self.billboards.enqueue_copy_commands(&mut self.gpu_context, uploads);
+        if let Some(particle_system) = self.scene.particle_system.as_ref() {
+            self.particle_renderer.enqueue_copy_commands(&mut self.gpu_context, uploads, particle_system);
+        }
+        self.minimap.enqueue_copy_commands(&mut self.gpu_context, uploads);
+
+        // With a render scale, the scene is drawn into the scaled target and its own depth buffer,
+        // then stretched over `color_view`. A caller-provided depth buffer fixes the resolution, so
+        // the scale does not apply then.
+        let scaled_target = self.scaled_target.as_ref().filter(|_| depth_view.is_none());
+        let scene_view = scaled_target.map_or(color_view, render_scale::ScaledTarget::color_view);
+        let region = self.view_region(if scaled_target.is_some() { self.render_scale } else { 1.0 });
+        // The deferred path draws into the G-buffer, which is the size of the render context's own
+        // depth buffer, so a caller-provided depth buffer falls back to the forward path.
+        let deferred_renderer = self.deferred.as_ref().filter(|_| depth_view.is_none());
+        let key = self.pipeline_key();
+        let gbuffer_key = pipeline_cache::PipelineKey { deferred: true, ..key };
+        let (gpu_context, pipeline_layout) = (&self.gpu_context, &self.pipeline_layout);
+        let (vs_module, fs_module, gbuffer_fs_module) = (&self.vs_module, &self.fs_module, &self.gbuffer_fs_module);
+        let create = |key: &pipeline_cache::PipelineKey| {
+            create_terrain_pipeline(gpu_context, pipeline_layout, vs_module, fs_module, gbuffer_fs_module, key)
+        };
+        // The forward variant is needed even on the deferred path, for the minimap.
+        self.pipeline_cache.get_or_create(key, create);
+        if deferred_renderer.is_some() {
+            self.pipeline_cache.get_or_create(gbuffer_key, create);
+        }
+        let terrain_pipeline = self.pipeline_cache.get(&key)
+            .ok_or(RenderContextError::ResourceCreationError("terrain pipeline"))?;
+        let pipelines = scene::ScenePipelines {
+            terrain: terrain_pipeline,
+            terrain_wireframe: key.wireframe,
+            terrain_topology: key.topology,
+            instanced: &self.instanced_pipeline,
+            instanced_transparent: &self.transparent_instanced_pipeline,
+            time_bind_group: &self.time_bind_group,
+        };
+
+        let gbuffer_pipelines;
+        let mut graph = frame_graph::FrameGraph::new();
+        graph.import(frame_graph::COLOR, scene_view);
+        graph.import(frame_graph::DEPTH, depth_view.unwrap_or(&self.depth_buffer_view));
+        let (scene, bind_group, pipelines_ref) = (&self.scene, &self.bind_group, &pipelines);
+        if let Some(deferred_renderer) = deferred_renderer {
+            gbuffer_pipelines = scene::ScenePipelines {
+                terrain: self.pipeline_cache.get(&gbuffer_key)
+                    .ok_or(RenderContextError::ResourceCreationError("G-buffer terrain pipeline"))?,
+                instanced: &deferred_renderer.instanced_pipelines,
+                ..pipelines
+            };
+            let gbuffer_pipelines_ref = &gbuffer_pipelines;
+            let gbuffer_views = deferred_renderer.gbuffer.views();
+            for (&resource, &view) in deferred::GBUFFER_RESOURCES.iter().zip(gbuffer_views.iter()) {
+                graph.import(resource, view);
+            }
+            graph.add_pass(frame_graph::PassNode {
+                name: "gbuffer",
+                inputs: vec![],
+                outputs: deferred::GBUFFER_RESOURCES.iter().copied().chain(Some(frame_graph::DEPTH)).collect(),
+                execute: Box::new(move |encoder: &mut wgpu::CommandEncoder, resources: &frame_graph::FrameResources| {
+                    let [position, normal, albedo, material] = deferred::GBUFFER_RESOURCES;
+                    scene.record_gbuffer_commands(
+                        encoder,
+                        gbuffer_pipelines_ref,
+                        bind_group,
+                        [
+                            resources.view(position),
+                            resources.view(normal),
+                            resources.view(albedo),
+                            resources.view(material),
+                        ],
+                        resources.view(frame_graph::DEPTH),
+                        &region,
+                    );
+                }),
+            });
+            let clear_color = scene.clear_color;
+            graph.add_pass(frame_graph::PassNode {
+                name: "lighting",
+                inputs: deferred::GBUFFER_RESOURCES.to_vec(),
+                outputs: vec![frame_graph::COLOR],
+                execute: Box::new(move |encoder: &mut wgpu::CommandEncoder, resources: &frame_graph::FrameResources| {
+                    deferred_renderer.record_lighting(encoder, resources.view(frame_graph::COLOR), clear_color);
+                }),
+            });
+            graph.add_pass(frame_graph::PassNode {
+                name: "transparent",
+                inputs: vec![frame_graph::COLOR, frame_graph::DEPTH],
+                outputs: vec![frame_graph::COLOR, frame_graph::DEPTH],
+                execute: Box::new(move |encoder: &mut wgpu::CommandEncoder, resources: &frame_graph::FrameResources| {
+                    scene.record_transparent_commands(
+                        encoder,
+                        pipelines_ref,
+                        bind_group,
+                        resources.view(frame_graph::COLOR),
+                        resources.view(frame_graph::DEPTH),
+                        &region,
+                    );
+                }),
+            });
+        } else {
+            graph.add_pass(frame_graph::PassNode {
+                name: "terrain",
+                inputs: vec![],
+                outputs: vec![frame_graph::COLOR, frame_graph::DEPTH],
+                execute: Box::new(move |encoder: &mut wgpu::CommandEncoder, resources: &frame_graph::FrameResources| {
+                    scene.record_commands(
+                        encoder,
+                        pipelines_ref,
+                        bind_group,
+                        resources.view(frame_graph::COLOR),
+                        resources.view(frame_graph::DEPTH),
+                        &region,
+                    );
+                }),
+            });
+        }
+        // Billboards are drawn only in the main view, since they are oriented toward its camera.
+        let billboards = &self.billboards;
+        graph.add_pass(frame_graph::PassNode {
+            name: "billboards",
+            inputs: vec![frame_graph::COLOR, frame_graph::DEPTH],
+            outputs: vec![frame_graph::COLOR, frame_graph::DEPTH],
+            execute: Box::new(move |encoder: &mut wgpu::CommandEncoder, resources: &frame_graph::FrameResources| {
+                billboards.record(
+                    encoder,
+                    resources.view(frame_graph::COLOR),
+                    resources.view(frame_graph::DEPTH),
+                    &region,
+                );
+            }),
+        });
+        let graph_config = FrameGraphConfig {
+            particles: self.scene.particle_system.is_some(),
+            deferred: deferred_renderer.is_some(),
+            outline: self.outline.enabled(),
+        };
+        if graph_config.particles {
+            let particle_renderer = &self.particle_renderer;
+            graph.add_pass(frame_graph::PassNode {
+                name: "particles",
+                inputs: vec![frame_graph::COLOR, frame_graph::DEPTH],
+                outputs: vec![frame_graph::COLOR],
+                execute: Box::new(move |encoder: &mut wgpu::CommandEncoder, resources: &frame_graph::FrameResources| {
+                    particle_renderer.record(
+                        encoder,
+                        resources.view(frame_graph::COLOR),
+                        resources.view(frame_graph::DEPTH),
+                        &region,
+                    );
+                }),
+            });
+        }
+        if graph_config.outline {
+            let (outline, chunks_x) = (&self.outline, self.scene.world_geometry_manager.config().chunks_x);
+            graph.add_pass(frame_graph::PassNode {
+                name: "outline",
+                inputs: vec![frame_graph::COLOR, frame_graph::DEPTH],
+                outputs: vec![frame_graph::COLOR, frame_graph::DEPTH],
+                execute: Box::new(move |encoder: &mut wgpu::CommandEncoder, resources: &frame_graph::FrameResources| {
+                    outline.record(
+                        gpu_context,
+                        encoder,
+                        &scene.terrain,
+                        chunks_x,
+                        bind_group,
+                        resources.view(frame_graph::COLOR),
+                        resources.view(frame_graph::DEPTH),
+                        &region,
+                    );
+                }),
+            });
+        }
+
+        // The schedule only depends on which passes are in the graph, so it is only recompiled when
+        // that changes.
+        let schedule = match self.frame_schedule.take() {
+            Some((config, schedule)) if config == graph_config => Ok(schedule),
+            _ => graph.compile(),
+        };
+        let mut scene_recorder = CommandRecorder::new(&self.gpu_context);
+        // A graph which cannot be scheduled draws no scene this frame, but the rest of the frame,
+        // including its cleanup, still happens.
+        let graph_result = match schedule {
+            Ok(schedule) => {
+                graph.execute(scene_recorder.encoder(), &schedule);
+                self.frame_schedule = Some((graph_config, schedule));
+                // The occlusion test reads the render context's own depth buffer, so it only
+                // follows frames drawn into it.
+                if let Some(occlusion) = self.occlusion.as_ref().filter(|_| depth_view.is_none()) {
+                    occlusion.record(&self.gpu_context, scene_recorder.encoder());
+                }
+                if let Some(scaled_target) = scaled_target {
+                    scaled_target.blit(scene_recorder.encoder(), color_view);
+                }
+                Ok(())
+            },
+            Err(err) => {
+                log::error!("Skipping the scene passes, as the frame graph could not be compiled: {}", err);
+                Err(RenderContextError::FrameGraphError(err))
+            },
+        };
+
+        let mut ui_recorder = CommandRecorder::new(&self.gpu_context);
+        if with_overlays {
+            if self.minimap.enabled() {
+                ui_recorder.ui_pass(
+                    &self.minimap,
+                    &self.scene,
+                    &pipelines,
+                    color_view,
+                    self.gpu_context.size(),
+                );
+            }
+            self.render_ui_overlay(ui_recorder.encoder(), color_view);
+        }
+
+        // Pull out the recorder we have been filling with uploads and other work since the last
+        // frame, which must execute before this frame's passes. We set up the next frame's recorder
+        // at the same time.
+        let uploads = std::mem::replace(
+            &mut self.next_frame_recorder,
+            CommandRecorder::new(&self.gpu_context),
+        );
+        self.submit_recorders(vec![uploads, scene_recorder, ui_recorder]);
+        self.pipeline_cache.end_frame();
+        // The scissor rectangle only ever applies to a single frame.
+        self.scissor_rect = None;
+        self.camera_dirty = false;
+        self.repaint_requested = false;
+        graph_result
+    }
+
+    /// Logs the GPU in use, along with the rendering options jvox picked for it, at `info` level.
+    fn log_gpu_info(gpu_context: &crate::gpu::GpuContext) {
+        gpu_context.log_info();
+        log::info!("MSAA sample count: {}", SAMPLE_COUNT);
+    }
+
+    /// Records the pass drawing the most recently flushed UI over `color_view`. Unlike the scene,
+    /// the UI is neither depth tested nor confined to the viewport.
+    fn render_ui_overlay(&self, encoder: &mut wgpu::CommandEncoder, color_view: &wgpu::TextureView) {
+        self.ui.record(encoder, color_view);
+    }
+
+    /// Queues a rectangle to be drawn over the main view with the next `ui_flush`. `x` and `y` are
+    /// the position of its top left corner and `w` and `h` its size, all in pixels from the top left
+    /// of the window. `color` is an RGBA8 color, as packed by `utils::pack_color_rgba8`; it is
+    /// blended over the scene by its alpha.
+    #[allow(dead_code)]
+    pub fn ui_draw_rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: u32) {
+        let (width, height) = self.gpu_context.size();
+        // Pixel rows count down from the top, but normalized device coordinates point y up.
+        let to_ndc = |px: f32, py: f32| [px / width as f32 * 2.0 - 1.0, 1.0 - py / height as f32 * 2.0];
+        self.ui.push_rect(to_ndc(x, y + h), to_ndc(x + w, y), color);
+    }
+
+    /// Replaces the UI drawn over the main view with everything queued since the last flush. The UI
+    /// is drawn every frame until the next flush, so a UI that changes must be queued and flushed
+    /// again each frame. Since positions are converted when queued, the UI should be queued again
+    /// after the window is resized.
+    #[allow(dead_code)]
+    pub fn ui_flush(&mut self) {
+        self.ui.flush(&mut self.gpu_context, self.next_frame_recorder.encoder());
+        self.force_redraw_next_frame();
+    }
+
+    /// Submits the commands in `recorders`, in order, in a single submission.
+    pub fn submit_recorders(&mut self, recorders: Vec<CommandRecorder>) {
+        self.gpu_context.finish_frame(recorders.into_iter().map(CommandRecorder::finish));
+    }
+
+    /// The key of the terrain pipeline variant selected by the current settings.
+    fn pipeline_key(&self) -> pipeline_cache::PipelineKey {
+        let (wireframe_depth_bias, wireframe_depth_bias_slope_scale) = if self.wireframe {
+            self.wireframe_depth_bias
+        } else {
+            (0, 0.0)
+        };
+        pipeline_cache::PipelineKey {
+            wireframe: self.wireframe,
+            topology: self.topology,
+            cull_mode: self.cull_mode,
+            wireframe_depth_bias,
+            wireframe_depth_bias_slope_scale: wireframe_depth_bias_slope_scale.to_bits(),
+            sample_count: SAMPLE_COUNT,
+            deferred: false,
+            shader_hash: self.shader_hash,
+        }
+    }
+
+    /// Returns the terrain pipeline variant selected by the current settings, creating it only if
+    /// it is not already cached.
+    #[allow(dead_code)]
+    pub fn active_pipeline(&mut self) -> &wgpu::RenderPipeline {
+        let key = self.pipeline_key();
+        let (gpu_context, pipeline_layout) = (&self.gpu_context, &self.pipeline_layout);
+        let (vs_module, fs_module, gbuffer_fs_module) = (&self.vs_module, &self.fs_module, &self.gbuffer_fs_module);
+        self.pipeline_cache.get_or_create(key, |key| {
+            create_terrain_pipeline(gpu_context, pipeline_layout, vs_module, fs_module, gbuffer_fs_module, key)
+        })
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    /// Draws the terrain as the edges of its triangles rather than as filled triangles.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        self.wireframe = wireframe;
+        self.force_redraw_next_frame();
+    }
+
+    /// Offsets the depth of the wireframe's lines by `constant` units of depth resolution, plus
+    /// `slope` times the depth slope of the surface being drawn.
+    ///
+    /// Edges lie exactly on the faces they border, so with no bias (the default in wgpu) a line and
+    /// any face drawn in the same place, such as instanced objects resting on the terrain, have
+    /// equal depths and the depth test picks between them arbitrarily from pixel to pixel, which
+    /// shows as flickering, broken lines. Since nearer fragments have smaller depths here, negative
+    /// values pull the lines towards the camera. Depth bias is part of core wgpu, so no feature has
+    /// to be checked for.
+    ///
+    /// Vulkan and Metal apply depth bias only to triangles, and the wireframe is drawn as a line
+    /// list, so on those backends this is a no-op and the flickering remains. Only D3D12 offsets
+    /// lines. Since wireframe mode draws no filled terrain, there is no filled pass to push back
+    /// instead.
+    #[allow(dead_code)]
+    pub fn set_wireframe_depth_bias(&mut self, constant: i32, slope: f32) {
+        self.wireframe_depth_bias = (constant, slope);
+        self.force_redraw_next_frame();
+    }
+
+    #[allow(dead_code)]
+    pub fn topology(&self) -> TopologyMode {
+        self.topology
+    }
+
+    /// Selects whether the filled terrain is drawn from a triangle list or from triangle strips.
+    /// Both draw the same triangles. The terrain's faces share no vertices, so each face is a strip
+    /// of its own, and with the primitive restart between faces strips take five indices per face
+    /// to the list's six.
+    #[allow(dead_code)]
+    pub fn set_topology(&mut self, topology: TopologyMode) {
+        self.topology = topology;
+        self.force_redraw_next_frame();
+    }
+
+    #[allow(dead_code)]
+    pub fn cull_mode(&self) -> wgpu::CullMode {
+        self.cull_mode
+    }
+
+    /// Selects which faces of the terrain are culled: `CullMode::None` shows back faces, which helps
+    /// when debugging, and `CullMode::Front` suits viewing surfaces from below.
+    #[allow(dead_code)]
+    pub fn set_cull_mode(&mut self, cull_mode: wgpu::CullMode) {
+        self.cull_mode = cull_mode;
+        self.force_redraw_next_frame();
+    }
+
+    /// Records a compute pass dispatching `pipeline` over an `x` by `y` by `z` grid of work groups,
+    /// with `bind_group` bound at index 0. The pass is recorded onto the encoder for the next frame,
+    /// so it will execute before any of that frame's render passes.
+    #[allow(dead_code)]
+    pub fn dispatch_compute(
+        &mut self,
+        pipeline: &wgpu::ComputePipeline,
+        bind_group: &wgpu::BindGroup,
+        x: u32,
+        y: u32,
+        z: u32,
+    ) {
+        let encoder = self.next_frame_recorder.encoder();
+        self.gpu_context.dispatch_compute_pass(encoder, pipeline, bind_group, x, y, z);
+    }
+
+    /// Adds an object which is drawn once for every matrix in `instances`, each of which transforms
+    /// `mesh` from model space into world space. Returns an index which can be passed to
+    /// `update_instanced_object`, or `None` if there is nothing to draw.
+    #[allow(dead_code)]
+    pub fn add_instanced_object(
+        &mut self,
+        mesh: &crate::mesh::Mesh,
+        instances: &[cgmath::Matrix4<f32>],
+    ) -> Option<usize> {
+        let instanced_mesh = instanced::InstancedMesh::new(&self.gpu_context, mesh, instances)?;
+        self.scene.instanced_meshes.push(instanced_mesh);
+        Some(self.scene.instanced_meshes.len() - 1)
+    }
+
+    /// Replaces the placement of every instance of a previously added instanced object. The new
+    /// placement takes effect with the next frame.
+    #[allow(dead_code)]
+    pub fn update_instanced_object(
+        &mut self,
+        index: usize,
+        instances: &[cgmath::Matrix4<f32>],
+    ) -> Option<()> {
+        self.scene.instanced_meshes.get_mut(index)?.update_instances(&self.gpu_context, instances)
+    }
+
+    /// Marks a previously added instanced object as transparent or opaque. Transparent objects are
+    /// drawn after opaque ones, blended with the mode set by `set_blend_mode`, from back to front.
+    #[allow(dead_code)]
+    pub fn set_instanced_object_transparent(&mut self, index: usize, transparent: bool) -> Option<()> {
+        self.scene.instanced_meshes.get_mut(index)?.set_transparent(transparent);
+        Some(())
+    }
+
+    #[allow(dead_code)]
+    pub fn blend_mode(&self) -> BlendMode {
+        self.transparent_blend_mode
+    }
+
+    /// Sets how transparent objects blend with what is behind them. Opaque objects are unaffected.
+    #[allow(dead_code)]
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        if blend_mode == self.transparent_blend_mode {
+            return;
+        }
+        self.transparent_blend_mode = blend_mode;
+        self.transparent_instanced_pipeline =
+            instanced::InstancedPipelines::new(&self.gpu_context, &self.bind_group_layout, blend_mode);
+    }
+
+    /// Adds a camera-facing sprite to the scene. The billboard list is re-uploaded with the next
+    /// frame.
+    #[allow(dead_code)]
+    pub fn add_billboard(&mut self, billboard: BillboardInstance) {
+        self.billboards.add(billboard);
+    }
+
+    /// Removes every billboard from the scene.
+    #[allow(dead_code)]
+    pub fn clear_billboards(&mut self) {
+        self.billboards.clear();
+    }
+
+    /// Adds a point light to the scene, shading both the terrain and the instanced meshes. Returns
+    /// `None` if the scene already has the maximum of 8 lights.
+    #[allow(dead_code)]
+    pub fn add_light(&mut self, light: PointLight) -> Option<LightId> {
+        let id = self.scene_lights.add(self.gpu_context.queue(), light)?;
+        self.force_redraw_next_frame();
+        Some(id)
+    }
+
+    /// Removes the light with the given id from the scene, returning it. Returns `None` if there is
+    /// no such light.
+    #[allow(dead_code)]
+    pub fn remove_light(&mut self, id: LightId) -> Option<PointLight> {
+        let light = self.scene_lights.remove(self.gpu_context.queue(), id)?;
+        self.force_redraw_next_frame();
+        Some(light)
+    }
+
+    /// Replaces the light with the given id, e.g. to move it. Returns `None` if there is no such
+    /// light.
+    #[allow(dead_code)]
+    pub fn update_light(&mut self, id: LightId, light: PointLight) -> Option<()> {
+        self.scene_lights.update(self.gpu_context.queue(), id, light)?;
+        self.force_redraw_next_frame();
+        Some(())
+    }
+
+    /// The light with the given id, if it is still in the scene.
+    #[allow(dead_code)]
+    pub fn light(&self, id: LightId) -> Option<&PointLight> {
+        self.scene_lights.get(id)
+    }
+
+    /// Moves the sun to `azimuth` degrees clockwise from north (the +y axis), and `elevation`
+    /// degrees above the horizon, which is clamped to `-90..=90`. Both the sunlight and the sky
+    /// change color with the elevation: toward orange-pink below 15 degrees, and to dark blue once
+    /// the sun has set.
+    #[allow(dead_code)]
+    pub fn set_sun_position(&mut self, azimuth: f32, elevation: f32) {
+        let elevation = elevation.max(-90.0).min(90.0);
+        self.scene_lights.set_sun_position(self.gpu_context.queue(), azimuth, elevation);
+        self.scene.clear_color = lights::sky_color(elevation);
+        self.force_redraw_next_frame();
+    }
+
+    /// The sun's `(azimuth, elevation)`, in degrees. See `set_sun_position`.
+    #[allow(dead_code)]
+    pub fn sun_position(&self) -> (f32, f32) {
+        self.scene_lights.sun_position()
+    }
+
+    /// Replaces the active particle system, or removes it when `None`. The particle system is
+    /// advanced and drawn every frame, and its particles collide with the terrain according to its
+    /// `ground_collision` setting.
+    #[allow(dead_code)]
+    pub fn set_particle_system(&mut self, particle_system: Option<ParticleSystem>) {
+        self.scene.particle_system = particle_system;
+    }
+
+    #[allow(dead_code)]
+    pub fn particle_system_mut(&mut self) -> Option<&mut ParticleSystem> {
+        self.scene.particle_system.as_mut()
+    }
+
+    /// Sets the size particles are drawn at, in world units.
+    #[allow(dead_code)]
+    pub fn set_particle_size(&mut self, size: f32) {
+        self.particle_renderer.set_size(size);
+    }
+
+    /// Records passes filling mip levels `1..mip_count` of `texture` by repeatedly downsampling the
+    /// level above. The passes run at the start of the next frame.
+    #[allow(dead_code)]
+    pub fn generate_mipmaps(&mut self, texture: &wgpu::Texture, format: wgpu::TextureFormat, mip_count: u32) {
+        self.mipmap_generator.generate(
+            &self.gpu_context,
+            self.next_frame_recorder.encoder(),
+            texture,
+            format,
+            mip_count,
+        );
+    }
+
+    /// Shades the instanced meshes as `material`, using the GGX microfacet BRDF for the sun and the
+    /// point lights, and the environment map for ambient light and reflections.
+    #[allow(dead_code)]
+    pub fn set_pbr_material(&mut self, material: PbrMaterial) {
+        self.pbr_material = material;
+        self.pbr_uniform_buf.update(self.gpu_context.queue(), &material.into());
+        self.force_redraw_next_frame();
+    }
+
+    /// The material the instanced meshes are shaded with.
+    #[allow(dead_code)]
+    pub fn pbr_material(&self) -> PbrMaterial {
+        self.pbr_material
+    }
+
+    /// Replaces the environment map, which the water reflects, with the equirectangular Radiance
+    /// HDR image at `path`. OpenEXR images are not supported. See
+    /// `environment_map::load_environment_map_hdr`.
+    #[allow(dead_code)]
+    pub fn load_environment_map(&mut self, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        self.environment_map = environment_map::load_environment_map_hdr(&self.gpu_context, path)?;
+        self.environment_map_view = environment_map::create_cube_view(&self.environment_map);
+        self.rebuild_bind_groups();
+        self.force_redraw_next_frame();
+        Ok(())
+    }
+
+    /// Replaces the terrain texture with the image at `path`. The image may be of any size and any
+    /// format supported by the `image` crate; it is converted to RGBA8 before being uploaded. Since
+    /// bind groups reference a specific texture view, every bind group which samples the terrain
+    /// texture is rebuilt. The previous texture is dropped. A full mip chain is generated only if
+    /// `generate_mipmaps` is set; otherwise the texture has a single level.
+    #[allow(dead_code)]
+    pub fn reload_terrain_texture(
+        &mut self,
+        path: &std::path::Path,
+        generate_mipmaps: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let texels = image::open(path)?.to_rgba();
+        // The new image is laid out in the same grid of tiles as the one it replaces.
+        let layout = crate::atlas::AtlasLayout {
+            tile_cols: self.terrain_uniforms.atlas_dims[0] as u32,
+            tile_rows: self.terrain_uniforms.atlas_dims[1] as u32,
+        };
+        let (texture, texture_view, mip_count) = create_terrain_texture(
+            &self.gpu_context,
+            self.next_frame_recorder.encoder(),
+            &mut self.mipmap_generator,
+            &texels,
+            layout,
+            generate_mipmaps,
+        );
+        self.texture = texture;
+        self.texture_view = texture_view;
+        // The new image may have a different number of mip levels, which the sampler clamps to.
+        self.texture_mip_count = mip_count;
+        self.texture_sampler = create_texture_sampler(&self.gpu_context, &self.sampler_config, mip_count);
+        self.rebuild_bind_groups();
+        Ok(())
+    }
+
+    /// Replaces the filtering used when sampling the terrain texture. Anisotropic filtering is
+    /// ignored if the device does not support it.
+    #[allow(dead_code)]
+    pub fn set_sampler_config(&mut self, config: SamplerConfig) {
+        self.texture_sampler = create_texture_sampler(&self.gpu_context, &config, self.texture_mip_count);
+        self.sampler_config = config;
+        // Bind groups reference a specific sampler, so they have to be rebuilt to see the new one.
+        self.rebuild_bind_groups();
+    }
+
+    /// Rebuilds every bind group which references the terrain texture view, sampler, or transforms.
+    fn rebuild_bind_groups(&mut self) {
+        let shared_bindings = SharedBindings {
+            texture_view: &self.texture_view,
+            texture_sampler: &self.texture_sampler,
+            transforms_buf: self.scene.terrain.transforms_buf(),
+            terrain_uniform_buf: self.terrain_uniform_buf.slice(..),
+            light_buf: self.scene_lights.slice(),
+            environment_map_view: &self.environment_map_view,
+            environment_sampler: &self.environment_sampler,
+            pbr_uniform_buf: self.pbr_uniform_buf.slice(),
+        };
+        self.bind_group = create_main_bind_group(
+            &self.gpu_context,
+            &self.bind_group_layout,
+            self.uniform_buf.slice(),
+            &shared_bindings,
+        );
+        self.minimap.rebuild_terrain_bind_group(
+            &self.gpu_context,
+            &self.bind_group_layout,
+            &shared_bindings,
+        );
+        if let Some(deferred) = self.deferred.as_mut() {
+            let inputs = deferred::LightingInputs {
+                camera: self.uniform_buf.slice(),
+                lights: self.scene_lights.slice(),
+                environment_map_view: &self.environment_map_view,
+                environment_sampler: &self.environment_sampler,
+            };
+            deferred.rebuild_bind_group(&self.gpu_context, &inputs);
+        }
+    }
+
+    /// Selects `region` of the terrain, e.g. the patch last edited with a brush, or clears the
+    /// selection if `None`. The selected patch is outlined once an outline color has been set with
+    /// `set_selected_patch_outline`. Returns `None`, leaving the selection unchanged, if the region
+    /// is empty or lies outside the terrain, or if the terrain is split into Voronoi regions.
+    #[allow(dead_code)]
+    pub fn set_selected_patch(&mut self, region: Option<PatchRegion>) -> Option<()> {
+        let selection = match region {
+            Some(_) if self.voronoi_regions.is_some() => return None,
+            Some(region) => {
+                let chunks_x = self.scene.world_geometry_manager.config().chunks_x;
+                Some((region, self.scene.terrain.region_center(chunks_x, region)?))
+            },
+            None => None,
+        };
+        self.outline.set_selection(selection);
+        self.force_redraw_next_frame();
+        Some(())
+    }
+
+    /// The selected region of the terrain, if any. See `set_selected_patch`.
+    #[allow(dead_code)]
+    pub fn selected_patch(&self) -> Option<PatchRegion> {
+        self.outline.selected_region()
+    }
+
+    /// Outlines the selected terrain patch in `color`, an RGBA color blended over the scene by its
+    /// alpha. The outline is drawn with the stencil buffer: the patch is marked in the stencil, and
+    /// a slightly enlarged copy of it is drawn only where the stencil is unmarked, leaving a ring
+    /// around the patch. It is drawn over everything, so that it stays visible behind hills.
+    #[allow(dead_code)]
+    pub fn set_selected_patch_outline(&mut self, color: [f32; 4]) {
+        self.outline.set_color(color);
+        self.force_redraw_next_frame();
+    }
+
+    /// Saves the terrain configuration, camera placement, and voxel terrain to a TOML file at
+    /// `path`.
+    #[allow(dead_code)]
+    pub fn save_scene(&self, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        self.scene_state().write(path)
+    }
+
+    /// The current terrain configuration, camera placement, and voxel terrain, as saved by
+    /// `save_scene`.
+    pub fn scene_state(&self) -> SceneState {
+        SceneState {
+            terrain_grid: self.terrain_grid.clone(),
+            ..self.scene.state(self.autosave)
+        }
+    }
+
+    /// Loads the terrain configuration, camera placement, and voxel terrain from the TOML file at
+    /// `path`, and regenerates the terrain to match.
+    #[allow(dead_code)]
+    pub fn load_scene(&mut self, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        let state = SceneState::read(path)?;
+        self.apply_scene_state(state).ok_or("failed to upload the regenerated terrain")?;
+        Ok(())
+    }
+
+    /// Replaces the terrain configuration, camera placement, and voxel terrain with those in
+    /// `state`, and regenerates the terrain to match. Returns `None` if the new terrain could not be
+    /// uploaded.
+    pub fn apply_scene_state(&mut self, state: SceneState) -> Option<()> {
+        self.autosave = state.autosave;
+
+        let camera = self.camera_mut();
+        camera.set_position(state.camera_position.into());
+        camera.set_yaw_pitch(cgmath::Rad(state.camera_yaw), cgmath::Rad(state.camera_pitch));
+
+        self.terrain_dirty = false;
+        self.chunk_lods.update_lod(&state.terrain_config, state.camera_position.into(), self.lod_bias);
+        self.scene.world_geometry_manager =
+            self.create_world_geometry_manager(state.terrain_config, state.sea_level as i32);
+        self.set_terrain_grid(state.terrain_grid);
+        self.terrain_uniforms.sea_level = self.scene.world_geometry_manager.sea_level() as f32;
+        self.terrain_uniform_buf.replace_data([self.terrain_uniforms]);
+        self.regenerate_mesh()
+    }
+
+    /// Shifts the level of detail of every chunk of the terrain: positive values coarsen it, and
+    /// negative ones refine it. A bias of -1 keeps each chunk one level finer than its distance from
+    /// the camera alone would give it. The terrain is regenerated with the next update if any chunk's
+    /// level changed.
+    #[allow(dead_code)]
+    pub fn set_lod_bias(&mut self, bias: f32) {
+        self.lod_bias = bias;
+    }
+
+    /// Selects whether chunks morph toward the next coarser level of detail as the camera moves
+    /// away, rather than dropping to it all at once. See `world_geometry::ChunkLod::morph`. The
+    /// terrain is regenerated with the next update if the setting changed.
+    #[allow(dead_code)]
+    pub fn set_lod_transition(&mut self, lod_transition: bool) {
+        set_field_dirty!(self, lod_transition, lod_transition);
+    }
+
+    /// Splits the terrain into `region_count` irregular Voronoi regions, one chunk each, rather than
+    /// a grid of chunks, or returns to the grid if `None`. Region boundaries wander across the
+    /// terrain, so seams between chunks no longer line up in straight rows. Regions are always
+    /// generated at full detail, and terrain patches cannot be applied or selected while they are
+    /// in use; any selection is cleared. The terrain is regenerated with the next update.
+    #[allow(dead_code)]
+    pub fn set_voronoi_regions(&mut self, region_count: Option<u32>) {
+        if region_count == self.voronoi_regions {
+            return;
+        }
+        self.voronoi_regions = region_count;
+        self.outline.set_selection(None);
+        self.terrain_dirty = true;
+    }
+
+    /// Samples the terrain's heights from `noise`, or from the default layers if `None`. The seed of
+    /// `noise` is replaced by that of the terrain configuration, so `set_seed` still selects a
+    /// different terrain. The terrain is regenerated with the next update.
+    #[allow(dead_code)]
+    pub fn set_terrain_noise(&mut self, noise: Option<crate::utils::NoiseCompositor>) {
+        self.terrain_noise = noise;
+        let world_geometry_manager = &self.scene.world_geometry_manager;
+        self.scene.world_geometry_manager =
+            self.create_world_geometry_manager(world_geometry_manager.config(), world_geometry_manager.sea_level());
+        self.terrain_dirty = true;
+    }
+
+    /// A `WorldGeometryManager` generating terrain from `config` and `sea_level`, sampling heights
+    /// from the noise set with `set_terrain_noise`.
+    fn create_world_geometry_manager(
+        &self,
+        config: crate::world_geometry::TerrainConfig,
+        sea_level: i32,
+    ) -> crate::world_geometry::WorldGeometryManager {
+        let select_tile = crate::atlas::default_tile_selector;
+        match &self.terrain_noise {
+            Some(noise) => crate::world_geometry::WorldGeometryManager::with_noise(
+                config,
+                sea_level,
+                select_tile,
+                noise.reseeded(config.seed),
+            ),
+            None => crate::world_geometry::WorldGeometryManager::new(config, sea_level, select_tile),
+        }
+    }
+
+    /// Replaces the terrain configuration, marking the terrain dirty only if `new_config` differs
+    /// from the current configuration in any field. This is the entry point for UI controls, which
+    /// may report a value even when it has not changed.
+    #[allow(dead_code)]
+    pub fn apply_terrain_diff(&mut self, new_config: &crate::world_geometry::TerrainConfig) {
+        if self.scene.world_geometry_manager.set_config(*new_config) {
+            self.terrain_dirty = true;
+        }
+    }
+
+    /// Scales the height of the terrain. The terrain is regenerated with the next update if the
+    /// amplitude changed.
+    #[allow(dead_code)]
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        set_field_dirty!(self, amplitude, amplitude);
+    }
+
+    /// Scales how quickly the terrain varies across the xy plane. The terrain is regenerated with
+    /// the next update if the frequency changed.
+    #[allow(dead_code)]
+    pub fn set_frequency(&mut self, frequency: f32) {
+        set_field_dirty!(self, frequency, frequency);
+    }
+
+    /// Selects a different terrain. The terrain is regenerated with the next update if the seed
+    /// changed.
+    #[allow(dead_code)]
+    pub fn set_seed(&mut self, seed: u32) {
+        set_field_dirty!(self, seed, seed);
+    }
+
+    /// Renders the terrain heights of the current terrain configuration as a top-down greyscale
+    /// texture, with one texel per column. Heights are normalised so that the lowest column is black
+    /// and the highest is white; the value is stored in the red channel, and repeated in green and
+    /// blue so that the texture can be displayed as is. Only the noise is sampled, so this is much
+    /// cheaper than regenerating the mesh and reflects configuration changes the mesh does not show
+    /// yet.
+    #[allow(dead_code)]
+    pub fn render_noise_preview(&self) -> wgpu::Texture {
+        let world_geometry_manager = &self.scene.world_geometry_manager;
+        let extent = world_geometry_manager.extent();
+        let (width, height) = (extent.x as u32, extent.y as u32);
+        let heights = world_geometry_manager.height_grid();
+
+        let min = heights.iter().copied().min().unwrap_or(0);
+        let max = heights.iter().copied().max().unwrap_or(0);
+        // A flat world would otherwise divide by zero; show it as black.
+        let range = (max - min).max(1) as f32;
+        let texels: Vec<u8> = heights
+            .iter()
+            .flat_map(|&z| {
+                let value = ((z - min) as f32 / range * 255.0).round() as u8;
+                std::iter::repeat(value).take(3).chain(std::iter::once(255))
+            })
+            .collect();
+
+        let texture_extent = wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        };
+        let texture = self.gpu_context.create_texture(&wgpu::TextureDescriptor {
+            size: texture_extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // The heights are linear values rather than colors, so they must not be gamma corrected.
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::COPY_SRC,
+            label: Some("noise preview"),
+        });
+        self.gpu_context.queue().write_texture(
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &texels,
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 4 * width,
+                rows_per_image: 0,
+            },
+            texture_extent,
+        );
+        texture
+    }
+
+    /// Regenerates the terrain mesh from the current terrain configuration and uploads it with the
+    /// next frame. Returns `None` if the new mesh could not be uploaded.
+    pub fn regenerate_mesh(&mut self) -> Option<()> {
+        let mesh = self.generate_terrain_mesh()?;
+        self.scene.terrain.upload(&self.gpu_context, mesh)?;
+        // The world may have changed size, and with it the transforms buffer.
+        self.minimap.set_world_extent(self.scene.world_geometry_manager.extent());
+        self.rebuild_bind_groups();
+        self.update_occlusion_bounds();
+        Some(())
+    }
+
+    /// Generates the chunks of the terrain at the indices `chunks` again, at their current levels of
+    /// detail, and uploads the terrain with the next frame. The other chunks are kept as they are,
+    /// unless the terrain is not a grid of chunks of the current configuration, in which case it is
+    /// all regenerated. Returns `None` if the new mesh could not be uploaded.
+    fn regenerate_chunks(&mut self, chunks: &[usize]) -> Option<()> {
+        let mut mesh = self.scene.terrain.world_mesh();
+        let world_geometry_manager = &self.scene.world_geometry_manager;
+        if world_geometry_manager.regenerate_chunks(&mut mesh, self.chunk_lods.lods(), chunks).is_none() {
+            return self.regenerate_mesh();
+        }
+        // The number of chunks is unchanged, so the transforms buffer, and with it the bind
+        // groups, are kept.
+        self.scene.terrain.upload(&self.gpu_context, mesh)?;
+        self.update_occlusion_bounds();
+        Some(())
+    }
+
+    /// Generates the terrain mesh from the current terrain configuration, split into Voronoi regions
+    /// if `set_voronoi_regions` asked for them, or into a grid of chunks at their levels of detail
+    /// otherwise. Returns `None` if the regions could not be generated.
+    fn generate_terrain_mesh(&self) -> Option<crate::world_geometry::WorldMesh> {
+        let world_geometry_manager = &self.scene.world_geometry_manager;
+        let region_count = match self.voronoi_regions {
+            Some(region_count) => region_count,
+            None => return Some(world_geometry_manager.generate_with_lods(self.chunk_lods.lods())),
+        };
+        let extent = world_geometry_manager.extent();
+        let partitioner = crate::voronoi::VoronoiChunkPartitioner::with_random_seeds(
+            region_count,
+            world_geometry_manager.config().seed as u64,
+            extent.x as u32,
+            extent.y as u32,
+        )?;
+        let mesh = world_geometry_manager.generate_voronoi(&partitioner);
+        if mesh.is_none() {
+            log::error!("Some of the {} Voronoi regions have too many vertices; use more regions.", region_count);
+        }
+        mesh
+    }
+
+    /// Like `regenerate_mesh`, but the new mesh is on the GPU by the time this returns. The upload
+    /// is submitted immediately, along with any other work queued for the next frame, and then the
+    /// device is waited on. This stalls, so it is meant for tests and tools which need the GPU in a
+    /// known state; normal rendering should use `regenerate_mesh`.
+    #[allow(dead_code)]
+    pub fn regenerate_mesh_blocking(&mut self) -> Option<()> {
+        self.regenerate_mesh()?;
+        let mut uploads = std::mem::replace(
+            &mut self.next_frame_recorder,
+            CommandRecorder::new(&self.gpu_context),
+        );
+        self.scene.terrain.enqueue_copy_commands(&mut self.gpu_context, uploads.encoder());
+        self.submit_recorders(vec![uploads]);
+        self.gpu_context.poll(wgpu::Maintain::Wait);
+        Some(())
+    }
+
+    /// Replaces the vertices of the chunks covered by `patch`, writing only those vertices to the
+    /// GPU rather than regenerating the terrain. See `TerrainPatch` for what a patch may change.
+    /// The patch is lost the next time the terrain is regenerated.
+    #[allow(dead_code)]
+    pub fn apply_patch(&mut self, patch: TerrainPatch) -> Result<(), RenderContextError> {
+        if self.voronoi_regions.is_some() {
+            return Err(RenderContextError::InvalidTerrainPatchError(
+                "patches address a grid of chunks, but the terrain is split into Voronoi regions",
+            ));
+        }
+        let chunks_x = self.scene.world_geometry_manager.config().chunks_x;
+        self.scene.terrain
+            .apply_patch(self.gpu_context.queue(), chunks_x, &patch)
+            .map_err(RenderContextError::InvalidTerrainPatchError)?;
+        self.update_occlusion_bounds();
+        self.force_redraw_next_frame();
+        Ok(())
+    }
+
+    /// Regenerates the terrain, then renders `frames` frames while orbiting the camera once around
+    /// the center of the terrain, and reports how long each took. The orbit depends only on the
+    /// terrain's extent and `frames`, so runs with the same scene are comparable. The camera is
+    /// restored afterwards. `frames` must be nonzero.
+    pub fn run_benchmark(&mut self, frames: u32) -> BenchmarkResult {
+        self.reset_frame_index();
+        let mesh_start = std::time::Instant::now();
+        let mesh = self.generate_terrain_mesh();
+        let mesh_generation_ms = mesh_start.elapsed().as_secs_f32() * 1000.0;
+        if mesh.and_then(|mesh| self.scene.terrain.upload(&self.gpu_context, mesh)).is_none() {
+            log::error!("Failed to upload the benchmark terrain; timing the existing terrain instead.");
+        }
+        self.rebuild_bind_groups();
+        self.update_occlusion_bounds();
+
+        let saved_position = self.scene.camera.position();
+        let (saved_yaw, saved_pitch) = (self.scene.camera.yaw(), self.scene.camera.pitch());
+
+        let extent = self.scene.world_geometry_manager.extent();
+        let center = cgmath::Point3::new(extent.x * 0.5, extent.y * 0.5, 0.0);
+        let radius = extent.x.max(extent.y) * 0.5;
+
+        let mut frame_ms = Vec::with_capacity(frames as usize);
+        for frame in 0..frames {
+            let theta = frame as f32 / frames as f32 * 2.0 * std::f32::consts::PI;
+            let camera = self.camera_mut();
+            camera.set_position(cgmath::Point3::new(
+                center.x + radius * theta.cos(),
+                center.y + radius * theta.sin(),
+                benchmark::ORBIT_ELEVATION,
+            ));
+            camera.look_at(center);
+
+            let frame_start = std::time::Instant::now();
+            self.render();
+            // Wait for the GPU, so that each frame is charged for its own work.
+            self.gpu_context.poll(wgpu::Maintain::Wait);
+            frame_ms.push(frame_start.elapsed().as_secs_f32() * 1000.0);
+        }
+
+        let camera = self.camera_mut();
+        camera.set_position(saved_position);
+        camera.set_yaw_pitch(saved_yaw, saved_pitch);
+
+        BenchmarkResult::from_frame_times(frame_ms, mesh_generation_ms, self.scene.triangle_count())
+    }
+
+    /// Tests the terrain's chunks for occlusion after each frame: the frame's depth buffer is reduced
+    /// into a hierarchical depth buffer by a chain of compute passes, and each chunk's bounding box
+    /// is tested against it by another. The results are read back with `chunk_visibility`.
+    #[allow(dead_code)]
+    pub fn set_occlusion_culling(&mut self, enabled: bool) {
+        self.occlusion = if enabled { Some(self.create_occlusion()) } else { None };
+    }
+
+    /// Whether each of the terrain's chunks was visible in the last frame, as determined by the
+    /// occlusion test, in the order the chunks are laid out in. This blocks until the GPU has
+    /// finished the frame. Returns `None` if occlusion culling is off, or the results could not be
+    /// read back.
+    #[allow(dead_code)]
+    pub fn chunk_visibility(&self) -> Option<Vec<bool>> {
+        self.occlusion.as_ref()?.read_visibility(&self.gpu_context)
+    }
+
+    fn create_occlusion(&self) -> hzb::HzbOcclusion {
+        let size = self.depth_buffer_size;
+        hzb::HzbOcclusion::new(
+            &self.gpu_context,
+            &self.depth_buffer,
+            size.width,
+            size.height,
+            self.uniform_buf.slice(),
+            &self.occlusion_bounds(),
+        )
+    }
+
+    /// Passes the terrain's chunks, as last uploaded, to the occlusion test, if it is on.
+    fn update_occlusion_bounds(&mut self) {
+        if self.occlusion.is_none() {
+            return;
+        }
+        let bounds = self.occlusion_bounds();
+        if let Some(occlusion) = self.occlusion.as_mut() {
+            occlusion.set_chunk_bounds(&self.gpu_context, self.uniform_buf.slice(), &bounds);
+        }
+    }
+
+    /// The bounding box of each of the terrain's chunks, for the occlusion test.
+    fn occlusion_bounds(&self) -> Vec<hzb::ChunkBounds> {
+        self.scene
+            .terrain
+            .all_chunk_bounds()
+            .into_iter()
+            .map(|(min, max)| hzb::ChunkBounds {
+                min: [min[0], min[1], min[2], 1.0],
+                max: [max[0], max[1], max[2], 1.0],
+            })
+            .collect()
+    }
+
+    /// Records a copy of the depth buffer into an `R32Float` texture which can be sampled, e.g. for
+    /// screen space effects. The copy is recorded onto the encoder for the next frame, so when
+    /// called after `render` it holds that frame's depth by the time the next frame's passes run.
+    /// See `depth_copy_view`.
+    #[allow(dead_code)]
+    pub fn copy_depth_to_texture(&mut self) {
+        let (gpu_context, depth_buffer, size) = (&self.gpu_context, &self.depth_buffer, self.depth_buffer_size);
+        let depth_copy = self.depth_copy.get_or_insert_with(|| {
+            depth_copy::DepthCopy::new(gpu_context, depth_buffer, size.width, size.height)
+        });
+        depth_copy.record(self.next_frame_recorder.encoder());
+    }
+
+    /// The view of the depth copied by the last call to `copy_depth_to_texture`, or `None` if it
+    /// has never been called.
+    #[allow(dead_code)]
+    pub fn depth_copy_view(&self) -> Option<&wgpu::TextureView> {
+        self.depth_copy.as_ref().map(depth_copy::DepthCopy::view)
+    }
+
+    /// Renders a frame offscreen at the size of the window, without the minimap, and reads it back.
+    /// This blocks until the GPU has finished the frame.
+    pub fn take_screenshot(&mut self) -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+        let (width, height) = self.gpu_context.size();
+        let target = self.gpu_context.create_render_target(width, height, screenshot::CAPTURE_FORMAT, false);
+        self.render_to_view(&target.color_view, None, false)?;
+        screenshot::read_texture(&self.gpu_context, &target.color_texture, width, height)
+    }
+
+    /// Renders a frame on the deferred path, then returns a single channel of its G-buffer as RGBA
+    /// pixels, row by row, at the render size. The pixels can be saved as is with
+    /// `image::save_buffer`. Fails if the deferred path is not selected; see `set_render_path`.
+    /// This blocks until the GPU has finished the frame.
+    #[allow(dead_code)]
+    pub fn capture_gbuffer_debug_view(
+        &mut self,
+        channel: GBufferChannel,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if self.deferred.is_none() {
+            return Err("the deferred render path is not selected".into());
+        }
+        // The frame itself is discarded; only the G-buffer it leaves behind is wanted.
+        let (width, height) = self.gpu_context.size();
+        let target = self.gpu_context.create_render_target(width, height, screenshot::CAPTURE_FORMAT, false);
+        self.render_to_view(&target.color_view, None, false)?;
+
+        let extent = self.scene.world_geometry_manager.extent();
+        let position_scale = 1.0 / extent.x.max(extent.y).max(1.0);
+        let size = self.depth_buffer_size;
+        let deferred = self.deferred.as_ref().ok_or("the deferred render path is not selected")?;
+        deferred.capture_debug_view(
+            &self.gpu_context,
+            &self.depth_buffer,
+            size.width,
+            size.height,
+            channel,
+            position_scale,
+        )
+    }
+
+    /// Writes the terrain, as most recently generated, to `path` as a binary PLY file, in world
+    /// space. Each vertex carries its face's normal and texture coordinates of one unit per block,
+    /// projected along that normal as the terrain shader does. Nothing is read back from the GPU.
+    #[allow(dead_code)]
+    pub fn export_terrain_ply(&self, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut vertices = Vec::with_capacity(self.vertex_data_snapshot().len());
+        let mut triangles = Vec::with_capacity(self.index_data_snapshot().len() / 3);
+        for (transform, chunk_vertices, chunk_indices) in self.scene.terrain.chunk_geometry() {
+            let first_vertex = vertices.len() as u32;
+            for vertex in chunk_vertices {
+                let [x, y, z] = vertex.position();
+                let world = transform * cgmath::Vector4::new(x as f32, y as f32, z as f32, 1.0);
+                let normal = vertex.normal();
+                // Texture v runs down the image, so z is negated to keep the sides upright.
+                let tex_coord = if normal[2] != 0.0 {
+                    [world.x, world.y]
+                } else if normal[1] != 0.0 {
+                    [world.x, -world.z]
+                } else {
+                    [world.y, -world.z]
+                };
+                vertices.push(ply::PlyVertex {
+                    position: [world.x, world.y, world.z],
+                    normal,
+                    tex_coord,
+                });
+            }
+            for triangle in chunk_indices.chunks(3) {
+                if let [a, b, c] = *triangle {
+                    triangles.push([first_vertex + a as u32, first_vertex + b as u32, first_vertex + c as u32]);
+                }
+            }
+        }
+        ply::write_ply(path, &vertices, &triangles)
+    }
+
+    /// Renders `frames` screenshots from evenly spaced points on a circle of `radius` around the
+    /// center of the terrain, each looking at the center, and saves them to `output_dir` as
+    /// `frame_0000.png`, `frame_0001.png`, and so on. `progress` is called after each frame is
+    /// saved. The camera is restored afterwards, even if a frame fails.
+    #[allow(dead_code)]
+    pub fn capture_turntable(
+        &mut self,
+        frames: u32,
+        radius: f32,
+        output_dir: &std::path::Path,
+        progress: fn(frame: u32, total: u32),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(output_dir)?;
+
+        let saved_position = self.scene.camera.position();
+        let (saved_yaw, saved_pitch) = (self.scene.camera.yaw(), self.scene.camera.pitch());
+
+        let extent = self.scene.world_geometry_manager.extent();
+        let center = cgmath::Point3::new(extent.x * 0.5, extent.y * 0.5, 0.0);
+
+        let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+            for frame in 0..frames {
+                let theta = frame as f32 / frames as f32 * 2.0 * std::f32::consts::PI;
+                let camera = self.camera_mut();
+                camera.set_position(cgmath::Point3::new(
+                    center.x + radius * theta.cos(),
+                    center.y + radius * theta.sin(),
+                    screenshot::TURNTABLE_ELEVATION,
+                ));
+                camera.look_at(center);
+
+                self.take_screenshot()?
+                    .save(output_dir.join(format!("frame_{:04}.png", frame)))?;
+                progress(frame, frames);
+            }
+            Ok(())
+        })();
+
+        let camera = self.camera_mut();
+        camera.set_position(saved_position);
+        camera.set_yaw_pitch(saved_yaw, saved_pitch);
+        result
+    }
+
+    pub fn autosave(&self) -> bool {
+        self.autosave
+    }
+
+    #[allow(dead_code)]
+    pub fn set_autosave(&mut self, autosave: bool) {
+        self.autosave = autosave;
+    }
+
+    /// Sets how sharply the terrain texture transitions between its three projections. Higher values
+    /// blend over a narrower range of surface angles.
+    #[allow(dead_code)]
+    pub fn set_triplanar_blend_sharpness(&mut self, blend_sharpness: f32) {
+        self.terrain_uniforms.blend_sharpness = blend_sharpness;
+        self.terrain_uniform_buf.replace_data([self.terrain_uniforms]);
+    }
+
+    /// Sets how many times the terrain texture repeats per block. Values above 1 shrink the tiles,
+    /// repeating them more often, and values below 1 stretch them across several blocks. The
+    /// default is 1.
+    #[allow(dead_code)]
+    pub fn set_terrain_tiling_scale(&mut self, scale: f32) {
+        self.terrain_uniforms.tiling_scale = scale;
+        self.terrain_uniform_buf.replace_data([self.terrain_uniforms]);
+        self.force_redraw_next_frame();
+    }
+
+    pub fn minimap_enabled(&self) -> bool {
+        self.minimap.enabled()
+    }
+
+    /// Shows or hides the top-down minimap in the corner of the view. While hidden, the minimap is
+    /// not rendered at all.
+    pub fn set_minimap_enabled(&mut self, enabled: bool) {
+        self.minimap.set_enabled(enabled);
+        self.force_redraw_next_frame();
+    }
+
+    // Expose raw mutation for some of the basic state variables.
+    fn set_camera_dirty(&mut self) {
+        self.camera_dirty = true;
+    }
+
+    /// The vertices of the terrain mesh as most recently generated, for queries on the CPU. These
+    /// are the same data the GPU draws, without a readback.
+    #[allow(dead_code)]
+    pub fn vertex_data_snapshot(&self) -> &[utils::IVertex] {
+        self.scene.terrain.vertices()
+    }
+
+    /// The indices of the terrain mesh as most recently generated. See `vertex_data_snapshot`.
+    #[allow(dead_code)]
+    pub fn index_data_snapshot(&self) -> &[u16] {
+        self.scene.terrain.indices()
+    }
+
+    #[allow(dead_code)]
+    /// Switches the camera between flying freely and orbiting `target`, or the center of the
+    /// terrain's surface if `target` is `None`. The camera starts orbiting from where it is, at its
+    /// current distance from the target.
+    pub fn toggle_orbit_mode(&mut self, target: Option<cgmath::Point3<f32>>) {
+        if self.camera().orbit_mode().is_some() {
+            self.camera_mut().free_fly();
+            return;
+        }
+        let target = target.unwrap_or_else(|| {
+            let world_geometry_manager = &self.scene.world_geometry_manager;
+            let center = world_geometry_manager.extent() * 0.5;
+            let height = world_geometry_manager.height_at(center.x, center.y).unwrap_or(0.0);
+            cgmath::Point3::new(center.x, center.y, height)
+        });
+        let distance = cgmath::MetricSpace::distance(self.camera().position(), target);
+        self.camera_mut().orbit(target, distance);
+    }
+
+    pub fn camera(&self) -> &camera::Camera {
+        &self.scene.camera
+    }
+    pub fn camera_mut(&mut self) -> &mut camera::Camera {
+        // This is aggressive; we set the camera to dirty any time someone takes a mutable reference
+        // to the camera; they do not have to mutate the camera. This is a negligible performance
+        // hit.
+        self.set_camera_dirty();
+        &mut self.scene.camera
+    }
+}
+
+// Prints the logical state of the render context; the GPU resources it owns are left out.
+impl std::fmt::Debug for RenderContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let terrain_config = self.scene.world_geometry_manager.config();
+        f.debug_struct("RenderContext")
+            .field("amplitude", &terrain_config.amplitude)
+            .field("frequency", &terrain_config.frequency)
+            .field("seed", &terrain_config.seed)
+            .field("grid_size", &(terrain_config.chunks_x, terrain_config.chunks_y))
+            .field("terrain_dirty", &self.terrain_dirty)
+            .field("camera_dirty", &self.camera_dirty)
+            .field("frame_index", &self.frame_index)
+            .field("gpu_context", &self.gpu_context)
+            .finish()
+    }
+}
+
+/// Creates a texture holding `texels` and writes the image data into it. The texture is sized to
+/// match the image, so it need not be square or a power of two. If `generate_mipmaps` is set, passes
+/// generating a mip chain are recorded onto `encoder`; the number of mip levels is returned
+/// alongside the texture.
+///
+/// The image is an atlas laid out as `layout`. The chain stops at the level where each tile is a
+/// single texel, since any smaller level would average neighbouring tiles together.
+fn create_terrain_texture(
+    gpu_context: &crate::gpu::GpuContext,
+    encoder: &mut wgpu::CommandEncoder,
+    mipmap_generator: &mut crate::postprocess::MipmapGenerator,
+    texels: &image::RgbaImage,
+    layout: crate::atlas::AtlasLayout,
+    generate_mipmaps: bool,
+) -> (wgpu::Texture, wgpu::TextureView, u32) {
+    let (width, height) = texels.dimensions();
+    let mip_count = if generate_mipmaps {
+        let tile_size = (width / layout.tile_cols.max(1))
+            .min(height / layout.tile_rows.max(1))
+            .max(1);
+        crate::postprocess::mip_level_count(tile_size, tile_size)
+    } else {
+        1
+    };
+    let texture_extent = wgpu::Extent3d {
+        width,
+        height,
+        depth: 1,
+    };
+    let texture = gpu_context.create_texture(&wgpu::TextureDescriptor {
+        size: texture_extent,
+        mip_level_count: mip_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        // Mip levels are rendered from the level above, so the texture is also a render target.
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        label: None,
+    });
+    let texture_view = texture.create_default_view();
+    // `write_texture` handles alignment internally: unlike `copy_buffer_to_texture`, it does not
+    // need rows padded to `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`, since the queue stages and aligns the
+    // data itself. This lands before any submitted command buffer runs, so only the mipmap passes
+    // need an encoder.
+    gpu_context.queue().write_texture(
+        wgpu::TextureCopyView {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        texels,
+        wgpu::TextureDataLayout {
+            offset: 0,
+            bytes_per_row: 4 * width,
+            rows_per_image: 0,
+        },
+        texture_extent,
+    );
+    mipmap_generator.generate(
+        gpu_context,
+        encoder,
+        &texture,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        mip_count,
+    );
+    (texture, texture_view, mip_count)
+}
+
+/// Creates the sampler for the terrain texture, allowing it to sample from all `mip_count` levels.
+fn create_texture_sampler(
+    gpu_context: &crate::gpu::GpuContext,
+    config: &SamplerConfig,
+    mip_count: u32,
+) -> wgpu::Sampler {
+    let anisotropy_clamp = if gpu_context.features().contains(wgpu::Features::SAMPLER_ANISOTROPY) {
+        config.anisotropy_clamp
+    } else {
+        None
+    };
+    gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+        label: None,
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: config.mag_filter,
+        min_filter: config.min_filter,
+        mipmap_filter: config.mipmap_filter,
+        lod_max_clamp: mip_count as f32 - 1.0,
+        anisotropy_clamp,
+        ..Default::default()
+    })
+}
+
+/// The resources bound in the main bind group which are the same for every view of the world.
+struct SharedBindings<'a> {
+    texture_view: &'a wgpu::TextureView,
+    texture_sampler: &'a wgpu::Sampler,
+    transforms_buf: wgpu::BufferSlice<'a>,
+    terrain_uniform_buf: wgpu::BufferSlice<'a>,
+    light_buf: wgpu::BufferSlice<'a>,
+    environment_map_view: &'a wgpu::TextureView,
+    environment_sampler: &'a wgpu::Sampler,
+    pbr_uniform_buf: wgpu::BufferSlice<'a>,
+}
+
+/// Describes the main bind group. The camera is selected by `camera`, so the same layout serves
+/// every view of the world.
+fn main_bind_group_builder<'a>(
+    camera: wgpu::BufferSlice<'a>,
+    shared: &SharedBindings<'a>,
+) -> crate::bind_group_builder::BindGroupBuilder<'a> {
+    crate::bind_group_builder::BindGroupBuilder::new()
+        // Our 0th binding is for small global data shared between all invocations of the shader.
+        // Currently, these are the camera matrices. We set this only once per frame. Fragment
+        // shaders read the camera's position from it for specular lighting.
+        .add_uniform_buffer(
+            0,
+            wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+            camera,
+            std::mem::size_of::<camera::CameraUniforms>() as u64,
+        )
+        // Our 1st binding is for texture data, which will be passed as an atlas. This may change a
+        // few times per frame if we need to render from multiple atlases. TODO: are texture atlases
+        // the right way to do this?
+        .add_texture(1, wgpu::ShaderStage::FRAGMENT, shared.texture_view)
+        // Our 2nd binding is the sampler for the above texture. This is likely to change only when
+        // the texture changes. TODO: is this true?
+        .add_sampler(2, wgpu::ShaderStage::FRAGMENT, shared.texture_sampler)
+        // Our 3rd binding is a per object buffer, holding any data needed for an individual
+        // abstract object being rendered. This might be a transform matrix, for instance.
+        .add_dynamic_uniform_buffer(3, wgpu::ShaderStage::VERTEX, shared.transforms_buf, 256)
+        // Our 4th binding holds the parameters used to texture the terrain.
+        .add_uniform_buffer(
+            4,
+            wgpu::ShaderStage::FRAGMENT,
+            shared.terrain_uniform_buf,
+            std::mem::size_of::<TerrainUniforms>() as u64,
+        )
+        // Our 5th binding holds the point lights, which are shaded in both the terrain and the
+        // instanced meshes.
+        .add_uniform_buffer(5, wgpu::ShaderStage::FRAGMENT, shared.light_buf, lights::LIGHTS_UNIFORM_SIZE)
+        // Our 6th and 7th bindings are the environment map, for reflections, and its sampler.
+        .add_cube_texture(6, wgpu::ShaderStage::FRAGMENT, shared.environment_map_view)
+        .add_sampler(7, wgpu::ShaderStage::FRAGMENT, shared.environment_sampler)
+        // Our 8th binding is the material the instanced meshes are shaded with.
+        .add_uniform_buffer(
+            8,
+            wgpu::ShaderStage::FRAGMENT,
+            shared.pbr_uniform_buf,
+            std::mem::size_of::<material::PbrUniforms>() as u64,
+        )
+}
+
+/// Creates a bind group matching the main bind group layout, viewing the world through `camera`.
+fn create_main_bind_group(
+    gpu_context: &crate::gpu::GpuContext,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    camera: wgpu::BufferSlice,
+    shared: &SharedBindings,
+) -> wgpu::BindGroup {
+    main_bind_group_builder(camera, shared).build_with_layout(gpu_context, bind_group_layout)
+}
+
+/// Creates a variant of the terrain render pipeline, as described by `key`. The shader modules must
+/// have been created from the SPIR-V hashed into `key.shader_hash`. Deferred variants use
+/// `gbuffer_fs_module` in place of `fs_module`.
+fn create_terrain_pipeline(
+    gpu_context: &crate::gpu::GpuContext,
+    pipeline_layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    gbuffer_fs_module: &wgpu::ShaderModule,
+    key: &pipeline_cache::PipelineKey,
+) -> wgpu::RenderPipeline {
+    let (fs_module, color_states) = if key.deferred {
+        (gbuffer_fs_module, deferred::gbuffer_color_states())
+    } else {
+        (fs_module, vec![wgpu::ColorStateDescriptor {
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            write_mask: wgpu::ColorWrite::ALL,
+        }])
+    };
+    gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        layout: pipeline_layout,
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: key.cull_mode,
+            depth_bias: key.wireframe_depth_bias,
+            depth_bias_slope_scale: f32::from_bits(key.wireframe_depth_bias_slope_scale),
+            depth_bias_clamp: 0.0,
+        }),
+        // Wireframe variants are drawn with the terrain's line indices, which list each triangle's edges.
+        primitive_topology: if key.wireframe {
+            wgpu::PrimitiveTopology::LineList
+        } else {
+            key.topology.primitive_topology()
+        },
+        color_states: &color_states,
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: crate::gpu::RENDER_TARGET_DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            // This also makes 0xFFFF the primitive restart index of `TopologyMode::Strip`.
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: utils::IVERTEX_SIZE as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &[
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Int3,
+                        offset: 0,
+                        shader_location: 0,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Int3,
+                        offset: 4*3,
+                        shader_location: 1,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Uint,
+                        offset: 4*3 + 4*3,
+                        shader_location: 2,
+                    },
+                ],
+            }],
+        },
+
+        sample_count: key.sample_count,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
+/// Hashes shader bytecode, so that pipelines built from different shaders get different keys.
+fn hash_shaders(spirv: &[&[u8]]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    spirv.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The width and height of the frames rendered by these tests.
+    const FRAME_SIZE: u32 = 64;
+
+    /// A render context on a headless GPU context, showing a small terrain. Panics if the machine has
+    /// no GPU adapter, so the tests calling this are ignored unless run with `--ignored`.
+    fn headless_render_context() -> RenderContext {
+        let desc = crate::gpu::GpuContextDescriptor::default();
+        let gpu_context =
+            futures::executor::block_on(crate::gpu::GpuContext::create_headless(&desc, FRAME_SIZE, FRAME_SIZE))
+                .expect("this test needs a GPU adapter");
+        let state = SceneState {
+            terrain_config: crate::world_geometry::TerrainConfig {
+                chunks_x: 2,
+                chunks_y: 2,
+                ..Default::default()
+            },
+            ..SceneState::default()
+        };
+        RenderContext::from_scene_state(&state, gpu_context).unwrap()
+    }
+
+    /// Renders a frame into a new offscreen target with `render_to_texture`, and reads it back.
+    fn render_offscreen(render_context: &mut RenderContext) -> image::RgbaImage {
+        let target =
+            render_context.gpu_context.create_render_target(FRAME_SIZE, FRAME_SIZE, screenshot::CAPTURE_FORMAT, true);
+        render_context.render_to_texture(&target.color_view, target.depth_view.as_ref()).unwrap();
+        screenshot::read_texture(&render_context.gpu_context, &target.color_texture, FRAME_SIZE, FRAME_SIZE).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn render_to_texture_draws_without_a_swap_chain() {
+        let mut render_context = headless_render_context();
+        assert!(matches!(render_context.gpu_context.get_next_frame(), Err(crate::gpu::GpuContextError::HeadlessError)));
+
+        let image = render_offscreen(&mut render_context);
+        // Even where nothing is drawn, the frame is cleared to the color of the sky.
+        assert!(image.pixels().any(|pixel| pixel.0 != [0, 0, 0, 0]));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn bind_groups_follow_a_new_sampler() {
+        let mut render_context = headless_render_context();
+        render_offscreen(&mut render_context);
+        // wgpu panics on validation errors, such as a bind group built for another layout or
+        // referencing a destroyed sampler, so rendering again is enough to check the rebuild.
+        render_context.set_sampler_config(SamplerConfig {
+            mag_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..SamplerConfig::default()
+        });
+        render_offscreen(&mut render_context);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn export_terrain_ply_counts_match_the_snapshots() {
+        let render_context = headless_render_context();
+        let path = std::env::temp_dir().join(format!("jvox_terrain_test_{}.ply", std::process::id()));
+        render_context.export_terrain_ply(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (elements, _) = ply::read_element_counts(&bytes).expect("no end_header line");
+        assert_eq!(
+            elements,
+            vec![
+                ("vertex".to_string(), render_context.vertex_data_snapshot().len()),
+                ("face".to_string(), render_context.index_data_snapshot().len() / 3),
+            ]
+        );
     }
 }
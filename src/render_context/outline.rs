@@ -0,0 +1,270 @@
+//! Outlines around the selected terrain patch, drawn with the stencil buffer. The patch is first
+//! drawn into the stencil buffer alone; a slightly enlarged copy of it is then drawn in the outline
+//! color wherever the stencil was left clear, which leaves only a ring around the patch.
+
+use bytemuck::{Pod, Zeroable};
+
+use super::scene::ViewRegion;
+use super::terrain_mesh::{PatchRegion, TerrainMesh};
+use crate::utils;
+
+/// How much larger than the patch the outline's copy of it is drawn, about the patch's center.
+const OUTLINE_SCALE: f32 = 1.03;
+
+/// The stencil value marking the pixels covered by the patch itself.
+const STENCIL_SELECTED: u32 = 1;
+
+/// The push constants of the outline shaders, matching the `Outline` block.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OutlineConstants {
+    color: [f32; 4],
+    /// The point the patch is scaled about, in world space. The `w` component is unused.
+    center: [f32; 4],
+    scale: f32,
+    _pad: [f32; 3],
+}
+
+unsafe impl Pod for OutlineConstants {}
+unsafe impl Zeroable for OutlineConstants {}
+
+/// The selected patch, and the center it is scaled about.
+#[derive(Clone, Copy, Debug)]
+struct Selection {
+    region: PatchRegion,
+    center: cgmath::Point3<f32>,
+}
+
+/// Draws the outline of the selected terrain patch. Nothing is drawn until both a patch and an
+/// outline color have been set.
+pub struct OutlineRenderer {
+    /// `None` if the device does not support push constants, in which case no outline is drawn.
+    pipelines: Option<OutlinePipelines>,
+    selection: Option<Selection>,
+    color: Option<[f32; 4]>,
+}
+
+struct OutlinePipelines {
+    /// Writes `STENCIL_SELECTED` wherever the patch covers, without touching the color target.
+    mask: wgpu::RenderPipeline,
+    /// Draws the enlarged patch in the outline color wherever the stencil is clear.
+    outline: wgpu::RenderPipeline,
+}
+
+impl OutlineRenderer {
+    /// Create a new `OutlineRenderer`. `bind_group_layout` is the main bind group layout, which
+    /// supplies the camera and the chunk transforms. The outline's parameters are push constants,
+    /// so nothing is ever drawn on devices without `wgpu::Features::PUSH_CONSTANTS`.
+    pub fn new(gpu_context: &crate::gpu::GpuContext, bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        if !gpu_context.features().contains(wgpu::Features::PUSH_CONSTANTS) {
+            log::warn!("Push constants are unsupported; selected terrain patches will not be outlined.");
+            return Self {
+                pipelines: None,
+                selection: None,
+                color: None,
+            };
+        }
+
+        let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                range: 0..std::mem::size_of::<OutlineConstants>() as u32,
+            }],
+        });
+        let vs_module = gpu_context.create_shader_module_from_bytes(include_shader!("outline.vert"));
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("outline.frag"));
+        let create = |stencil_compare, stencil_pass_op, write_mask| {
+            create_outline_pipeline(
+                gpu_context,
+                &pipeline_layout,
+                &vs_module,
+                &fs_module,
+                stencil_compare,
+                stencil_pass_op,
+                write_mask,
+            )
+        };
+
+        Self {
+            pipelines: Some(OutlinePipelines {
+                mask: create(
+                    wgpu::CompareFunction::Always,
+                    wgpu::StencilOperation::Replace,
+                    wgpu::ColorWrite::empty(),
+                ),
+                outline: create(
+                    wgpu::CompareFunction::NotEqual,
+                    wgpu::StencilOperation::Keep,
+                    wgpu::ColorWrite::ALL,
+                ),
+            }),
+            selection: None,
+            color: None,
+        }
+    }
+
+    /// Selects `region` of the terrain, to be scaled about `center` for its outline, or clears the
+    /// selection if `None`.
+    pub fn set_selection(&mut self, selection: Option<(PatchRegion, cgmath::Point3<f32>)>) {
+        self.selection = selection.map(|(region, center)| Selection { region, center });
+    }
+
+    /// The selected region of the terrain, if any.
+    pub fn selected_region(&self) -> Option<PatchRegion> {
+        self.selection.map(|selection| selection.region)
+    }
+
+    /// Sets the RGBA color of the outline, which is blended over the scene by its alpha.
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self.color = Some(color);
+    }
+
+    /// Whether there is anything to draw: both a selection and an outline color are set, and the
+    /// device can draw outlines at all.
+    pub fn enabled(&self) -> bool {
+        self.pipelines.is_some() && self.selection.is_some() && self.color.is_some()
+    }
+
+    /// Records a render pass drawing the outline of the selected patch into `region` of
+    /// `color_view`. The outline is drawn over everything, including terrain in front of the patch.
+    /// The stencil of `depth_view` is cleared at the start of the pass; its depth is untouched.
+    /// `chunks_x` is the number of chunks in each row of the terrain.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        gpu_context: &crate::gpu::GpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+        terrain: &TerrainMesh,
+        chunks_x: usize,
+        bind_group: &wgpu::BindGroup,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        region: &ViewRegion,
+    ) {
+        let (pipelines, selection, color) = match (self.pipelines.as_ref(), self.selection, self.color) {
+            (Some(pipelines), Some(selection), Some(color)) => (pipelines, selection, color),
+            _ => return,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: true,
+                }),
+            }),
+        });
+
+        region.apply(&mut render_pass);
+        render_pass.set_stencil_reference(STENCIL_SELECTED);
+        let center = [selection.center.x, selection.center.y, selection.center.z, 1.0];
+        let passes = [(&pipelines.mask, 1.0), (&pipelines.outline, OUTLINE_SCALE)];
+        for &(pipeline, scale) in passes.iter() {
+            render_pass.set_pipeline(pipeline);
+            gpu_context.set_push_constants(
+                &mut render_pass,
+                wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                0,
+                &OutlineConstants {
+                    color,
+                    center,
+                    scale,
+                    _pad: [0.0; 3],
+                },
+            );
+            terrain.draw_region(&mut render_pass, bind_group, chunks_x, selection.region);
+        }
+    }
+}
+
+/// Creates a pipeline drawing the terrain's vertices with the outline shaders. Fragments pass the
+/// stencil test by comparing `STENCIL_SELECTED` to the stencil with `stencil_compare`, and then
+/// update it with `stencil_pass_op`. Depth is neither tested nor written, so the whole patch is
+/// drawn even where other terrain hides it.
+fn create_outline_pipeline(
+    gpu_context: &crate::gpu::GpuContext,
+    pipeline_layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    stencil_compare: wgpu::CompareFunction,
+    stencil_pass_op: wgpu::StencilOperation,
+    write_mask: wgpu::ColorWrite,
+) -> wgpu::RenderPipeline {
+    let stencil_face = || wgpu::StencilStateFaceDescriptor {
+        compare: stencil_compare,
+        fail_op: wgpu::StencilOperation::Keep,
+        depth_fail_op: wgpu::StencilOperation::Keep,
+        pass_op: stencil_pass_op,
+    };
+    gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        layout: pipeline_layout,
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        // Both faces are drawn, so that the silhouette is complete from any angle.
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::None,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states: &[wgpu::ColorStateDescriptor {
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            write_mask,
+        }],
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: crate::gpu::RENDER_TARGET_DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil_front: stencil_face(),
+            stencil_back: stencil_face(),
+            stencil_read_mask: !0,
+            stencil_write_mask: !0,
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: utils::IVERTEX_SIZE as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &[wgpu::VertexAttributeDescriptor {
+                    format: wgpu::VertexFormat::Int3,
+                    offset: 0,
+                    shader_location: 0,
+                }],
+            }],
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
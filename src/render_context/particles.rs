@@ -0,0 +1,255 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::prelude::*;
+
+use crate::camera;
+use crate::managed_buffer::ManagedBuffer;
+use crate::particles::ParticleSystem;
+
+/// The width and height of the quad each particle is drawn as, in world units.
+const DEFAULT_PARTICLE_SIZE: f32 = 0.25;
+
+/// The per-particle data uploaded to the GPU.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ParticleVertex {
+    pos: [f32; 3],
+    /// The fraction of the particle's lifetime remaining, used to fade it out.
+    life: f32,
+}
+
+unsafe impl Pod for ParticleVertex {}
+unsafe impl Zeroable for ParticleVertex {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ParticleUniforms {
+    transform: [[f32; 4]; 4],
+    right: [f32; 4],
+    up: [f32; 4],
+    size: f32,
+    _pad: [f32; 3],
+}
+
+unsafe impl Pod for ParticleUniforms {}
+unsafe impl Zeroable for ParticleUniforms {}
+
+impl ParticleUniforms {
+    fn new(camera: &mut camera::Camera, size: f32) -> Self {
+        // Particles face the camera the same way billboards do.
+        let right = camera.right().normalize();
+        let up = right.cross(camera.view()).normalize();
+        Self {
+            transform: camera.matrix().into(),
+            right: right.extend(0.0).into(),
+            up: up.extend(0.0).into(),
+            size,
+            _pad: [0.0; 3],
+        }
+    }
+}
+
+/// Draws the live particles of a `ParticleSystem` as camera-facing quads. Each particle is a single
+/// instance; its quad is generated in the vertex shader, like a billboard. The particle buffer is
+/// rewritten every frame, since every particle moves every frame.
+pub struct ParticleRenderer {
+    /// The camera data most recently uploaded, kept so that the particle size can change on its
+    /// own.
+    uniforms: ParticleUniforms,
+    uniform_buf: ManagedBuffer<ParticleUniforms, [ParticleUniforms; 1]>,
+    /// `None` while there are no particles, since an empty buffer cannot be bound.
+    vertex_buf: Option<ManagedBuffer<ParticleVertex, Vec<ParticleVertex>>>,
+
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ParticleRenderer {
+    pub fn new(gpu_context: &crate::gpu::GpuContext, camera: &mut camera::Camera) -> Option<Self> {
+        let uniforms = ParticleUniforms::new(camera, DEFAULT_PARTICLE_SIZE);
+        let uniform_buf = ManagedBuffer::new_uniform_buf_with_data(gpu_context, [uniforms]).ok()?;
+
+        let (bind_group_layout, bind_group) = crate::bind_group_builder::BindGroupBuilder::new()
+            .add_uniform_buffer(
+                0,
+                wgpu::ShaderStage::VERTEX,
+                uniform_buf.slice(..),
+                std::mem::size_of::<ParticleUniforms>() as u64,
+            )
+            .build(gpu_context);
+
+        let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = gpu_context.create_shader_module_from_bytes(include_shader!("particle.vert"));
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("particle.frag"));
+
+        let pipeline = gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: wgpu::TextureFormat::Bgra8UnormSrgb,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            // Particles are translucent, so they are tested against the world but do not occlude
+            // each other.
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: crate::gpu::RENDER_TARGET_DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<ParticleVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Instance,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float3,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float,
+                            offset: 4*3,
+                            shader_location: 1,
+                        },
+                    ],
+                }],
+            },
+
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Some(Self {
+            uniforms,
+            uniform_buf,
+            vertex_buf: None,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    /// Updates the camera data used to draw the particles. This should be called whenever the
+    /// camera moves.
+    pub fn update_camera(&mut self, camera: &mut camera::Camera) {
+        self.uniforms = ParticleUniforms::new(camera, self.uniforms.size);
+        self.uniform_buf.replace_data([self.uniforms]);
+    }
+
+    pub fn set_size(&mut self, size: f32) {
+        self.uniforms.size = size;
+        self.uniform_buf.replace_data([self.uniforms]);
+    }
+
+    /// Writes the live particles of `particle_system` into the particle buffer and flushes any
+    /// dirty buffers to the GPU. The particle buffer is recreated if the particle count changed.
+    pub fn enqueue_copy_commands(
+        &mut self,
+        gpu_context: &mut crate::gpu::GpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+        particle_system: &ParticleSystem,
+    ) {
+        let lifetime = particle_system.lifetime;
+        let vertices: Vec<ParticleVertex> = particle_system.particles
+            .iter()
+            .map(|p| ParticleVertex {
+                pos: p.pos,
+                life: 1.0 - p.age / lifetime,
+            })
+            .collect();
+
+        let same_len = self.vertex_buf
+            .as_ref()
+            .map_or(false, |vertex_buf| vertex_buf.len() == vertices.len());
+        if vertices.is_empty() {
+            self.vertex_buf = None;
+        } else if same_len {
+            if let Some(vertex_buf) = self.vertex_buf.as_mut() {
+                vertex_buf.replace_data(vertices);
+            }
+        } else {
+            self.vertex_buf = ManagedBuffer::new_vertex_buf_with_data(gpu_context, vertices).ok();
+        }
+
+        if let Some(vertex_buf) = self.vertex_buf.as_mut() {
+            if vertex_buf.dirty() {
+                vertex_buf.enqueue_copy_command(gpu_context, encoder);
+            }
+        }
+        if self.uniform_buf.dirty() {
+            self.uniform_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+    }
+
+    /// Records a render pass drawing the most recently uploaded particles on top of the contents of
+    /// `region` of `color_view`, depth-tested against `depth_view`.
+    pub fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        region: &super::scene::ViewRegion,
+    ) {
+        let vertex_buf = match self.vertex_buf.as_ref() {
+            Some(vertex_buf) => vertex_buf,
+            None => return,
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        region.apply(&mut render_pass);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buf.slice(..));
+        render_pass.draw(0..6, 0..vertex_buf.len() as u32);
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+/// Everything which distinguishes one variant of the terrain pipeline from another. Two equal keys
+/// always describe interchangeable pipelines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    /// Draw triangle edges as lines rather than filling triangles.
+    pub wireframe: bool,
+    /// The topology of filled variants. Wireframe variants always draw a line list.
+    pub topology: super::TopologyMode,
+    /// Which faces are culled. The front face winding is fixed, since the mesh winding never changes.
+    pub cull_mode: wgpu::CullMode,
+    /// The constant and slope-scaled depth bias of wireframe variants, the latter as the bits of an
+    /// `f32` so that the key can be hashed. Both are zero for filled variants.
+    pub wireframe_depth_bias: i32,
+    pub wireframe_depth_bias_slope_scale: u32,
+    pub sample_count: u32,
+    /// Draw into the G-buffer of the deferred path, rather than shading into a color target.
+    pub deferred: bool,
+    /// A hash of the SPIR-V the pipeline's shader modules were created from.
+    pub shader_hash: u64,
+}
+
+struct CachedPipeline {
+    pipeline: wgpu::RenderPipeline,
+    /// The frame in which this pipeline was last requested.
+    last_used: u64,
+}
+
+/// Holds every pipeline variant created so far, so that switching back to a previous variant does
+/// not compile a pipeline again. Variants which go unused for long enough are dropped.
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, CachedPipeline>,
+    frame: u64,
+    /// Pipelines unused for more than this many frames are evicted by `end_frame`.
+    max_unused_frames: u64,
+}
+
+impl PipelineCache {
+    pub fn new(max_unused_frames: u64) -> Self {
+        Self {
+            pipelines: HashMap::new(),
+            frame: 0,
+            max_unused_frames,
+        }
+    }
+
+    /// Returns the pipeline for `key`, calling `create` to build it only if it is not cached.
+    pub fn get_or_create<F>(&mut self, key: PipelineKey, create: F) -> &wgpu::RenderPipeline
+    where
+        F: FnOnce(&PipelineKey) -> wgpu::RenderPipeline,
+    {
+        let frame = self.frame;
+        let cached = self.pipelines.entry(key).or_insert_with(|| CachedPipeline {
+            pipeline: create(&key),
+            last_used: frame,
+        });
+        cached.last_used = frame;
+        &cached.pipeline
+    }
+
+    /// Returns the pipeline for `key` if it is cached. Unlike `get_or_create`, this does not count
+    /// as a use of the pipeline; it is for borrowing several pipelines at once, each of which has
+    /// already been requested with `get_or_create` this frame.
+    pub fn get(&self, key: &PipelineKey) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(key).map(|cached| &cached.pipeline)
+    }
+
+    /// Advances the frame counter, and evicts any pipeline which has not been requested within the
+    /// last `max_unused_frames` frames.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+        let (frame, max_unused_frames) = (self.frame, self.max_unused_frames);
+        self.pipelines.retain(|_, cached| frame - cached.last_used <= max_unused_frames);
+    }
+}
@@ -0,0 +1,92 @@
+//! Writing meshes out as binary PLY (Polygon File Format) files.
+
+use std::io::Write;
+
+/// A vertex as written to a PLY file.
+pub struct PlyVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coord: [f32; 2],
+}
+
+/// Writes `vertices` and the triangles in `triangles`, which index into `vertices`, to `path` as a
+/// binary little-endian PLY file. Each vertex has the float properties `x`, `y`, `z`, `nx`, `ny`,
+/// `nz`, `s`, and `t`, and each face a `vertex_indices` list with a `uchar` count and `uint`
+/// indices, which is what MeshLab and CloudCompare expect.
+pub fn write_ply(
+    path: &std::path::Path,
+    vertices: &[PlyVertex],
+    triangles: &[[u32; 3]],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+    writeln!(file, "ply")?;
+    writeln!(file, "format binary_little_endian 1.0")?;
+    writeln!(file, "comment exported by jvox")?;
+    writeln!(file, "element vertex {}", vertices.len())?;
+    for property in &["x", "y", "z", "nx", "ny", "nz", "s", "t"] {
+        writeln!(file, "property float {}", property)?;
+    }
+    writeln!(file, "element face {}", triangles.len())?;
+    writeln!(file, "property list uchar uint vertex_indices")?;
+    writeln!(file, "end_header")?;
+
+    for vertex in vertices {
+        let values = vertex.position.iter().chain(vertex.normal.iter()).chain(vertex.tex_coord.iter());
+        for value in values {
+            file.write_all(&value.to_le_bytes())?;
+        }
+    }
+    for triangle in triangles {
+        file.write_all(&[3])?;
+        for index in triangle {
+            file.write_all(&index.to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Reads the header of a PLY file written by `write_ply`, returning the name and count of each
+/// element in order, and the length of the header in bytes. Returns `None` if there is no
+/// `end_header` line.
+#[cfg(test)]
+pub fn read_element_counts(bytes: &[u8]) -> Option<(Vec<(String, usize)>, usize)> {
+    const END_HEADER: &[u8] = b"end_header\n";
+    let header_len = bytes.windows(END_HEADER.len()).position(|window| window == END_HEADER)? + END_HEADER.len();
+    let header = std::str::from_utf8(&bytes[..header_len]).ok()?;
+    let elements = header
+        .lines()
+        .filter_map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["element", name, count] => Some((name.to_string(), count.parse().ok()?)),
+            _ => None,
+        })
+        .collect();
+    Some((elements, header_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_counts_match_the_body() {
+        let vertex = |x: f32| PlyVertex {
+            position: [x, 0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            tex_coord: [x, 0.0],
+        };
+        let vertices = [vertex(0.0), vertex(1.0), vertex(2.0), vertex(3.0)];
+        let triangles = [[0, 1, 2], [2, 3, 0]];
+        let path = std::env::temp_dir().join(format!("jvox_ply_test_{}.ply", std::process::id()));
+        write_ply(&path, &vertices, &triangles).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(bytes.starts_with(b"ply\nformat binary_little_endian 1.0\n"));
+        let (elements, header_len) = read_element_counts(&bytes).expect("no end_header line");
+        assert_eq!(elements, vec![("vertex".to_string(), 4), ("face".to_string(), 2)]);
+        // Eight floats per vertex, and a count byte and three indices per face.
+        assert_eq!(bytes.len() - header_len, 4 * 8 * 4 + 2 * (1 + 3 * 4));
+    }
+}
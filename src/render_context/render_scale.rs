@@ -0,0 +1,126 @@
+//! Rendering the scene at a different resolution than the window: above it for supersampling, or
+//! below it for performance. The scene is drawn into an offscreen target, which is then stretched
+//! over the window with bilinear filtering.
+
+use crate::postprocess::FullscreenQuad;
+
+/// The smallest and largest factors `set_render_scale` accepts; anything outside is clamped.
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 4.0;
+
+/// The format of the offscreen target, matching the swap chain so that every scene pipeline can
+/// draw into either.
+const SCALED_TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
+
+/// Returns the size of a `width` by `height` target rendered at `factor` times the resolution.
+/// Neither dimension drops below one pixel.
+pub fn scaled_size(width: u32, height: u32, factor: f32) -> (u32, u32) {
+    (
+        ((width as f32 * factor) as u32).max(1),
+        ((height as f32 * factor) as u32).max(1),
+    )
+}
+
+/// The offscreen color target the scene is rendered into at a scaled resolution, along with the
+/// pass blitting it onto the window.
+pub struct ScaledTarget {
+    target: crate::gpu::RenderTarget,
+    size: (u32, u32),
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    /// Samples `target`; rebuilt whenever the target is recreated.
+    bind_group: wgpu::BindGroup,
+    blit_quad: FullscreenQuad,
+}
+
+impl ScaledTarget {
+    /// Create a new `ScaledTarget` of `width` by `height` pixels.
+    pub fn new(gpu_context: &crate::gpu::GpuContext, width: u32, height: u32) -> Self {
+        let bind_group_layout = gpu_context.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            bindings: &[
+                wgpu::BindGroupLayoutEntry::new(
+                    0,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        component_type: wgpu::TextureComponentType::Float,
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    1,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::Sampler { comparison: false },
+                ),
+            ],
+        });
+
+        // Bilinear filtering in both directions, so that upscaling is smooth and downscaling
+        // averages neighbouring samples.
+        let sampler = gpu_context.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("blit.frag"));
+        let blit_quad = FullscreenQuad::new(gpu_context, &bind_group_layout, &fs_module, SCALED_TARGET_FORMAT);
+
+        let target = gpu_context.create_render_target(width, height, SCALED_TARGET_FORMAT, false);
+        let bind_group = create_blit_bind_group(gpu_context, &bind_group_layout, &target.color_view, &sampler);
+
+        Self {
+            target,
+            size: (width, height),
+            bind_group_layout,
+            sampler,
+            bind_group,
+            blit_quad,
+        }
+    }
+
+    /// The size of the target, in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// The view the scene should be rendered into.
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.target.color_view
+    }
+
+    /// Recreates the target at `width` by `height` pixels. The old contents are discarded.
+    pub fn resize(&mut self, gpu_context: &crate::gpu::GpuContext, width: u32, height: u32) {
+        if self.size == (width, height) {
+            return;
+        }
+        self.target.resize(gpu_context, width, height);
+        self.size = (width, height);
+        self.bind_group = create_blit_bind_group(gpu_context, &self.bind_group_layout, &self.target.color_view, &self.sampler);
+    }
+
+    /// Records a pass stretching the target over all of `target_view`, which must be in the swap
+    /// chain's format.
+    pub fn blit(&self, encoder: &mut wgpu::CommandEncoder, target_view: &wgpu::TextureView) {
+        self.blit_quad.render(encoder, target_view, &self.bind_group);
+    }
+}
+
+fn create_blit_bind_group(
+    gpu_context: &crate::gpu::GpuContext,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    source_view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    crate::bind_group_builder::BindGroupBuilder::new()
+        .add_texture(0, wgpu::ShaderStage::FRAGMENT, source_view)
+        .add_sampler(1, wgpu::ShaderStage::FRAGMENT, sampler)
+        .build_with_layout(gpu_context, bind_group_layout)
+}
@@ -0,0 +1,412 @@
+use serde::{Deserialize, Serialize};
+
+use crate::camera;
+use crate::particles::ParticleSystem;
+use crate::world_geometry::{TerrainConfig, WorldGeometryManager};
+
+use super::instanced::{InstancedMesh, InstancedPipelines};
+use super::terrain_mesh::TerrainMesh;
+use super::voxel_terrain::VoxelTerrainMesh;
+
+/// Everything that is rendered, as opposed to the machinery that renders it. `RenderContext` owns
+/// the GPU infrastructure (pipelines, bind groups, render targets) and hands it to the scene each
+/// frame; the scene owns the content and knows how to advance and draw it.
+pub struct Scene {
+    pub camera: camera::Camera,
+
+    /// Generates the terrain, and answers queries about its shape.
+    pub world_geometry_manager: WorldGeometryManager,
+    pub terrain: TerrainMesh,
+
+    pub instanced_meshes: Vec<InstancedMesh>,
+    /// The mesh of the voxel terrain grid, if there is one. Each of its chunks is drawn like an
+    /// opaque instanced mesh with a single instance.
+    pub voxel_terrain: Option<VoxelTerrainMesh>,
+    pub particle_system: Option<ParticleSystem>,
+    /// The color the sky is cleared to before anything is drawn. See `lights::sky_color`.
+    pub clear_color: wgpu::Color,
+}
+
+/// The persistent parts of a `Scene`: enough to regenerate the same terrain and view it from the
+/// same place. This is saved to and loaded from TOML files.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneState {
+    pub terrain_config: TerrainConfig,
+    pub camera_position: [f32; 3],
+    /// The camera's yaw, in radians.
+    pub camera_yaw: f32,
+    /// The camera's pitch, in radians.
+    pub camera_pitch: f32,
+    pub sea_level: f32,
+    /// When set, the scene is restored from `DEFAULT_SCENE_PATH` on startup and saved back to it
+    /// on exit.
+    #[serde(default)]
+    pub autosave: bool,
+    /// Replaces the default controls, if present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_bindings: Option<crate::input::KeyBindings>,
+    /// The voxel terrain, if there is one, run-length encoded. See `RenderContext::set_terrain_grid`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terrain_grid: Option<crate::voxel::VoxelGrid<bool>>,
+}
+
+impl Default for SceneState {
+    /// The default terrain, viewed from a vantage point looking toward the origin.
+    fn default() -> Self {
+        use cgmath::InnerSpace;
+
+        let view = cgmath::Vector3::new(-1.0f32, -1.0, -1.0).normalize();
+        Self {
+            terrain_config: TerrainConfig::default(),
+            camera_position: [32.0, 32.0, 32.0],
+            camera_yaw: view.y.atan2(view.x),
+            camera_pitch: view.z.asin(),
+            sea_level: 0.0,
+            autosave: false,
+            key_bindings: None,
+            terrain_grid: None,
+        }
+    }
+}
+
+impl SceneState {
+    /// Reads a `SceneState` from the TOML file at `path`.
+    pub fn read(path: &std::path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Writes this `SceneState` to `path` as TOML, replacing any existing file.
+    pub fn write(&self, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    /// Formats this `SceneState` as TOML.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        // TOML requires plain values to come before tables. Going through a `toml::Value` orders
+        // them so, whatever the order of the fields.
+        toml::to_string(&toml::Value::try_from(self)?)
+    }
+}
+
+/// The pipelines used to draw the contents of a `Scene`. All must use the main bind group layout.
+pub struct ScenePipelines<'a> {
+    pub terrain: &'a wgpu::RenderPipeline,
+    /// Whether `terrain` draws lines rather than triangles, and so needs the terrain's line indices.
+    pub terrain_wireframe: bool,
+    /// The topology `terrain` draws filled triangles with.
+    pub terrain_topology: super::TopologyMode,
+    pub instanced: &'a InstancedPipelines,
+    /// Draws the instanced meshes marked transparent.
+    pub instanced_transparent: &'a InstancedPipelines,
+    /// The time uniform, bound at group 1 of the terrain pipeline.
+    pub time_bind_group: &'a wgpu::BindGroup,
+}
+
+/// The part of the render target a view is drawn into. Each field covers the whole target when
+/// `None`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ViewRegion {
+    /// The `(x, y, width, height)` rectangle, in pixels, that normalized device coordinates are
+    /// mapped onto.
+    pub viewport: Option<(f32, f32, f32, f32)>,
+    /// The `(x, y, width, height)` rectangle, in pixels, outside of which fragments are discarded.
+    /// This must lie within the render target.
+    pub scissor_rect: Option<(u32, u32, u32, u32)>,
+}
+
+impl ViewRegion {
+    /// Restricts the following draws in `render_pass` to this region.
+    pub fn apply(&self, render_pass: &mut wgpu::RenderPass) {
+        if let Some((x, y, width, height)) = self.viewport {
+            render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        }
+        if let Some((x, y, width, height)) = self.scissor_rect {
+            render_pass.set_scissor_rect(x, y, width, height);
+        }
+    }
+}
+
+impl Scene {
+    pub fn new(
+        camera: camera::Camera,
+        world_geometry_manager: WorldGeometryManager,
+        terrain: TerrainMesh,
+    ) -> Self {
+        Self {
+            camera,
+            world_geometry_manager,
+            terrain,
+            instanced_meshes: Vec::new(),
+            voxel_terrain: None,
+            particle_system: None,
+            clear_color: super::lights::sky_color(super::lights::DEFAULT_SUN_ELEVATION),
+        }
+    }
+
+    /// Captures the persistent parts of the scene.
+    pub fn state(&self, autosave: bool) -> SceneState {
+        SceneState {
+            terrain_config: self.world_geometry_manager.config(),
+            camera_position: self.camera.position().into(),
+            camera_yaw: self.camera.yaw().0,
+            camera_pitch: self.camera.pitch().0,
+            sea_level: self.world_geometry_manager.sea_level() as f32,
+            autosave,
+            key_bindings: None,
+            terrain_grid: None,
+        }
+    }
+
+    /// The number of triangles drawn by `record_commands`.
+    pub fn triangle_count(&self) -> u64 {
+        let voxel_meshes = self.voxel_terrain.iter().flat_map(VoxelTerrainMesh::meshes);
+        self.terrain.triangle_count()
+            + voxel_meshes.map(InstancedMesh::triangle_count).sum::<u64>()
+            + self.instanced_meshes.iter().map(InstancedMesh::triangle_count).sum::<u64>()
+    }
+
+    /// Advances any simulated content by `delta_secs`.
+    pub fn update(&mut self, delta_secs: f32) {
+        if let Some(particle_system) = self.particle_system.as_mut() {
+            let world_geometry_manager = &self.world_geometry_manager;
+            particle_system.tick_with_ground(delta_secs, |x, y| world_geometry_manager.height_at(x, y));
+        }
+    }
+
+    /// Flushes any dirty scene buffers to the GPU.
+    pub fn enqueue_copy_commands(
+        &mut self,
+        gpu_context: &mut crate::gpu::GpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        self.terrain.enqueue_copy_commands(gpu_context, encoder);
+        let voxel_meshes = self.voxel_terrain.iter_mut().flat_map(VoxelTerrainMesh::meshes_mut);
+        for instanced_mesh in voxel_meshes.chain(self.instanced_meshes.iter_mut()) {
+            instanced_mesh.enqueue_copy_commands(gpu_context, encoder);
+        }
+    }
+
+    /// Records a render pass drawing every chunk of terrain into `region` of `color_view`, followed
+    /// by all opaque instanced objects, and then all transparent ones from back to front. The whole
+    /// target is cleared first. The camera the scene is viewed
+    /// from is selected by `bind_group`, which must match the main bind group layout, so the same
+    /// scene can be drawn from several views.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_commands(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipelines: &ScenePipelines,
+        bind_group: &wgpu::BindGroup,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        region: &ViewRegion,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color),
+                    store: true,
+                }
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: true,
+                }),
+            }),
+        });
+
+        region.apply(&mut render_pass);
+        self.draw_opaque(&mut render_pass, pipelines, bind_group);
+        self.draw_transparent(&mut render_pass, pipelines, bind_group);
+    }
+
+    /// Records a render pass drawing the opaque contents of the scene into `region` of the deferred
+    /// path's G-buffer, whose targets are `gbuffer_views`. The G-buffer is cleared to zero first,
+    /// which the lighting pass reads as empty, and the depth buffer is cleared too. `pipelines`
+    /// must draw into the G-buffer; the transparent instanced pipelines are unused.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_gbuffer_commands(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipelines: &ScenePipelines,
+        bind_group: &wgpu::BindGroup,
+        gbuffer_views: [&wgpu::TextureView; 4],
+        depth_view: &wgpu::TextureView,
+        region: &ViewRegion,
+    ) {
+        let color_attachments: Vec<_> = gbuffer_views
+            .iter()
+            .map(|&attachment| wgpu::RenderPassColorAttachmentDescriptor {
+                attachment,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })
+            .collect();
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: true,
+                }),
+            }),
+        });
+
+        region.apply(&mut render_pass);
+        self.draw_opaque(&mut render_pass, pipelines, bind_group);
+    }
+
+    /// Records a render pass drawing the transparent instanced objects into `region` of
+    /// `color_view`, from back to front, over what is already there. `depth_view` must hold the
+    /// depth of the opaque contents, as left by `record_gbuffer_commands`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_transparent_commands(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipelines: &ScenePipelines,
+        bind_group: &wgpu::BindGroup,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        region: &ViewRegion,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        region.apply(&mut render_pass);
+        self.draw_transparent(&mut render_pass, pipelines, bind_group);
+    }
+
+    /// Draws the terrain, the voxel terrain, and the opaque instanced objects.
+    fn draw_opaque<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &ScenePipelines<'a>,
+        bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(pipelines.terrain);
+        render_pass.set_bind_group(1, pipelines.time_bind_group, &[]);
+        self.terrain.draw(render_pass, bind_group, pipelines.terrain_wireframe, pipelines.terrain_topology);
+
+        // Instanced objects carry their own model matrices, so the per-chunk transform is unused; the
+        // dynamic offset only needs to be valid. The pipeline depends on each mesh's index format,
+        // so it is only switched when that changes.
+        let mut bound_format = None;
+        let opaque_meshes = self.instanced_meshes.iter().filter(|mesh| !mesh.transparent());
+        let voxel_meshes = self.voxel_terrain.iter().flat_map(VoxelTerrainMesh::meshes);
+        for instanced_mesh in voxel_meshes.chain(opaque_meshes) {
+            let index_format = instanced_mesh.index_format();
+            if bound_format != Some(index_format) {
+                render_pass.set_pipeline(pipelines.instanced.get(index_format));
+                render_pass.set_bind_group(0, bind_group, &[0]);
+                bound_format = Some(index_format);
+            }
+            instanced_mesh.draw(render_pass);
+        }
+    }
+
+    /// Draws the transparent instanced objects, from back to front.
+    fn draw_transparent<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &ScenePipelines<'a>,
+        bind_group: &'a wgpu::BindGroup,
+    ) {
+        let mut bound_format = None;
+        let mut bound_mesh = None;
+        for (mesh_index, instance_index) in self.sorted_transparent_draws() {
+            let instanced_mesh = &self.instanced_meshes[mesh_index];
+            let index_format = instanced_mesh.index_format();
+            if bound_format != Some(index_format) {
+                render_pass.set_pipeline(pipelines.instanced_transparent.get(index_format));
+                render_pass.set_bind_group(0, bind_group, &[0]);
+                bound_format = Some(index_format);
+            }
+            if bound_mesh != Some(mesh_index) {
+                instanced_mesh.set_buffers(render_pass);
+                bound_mesh = Some(mesh_index);
+            }
+            instanced_mesh.draw_instance(render_pass, instance_index);
+        }
+    }
+
+    /// Every instance of a transparent instanced mesh, as `(mesh index, instance index)` pairs,
+    /// ordered from farthest to nearest the camera so that each blends over what lies behind it.
+    // TODO: Views with a camera of their own (i.e. the minimap) are sorted by the main camera too.
+    // From straight above, the order rarely matters.
+    fn sorted_transparent_draws(&self) -> Vec<(usize, usize)> {
+        use cgmath::MetricSpace;
+
+        let eye = self.camera.position();
+        let mut draws: Vec<(usize, usize, f32)> = self.instanced_meshes
+            .iter()
+            .enumerate()
+            .filter(|(_, mesh)| mesh.transparent())
+            .flat_map(|(mesh_index, mesh)| {
+                (0..mesh.instance_count()).map(move |instance_index| {
+                    (mesh_index, instance_index, mesh.instance_position(instance_index).distance2(eye))
+                })
+            })
+            .collect();
+        draws.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
+        draws.into_iter().map(|(mesh_index, instance_index, _)| (mesh_index, instance_index)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terrain_grid_round_trips_through_toml() {
+        let mut grid = crate::voxel::VoxelGrid::new(6, 5, 4);
+        for (x, y, z) in [(0, 0, 0), (5, 4, 3), (2, 3, 1), (3, 3, 1)].iter().copied() {
+            grid.set(x, y, z, true);
+        }
+        let state = SceneState {
+            terrain_grid: Some(grid.clone()),
+            ..SceneState::default()
+        };
+        let loaded: SceneState = toml::from_str(&state.to_toml().unwrap()).unwrap();
+        assert_eq!(loaded.terrain_grid, Some(grid));
+
+        // Without a grid, nothing is written, and nothing is read back.
+        let toml = SceneState::default().to_toml().unwrap();
+        assert!(!toml.contains("terrain_grid"));
+        let loaded: SceneState = toml::from_str(&toml).unwrap();
+        assert_eq!(loaded.terrain_grid, None);
+    }
+}
@@ -0,0 +1,74 @@
+//! Reading rendered frames back from the GPU.
+
+/// The height above the terrain from which turntable frames are captured.
+pub const TURNTABLE_ELEVATION: f32 = 80.0;
+
+/// The format screenshots are rendered in. This matches the swap chain, so the same pipelines can
+/// draw into either.
+pub const CAPTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8UnormSrgb;
+
+/// Rows copied out of a texture must be padded to a multiple of this many bytes.
+const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+
+/// Copies the contents of a `CAPTURE_FORMAT` texture into an image. This
+/// submits its own commands and blocks until the GPU has finished them.
+pub fn read_texture(
+    gpu_context: &crate::gpu::GpuContext,
+    texture: &wgpu::Texture,
+    width: u32,
+    height: u32,
+) -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+    let unpadded_bytes_per_row = 4 * width;
+    let padded_bytes_per_row = (unpadded_bytes_per_row + COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+        / COPY_BYTES_PER_ROW_ALIGNMENT
+        * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let buffer = gpu_context.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = gpu_context.create_command_encoder();
+    encoder.copy_texture_to_buffer(
+        wgpu::TextureCopyView {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        wgpu::BufferCopyView {
+            buffer: &buffer,
+            layout: wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: padded_bytes_per_row,
+                rows_per_image: 0,
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+    );
+    gpu_context.submit_command_encoder(encoder);
+
+    let slice = buffer.slice(..);
+    let mapped = slice.map_async(wgpu::MapMode::Read);
+    gpu_context.poll(wgpu::Maintain::Wait);
+    futures::executor::block_on(mapped).map_err(|_| "failed to map the screenshot buffer")?;
+
+    // Strip the row padding, and swizzle each pixel from BGRA to RGBA.
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            for bgra in row[..unpadded_bytes_per_row as usize].chunks(4) {
+                pixels.extend_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+            }
+        }
+    }
+    buffer.unmap();
+
+    image::RgbaImage::from_raw(width, height, pixels).ok_or_else(|| "screenshot has the wrong size".into())
+}
@@ -0,0 +1,512 @@
+use crate::managed_buffer::ManagedBuffer;
+use crate::utils;
+use crate::world_geometry::{ChunkIndex, WorldMesh};
+
+/// The primitive topology the filled terrain is drawn with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TopologyMode {
+    /// Three indices per triangle.
+    List,
+    /// Each face is a triangle strip of its own, separated from the next by a primitive restart.
+    Strip,
+}
+
+impl TopologyMode {
+    pub fn primitive_topology(self) -> wgpu::PrimitiveTopology {
+        match self {
+            TopologyMode::List => wgpu::PrimitiveTopology::TriangleList,
+            TopologyMode::Strip => wgpu::PrimitiveTopology::TriangleStrip,
+        }
+    }
+}
+
+/// New vertices for a rectangle of chunks, replacing theirs without regenerating the rest of the
+/// terrain. The rectangle is given in chunk coordinates: `origin_x` and `origin_y` are those of its
+/// first chunk, and it covers `width` chunks along x and `depth` along y. `vertices` holds the new
+/// vertices of each chunk in the same row-major order the terrain is laid out in.
+///
+/// Only the vertices are replaced, so each chunk must keep its vertex count, and the new vertices
+/// must fit the chunk's existing indices. This suits edits which move, recolor, or retexture
+/// faces, but not ones which add or remove them.
+#[derive(Clone, Debug)]
+pub struct TerrainPatch {
+    pub origin_x: u32,
+    pub origin_y: u32,
+    pub width: u32,
+    pub depth: u32,
+    pub vertices: Vec<utils::IVertex>,
+}
+
+impl TerrainPatch {
+    /// The rectangle of chunks the patch covers.
+    #[allow(dead_code)]
+    pub fn region(&self) -> PatchRegion {
+        PatchRegion {
+            origin_x: self.origin_x,
+            origin_y: self.origin_y,
+            width: self.width,
+            depth: self.depth,
+        }
+    }
+}
+
+/// A rectangle of chunks, in chunk coordinates, as covered by a `TerrainPatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PatchRegion {
+    pub origin_x: u32,
+    pub origin_y: u32,
+    pub width: u32,
+    pub depth: u32,
+}
+
+/// The GPU side of the world geometry: the vertex and index buffers holding every chunk, the buffer
+/// of per-chunk transforms, and the index ranges needed to draw each chunk. Mesh generation happens
+/// on the CPU in `world_geometry`, so it can be exercised without any GPU resources.
+pub struct TerrainMesh {
+    chunks: Vec<ChunkIndex>,
+
+    /// This buffer holds the transforms for each of our chunks.
+    transforms_buf: ManagedBuffer<utils::PaddedMatrix4, Vec<utils::PaddedMatrix4>>,
+
+    /// This buffer holds the full mesh for the world geometry.
+    vertex_buf: ManagedBuffer<utils::IVertex, Vec<utils::IVertex>>,
+    index_buf: ManagedBuffer<u16, Vec<u16>>,
+    /// The edges of every triangle in `index_buf`, as a line list. Each chunk's line indices start
+    /// at twice its `index_offset`, and number twice its `index_count`.
+    line_index_buf: ManagedBuffer<u16, Vec<u16>>,
+    /// Every chunk's triangles as a triangle strip, for `TopologyMode::Strip`. Each chunk's range of
+    /// this buffer is in `strip_ranges`.
+    strip_index_buf: ManagedBuffer<u16, Vec<u16>>,
+    strip_ranges: Vec<std::ops::Range<u32>>,
+}
+
+impl TerrainMesh {
+    /// Create a new `TerrainMesh` holding `mesh`. The data is flushed to the GPU with the next call
+    /// to `enqueue_copy_commands`.
+    pub fn new(gpu_context: &crate::gpu::GpuContext, mesh: WorldMesh) -> Option<Self> {
+        let (strip_indices, strip_ranges) = triangle_strips(&mesh);
+        Some(Self {
+            transforms_buf: ManagedBuffer::new_uniform_buf_with_data(gpu_context, mesh.chunk_transforms).ok()?,
+            vertex_buf: ManagedBuffer::new_vertex_buf_with_data(gpu_context, mesh.vertices).ok()?,
+            line_index_buf: ManagedBuffer::new_index_buf_with_data(gpu_context, triangle_edges(&mesh.indices)).ok()?,
+            index_buf: ManagedBuffer::new_index_buf_with_data(gpu_context, mesh.indices).ok()?,
+            strip_index_buf: ManagedBuffer::new_index_buf_with_data(gpu_context, strip_indices).ok()?,
+            strip_ranges,
+            chunks: mesh.chunks,
+        })
+    }
+
+    /// Replaces the terrain with `mesh`. Buffers are reused where the new data fits, and recreated
+    /// otherwise. If the number of chunks changed the transforms buffer is recreated, so any bind
+    /// group referencing `transforms_buf` must then be rebuilt.
+    pub fn upload(&mut self, gpu_context: &crate::gpu::GpuContext, mesh: WorldMesh) -> Option<()> {
+        let (strip_indices, strip_ranges) = triangle_strips(&mesh);
+        self.resize_if_needed(gpu_context, &mesh)?;
+        if self.strip_index_buf.len() != strip_indices.len() {
+            self.strip_index_buf = ManagedBuffer::new_index_buf_with_data(gpu_context, strip_indices).ok()?;
+        } else {
+            self.strip_index_buf.replace_data(strip_indices);
+        }
+        self.strip_ranges = strip_ranges;
+        self.chunks = mesh.chunks;
+        // After resizing every length matches, so these replacements cannot fail.
+        self.transforms_buf.replace_data(mesh.chunk_transforms);
+        self.vertex_buf.replace_data(mesh.vertices);
+        self.line_index_buf.replace_data(triangle_edges(&mesh.indices));
+        self.index_buf.replace_data(mesh.indices);
+        Some(())
+    }
+
+    /// Recreates any buffer whose length differs from the corresponding data in `mesh`, since a
+    /// `ManagedBuffer` cannot change length in place. The recreated buffers are zeroed.
+    pub fn resize_if_needed(&mut self, gpu_context: &crate::gpu::GpuContext, mesh: &WorldMesh) -> Option<()> {
+        if self.transforms_buf.len() != mesh.chunk_transforms.len() {
+            self.transforms_buf = ManagedBuffer::new_uniform_buf_with_data(
+                gpu_context,
+                vec![bytemuck::Zeroable::zeroed(); mesh.chunk_transforms.len()],
+            ).ok()?;
+        }
+        if self.vertex_buf.len() != mesh.vertices.len() {
+            self.vertex_buf = ManagedBuffer::new_vertex_buf_with_data(
+                gpu_context,
+                vec![bytemuck::Zeroable::zeroed(); mesh.vertices.len()],
+            ).ok()?;
+        }
+        if self.index_buf.len() != mesh.indices.len() {
+            self.index_buf = ManagedBuffer::new_index_buf_with_data(
+                gpu_context,
+                vec![0; mesh.indices.len()],
+            ).ok()?;
+        }
+        if self.line_index_buf.len() != mesh.indices.len() * 2 {
+            self.line_index_buf = ManagedBuffer::new_index_buf_with_data(
+                gpu_context,
+                vec![0; mesh.indices.len() * 2],
+            ).ok()?;
+        }
+        Some(())
+    }
+
+    /// The number of triangles drawn by `draw`.
+    pub fn triangle_count(&self) -> u64 {
+        (self.index_buf.len() / 3) as u64
+    }
+
+    /// The vertices of every chunk, as last uploaded.
+    pub fn vertices(&self) -> &[utils::IVertex] {
+        self.vertex_buf.host_data()
+    }
+
+    /// The triangle list indices of every chunk, as last uploaded. Each chunk's indices are
+    /// relative to its `vertex_offset`.
+    pub fn indices(&self) -> &[u16] {
+        self.index_buf.host_data()
+    }
+
+    /// The terrain as last uploaded, so that some of its chunks can be generated again without
+    /// generating the rest; see `WorldGeometryManager::regenerate_chunks`.
+    pub fn world_mesh(&self) -> WorldMesh {
+        WorldMesh {
+            chunks: self.chunks.clone(),
+            chunk_transforms: self.transforms_buf.host_data().to_vec(),
+            vertices: self.vertex_buf.host_data().to_vec(),
+            indices: self.index_buf.host_data().to_vec(),
+        }
+    }
+
+    /// Writes the vertices of `patch` straight into the vertex buffer through `queue`, leaving
+    /// every vertex outside the patch untouched. `chunks_x` is the number of chunks in each row of
+    /// the terrain. Each row of chunks in the patch is contiguous in the vertex buffer, so there is
+    /// one write per row. Nothing is written if the patch lies outside the terrain or has the wrong
+    /// number of vertices.
+    pub fn apply_patch(
+        &mut self,
+        queue: &wgpu::Queue,
+        chunks_x: usize,
+        patch: &TerrainPatch,
+    ) -> Result<(), &'static str> {
+        // Find the range of vertices covered by each row before writing any of them.
+        let rows = patch_rows(&self.chunks, self.vertex_buf.len(), chunks_x, patch)?;
+        let mut remaining = &patch.vertices[..];
+        for row in rows {
+            let (row_vertices, rest) = remaining.split_at(row.len());
+            remaining = rest;
+            self.vertex_buf
+                .write_range(queue, row.start, row_vertices)
+                .ok_or("the patch runs past the end of the vertex buffer")?;
+        }
+        Ok(())
+    }
+
+    /// Each chunk as last uploaded: its transform into world space, its vertices, and its triangle
+    /// list indices, relative to its first vertex.
+    pub fn chunk_geometry(&self) -> impl Iterator<Item = (cgmath::Matrix4<f32>, &[utils::IVertex], &[u16])> {
+        (0..self.chunks.len()).map(move |i| {
+            let chunk = &self.chunks[i];
+            (
+                self.transforms_buf.host_data()[chunk.transform_index].matrix(),
+                &self.vertex_buf.host_data()[self.chunk_vertex_range(i)],
+                &self.index_buf.host_data()[chunk.index_offset..chunk.index_offset + chunk.index_count],
+            )
+        })
+    }
+
+    /// The indices of the chunks in `region`, row by row, or `None` if it lies outside the terrain.
+    /// `chunks_x` is the number of chunks in each row of the terrain.
+    fn region_chunks(&self, chunks_x: usize, region: PatchRegion) -> Option<Vec<usize>> {
+        let (origin_x, origin_y) = (region.origin_x as usize, region.origin_y as usize);
+        let (width, depth) = (region.width as usize, region.depth as usize);
+        if origin_x + width > chunks_x || (origin_y + depth) * chunks_x > self.chunks.len() {
+            return None;
+        }
+        Some((origin_y..origin_y + depth)
+            .flat_map(|y| (y * chunks_x + origin_x)..(y * chunks_x + origin_x + width))
+            .collect())
+    }
+
+    /// The center of the world space bounding box of the chunks in `region`, as last uploaded, or
+    /// `None` if the region is empty or lies outside the terrain.
+    pub fn region_center(&self, chunks_x: usize, region: PatchRegion) -> Option<cgmath::Point3<f32>> {
+        let (min, max) = self.region_chunks(chunks_x, region)?
+            .into_iter()
+            .map(|chunk| self.chunk_bounds(chunk))
+            .fold(EMPTY_BOUNDS, union_bounds);
+        if min[0] > max[0] {
+            return None;
+        }
+        Some(cgmath::Point3::new(
+            (min[0] + max[0]) / 2.0,
+            (min[1] + max[1]) / 2.0,
+            (min[2] + max[2]) / 2.0,
+        ))
+    }
+
+    /// The world space bounding box of every chunk, as last uploaded, as `(min, max)` corners. A
+    /// chunk without vertices has a box whose minimum exceeds its maximum.
+    pub fn all_chunk_bounds(&self) -> Vec<([f32; 3], [f32; 3])> {
+        (0..self.chunks.len()).map(|chunk| self.chunk_bounds(chunk)).collect()
+    }
+
+    /// The world space bounding box of the `chunk`th chunk, as last uploaded.
+    fn chunk_bounds(&self, chunk: usize) -> ([f32; 3], [f32; 3]) {
+        let transform = self.transforms_buf.host_data()[self.chunks[chunk].transform_index].matrix();
+        self.vertex_buf.host_data()[self.chunk_vertex_range(chunk)]
+            .iter()
+            .map(|vertex| {
+                let [x, y, z] = vertex.position();
+                let world = transform * cgmath::Vector4::new(x as f32, y as f32, z as f32, 1.0);
+                let point = [world.x, world.y, world.z];
+                (point, point)
+            })
+            .fold(EMPTY_BOUNDS, union_bounds)
+    }
+
+    /// The range of `vertex_buf` holding the vertices of the `chunk`th chunk.
+    fn chunk_vertex_range(&self, chunk: usize) -> std::ops::Range<usize> {
+        chunk_vertex_range(&self.chunks, self.vertex_buf.len(), chunk)
+    }
+
+    pub fn transforms_buf(&self) -> wgpu::BufferSlice {
+        self.transforms_buf.slice(..)
+    }
+
+    /// Flushes any dirty buffers to the GPU.
+    pub fn enqueue_copy_commands(
+        &mut self,
+        gpu_context: &mut crate::gpu::GpuContext,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.transforms_buf.dirty() {
+            self.transforms_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+        if self.vertex_buf.dirty() {
+            self.vertex_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+        if self.index_buf.dirty() {
+            self.index_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+        if self.line_index_buf.dirty() {
+            self.line_index_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+        if self.strip_index_buf.dirty() {
+            self.strip_index_buf.enqueue_copy_command(gpu_context, encoder);
+        }
+    }
+
+    /// Draws every chunk. The terrain pipeline must already be set on `render_pass`; `bind_group`
+    /// is rebound for each chunk with the dynamic offset selecting that chunk's transform. If
+    /// `wireframe` is set, the triangles' edges are drawn instead, so the pipeline must draw lines.
+    /// Otherwise, the pipeline's topology must match `topology`.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        bind_group: &'a wgpu::BindGroup,
+        wireframe: bool,
+        topology: TopologyMode,
+    ) {
+        let index_buf = if wireframe {
+            &self.line_index_buf
+        } else {
+            match topology {
+                TopologyMode::List => &self.index_buf,
+                TopologyMode::Strip => &self.strip_index_buf,
+            }
+        };
+        render_pass.set_index_buffer(index_buf.slice(..));
+        render_pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        for (chunk, strip_range) in self.chunks.iter().zip(self.strip_ranges.iter()) {
+            render_pass.set_bind_group(
+                0,
+                bind_group,
+                &[(chunk.transform_index * self.transforms_buf.t_size()) as u32],
+            );
+            let indices = if !wireframe && topology == TopologyMode::Strip {
+                strip_range.clone()
+            } else {
+                // Every triangle contributes three lines of two indices each.
+                let indices_per_index = if wireframe { 2 } else { 1 };
+                let start = chunk.index_offset * indices_per_index;
+                let end = (chunk.index_offset + chunk.index_count) * indices_per_index;
+                start as u32..end as u32
+            };
+            render_pass.draw_indexed(indices, chunk.vertex_offset as i32, 0..1);
+        }
+    }
+
+    /// Draws the filled triangles of the chunks in `region`, as a triangle list, like `draw`.
+    /// `chunks_x` is the number of chunks in each row of the terrain. Nothing is drawn if the
+    /// region lies outside the terrain.
+    pub fn draw_region<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        bind_group: &'a wgpu::BindGroup,
+        chunks_x: usize,
+        region: PatchRegion,
+    ) {
+        let chunks = match self.region_chunks(chunks_x, region) {
+            Some(chunks) => chunks,
+            None => return,
+        };
+        render_pass.set_index_buffer(self.index_buf.slice(..));
+        render_pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        for chunk in chunks.into_iter().map(|index| &self.chunks[index]) {
+            render_pass.set_bind_group(
+                0,
+                bind_group,
+                &[(chunk.transform_index * self.transforms_buf.t_size()) as u32],
+            );
+            let indices = chunk.index_offset as u32..(chunk.index_offset + chunk.index_count) as u32;
+            render_pass.draw_indexed(indices, chunk.vertex_offset as i32, 0..1);
+        }
+    }
+}
+
+/// A bounding box containing nothing, which any other box can be merged into.
+const EMPTY_BOUNDS: ([f32; 3], [f32; 3]) = ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]);
+
+/// The smallest bounding box containing both `a` and `b`, each as `(min, max)` corners.
+fn union_bounds(a: ([f32; 3], [f32; 3]), b: ([f32; 3], [f32; 3])) -> ([f32; 3], [f32; 3]) {
+    let ((a_min, a_max), (b_min, b_max)) = (a, b);
+    (
+        [a_min[0].min(b_min[0]), a_min[1].min(b_min[1]), a_min[2].min(b_min[2])],
+        [a_max[0].max(b_max[0]), a_max[1].max(b_max[1]), a_max[2].max(b_max[2])],
+    )
+}
+
+/// The range of vertices of the `chunk`th of `chunks`, whose vertices are laid out one chunk after
+/// another in a buffer of `vertex_count` vertices.
+fn chunk_vertex_range(chunks: &[ChunkIndex], vertex_count: usize, chunk: usize) -> std::ops::Range<usize> {
+    let start = chunks[chunk].vertex_offset;
+    let end = chunks.get(chunk + 1).map_or(vertex_count, |next| next.vertex_offset);
+    start..end
+}
+
+/// The range of vertices covered by each row of chunks in `patch`, in order, for a terrain of
+/// `chunks` laid out in rows of `chunks_x` over a buffer of `vertex_count` vertices. Fails if the
+/// patch lies outside the terrain or has the wrong number of vertices.
+fn patch_rows(
+    chunks: &[ChunkIndex],
+    vertex_count: usize,
+    chunks_x: usize,
+    patch: &TerrainPatch,
+) -> Result<Vec<std::ops::Range<usize>>, &'static str> {
+    let (origin_x, origin_y) = (patch.origin_x as usize, patch.origin_y as usize);
+    let (width, depth) = (patch.width as usize, patch.depth as usize);
+    if origin_x + width > chunks_x || (origin_y + depth) * chunks_x > chunks.len() {
+        return Err("the patch lies outside the terrain");
+    }
+
+    let rows: Vec<std::ops::Range<usize>> = (origin_y..origin_y + depth)
+        .filter(|_| width > 0)
+        .map(|y| {
+            let first = y * chunks_x + origin_x;
+            chunk_vertex_range(chunks, vertex_count, first).start
+                ..chunk_vertex_range(chunks, vertex_count, first + width - 1).end
+        })
+        .collect();
+    if rows.iter().map(|row| row.len()).sum::<usize>() != patch.vertices.len() {
+        return Err("the patch does not have the same number of vertices as the chunks it covers");
+    }
+    Ok(rows)
+}
+
+/// Converts each chunk of `mesh` into triangle strips. Returns the strips, one after another, and
+/// the range each chunk's strips occupy. Chunks are drawn separately, so their strips need not be
+/// joined. The terrain's indices are 16-bit, so 0xFFFF restarts a strip; no chunk has that many
+/// vertices.
+fn triangle_strips(mesh: &WorldMesh) -> (Vec<u16>, Vec<std::ops::Range<u32>>) {
+    let mut strips = Vec::with_capacity(mesh.indices.len());
+    let mut ranges = Vec::with_capacity(mesh.chunks.len());
+    for chunk in mesh.chunks.iter() {
+        let start = strips.len() as u32;
+        let chunk_indices = &mesh.indices[chunk.index_offset..chunk.index_offset + chunk.index_count];
+        strips.extend(utils::triangle_list_to_strip(chunk_indices, u16::max_value()));
+        ranges.push(start..strips.len() as u32);
+    }
+    (strips, ranges)
+}
+
+/// Converts a triangle list into a line list of each triangle's three edges.
+fn triangle_edges(indices: &[u16]) -> Vec<u16> {
+    let mut edges = Vec::with_capacity(indices.len() * 2);
+    for triangle in indices.chunks(3) {
+        if let [a, b, c] = *triangle {
+            edges.extend_from_slice(&[a, b, b, c, c, a]);
+        }
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A terrain of 3 by 2 chunks, the `i`th of which has `i + 1` vertices, all of them at
+    /// `(i, 0, 0)`.
+    fn chunks_and_vertices() -> (Vec<ChunkIndex>, Vec<utils::IVertex>) {
+        let mut chunks = Vec::new();
+        let mut vertices = Vec::new();
+        for i in 0..6 {
+            chunks.push(ChunkIndex {
+                vertex_offset: vertices.len(),
+                index_offset: 0,
+                index_count: 0,
+                transform_index: i,
+            });
+            let position = cgmath::Vector3::new(i as i32, 0, 0);
+            let vertex = utils::IVertex::new(position, cgmath::Point3::new(0, 0, 0), 0, (0, 0));
+            vertices.extend(std::iter::repeat(vertex).take(i + 1));
+        }
+        (chunks, vertices)
+    }
+
+    #[test]
+    fn patch_leaves_vertices_outside_it_alone() {
+        let (chunks, mut vertices) = chunks_and_vertices();
+        let original = vertices.clone();
+        // The right two chunks of both rows: chunks 1, 2, 4, and 5, with 2 + 3 + 5 + 6 vertices.
+        let patched = utils::IVertex::new(cgmath::Vector3::new(-1, -1, -1), cgmath::Point3::new(0, 0, 0), 0, (0, 0));
+        let patch = TerrainPatch {
+            origin_x: 1,
+            origin_y: 0,
+            width: 2,
+            depth: 2,
+            vertices: vec![patched; 16],
+        };
+
+        // Apply the patch as `apply_patch` does, to the CPU copy only.
+        let rows = patch_rows(&chunks, vertices.len(), 3, &patch).unwrap();
+        let mut remaining = &patch.vertices[..];
+        for row in rows {
+            let (row_vertices, rest) = remaining.split_at(row.len());
+            remaining = rest;
+            vertices[row].copy_from_slice(row_vertices);
+        }
+
+        for chunk in 0..chunks.len() {
+            let inside = [1, 2, 4, 5].contains(&chunk);
+            for (vertex, original) in vertices[chunk_vertex_range(&chunks, vertices.len(), chunk)]
+                .iter()
+                .zip(original[chunk_vertex_range(&chunks, original.len(), chunk)].iter())
+            {
+                let expected = if inside { patched.position() } else { original.position() };
+                assert_eq!(vertex.position(), expected, "chunk {}", chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn patch_must_fit_the_terrain() {
+        let (chunks, vertices) = chunks_and_vertices();
+        let patch = |origin_x, width, vertex_count| TerrainPatch {
+            origin_x,
+            origin_y: 1,
+            width,
+            depth: 1,
+            vertices: vec![vertices[0]; vertex_count],
+        };
+        assert!(patch_rows(&chunks, vertices.len(), 3, &patch(2, 1, 6)).is_ok());
+        assert!(patch_rows(&chunks, vertices.len(), 3, &patch(2, 2, 6)).is_err());
+        assert!(patch_rows(&chunks, vertices.len(), 3, &patch(2, 1, 5)).is_err());
+    }
+}
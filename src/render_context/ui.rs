@@ -0,0 +1,179 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::managed_buffer::ManagedBuffer;
+
+/// A vertex of a 2D UI element. Positions are in normalized device coordinates, so the UI is drawn
+/// without any projection.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct UiVertex {
+    pub pos: [f32; 2],
+    pub uv: [f32; 2],
+    /// An RGBA8 color, as packed by `utils::pack_color_rgba8`.
+    pub color: u32,
+}
+
+unsafe impl Pod for UiVertex {}
+unsafe impl Zeroable for UiVertex {}
+
+/// Draws flat-shaded 2D triangles over the finished frame, for HUD elements. Triangles are
+/// collected with `push_rect` and `push_triangle`, and `flush` replaces whatever was drawn before
+/// with them, so the same UI is drawn every frame until the next flush.
+pub struct UiRenderer {
+    /// Triangles pushed since the last flush.
+    pending: Vec<UiVertex>,
+    /// `None` while there is nothing to draw, since an empty buffer cannot be bound.
+    vertex_buf: Option<ManagedBuffer<UiVertex, Vec<UiVertex>>>,
+
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl UiRenderer {
+    pub fn new(gpu_context: &crate::gpu::GpuContext) -> Self {
+        let pipeline_layout = gpu_context.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = gpu_context.create_shader_module_from_bytes(include_shader!("ui.vert"));
+        let fs_module = gpu_context.create_shader_module_from_bytes(include_shader!("ui.frag"));
+
+        let pipeline = gpu_context.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: wgpu::TextureFormat::Bgra8UnormSrgb,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            // The UI is drawn over everything, in the order it was pushed.
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<UiVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: 4*2,
+                            shader_location: 1,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Uchar4Norm,
+                            offset: 4*2 + 4*2,
+                            shader_location: 2,
+                        },
+                    ],
+                }],
+            },
+
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        Self {
+            pending: Vec::new(),
+            vertex_buf: None,
+            pipeline,
+        }
+    }
+
+    /// Queues a triangle for the next flush.
+    pub fn push_triangle(&mut self, vertices: [UiVertex; 3]) {
+        self.pending.extend_from_slice(&vertices);
+    }
+
+    /// Queues an axis-aligned rectangle of a single `color` for the next flush. `min` and `max` are
+    /// opposite corners, in normalized device coordinates.
+    pub fn push_rect(&mut self, min: [f32; 2], max: [f32; 2], color: u32) {
+        let vertex = |x: f32, y: f32, u: f32, v: f32| UiVertex {
+            pos: [x, y],
+            uv: [u, v],
+            color,
+        };
+        let bottom_left = vertex(min[0], min[1], 0.0, 1.0);
+        let bottom_right = vertex(max[0], min[1], 1.0, 1.0);
+        let top_right = vertex(max[0], max[1], 1.0, 0.0);
+        let top_left = vertex(min[0], max[1], 0.0, 0.0);
+        self.push_triangle([bottom_left, bottom_right, top_right]);
+        self.push_triangle([top_right, top_left, bottom_left]);
+    }
+
+    /// Replaces the UI being drawn with the triangles pushed since the last flush, and writes them
+    /// to the GPU. The vertex buffer is recreated if the vertex count changed.
+    pub fn flush(&mut self, gpu_context: &mut crate::gpu::GpuContext, encoder: &mut wgpu::CommandEncoder) {
+        let vertices = std::mem::replace(&mut self.pending, Vec::new());
+        let same_len = self.vertex_buf
+            .as_ref()
+            .map_or(false, |vertex_buf| vertex_buf.len() == vertices.len());
+        if vertices.is_empty() {
+            self.vertex_buf = None;
+        } else if same_len {
+            if let Some(vertex_buf) = self.vertex_buf.as_mut() {
+                vertex_buf.replace_data(vertices);
+            }
+        } else {
+            self.vertex_buf = ManagedBuffer::new_vertex_buf_with_data(gpu_context, vertices).ok();
+        }
+
+        if let Some(vertex_buf) = self.vertex_buf.as_mut() {
+            if vertex_buf.dirty() {
+                vertex_buf.enqueue_copy_command(gpu_context, encoder);
+            }
+        }
+    }
+
+    /// Records a render pass drawing the most recently flushed UI on top of `color_view`.
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder, color_view: &wgpu::TextureView) {
+        let vertex_buf = match self.vertex_buf.as_ref() {
+            Some(vertex_buf) => vertex_buf,
+            None => return,
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, vertex_buf.slice(..));
+        render_pass.draw(0..vertex_buf.len() as u32, 0..1);
+    }
+}
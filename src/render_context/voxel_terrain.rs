@@ -0,0 +1,266 @@
+//! The voxel terrain's mesh, split into cubic chunks of voxels so that editing a voxel only
+//! remeshes and uploads the chunks around it.
+
+use super::instanced::InstancedMesh;
+use crate::utils;
+use crate::voxel::{MeshExtractor, VoxelGrid};
+
+/// The number of voxels along each edge of a chunk. Chunks at the far faces of the grid may be
+/// smaller.
+pub const VOXEL_CHUNK_SIZE: u32 = 16;
+
+/// How far, in voxels along each axis, the mesh around a voxel depends on it. The faces of a cube
+/// depend on its 6 neighbours, and their ambient occlusion on the diagonal ones too. A dual
+/// contoured cell vertex is placed using the gradient at its corners, which is taken by central
+/// differences, and each quad joins the vertices of the 4 cells around its edge.
+fn edit_reach(extractor: MeshExtractor) -> u32 {
+    match extractor {
+        MeshExtractor::Cubes => 1,
+        MeshExtractor::DualContour => 2,
+    }
+}
+
+/// The number of chunks along each axis of a grid of `dims` voxels.
+fn chunk_counts(dims: [u32; 3]) -> [u32; 3] {
+    let count = |dim: u32| (dim + VOXEL_CHUNK_SIZE - 1) / VOXEL_CHUNK_SIZE;
+    [count(dims[0]), count(dims[1]), count(dims[2])]
+}
+
+/// Returns the chunks, in `x`, then `y`, then `z` order, holding any voxel within `reach` along
+/// each axis of `pos` in a grid of `dims` voxels.
+fn affected_chunks(pos: (u32, u32, u32), reach: u32, dims: [u32; 3]) -> Vec<[u32; 3]> {
+    let pos = [pos.0, pos.1, pos.2];
+    let range = |axis: usize| {
+        let lo = pos[axis].saturating_sub(reach) / VOXEL_CHUNK_SIZE;
+        let hi = (pos[axis] + reach).min(dims[axis] - 1) / VOXEL_CHUNK_SIZE;
+        lo..=hi
+    };
+    let mut chunks = Vec::new();
+    for z in range(2) {
+        for y in range(1) {
+            for x in range(0) {
+                chunks.push([x, y, z]);
+            }
+        }
+    }
+    chunks
+}
+
+/// Meshes the voxels of `chunk` in `grid` with `extractor`, returning the vertices and indices of
+/// the chunk along with the model matrix placing them.
+fn mesh_chunk(
+    grid: &VoxelGrid<bool>,
+    extractor: MeshExtractor,
+    chunk: [u32; 3],
+) -> (crate::mesh::Mesh, cgmath::Matrix4<f32>) {
+    let dims = [grid.width(), grid.height(), grid.depth()];
+    let lo = [chunk[0] * VOXEL_CHUNK_SIZE, chunk[1] * VOXEL_CHUNK_SIZE, chunk[2] * VOXEL_CHUNK_SIZE];
+    let hi = [
+        (lo[0] + VOXEL_CHUNK_SIZE).min(dims[0]),
+        (lo[1] + VOXEL_CHUNK_SIZE).min(dims[1]),
+        (lo[2] + VOXEL_CHUNK_SIZE).min(dims[2]),
+    ];
+    let ((vertices, indices), transform) = match extractor {
+        MeshExtractor::Cubes => (
+            utils::create_vertices_from_voxel_region(grid, cgmath::Point3::new(0.0, 0.0, 0.0), lo, hi),
+            cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.0, 0.0, 0.0)),
+        ),
+        MeshExtractor::DualContour => {
+            // The lattice points before the grid close the surface around its minimum faces, so
+            // the first chunk along each axis takes them in too.
+            let start = |axis: usize| if chunk[axis] == 0 { -1 } else { lo[axis] as i64 };
+            let density = |filled| if filled { 1.0 } else { 0.0 };
+            // Densities are sampled at the lattice points, so each is moved to the center of its
+            // voxel to line up with the cubes.
+            (
+                utils::dual_contour_region(
+                    grid,
+                    density,
+                    0.5,
+                    [start(0), start(1), start(2)],
+                    [hi[0] as i64, hi[1] as i64, hi[2] as i64],
+                ),
+                cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.5, 0.5, 0.5)),
+            )
+        },
+    };
+    (crate::mesh::Mesh { vertices, indices }, transform)
+}
+
+/// The voxel terrain, drawn as one `InstancedMesh` per chunk of `VOXEL_CHUNK_SIZE` voxels along
+/// each axis.
+pub struct VoxelTerrainMesh {
+    /// Indexed by chunk, `x` fastest. Chunks with nothing to draw have no mesh.
+    chunks: Vec<Option<InstancedMesh>>,
+    chunk_counts: [u32; 3],
+}
+
+impl VoxelTerrainMesh {
+    /// Meshes every chunk of `grid` with `extractor` and uploads them to the GPU.
+    pub fn new(gpu_context: &crate::gpu::GpuContext, grid: &VoxelGrid<bool>, extractor: MeshExtractor) -> Self {
+        let chunk_counts = chunk_counts([grid.width(), grid.height(), grid.depth()]);
+        let mut terrain = Self {
+            chunks: Vec::new(),
+            chunk_counts,
+        };
+        for z in 0..chunk_counts[2] {
+            for y in 0..chunk_counts[1] {
+                for x in 0..chunk_counts[0] {
+                    let mesh = terrain.upload_chunk(gpu_context, grid, extractor, [x, y, z]);
+                    terrain.chunks.push(mesh);
+                }
+            }
+        }
+        terrain
+    }
+
+    /// Remeshes and uploads the chunks whose mesh depends on the voxel at `pos`, after it changed in
+    /// `grid`. `grid` must have the dimensions this mesh was built with.
+    pub fn remesh_around(
+        &mut self,
+        gpu_context: &crate::gpu::GpuContext,
+        grid: &VoxelGrid<bool>,
+        extractor: MeshExtractor,
+        pos: (u32, u32, u32),
+    ) {
+        let dims = [grid.width(), grid.height(), grid.depth()];
+        assert_eq!(chunk_counts(dims), self.chunk_counts, "the grid was resized");
+        for chunk in affected_chunks(pos, edit_reach(extractor), dims) {
+            let index = ((chunk[2] * self.chunk_counts[1] + chunk[1]) * self.chunk_counts[0] + chunk[0]) as usize;
+            self.chunks[index] = self.upload_chunk(gpu_context, grid, extractor, chunk);
+        }
+    }
+
+    fn upload_chunk(
+        &self,
+        gpu_context: &crate::gpu::GpuContext,
+        grid: &VoxelGrid<bool>,
+        extractor: MeshExtractor,
+        chunk: [u32; 3],
+    ) -> Option<InstancedMesh> {
+        let (mesh, transform) = mesh_chunk(grid, extractor, chunk);
+        InstancedMesh::new(gpu_context, &mesh, &[transform])
+    }
+
+    /// The meshes of every chunk with something to draw.
+    pub fn meshes(&self) -> impl Iterator<Item = &InstancedMesh> {
+        self.chunks.iter().flatten()
+    }
+
+    pub fn meshes_mut(&mut self) -> impl Iterator<Item = &mut InstancedMesh> {
+        self.chunks.iter_mut().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A lumpy grid spanning several chunks along each axis, with its far chunks partly filled.
+    fn test_grid() -> VoxelGrid<bool> {
+        let mut grid = VoxelGrid::new(VOXEL_CHUNK_SIZE * 2 + 3, VOXEL_CHUNK_SIZE + 5, 7);
+        for z in 0..grid.depth() {
+            for y in 0..grid.height() {
+                for x in 0..grid.width() {
+                    if (x * 7 + y * 3 + z * 5) % 11 < 4 + z {
+                        grid.set(x, y, z, true);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    type Triangle = [Vec<u8>; 3];
+
+    /// The triangles of `vertices` and `indices` as the bytes of their vertices, sorted, so that
+    /// meshes built in a different order can be compared.
+    fn sorted_triangles(vertices: &[utils::Vertex], indices: &[u32]) -> Vec<Triangle> {
+        let key = |i: u32| bytemuck::bytes_of(&vertices[i as usize]).to_vec();
+        let mut triangles: Vec<_> = indices.chunks(3).map(|t| [key(t[0]), key(t[1]), key(t[2])]).collect();
+        triangles.sort();
+        triangles
+    }
+
+    fn chunked_triangles(grid: &VoxelGrid<bool>, extractor: MeshExtractor) -> Vec<Triangle> {
+        let counts = chunk_counts([grid.width(), grid.height(), grid.depth()]);
+        let mut triangles = Vec::new();
+        for z in 0..counts[2] {
+            for y in 0..counts[1] {
+                for x in 0..counts[0] {
+                    let (mesh, _) = mesh_chunk(grid, extractor, [x, y, z]);
+                    triangles.extend(sorted_triangles(&mesh.vertices, &mesh.indices));
+                }
+            }
+        }
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn chunked_cubes_match_the_whole_grid() {
+        let grid = test_grid();
+        let (vertices, indices) = utils::create_vertices_from_voxel_grid(&grid, cgmath::Point3::new(0.0, 0.0, 0.0));
+        assert!(!indices.is_empty());
+        assert_eq!(
+            chunked_triangles(&grid, MeshExtractor::Cubes),
+            sorted_triangles(&vertices, &indices)
+        );
+    }
+
+    #[test]
+    fn chunked_dual_contouring_matches_the_whole_grid() {
+        let grid = test_grid();
+        let mut density = VoxelGrid::new(grid.width(), grid.height(), grid.depth());
+        for (x, y, z) in grid.iter_filled() {
+            density.set(x, y, z, 1.0);
+        }
+        let (vertices, indices) = utils::dual_contour(&density, 0.5);
+        assert!(!indices.is_empty());
+        assert_eq!(
+            chunked_triangles(&grid, MeshExtractor::DualContour),
+            sorted_triangles(&vertices, &indices)
+        );
+    }
+
+    #[test]
+    fn editing_a_voxel_only_changes_the_affected_chunks() {
+        for &extractor in &[MeshExtractor::Cubes, MeshExtractor::DualContour] {
+            let mut grid = test_grid();
+            let dims = [grid.width(), grid.height(), grid.depth()];
+            // Two voxels from the chunk boundaries, which dual contouring reaches across.
+            let pos = (VOXEL_CHUNK_SIZE - 2, VOXEL_CHUNK_SIZE + 1, 3);
+            let counts = chunk_counts(dims);
+            let chunks: Vec<_> = (0..counts[2])
+                .flat_map(|z| (0..counts[1]).flat_map(move |y| (0..counts[0]).map(move |x| [x, y, z])))
+                .collect();
+            let before: Vec<_> = chunks.iter().map(|&chunk| mesh_chunk(&grid, extractor, chunk).0).collect();
+            grid.set(pos.0, pos.1, pos.2, !grid.get(pos.0, pos.1, pos.2));
+
+            let affected = affected_chunks(pos, edit_reach(extractor), dims);
+            for (chunk, before) in chunks.iter().zip(before) {
+                if !affected.contains(chunk) {
+                    let after = mesh_chunk(&grid, extractor, *chunk).0;
+                    assert_eq!(
+                        sorted_triangles(&after.vertices, &after.indices),
+                        sorted_triangles(&before.vertices, &before.indices),
+                        "{:?} changed chunk {:?}",
+                        extractor,
+                        chunk
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn affected_chunks_span_chunk_boundaries() {
+        let dims = [VOXEL_CHUNK_SIZE * 2, VOXEL_CHUNK_SIZE * 2, VOXEL_CHUNK_SIZE * 2];
+        let corner = (VOXEL_CHUNK_SIZE, VOXEL_CHUNK_SIZE, VOXEL_CHUNK_SIZE);
+        assert_eq!(affected_chunks(corner, 1, dims).len(), 8);
+        assert_eq!(affected_chunks((5, 5, 5), 1, dims), vec![[0, 0, 0]]);
+        assert_eq!(affected_chunks((0, 0, 2 * VOXEL_CHUNK_SIZE - 1), 2, dims), vec![[0, 0, 1]]);
+        assert_eq!(affected_chunks((2, 3, 13), 2, dims), vec![[0, 0, 0]]);
+        assert_eq!(affected_chunks((2, 3, 14), 2, dims), vec![[0, 0, 0], [0, 0, 1]]);
+    }
+}
@@ -29,6 +29,12 @@ impl AsRef<[f32]> for Matrix4 {
 #[derive(Clone, Copy)]
 pub struct PaddedMatrix4(cgmath::Matrix4<f32>, [u8; 192]);
 
+impl PaddedMatrix4 {
+    pub fn matrix(&self) -> cgmath::Matrix4<f32> {
+        self.0
+    }
+}
+
 unsafe impl Pod for PaddedMatrix4 {}
 unsafe impl Zeroable for PaddedMatrix4 {}
 
@@ -42,22 +48,128 @@ impl From<cgmath::Matrix4<f32>> for PaddedMatrix4 {
 #[derive(Clone, Copy)]
 pub struct Vertex {
     pos: [f32; 4],
+    #[cfg(not(feature = "oct-normals"))]
     normal: [f32; 3],
+    /// The normal, packed with `pack_normal_oct`.
+    #[cfg(feature = "oct-normals")]
+    normal_oct: [i16; 2],
     tc: [f32; 2],
+    /// An RGBA color the texture is multiplied by, packed with `pack_color_rgba8`.
+    color: u32,
+    /// The ambient occlusion brightness at this vertex, from `compute_voxel_ao`. The lit color is
+    /// scaled by this, so 1.0 is unoccluded.
+    ao: f32,
 }
 
 impl Vertex {
     pub fn new(pos: [f32; 3], normal: [f32; 3], tc: [f32; 2]) -> Vertex {
         Vertex {
             pos: [pos[0], pos[1], pos[2], 1.0],
+            #[cfg(not(feature = "oct-normals"))]
             normal,
+            #[cfg(feature = "oct-normals")]
+            normal_oct: pack_normal_oct(normal),
             tc,
+            color: WHITE_RGBA8,
+            ao: 1.0,
         }
     }
+
+    /// Tints this vertex by the RGBA `color`. Vertices are white unless given another color.
+    pub fn with_color(mut self, color: [f32; 4]) -> Vertex {
+        self.color = pack_color_rgba8(color[0], color[1], color[2], color[3]);
+        self
+    }
+
+    /// Darkens this vertex by the ambient occlusion brightness `ao`. Vertices are unoccluded unless
+    /// given another value.
+    pub fn with_ao(mut self, ao: f32) -> Vertex {
+        self.ao = ao;
+        self
+    }
 }
 
 pub const VERTEX_SIZE: usize = std::mem::size_of::<Vertex>();
 
+/// The format of the normal attribute of `Vertex`, which directly follows its position.
+#[cfg(not(feature = "oct-normals"))]
+pub const VERTEX_NORMAL_FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::Float3;
+#[cfg(feature = "oct-normals")]
+pub const VERTEX_NORMAL_FORMAT: wgpu::VertexFormat = wgpu::VertexFormat::Short2Norm;
+
+/// The offsets of the texture coordinates, the color, and the ambient occlusion of `Vertex`, which
+/// are its last attributes.
+pub const VERTEX_AO_OFFSET: usize = VERTEX_SIZE - std::mem::size_of::<f32>();
+pub const VERTEX_COLOR_OFFSET: usize = VERTEX_AO_OFFSET - std::mem::size_of::<u32>();
+pub const VERTEX_TC_OFFSET: usize = VERTEX_COLOR_OFFSET - std::mem::size_of::<[f32; 2]>();
+
+// The vertex buffer layouts describe `Vertex` attribute by attribute, so its size must be exactly
+// that of its attributes: a position, a normal, texture coordinates, a color, and an ambient
+// occlusion value. If a field is added, or the compiler pads the struct, these fail to build.
+#[cfg(not(feature = "oct-normals"))]
+const _: () = assert!(
+    VERTEX_SIZE == 4 * 4 + 4 * 3 + 4 * 2 + 4 + 4,
+    "Vertex must be exactly the size of its attributes"
+);
+#[cfg(feature = "oct-normals")]
+const _: () = assert!(
+    VERTEX_SIZE == 4 * 4 + 2 * 2 + 4 * 2 + 4 + 4,
+    "Vertex must be exactly the size of its attributes"
+);
+
+/// Opaque white, as packed by `pack_color_rgba8`.
+const WHITE_RGBA8: u32 = 0xFFFF_FFFF;
+
+/// Packs an RGBA color, with components in [0, 1], into a `u32` with 8 bits per component. Red is
+/// in the lowest byte and alpha in the highest, so in memory the bytes are in RGBA order, as
+/// `VertexFormat::Uchar4Norm` reads them.
+pub fn pack_color_rgba8(r: f32, g: f32, b: f32, a: f32) -> u32 {
+    let quantize = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u32;
+    quantize(r) | quantize(g) << 8 | quantize(b) << 16 | quantize(a) << 24
+}
+
+/// Recovers the `[r, g, b, a]` color packed by `pack_color_rgba8`, to within 1/510 per component.
+pub fn unpack_color_rgba8(packed: u32) -> [f32; 4] {
+    let component = |shift: u32| ((packed >> shift) & 0xFF) as f32 / 255.0;
+    [component(0), component(8), component(16), component(24)]
+}
+
+/// -1.0 for negative numbers, and 1.0 otherwise. Unlike `f32::signum`, zero (of either sign) maps
+/// to 1.0, matching `unpack_normal_oct` in `instanced.vert`.
+fn sign_not_zero(v: f32) -> f32 {
+    if v < 0.0 { -1.0 } else { 1.0 }
+}
+
+/// Packs the unit vector `n` into two 16-bit values with an octahedral mapping: `n` is projected
+/// onto the octahedron `|x| + |y| + |z| = 1`, whose lower half is then folded over the upper half,
+/// so the whole sphere covers the square [-1, 1]². The error after `unpack_normal_oct` is a few
+/// hundredths of a degree at most; with 8 bits it would be around a degree.
+pub fn pack_normal_oct(n: [f32; 3]) -> [i16; 2] {
+    let l1_norm = n[0].abs() + n[1].abs() + n[2].abs();
+    let (mut x, mut y) = (n[0] / l1_norm, n[1] / l1_norm);
+    if n[2] < 0.0 {
+        let (folded_x, folded_y) = ((1.0 - y.abs()) * sign_not_zero(x), (1.0 - x.abs()) * sign_not_zero(y));
+        x = folded_x;
+        y = folded_y;
+    }
+    [(x * 32767.0).round() as i16, (y * 32767.0).round() as i16]
+}
+
+/// Recovers the unit vector packed by `pack_normal_oct`. The values are read as signed normalized
+/// values, as `VertexFormat::Short2Norm` does.
+pub fn unpack_normal_oct(packed: [i16; 2]) -> [f32; 3] {
+    let mut x = (packed[0] as f32 / 32767.0).max(-1.0);
+    let mut y = (packed[1] as f32 / 32767.0).max(-1.0);
+    let z = 1.0 - x.abs() - y.abs();
+    if z < 0.0 {
+        let (unfolded_x, unfolded_y) = ((1.0 - y.abs()) * sign_not_zero(x), (1.0 - x.abs()) * sign_not_zero(y));
+        x = unfolded_x;
+        y = unfolded_y;
+    }
+    let length = (x * x + y * y + z * z).sqrt();
+    [x / length, y / length, z / length]
+}
+
 unsafe impl Pod for Vertex {}
 unsafe impl Zeroable for Vertex {}
 
@@ -66,26 +178,880 @@ unsafe impl Zeroable for Vertex {}
 pub struct IVertex {
     v_pos: [i32; 3],
     b_pos: [i32; 3],
-    tc: [f32; 2],
+    /// The face this vertex belongs to, followed by the column and row of its atlas tile.
     data: [u8; 4],
 }
 
 impl IVertex {
-    pub fn new(v_pos: cgmath::Vector3<i32>, b_pos: cgmath::Point3<i32>, tc: [f32; 2], face: u8) -> IVertex {
+    pub fn new(v_pos: cgmath::Vector3<i32>, b_pos: cgmath::Point3<i32>, face: u8, (tile_col, tile_row): (u8, u8)) -> IVertex {
         IVertex {
             v_pos: v_pos.into(),
             b_pos: b_pos.into(),
-            tc,
-            data: [face, 0, 0, 0],
+            data: [face, tile_col, tile_row, 0],
+        }
+    }
+
+    /// The position of this vertex within its chunk.
+    pub fn position(&self) -> [i32; 3] {
+        self.v_pos
+    }
+
+    /// The outward normal of the face this vertex belongs to. This matches `face_normal` in the
+    /// terrain vertex shader.
+    pub fn normal(&self) -> [f32; 3] {
+        match crate::mesh::CuboidFaces::from_bits_truncate(self.data[0]) {
+            crate::mesh::CuboidFaces::TOP => [0.0, 0.0, 1.0],
+            crate::mesh::CuboidFaces::BOTTOM => [0.0, 0.0, -1.0],
+            crate::mesh::CuboidFaces::FRONT => [0.0, 1.0, 0.0],
+            crate::mesh::CuboidFaces::BACK => [0.0, -1.0, 0.0],
+            crate::mesh::CuboidFaces::LEFT => [-1.0, 0.0, 0.0],
+            _ => [1.0, 0.0, 0.0],
         }
     }
 }
 
 pub const IVERTEX_SIZE: usize = std::mem::size_of::<IVertex>();
 
+// As for `Vertex`: the vertex and block positions, followed by the packed face and tile.
+const _: () = assert!(IVERTEX_SIZE == 4 * 3 + 4 * 3 + 4, "IVertex must be exactly the size of its attributes");
+
 unsafe impl Pod for IVertex {}
 unsafe impl Zeroable for IVertex {}
 
+/// The indices of a mesh, in the narrowest format able to address all of its vertices. 16-bit
+/// indices halve the size of the index buffer, and the bandwidth spent reading it.
+pub enum IndexData {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl IndexData {
+    /// Narrows `indices` to 16 bits if every vertex of a mesh with `vertex_count` vertices can be
+    /// addressed that way. 0xFFFF is left unused, since strips treat it as a primitive restart.
+    pub fn new(indices: Vec<u32>, vertex_count: usize) -> Self {
+        if vertex_count <= u16::max_value() as usize {
+            IndexData::U16(indices.into_iter().map(|i| i as u16).collect())
+        } else {
+            IndexData::U32(indices)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IndexData::U16(indices) => indices.len(),
+            IndexData::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The format pipelines drawing with these indices must be created with.
+    pub fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            IndexData::U16(_) => wgpu::IndexFormat::Uint16,
+            IndexData::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+}
+
+/// The ambient occlusion brightness of a vertex, indexed by how many of the voxels around it are
+/// filled (see `compute_voxel_ao`).
+const VOXEL_AO_BRIGHTNESS: [f32; 4] = [1.0, 0.8, 0.6, 0.4];
+
+/// Returns the ambient occlusion brightness at each corner of `face` of the voxel at `(x, y, z)`,
+/// in the order `mesh::MeshAccumulator` emits that face's vertices. `face` must be a single face.
+///
+/// Each corner touches three voxels in the layer the face looks out onto, besides the one directly
+/// in front of the face: one along each edge of the face, and one diagonally across the corner.
+/// The more of these are filled, the darker the corner. If both edge voxels are filled, the corner
+/// voxel cannot be seen from it either way, so it counts as fully occluded.
+pub fn compute_voxel_ao(
+    grid: &crate::voxel::VoxelGrid<bool>,
+    x: u32,
+    y: u32,
+    z: u32,
+    face: crate::mesh::CuboidFaces,
+) -> [f32; 4] {
+    use crate::mesh::CuboidFaces;
+
+    // The direction the face looks in, and the direction from its center to each of its corners,
+    // matching the vertex order of `MeshAccumulator::add_cuboid_quat_faces`.
+    let (normal, corners): ([i64; 3], [[i64; 3]; 4]) = match face {
+        CuboidFaces::TOP => ([0, 0, 1], [[-1, -1, 0], [1, -1, 0], [1, 1, 0], [-1, 1, 0]]),
+        CuboidFaces::BOTTOM => ([0, 0, -1], [[-1, 1, 0], [1, 1, 0], [1, -1, 0], [-1, -1, 0]]),
+        CuboidFaces::RIGHT => ([1, 0, 0], [[0, -1, 1], [0, -1, -1], [0, 1, -1], [0, 1, 1]]),
+        CuboidFaces::LEFT => ([-1, 0, 0], [[0, -1, -1], [0, -1, 1], [0, 1, 1], [0, 1, -1]]),
+        CuboidFaces::FRONT => ([0, 1, 0], [[-1, 0, 1], [1, 0, 1], [1, 0, -1], [-1, 0, -1]]),
+        CuboidFaces::BACK => ([0, -1, 0], [[-1, 0, -1], [1, 0, -1], [1, 0, 1], [-1, 0, 1]]),
+        _ => panic!("bad faces"),
+    };
+
+    let front = [x as i64 + normal[0], y as i64 + normal[1], z as i64 + normal[2]];
+    let filled = |offset: [i64; 3]| {
+        grid.is_filled(front[0] + offset[0], front[1] + offset[1], front[2] + offset[2])
+    };
+
+    let mut ao = [1.0; 4];
+    for (ao, corner) in ao.iter_mut().zip(corners.iter()) {
+        // Split the corner's direction into its two components along the edges of the face.
+        let axes: Vec<usize> = (0..3).filter(|&axis| corner[axis] != 0).collect();
+        let along = |axis: usize| {
+            let mut offset = [0; 3];
+            offset[axis] = corner[axis];
+            offset
+        };
+        let side_1 = filled(along(axes[0]));
+        let side_2 = filled(along(axes[1]));
+        let occlusion = if side_1 && side_2 {
+            3
+        } else {
+            side_1 as usize + side_2 as usize + filled(*corner) as usize
+        };
+        *ao = VOXEL_AO_BRIGHTNESS[occlusion];
+    }
+    ao
+}
+
+/// Builds a mesh of unit cubes, one for each filled voxel of `grid`, with `min` as the corner of the
+/// voxel at `(0, 0, 0)`. Only the faces between a filled voxel and an empty one are emitted, so
+/// overhangs and tunnels are meshed the same as the surface. Voxels outside the grid are empty.
+/// Each vertex is darkened by its ambient occlusion; see `compute_voxel_ao`.
+pub fn create_vertices_from_voxel_grid(
+    grid: &crate::voxel::VoxelGrid<bool>,
+    min: cgmath::Point3<f32>,
+) -> (Vec<Vertex>, Vec<u32>) {
+    create_vertices_from_voxel_region(grid, min, [0, 0, 0], [grid.width(), grid.height(), grid.depth()])
+}
+
+/// Builds the part of the mesh of `create_vertices_from_voxel_grid` for the voxels from `lo` up to
+/// but excluding `hi`. Faces and ambient occlusion still account for the voxels around the region,
+/// so meshing a grid region by region gives the same faces as meshing it whole.
+pub fn create_vertices_from_voxel_region(
+    grid: &crate::voxel::VoxelGrid<bool>,
+    min: cgmath::Point3<f32>,
+    lo: [u32; 3],
+    hi: [u32; 3],
+) -> (Vec<Vertex>, Vec<u32>) {
+    use crate::mesh::{CuboidFaces, MeshAccumulator};
+    use cgmath::{One, Quaternion, Vector3};
+
+    let mut m = MeshAccumulator::new();
+    let filled = (lo[2]..hi[2])
+        .flat_map(|z| (lo[1]..hi[1]).flat_map(move |y| (lo[0]..hi[0]).map(move |x| (x, y, z))))
+        .filter(|&(x, y, z)| grid.get(x, y, z));
+    for (x, y, z) in filled {
+        let (x_i, y_i, z_i) = (x as i64, y as i64, z as i64);
+        let neighbours = [
+            (CuboidFaces::TOP, (x_i, y_i, z_i + 1)),
+            (CuboidFaces::BOTTOM, (x_i, y_i, z_i - 1)),
+            (CuboidFaces::FRONT, (x_i, y_i + 1, z_i)),
+            (CuboidFaces::BACK, (x_i, y_i - 1, z_i)),
+            (CuboidFaces::RIGHT, (x_i + 1, y_i, z_i)),
+            (CuboidFaces::LEFT, (x_i - 1, y_i, z_i)),
+        ];
+        let mut faces = CuboidFaces::empty();
+        for &(face, (n_x, n_y, n_z)) in neighbours.iter() {
+            if !grid.is_filled(n_x, n_y, n_z) {
+                faces |= face;
+            }
+        }
+        if faces.is_empty() {
+            continue;
+        }
+        let center = min + Vector3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5);
+        m.add_cuboid_quat_faces_ao(center, Quaternion::one(), faces, 0.5, 0.5, 0.5, |face| {
+            compute_voxel_ao(grid, x, y, z, face)
+        });
+    }
+    let mesh = m.report();
+    (mesh.vertices, mesh.indices)
+}
+
+/// The edges of a cell of the density lattice, as pairs of corner offsets from its minimum corner.
+const CELL_EDGES: [([i64; 3], [i64; 3]); 12] = [
+    ([0, 0, 0], [1, 0, 0]), ([0, 1, 0], [1, 1, 0]), ([0, 0, 1], [1, 0, 1]), ([0, 1, 1], [1, 1, 1]),
+    ([0, 0, 0], [0, 1, 0]), ([1, 0, 0], [1, 1, 0]), ([0, 0, 1], [0, 1, 1]), ([1, 0, 1], [1, 1, 1]),
+    ([0, 0, 0], [0, 0, 1]), ([1, 0, 0], [1, 0, 1]), ([0, 1, 0], [0, 1, 1]), ([1, 1, 0], [1, 1, 1]),
+];
+
+/// The number of relaxation steps `dual_contour` takes toward each cell's QEF minimum.
+const QEF_ITERATIONS: usize = 16;
+
+/// Extracts the surface where `density` crosses `isolevel` by dual contouring. Densities are
+/// samples at the lattice points `(x, y, z)`, and points with a density above `isolevel` are
+/// inside. Points outside the grid are outside, so the surface is closed.
+///
+/// Each cell of the lattice with a sign change along one of its edges gets a single vertex. The
+/// crossing point along each such edge is interpolated from the densities, with the normal from
+/// the density gradient. The vertex is placed where it best fits the planes through those points,
+/// by minimizing their quadratic error function (QEF), which keeps sharp features that marching
+/// cubes would round off. Each lattice edge with a sign change then becomes a quad joining the
+/// vertices of the four cells around it.
+pub fn dual_contour(density: &crate::voxel::VoxelGrid<f32>, isolevel: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let dims = [density.width() as i64, density.height() as i64, density.depth() as i64];
+    dual_contour_region(density, |d| d, isolevel, [-1, -1, -1], dims)
+}
+
+/// Builds the part of the mesh of `dual_contour` made of the quads for the lattice edges starting
+/// at the points from `lo` up to but excluding `hi`, where the densities of `grid` are given by
+/// `density`. Cells run from -1 on each axis, so the region starting at -1 includes the edges
+/// which close the surface around the grid's minimum faces. The vertices of the cells around the
+/// region are recomputed as needed, so meshing a grid region by region gives the same quads as
+/// meshing it whole.
+pub fn dual_contour_region<T: Copy + Default>(
+    grid: &crate::voxel::VoxelGrid<T>,
+    density: impl Fn(T) -> f32,
+    isolevel: f32,
+    lo: [i64; 3],
+    hi: [i64; 3],
+) -> (Vec<Vertex>, Vec<u32>) {
+    use cgmath::{InnerSpace, Vector3, Zero};
+
+    let dims = [grid.width() as i64, grid.height() as i64, grid.depth() as i64];
+    let sample = |p: [i64; 3]| {
+        let in_bounds = (0..3).all(|axis| p[axis] >= 0 && p[axis] < dims[axis]);
+        if in_bounds {
+            density(grid.get(p[0] as u32, p[1] as u32, p[2] as u32))
+        } else {
+            isolevel - 1.0
+        }
+    };
+    let inside = |p: [i64; 3]| sample(p) > isolevel;
+    let offset = |p: [i64; 3], d: [i64; 3]| [p[0] + d[0], p[1] + d[1], p[2] + d[2]];
+    let to_vector = |p: [i64; 3]| Vector3::new(p[0] as f32, p[1] as f32, p[2] as f32);
+    let gradient = |p: [i64; 3]| {
+        let difference = |d: [i64; 3]| (sample(offset(p, d)) - sample(offset(p, [-d[0], -d[1], -d[2]]))) * 0.5;
+        Vector3::new(difference([1, 0, 0]), difference([0, 1, 0]), difference([0, 0, 1]))
+    };
+
+    // Cells are identified by their minimum corner. They start one before the grid on each axis,
+    // so that the surface closes around its boundary. The quads of the region join the cells from
+    // one before `lo` up to `hi`.
+    let cell_lo = [(lo[0] - 1).max(-1), (lo[1] - 1).max(-1), (lo[2] - 1).max(-1)];
+    let cell_hi = [hi[0].min(dims[0]), hi[1].min(dims[1]), hi[2].min(dims[2])];
+    let cell_dims = [
+        (cell_hi[0] - cell_lo[0]).max(0),
+        (cell_hi[1] - cell_lo[1]).max(0),
+        (cell_hi[2] - cell_lo[2]).max(0),
+    ];
+    let cell_index = |c: [i64; 3]| {
+        if (0..3).all(|axis| c[axis] >= cell_lo[axis] && c[axis] < cell_hi[axis]) {
+            let [x, y, z] = [c[0] - cell_lo[0], c[1] - cell_lo[1], c[2] - cell_lo[2]];
+            Some(((z * cell_dims[1] + y) * cell_dims[0] + x) as usize)
+        } else {
+            None
+        }
+    };
+
+    let mut cell_vertices = vec![None; (cell_dims[0] * cell_dims[1] * cell_dims[2]) as usize];
+    let mut vertices = Vec::new();
+    for z in cell_lo[2]..cell_hi[2] {
+        for y in cell_lo[1]..cell_hi[1] {
+            for x in cell_lo[0]..cell_hi[0] {
+                let cell = [x, y, z];
+                let mut points = Vec::new();
+                let mut normals = Vec::new();
+                for &(a, b) in CELL_EDGES.iter() {
+                    let (p_a, p_b) = (offset(cell, a), offset(cell, b));
+                    if inside(p_a) == inside(p_b) {
+                        continue;
+                    }
+                    let (d_a, d_b) = (sample(p_a), sample(p_b));
+                    let t = (isolevel - d_a) / (d_b - d_a);
+                    points.push(to_vector(p_a) + (to_vector(p_b) - to_vector(p_a)) * t);
+                    // Density increases inward, so the outward normal is against the gradient.
+                    let normal = -(gradient(p_a) + (gradient(p_b) - gradient(p_a)) * t);
+                    normals.push(if normal.magnitude2() > 0.0 { normal.normalize() } else { normal });
+                }
+                if points.is_empty() {
+                    continue;
+                }
+
+                // Relax from the mass point toward the QEF minimum, staying inside the cell.
+                let mass_point = points.iter().fold(Vector3::zero(), |sum, p| sum + p) / points.len() as f32;
+                let mut position = mass_point;
+                for _ in 0..QEF_ITERATIONS {
+                    let force = points
+                        .iter()
+                        .zip(normals.iter())
+                        .fold(Vector3::zero(), |sum, (p, n)| sum + n * n.dot(p - position));
+                    position += force / points.len() as f32;
+                }
+                let min = to_vector(cell);
+                position = Vector3::new(
+                    position.x.max(min.x).min(min.x + 1.0),
+                    position.y.max(min.y).min(min.y + 1.0),
+                    position.z.max(min.z).min(min.z + 1.0),
+                );
+
+                let normal = normals.iter().fold(Vector3::zero(), |sum, n| sum + n);
+                let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { Vector3::unit_z() };
+                if let Some(i) = cell_index(cell) {
+                    cell_vertices[i] = Some(vertices.len() as u32);
+                }
+                vertices.push(Vertex::new(position.into(), normal.into(), [position.x, position.y]));
+            }
+        }
+    }
+
+    // Each edge with a sign change is shared by four cells, whose vertices make a quad. For an edge
+    // along `axis`, the other two axes `(b, c)` follow it in right-handed order, and the cells are
+    // taken counterclockwise around the edge when looking down it from its far end.
+    let mut indices = Vec::new();
+    for z in lo[2].max(-1)..hi[2].min(dims[2]) {
+        for y in lo[1].max(-1)..hi[1].min(dims[1]) {
+            for x in lo[0].max(-1)..hi[0].min(dims[0]) {
+                let p = [x, y, z];
+                for axis in 0..3 {
+                    let mut e_a = [0; 3];
+                    e_a[axis] = 1;
+                    let inside_start = inside(p);
+                    if inside_start == inside(offset(p, e_a)) {
+                        continue;
+                    }
+                    let (mut e_b, mut e_c) = ([0; 3], [0; 3]);
+                    e_b[(axis + 1) % 3] = 1;
+                    e_c[(axis + 2) % 3] = 1;
+                    let cells = [
+                        offset(offset(p, [-e_b[0], -e_b[1], -e_b[2]]), [-e_c[0], -e_c[1], -e_c[2]]),
+                        offset(p, [-e_c[0], -e_c[1], -e_c[2]]),
+                        p,
+                        offset(p, [-e_b[0], -e_b[1], -e_b[2]]),
+                    ];
+                    let quad: Option<Vec<u32>> = cells
+                        .iter()
+                        .map(|&cell| cell_index(cell).and_then(|i| cell_vertices[i]))
+                        .collect();
+                    let quad = match quad {
+                        Some(quad) => quad,
+                        None => continue,
+                    };
+                    // The quad faces along the edge, so it is flipped when the surface faces the
+                    // other way.
+                    if inside_start {
+                        indices.extend_from_slice(&[quad[0], quad[1], quad[2], quad[2], quad[3], quad[0]]);
+                    } else {
+                        indices.extend_from_slice(&[quad[0], quad[3], quad[2], quad[2], quad[1], quad[0]]);
+                    }
+                }
+            }
+        }
+    }
+
+    (vertices, indices)
+}
+
+/// Returns every empty voxel of `grid` reachable from `start` by stepping between face-adjacent
+/// empty voxels, `start` included, in the order they are reached. This is empty if `start` is
+/// filled or outside the grid. The search is breadth first over an explicit queue, so a large
+/// cavern cannot overflow the stack.
+pub fn voxel_flood_fill(grid: &crate::voxel::VoxelGrid<bool>, start: (u32, u32, u32)) -> Vec<(u32, u32, u32)> {
+    let mut visited = vec![false; grid.data().len()];
+    flood_fill_empty(grid, start, &mut visited)
+}
+
+/// Counts the connected regions of empty voxels in `grid`, where voxels are connected through
+/// their faces. A grid with no empty voxels has no components.
+pub fn count_components(grid: &crate::voxel::VoxelGrid<bool>) -> u32 {
+    let mut visited = vec![false; grid.data().len()];
+    let mut components = 0;
+    for z in 0..grid.depth() {
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                if !flood_fill_empty(grid, (x, y, z), &mut visited).is_empty() {
+                    components += 1;
+                }
+            }
+        }
+    }
+    components
+}
+
+/// The breadth first search behind `voxel_flood_fill`, skipping any voxel already marked in
+/// `visited`, which is indexed in the grid's storage order. Reached voxels are marked, so repeated
+/// calls sharing `visited` find each component once.
+fn flood_fill_empty(
+    grid: &crate::voxel::VoxelGrid<bool>,
+    start: (u32, u32, u32),
+    visited: &mut [bool],
+) -> Vec<(u32, u32, u32)> {
+    let (width, height) = (grid.width() as usize, grid.height() as usize);
+    let index = |(x, y, z): (u32, u32, u32)| (z as usize * height + y as usize) * width + x as usize;
+
+    let (x, y, z) = start;
+    if !grid.in_bounds(x, y, z) || grid.get(x, y, z) || visited[index(start)] {
+        return Vec::new();
+    }
+
+    let mut reached = Vec::new();
+    let mut queue = std::collections::VecDeque::new();
+    visited[index(start)] = true;
+    queue.push_back(start);
+    while let Some((x, y, z)) = queue.pop_front() {
+        reached.push((x, y, z));
+        let (x_i, y_i, z_i) = (x as i64, y as i64, z as i64);
+        let neighbours = [
+            (x_i + 1, y_i, z_i), (x_i - 1, y_i, z_i),
+            (x_i, y_i + 1, z_i), (x_i, y_i - 1, z_i),
+            (x_i, y_i, z_i + 1), (x_i, y_i, z_i - 1),
+        ];
+        for &(n_x, n_y, n_z) in neighbours.iter() {
+            if n_x < 0 || n_y < 0 || n_z < 0 {
+                continue;
+            }
+            let neighbour = (n_x as u32, n_y as u32, n_z as u32);
+            if !grid.in_bounds(neighbour.0, neighbour.1, neighbour.2)
+                || grid.get(neighbour.0, neighbour.1, neighbour.2)
+                || visited[index(neighbour)]
+            {
+                continue;
+            }
+            visited[index(neighbour)] = true;
+            queue.push_back(neighbour);
+        }
+    }
+    reached
+}
+
+/// Generates triangle strip indices for a `width` by `depth` grid of vertices laid out row by row.
+/// Each pair of rows becomes one strip, and consecutive strips are joined by repeating the last
+/// index of one and the first of the next, which produces degenerate triangles the GPU discards.
+/// Triangles are wound counterclockwise when x increases to the right and rows increase upwards.
+pub fn create_indices_strip(width: u32, depth: u32) -> Vec<u32> {
+    if width < 2 || depth < 2 {
+        return Vec::new();
+    }
+    let mut indices = Vec::with_capacity(((depth - 1) * (2 * width + 2)) as usize);
+    for row in 0..depth - 1 {
+        if row > 0 {
+            // Each row adds an even number of indices, so the winding parity carries over.
+            indices.push(row * width - 1);
+            indices.push((row + 1) * width);
+        }
+        for x in 0..width {
+            indices.push((row + 1) * width + x);
+            indices.push(row * width + x);
+        }
+    }
+    indices
+}
+
+/// Converts a triangle list into triangle strips drawing the same triangles with the same winding.
+/// Quads listed as `[a, b, c, c, d, a]`, as `MeshAccumulator` and `IMeshAccumulator` emit them,
+/// become strips of four indices; any other triangle is a strip of its own. Strips are separated by
+/// `restart`, the primitive restart index of the index format (all ones), so a mesh of quads takes
+/// five indices per quad rather than six. Joining the strips with degenerate triangles instead
+/// would take at least six.
+pub fn triangle_list_to_strip<T: Copy + PartialEq>(indices: &[T], restart: T) -> Vec<T> {
+    fn push_segment<T: Copy>(strip: &mut Vec<T>, segment: &[T], restart: T) {
+        if !strip.is_empty() {
+            strip.push(restart);
+        }
+        strip.extend_from_slice(segment);
+    }
+
+    let mut strip = Vec::with_capacity(indices.len());
+
+    let mut rest = indices;
+    while rest.len() >= 3 {
+        match *rest {
+            [a, b, c, c2, d, a2, ..] if c == c2 && a == a2 => {
+                push_segment(&mut strip, &[b, c, a, d], restart);
+                rest = &rest[6..];
+            },
+            [a, b, c, ..] => {
+                push_segment(&mut strip, &[a, b, c], restart);
+                rest = &rest[3..];
+            },
+            _ => unreachable!(),
+        }
+    }
+    strip
+}
+
+/// The number of entries in the vertex cache modeled by `optimize_vertex_cache`. Real caches are
+/// somewhere between 16 and 32 entries; the Forsyth algorithm is not very sensitive to the exact size.
+const VERTEX_CACHE_SIZE: usize = 32;
+
+/// Reorders the triangles of a mesh so that consecutive triangles tend to share vertices, letting
+/// the GPU's post-transform vertex cache skip re-running the vertex shader for them. The vertices are
+/// then renumbered in the order the new index list first uses them, so that vertex fetches are
+/// mostly sequential too. The mesh draws the same either way; only the order changes.
+///
+/// With the `meshopt` feature enabled, the triangle order comes from meshoptimizer. Otherwise, Tom
+/// Forsyth's "Linear-Speed Vertex Cache Optimisation" is used.
+pub fn optimize_vertex_cache(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+    #[cfg(feature = "meshopt")]
+    {
+        *indices = meshopt::optimize_vertex_cache(indices, vertices.len());
+    }
+    #[cfg(not(feature = "meshopt"))]
+    forsyth_reorder_triangles(vertices.len(), indices);
+
+    // Renumber the vertices by first use. Vertices no index refers to are dropped.
+    let mut remap = vec![u32::max_value(); vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+    for index in indices.iter_mut() {
+        let old = *index as usize;
+        if remap[old] == u32::max_value() {
+            remap[old] = reordered.len() as u32;
+            reordered.push(vertices[old]);
+        }
+        *index = remap[old];
+    }
+    *vertices = reordered;
+}
+
+/// The score of a vertex in Forsyth's algorithm, given its position in the modeled cache (if any)
+/// and the number of triangles still to be emitted which use it. Higher is better.
+#[cfg(not(feature = "meshopt"))]
+fn forsyth_vertex_score(cache_pos: Option<usize>, remaining_triangles: usize) -> f32 {
+    const CACHE_DECAY_POWER: f32 = 1.5;
+    const LAST_TRIANGLE_SCORE: f32 = 0.75;
+    const VALENCE_BOOST_SCALE: f32 = 2.0;
+    const VALENCE_BOOST_POWER: f32 = 0.5;
+
+    if remaining_triangles == 0 {
+        // No triangle needs this vertex any more.
+        return -1.0;
+    }
+
+    let cache_score = match cache_pos {
+        None => 0.0,
+        // The vertices of the most recent triangle get a fixed score, so that the next triangle
+        // is not biased toward any one edge of it.
+        Some(pos) if pos < 3 => LAST_TRIANGLE_SCORE,
+        Some(pos) => {
+            let scale = 1.0 / (VERTEX_CACHE_SIZE - 3) as f32;
+            (1.0 - (pos - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        },
+    };
+    // Favor vertices with few triangles left, so that lone triangles are not left stranded.
+    let valence_boost = VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER);
+
+    cache_score + valence_boost
+}
+
+/// Reorders the triangles of `indices` in place with Forsyth's algorithm. Triangles are emitted
+/// greedily, each time taking the triangle whose vertices score highest.
+#[cfg(not(feature = "meshopt"))]
+fn forsyth_reorder_triangles(vertex_count: usize, indices: &mut Vec<u32>) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // The triangles using each vertex which have not been emitted yet.
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &vertex in corners {
+            vertex_triangles[vertex as usize].push(triangle);
+        }
+    }
+    let mut vertex_scores: Vec<f32> = vertex_triangles
+        .iter()
+        .map(|triangles| forsyth_vertex_score(None, triangles.len()))
+        .collect();
+    let triangle_score = |triangle: usize, vertex_scores: &[f32]| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3].iter().map(|&v| vertex_scores[v as usize]).sum()
+    };
+    let mut triangle_scores: Vec<f32> = (0..triangle_count)
+        .map(|triangle| triangle_score(triangle, &vertex_scores))
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+
+    let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(indices.len());
+    let mut best = None;
+    // Where to resume searching the whole mesh when no triangle touching the cache is left.
+    let mut scan_start = 0;
+
+    for _ in 0..triangle_count {
+        let triangle = match best.take() {
+            Some(triangle) => triangle,
+            None => {
+                let mut best_score = -1.0;
+                let mut best_triangle = scan_start;
+                for triangle in scan_start..triangle_count {
+                    if !emitted[triangle] && triangle_scores[triangle] > best_score {
+                        best_score = triangle_scores[triangle];
+                        best_triangle = triangle;
+                    }
+                }
+                best_triangle
+            },
+        };
+        emitted[triangle] = true;
+        while scan_start < triangle_count && emitted[scan_start] {
+            scan_start += 1;
+        }
+
+        // Emit the triangle and move its vertices to the front of the cache.
+        let corners = [indices[triangle * 3], indices[triangle * 3 + 1], indices[triangle * 3 + 2]];
+        output.extend_from_slice(&corners);
+        for &vertex in corners.iter() {
+            let triangles = &mut vertex_triangles[vertex as usize];
+            if let Some(pos) = triangles.iter().position(|&t| t == triangle) {
+                triangles.swap_remove(pos);
+            }
+        }
+        let mut new_cache: Vec<u32> = corners.to_vec();
+        new_cache.dedup();
+        new_cache.extend(cache.iter().filter(|v| !corners.contains(*v)));
+        // Vertices pushed out of the cache lose their cache score.
+        for &vertex in new_cache.iter().skip(VERTEX_CACHE_SIZE) {
+            vertex_scores[vertex as usize] = forsyth_vertex_score(None, vertex_triangles[vertex as usize].len());
+        }
+        new_cache.truncate(VERTEX_CACHE_SIZE);
+        cache = new_cache;
+
+        // Rescore the cached vertices and their triangles, and pick the best of those to go next.
+        for (pos, &vertex) in cache.iter().enumerate() {
+            vertex_scores[vertex as usize] = forsyth_vertex_score(Some(pos), vertex_triangles[vertex as usize].len());
+        }
+        let mut best_score = -1.0;
+        for &vertex in cache.iter() {
+            for &triangle in vertex_triangles[vertex as usize].iter() {
+                let score = triangle_score(triangle, &vertex_scores);
+                triangle_scores[triangle] = score;
+                if score > best_score {
+                    best_score = score;
+                    best = Some(triangle);
+                }
+            }
+        }
+    }
+
+    *indices = output;
+}
+
+/// Skews the input space of 2D simplex noise onto the simplex grid.
+const SIMPLEX_F2: f32 = 0.366_025_4; // (sqrt(3) - 1) / 2
+/// Unskews the simplex grid back into input space.
+const SIMPLEX_G2: f32 = 0.211_324_87; // (3 - sqrt(3)) / 6
+
+/// The gradients of 2D simplex noise, as in Stefan Gustavson's reference implementation.
+const SIMPLEX_GRADIENTS: [[f32; 2]; 12] = [
+    [1.0, 1.0], [-1.0, 1.0], [1.0, -1.0], [-1.0, -1.0],
+    [1.0, 0.0], [-1.0, 0.0], [1.0, 0.0], [-1.0, 0.0],
+    [0.0, 1.0], [0.0, -1.0], [0.0, 1.0], [0.0, -1.0],
+];
+
+/// Picks the gradient of the simplex grid point `(i, j)` for noise seeded with `seed`. This hashes
+/// the coordinates rather than looking them up in a permutation table, so the noise does not
+/// repeat.
+fn simplex_gradient(seed: u32, i: i32, j: i32) -> [f32; 2] {
+    let mut h = (i as u32).wrapping_mul(0x27d4_eb2d) ^ (j as u32).wrapping_mul(0x1656_67b1) ^ seed;
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    SIMPLEX_GRADIENTS[(h % 12) as usize]
+}
+
+/// The contribution of one simplex corner, `(x, y)` away from the sample point, with gradient
+/// `(gx, gy)`.
+fn simplex_corner(x: f32, y: f32, gx: f32, gy: f32) -> f32 {
+    let t = 0.5 - x * x - y * y;
+    if t < 0.0 {
+        0.0
+    } else {
+        let t2 = t * t;
+        t2 * t2 * (gx * x + gy * y)
+    }
+}
+
+/// 2D simplex noise at `(x, y)`, seeded with `seed`, in the range [-1, 1].
+///
+/// `simplex_noise_2d_x4` evaluates four samples at once and gives bit-identical results, so the
+/// two can be mixed freely. Every floating point operation here has a counterpart there, in the
+/// same order; keep them in sync.
+pub fn simplex_noise_2d(seed: u32, x: f32, y: f32) -> f32 {
+    let s = (x + y) * SIMPLEX_F2;
+    let i = (x + s).floor();
+    let j = (y + s).floor();
+    let t = (i + j) * SIMPLEX_G2;
+    let x0 = x - (i - t);
+    let y0 = y - (j - t);
+
+    // Which of the two triangles of the skewed cell the point lies in.
+    let (i1, j1) = if x0 > y0 { (1.0, 0.0) } else { (0.0, 1.0) };
+    let x1 = x0 - i1 + SIMPLEX_G2;
+    let y1 = y0 - j1 + SIMPLEX_G2;
+    let x2 = x0 - 1.0 + 2.0 * SIMPLEX_G2;
+    let y2 = y0 - 1.0 + 2.0 * SIMPLEX_G2;
+
+    let (i, j) = (i as i32, j as i32);
+    let g0 = simplex_gradient(seed, i, j);
+    let g1 = simplex_gradient(seed, i + i1 as i32, j + j1 as i32);
+    let g2 = simplex_gradient(seed, i + 1, j + 1);
+
+    let n0 = simplex_corner(x0, y0, g0[0], g0[1]);
+    let n1 = simplex_corner(x1, y1, g1[0], g1[1]);
+    let n2 = simplex_corner(x2, y2, g2[0], g2[1]);
+
+    // Scale the result to cover [-1, 1].
+    70.0 * (n0 + n1 + n2)
+}
+
+/// Evaluates `simplex_noise_2d` at the four points `(xs[k], ys[k])`. Uses SSE4.1 if the CPU
+/// supports it, which is checked at runtime, and falls back to the scalar implementation otherwise.
+pub fn simplex_noise_2d_x4(seed: u32, xs: [f32; 4], ys: [f32; 4]) -> [f32; 4] {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.1") {
+            // Safe, since the CPU was just checked for SSE4.1.
+            return unsafe { simplex_noise_2d_x4_sse41(seed, xs, ys) };
+        }
+    }
+    simplex_noise_2d_x4_scalar(seed, xs, ys)
+}
+
+fn simplex_noise_2d_x4_scalar(seed: u32, xs: [f32; 4], ys: [f32; 4]) -> [f32; 4] {
+    [
+        simplex_noise_2d(seed, xs[0], ys[0]),
+        simplex_noise_2d(seed, xs[1], ys[1]),
+        simplex_noise_2d(seed, xs[2], ys[2]),
+        simplex_noise_2d(seed, xs[3], ys[3]),
+    ]
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1")]
+unsafe fn simplex_noise_2d_x4_sse41(seed: u32, xs: [f32; 4], ys: [f32; 4]) -> [f32; 4] {
+    use std::arch::x86_64::*;
+
+    let x = _mm_loadu_ps(xs.as_ptr());
+    let y = _mm_loadu_ps(ys.as_ptr());
+    let f2 = _mm_set1_ps(SIMPLEX_F2);
+    let g2 = _mm_set1_ps(SIMPLEX_G2);
+    let one = _mm_set1_ps(1.0);
+
+    let s = _mm_mul_ps(_mm_add_ps(x, y), f2);
+    let i = _mm_floor_ps(_mm_add_ps(x, s));
+    let j = _mm_floor_ps(_mm_add_ps(y, s));
+    let t = _mm_mul_ps(_mm_add_ps(i, j), g2);
+    let x0 = _mm_sub_ps(x, _mm_sub_ps(i, t));
+    let y0 = _mm_sub_ps(y, _mm_sub_ps(j, t));
+
+    let upper = _mm_cmpgt_ps(x0, y0);
+    let i1 = _mm_and_ps(upper, one);
+    let j1 = _mm_andnot_ps(upper, one);
+    let x1 = _mm_add_ps(_mm_sub_ps(x0, i1), g2);
+    let y1 = _mm_add_ps(_mm_sub_ps(y0, j1), g2);
+    let two_g2 = _mm_set1_ps(2.0 * SIMPLEX_G2);
+    let x2 = _mm_add_ps(_mm_sub_ps(x0, one), two_g2);
+    let y2 = _mm_add_ps(_mm_sub_ps(y0, one), two_g2);
+
+    // There is no gather in SSE, so the gradients are looked up one lane at a time.
+    let (mut is, mut js, mut i1s) = ([0.0f32; 4], [0.0f32; 4], [0.0f32; 4]);
+    _mm_storeu_ps(is.as_mut_ptr(), i);
+    _mm_storeu_ps(js.as_mut_ptr(), j);
+    _mm_storeu_ps(i1s.as_mut_ptr(), i1);
+    let mut gradients = [[0.0f32; 4]; 6];
+    for lane in 0..4 {
+        let (i, j) = (is[lane] as i32, js[lane] as i32);
+        let (i1, j1) = if i1s[lane] == 1.0 { (1, 0) } else { (0, 1) };
+        let corners = [
+            simplex_gradient(seed, i, j),
+            simplex_gradient(seed, i + i1, j + j1),
+            simplex_gradient(seed, i + 1, j + 1),
+        ];
+        for (corner, g) in corners.iter().enumerate() {
+            gradients[corner * 2][lane] = g[0];
+            gradients[corner * 2 + 1][lane] = g[1];
+        }
+    }
+
+    let corner = |x: __m128, y: __m128, gx: &[f32; 4], gy: &[f32; 4]| -> __m128 {
+        let t = _mm_sub_ps(_mm_sub_ps(_mm_set1_ps(0.5), _mm_mul_ps(x, x)), _mm_mul_ps(y, y));
+        let t2 = _mm_mul_ps(t, t);
+        let dot = _mm_add_ps(_mm_mul_ps(_mm_loadu_ps(gx.as_ptr()), x), _mm_mul_ps(_mm_loadu_ps(gy.as_ptr()), y));
+        let n = _mm_mul_ps(_mm_mul_ps(t2, t2), dot);
+        // Zero the lanes where the corner is too far away to contribute.
+        _mm_and_ps(_mm_cmpge_ps(t, _mm_setzero_ps()), n)
+    };
+    let n0 = corner(x0, y0, &gradients[0], &gradients[1]);
+    let n1 = corner(x1, y1, &gradients[2], &gradients[3]);
+    let n2 = corner(x2, y2, &gradients[4], &gradients[5]);
+
+    let mut result = [0.0f32; 4];
+    _mm_storeu_ps(result.as_mut_ptr(), _mm_mul_ps(_mm_set1_ps(70.0), _mm_add_ps(_mm_add_ps(n0, n1), n2)));
+    result
+}
+
+/// A vertical color ramp for terrain textures, from the lowest ground (at 0.0) to the peaks (at
+/// 1.0). Colors are interpolated linearly between stops.
+#[derive(Clone, Debug)]
+pub struct HeightGradient {
+    /// `(height, color)` pairs, sorted by height. Heights outside the first and last stops take
+    /// the color of the nearest one.
+    pub stops: Vec<(f32, [u8; 4])>,
+}
+
+impl HeightGradient {
+    pub const DIRT: [u8; 4] = [110, 80, 50, 255];
+    pub const GRASS: [u8; 4] = [70, 130, 50, 255];
+    pub const ROCK: [u8; 4] = [128, 128, 128, 255];
+    pub const SNOW: [u8; 4] = [245, 248, 255, 255];
+
+    /// The color of the gradient at `height`.
+    pub fn color_at(&self, height: f32) -> [u8; 4] {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return [255; 4],
+        };
+        if height <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let ((h0, c0), (h1, c1)) = (pair[0], pair[1]);
+            if height <= h1 {
+                let t = if h1 > h0 { (height - h0) / (h1 - h0) } else { 1.0 };
+                let mut color = [0; 4];
+                for (channel, (a, b)) in color.iter_mut().zip(c0.iter().zip(c1.iter())) {
+                    *channel = (*a as f32 + (*b as f32 - *a as f32) * t).round() as u8;
+                }
+                return color;
+            }
+        }
+        last.1
+    }
+}
+
+impl Default for HeightGradient {
+    /// Brown at the bottom, green through the middle, grey above the treeline, and white at the peak.
+    fn default() -> Self {
+        Self {
+            stops: vec![
+                (0.0, Self::DIRT),
+                (0.2, Self::DIRT),
+                (0.35, Self::GRASS),
+                (0.6, Self::GRASS),
+                (0.75, Self::ROCK),
+                (0.9, Self::ROCK),
+                (1.0, Self::SNOW),
+            ],
+        }
+    }
+}
+
+/// Generates a `size` by `size` RGBA8 texture tile colored by `gradient`. The bottom row is the
+/// bottom of the gradient and the top row is its peak; every row is a single color.
+pub fn create_texels(size: u32, gradient: &HeightGradient) -> Vec<u8> {
+    let mut texels = Vec::with_capacity((size * size * 4) as usize);
+    for row in 0..size {
+        // Rows run top to bottom, so the first row is the peak.
+        let height = if size > 1 { 1.0 - row as f32 / (size - 1) as f32 } else { 1.0 };
+        let color = gradient.color_at(height);
+        for _ in 0..size {
+            texels.extend_from_slice(&color);
+        }
+    }
+    texels
+}
+
 pub fn load_image_bytes(path: &str) -> Vec<u8> {
     let image = image::open(path).unwrap();
     image.to_rgba().into_raw()
@@ -98,6 +1064,185 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+/// The view matrix of a camera at `eye` looking at `center`, with `up` pointing up.
+pub fn view_matrix(
+    eye: cgmath::Point3<f32>,
+    center: cgmath::Point3<f32>,
+    up: cgmath::Vector3<f32>,
+) -> cgmath::Matrix4<f32> {
+    cgmath::Matrix4::look_at(eye, center, up)
+}
+
+/// A perspective projection matrix with a vertical field of view of `fovy` degrees, mapping onto
+/// wgpu's clip space (see `OPENGL_TO_WGPU_MATRIX`).
+pub fn projection_matrix(aspect_ratio: f32, fovy: f32, z_near: f32, z_far: f32) -> cgmath::Matrix4<f32> {
+    OPENGL_TO_WGPU_MATRIX * cgmath::perspective(cgmath::Deg(fovy), aspect_ratio, z_near, z_far)
+}
+
+/// The combined view-projection matrix of `view_matrix` and `projection_matrix`.
+pub fn generate_matrix(
+    eye: cgmath::Point3<f32>,
+    center: cgmath::Point3<f32>,
+    up: cgmath::Vector3<f32>,
+    aspect_ratio: f32,
+    fovy: f32,
+    z_near: f32,
+    z_far: f32,
+) -> cgmath::Matrix4<f32> {
+    projection_matrix(aspect_ratio, fovy, z_near, z_far) * view_matrix(eye, center, up)
+}
+
+/// The shape of the noise a `NoiseLayer` samples. Each is derived from the same simplex noise, in
+/// the range [-1, 1].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseType {
+    /// Smooth, rolling noise.
+    Simplex,
+    /// Sharp crests where the simplex noise crosses zero, for mountain ridges. Mostly positive.
+    Ridged,
+    /// Rounded bumps with sharp creases between them, for hills and dunes. Mostly negative.
+    Billow,
+}
+
+impl NoiseType {
+    /// Reshapes a sample of simplex noise into this type of noise.
+    fn shape(self, value: f64) -> f64 {
+        match self {
+            NoiseType::Simplex => value,
+            NoiseType::Ridged => 1.0 - 2.0 * value.abs(),
+            NoiseType::Billow => 2.0 * value.abs() - 1.0,
+        }
+    }
+}
+
+/// How a `NoiseCompositor` combines the values of its layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseBlendMode {
+    /// Sums the layers, each scaled by its weight.
+    Add,
+    /// Multiplies the layers together. A layer's weight fades it out toward 1, so that a weight of
+    /// 0 leaves the product unchanged; this lets one layer mask another.
+    Multiply,
+    /// The highest of the layers, each scaled by its weight.
+    Max,
+    /// The lowest of the layers, each scaled by its weight.
+    Min,
+}
+
+/// One noise function sampled by a `NoiseCompositor`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseLayer {
+    pub noise_type: NoiseType,
+    /// How strongly the layer contributes to the blend. See `NoiseBlendMode`.
+    pub weight: f32,
+    /// Multiplies the sample coordinates, so that larger values vary more quickly.
+    pub frequency_scale: f32,
+    /// Multiplies the layer's value, before it is blended.
+    pub amplitude_scale: f32,
+}
+
+/// Blends several layers of noise into a single value, e.g. ridged mountains masked in over smooth
+/// plains. Every layer samples the same seeded simplex noise, at its own frequency.
+pub struct NoiseCompositor {
+    layers: Vec<NoiseLayer>,
+    blend_mode: NoiseBlendMode,
+    seed: u32,
+}
+
+impl NoiseCompositor {
+    /// Create a new `NoiseCompositor` blending `layers` by `blend_mode`, over noise seeded with
+    /// `seed`.
+    pub fn new(seed: u32, layers: Vec<NoiseLayer>, blend_mode: NoiseBlendMode) -> Self {
+        Self {
+            layers,
+            blend_mode,
+            seed,
+        }
+    }
+
+    /// The layers the terrain has always been generated from: a broad swell, a finer layer varying
+    /// its height, and a very broad layer making some regions much more extreme than others, all
+    /// multiplied together.
+    pub fn terrain(seed: u32) -> Self {
+        let layer = |frequency_scale, amplitude_scale| NoiseLayer {
+            noise_type: NoiseType::Simplex,
+            weight: 1.0,
+            frequency_scale,
+            amplitude_scale,
+        };
+        Self::new(
+            seed,
+            vec![layer(0.5, 20.0), layer(1.0, 2.0), layer(0.1, 10.0)],
+            NoiseBlendMode::Multiply,
+        )
+    }
+
+    /// A copy of this compositor, with the same layers over noise seeded with `seed`.
+    pub fn reseeded(&self, seed: u32) -> Self {
+        Self::new(seed, self.layers.clone(), self.blend_mode)
+    }
+
+    pub fn layers(&self) -> &[NoiseLayer] {
+        &self.layers
+    }
+
+    pub fn blend_mode(&self) -> NoiseBlendMode {
+        self.blend_mode
+    }
+
+    /// Evaluates every layer at `(x, y)` and blends them. With no layers, this is 0.
+    pub fn sample(&self, x: f64, y: f64) -> f64 {
+        self.blend(self.layers.iter().map(|layer| {
+            let frequency = layer.frequency_scale as f64;
+            let noise = simplex_noise_2d(self.seed, (x * frequency) as f32, (y * frequency) as f32);
+            self.layer_value(layer, noise)
+        }))
+    }
+
+    /// Evaluates `sample` at the four points `(xs[k], ys[k])`, sampling the noise of all four at
+    /// once. The results are bit-identical to those of `sample`.
+    pub fn sample_x4(&self, xs: [f64; 4], ys: [f64; 4]) -> [f64; 4] {
+        let noise: Vec<[f32; 4]> = self.layers
+            .iter()
+            .map(|layer| {
+                let frequency = layer.frequency_scale as f64;
+                let scale = |v: [f64; 4]| [
+                    (v[0] * frequency) as f32,
+                    (v[1] * frequency) as f32,
+                    (v[2] * frequency) as f32,
+                    (v[3] * frequency) as f32,
+                ];
+                simplex_noise_2d_x4(self.seed, scale(xs), scale(ys))
+            })
+            .collect();
+        let mut result = [0.0; 4];
+        for (lane, value) in result.iter_mut().enumerate() {
+            *value = self.blend(
+                self.layers.iter().zip(noise.iter()).map(|(layer, noise)| self.layer_value(layer, noise[lane])),
+            );
+        }
+        result
+    }
+
+    /// Shapes and scales a sample of `layer`'s noise, returning it with the layer's weight.
+    fn layer_value(&self, layer: &NoiseLayer, noise: f32) -> (f64, f64) {
+        let value = layer.noise_type.shape(noise as f64);
+        (value * layer.amplitude_scale as f64, layer.weight as f64)
+    }
+
+    /// Blends the `(value, weight)` of each layer at one point.
+    fn blend(&self, values: impl Iterator<Item = (f64, f64)> + Clone) -> f64 {
+        let weighted = values.clone().map(|(value, weight)| value * weight);
+        match self.blend_mode {
+            _ if self.layers.is_empty() => 0.0,
+            NoiseBlendMode::Add => weighted.sum(),
+            NoiseBlendMode::Multiply => values.map(|(value, weight)| 1.0 + (value - 1.0) * weight).product(),
+            NoiseBlendMode::Max => weighted.fold(std::f64::NEG_INFINITY, f64::max),
+            NoiseBlendMode::Min => weighted.fold(std::f64::INFINITY, f64::min),
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! benchmark {
     ($label:expr, $body:expr) => {{
@@ -108,3 +1253,318 @@ macro_rules! benchmark {
         res
     }}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points spread over several simplex cells, including negative coordinates and cell edges.
+    fn noise_sample_points() -> Vec<([f32; 4], [f32; 4])> {
+        (0..256)
+            .map(|i| {
+                let base = i as f32 * 0.37 - 40.0;
+                ([base, base + 0.5, -base * 1.3, 0.0], [base * 0.7, 1.0, base + 2.25, -base])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn simplex_x4_matches_scalar() {
+        for seed in [0, 1, 0xdead_beef].iter().copied() {
+            for (xs, ys) in noise_sample_points() {
+                let simd = simplex_noise_2d_x4(seed, xs, ys);
+                for ((&x, &y), simd) in xs.iter().zip(ys.iter()).zip(simd.iter()) {
+                    let scalar = simplex_noise_2d(seed, x, y);
+                    assert_eq!(simd.to_bits(), scalar.to_bits(), "({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compositor_sample_x4_matches_sample() {
+        let noise = NoiseCompositor::terrain(7);
+        let to_f64 = |v: [f32; 4]| [v[0] as f64, v[1] as f64, v[2] as f64, v[3] as f64];
+        for (xs, ys) in noise_sample_points() {
+            let (xs, ys) = (to_f64(xs), to_f64(ys));
+            let batch = noise.sample_x4(xs, ys);
+            for ((&x, &y), batch) in xs.iter().zip(ys.iter()).zip(batch.iter()) {
+                assert_eq!(batch.to_bits(), noise.sample(x, y).to_bits());
+            }
+        }
+    }
+
+    /// Compares the throughput of the scalar and SIMD simplex noise. Run it with
+    /// `cargo test --release -- --ignored --nocapture simplex_x4_benchmark`.
+    #[test]
+    #[ignore]
+    fn simplex_x4_benchmark() {
+        const ITERATIONS: usize = 1 << 20;
+        let points = noise_sample_points();
+
+        let start = std::time::Instant::now();
+        let mut scalar_sum = 0.0;
+        for i in 0..ITERATIONS {
+            let (xs, ys) = points[i % points.len()];
+            scalar_sum += simplex_noise_2d_x4_scalar(0, xs, ys).iter().sum::<f32>();
+        }
+        let scalar_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut simd_sum = 0.0;
+        for i in 0..ITERATIONS {
+            let (xs, ys) = points[i % points.len()];
+            simd_sum += simplex_noise_2d_x4(0, xs, ys).iter().sum::<f32>();
+        }
+        let simd_time = start.elapsed();
+
+        assert_eq!(scalar_sum.to_bits(), simd_sum.to_bits());
+        println!("{} samples: scalar {:?}, SIMD {:?}", ITERATIONS * 4, scalar_time, simd_time);
+    }
+
+    #[test]
+    fn create_texels_ramps_from_dirt_to_snow() {
+        let size = 16;
+        let texels = create_texels(size, &HeightGradient::default());
+        assert_eq!(texels.len(), (size * size * 4) as usize);
+
+        let row_bytes = (size * 4) as usize;
+        let (top_row, bottom_row) = (&texels[..row_bytes], &texels[texels.len() - row_bytes..]);
+        for (top, bottom) in top_row.chunks_exact(4).zip(bottom_row.chunks_exact(4)) {
+            assert_eq!(top[2], HeightGradient::SNOW[2]);
+            assert_eq!(bottom, HeightGradient::DIRT);
+        }
+    }
+
+    #[test]
+    fn height_gradient_interpolates_between_stops() {
+        let gradient = HeightGradient {
+            stops: vec![(0.0, [0, 0, 0, 255]), (1.0, [200, 100, 50, 255])],
+        };
+        assert_eq!(gradient.color_at(-1.0), [0, 0, 0, 255]);
+        assert_eq!(gradient.color_at(0.5), [100, 50, 25, 255]);
+        assert_eq!(gradient.color_at(2.0), [200, 100, 50, 255]);
+    }
+
+    #[test]
+    fn packed_colors_round_trip_within_one_step() {
+        for i in 0..=1000 {
+            let c = i as f32 / 1000.0;
+            let color = [c, 1.0 - c, (c * 7.0).fract(), 0.5];
+            let unpacked = unpack_color_rgba8(pack_color_rgba8(color[0], color[1], color[2], color[3]));
+            for (original, unpacked) in color.iter().zip(unpacked.iter()) {
+                assert!((original - unpacked).abs() <= 1.0 / 255.0, "{:?} became {:?}", color, unpacked);
+            }
+        }
+        // Components outside [0, 1] are clamped.
+        assert_eq!(unpack_color_rgba8(pack_color_rgba8(-1.0, 2.0, 0.0, 1.0)), [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(pack_color_rgba8(1.0, 1.0, 1.0, 1.0), WHITE_RGBA8);
+    }
+
+    #[test]
+    fn index_data_narrows_small_grids() {
+        let small = IndexData::new(create_indices_strip(64, 64), 64 * 64);
+        assert!(matches!(small, IndexData::U16(_)));
+        assert_eq!(small.format(), wgpu::IndexFormat::Uint16);
+
+        let large = IndexData::new(create_indices_strip(512, 512), 512 * 512);
+        assert!(matches!(large, IndexData::U32(_)));
+        assert_eq!(large.format(), wgpu::IndexFormat::Uint32);
+
+        // The last index 16 bits can address, 0xFFFF itself being reserved.
+        assert!(matches!(IndexData::new(vec![0xFFFE], 0xFFFF), IndexData::U16(_)));
+        assert!(matches!(IndexData::new(vec![0xFFFF], 0x1_0000), IndexData::U32(_)));
+    }
+
+    /// The triangles drawn by the triangle strips `strip`, separated by `restart`, as a triangle
+    /// list. Degenerate triangles are dropped.
+    fn strip_triangles(strip: &[u32], restart: u32) -> Vec<[u32; 3]> {
+        let mut triangles = Vec::new();
+        for segment in strip.split(|&index| index == restart) {
+            for (i, t) in segment.windows(3).enumerate() {
+                // Every other triangle of a strip is wound the other way around.
+                let triangle = if i % 2 == 0 { [t[0], t[1], t[2]] } else { [t[1], t[0], t[2]] };
+                if triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[2] != triangle[0] {
+                    triangles.push(triangle);
+                }
+            }
+        }
+        triangles
+    }
+
+    /// Rotates each triangle to start at its lowest index, keeping its winding, and sorts them, so
+    /// that lists of the same triangles compare equal.
+    fn canonical_triangles(mut triangles: Vec<[u32; 3]>) -> Vec<[u32; 3]> {
+        for t in triangles.iter_mut() {
+            let first = (0..3).min_by_key(|&i| t[i]).unwrap();
+            *t = [t[first], t[(first + 1) % 3], t[(first + 2) % 3]];
+        }
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn triangle_list_to_strip_restarts_between_quads() {
+        // Two quads, as the mesh accumulators emit them, and a lone triangle.
+        let list = vec![0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4, 8, 9, 10];
+        let strip = triangle_list_to_strip(&list, u32::max_value());
+        assert_eq!(strip.len(), 4 + 1 + 4 + 1 + 3);
+
+        let list_triangles = list.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+        assert_eq!(
+            canonical_triangles(strip_triangles(&strip, u32::max_value())),
+            canonical_triangles(list_triangles)
+        );
+    }
+
+    #[test]
+    fn grid_strip_draws_the_grid() {
+        let (width, depth) = (5, 4);
+        let strip = create_indices_strip(width, depth);
+        let mut grid_triangles = Vec::new();
+        for row in 0..depth - 1 {
+            for x in 0..width - 1 {
+                let a = row * width + x;
+                let (b, c, d) = (a + 1, a + width + 1, a + width);
+                grid_triangles.push([a, b, c]);
+                grid_triangles.push([a, c, d]);
+            }
+        }
+        assert_eq!(
+            canonical_triangles(strip_triangles(&strip, u32::max_value())),
+            canonical_triangles(grid_triangles)
+        );
+    }
+
+    #[test]
+    fn oct_normals_round_trip_within_a_hundredth_of_a_radian() {
+        const STEPS: usize = 64;
+        let mut worst: f32 = 0.0;
+        // Directions spread over the whole sphere, including the poles and the octahedron's edges.
+        for i in 0..=STEPS {
+            let polar = std::f32::consts::PI * i as f32 / STEPS as f32;
+            for j in 0..STEPS {
+                let azimuth = 2.0 * std::f32::consts::PI * j as f32 / STEPS as f32;
+                let n = [polar.sin() * azimuth.cos(), polar.sin() * azimuth.sin(), polar.cos()];
+                let m = unpack_normal_oct(pack_normal_oct(n));
+                let cos_angle = n.iter().zip(m.iter()).map(|(a, b)| a * b).sum::<f32>();
+                worst = worst.max(cos_angle.min(1.0).acos());
+            }
+        }
+        assert!(worst < 0.01, "worst error is {} radians", worst);
+    }
+
+    /// The average cache miss ratio of `indices`: vertex shader runs per triangle, with a FIFO
+    /// post-transform cache of `cache_size` entries.
+    fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+        let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+        let mut misses = 0;
+        for index in indices {
+            if !cache.contains(index) {
+                misses += 1;
+                if cache.len() == cache_size {
+                    cache.pop_front();
+                }
+                cache.push_back(*index);
+            }
+        }
+        misses as f32 / (indices.len() / 3) as f32
+    }
+
+    /// A flat grid of `size` by `size` quads, with its triangles in scan-line order.
+    fn grid_mesh(size: u32) -> (Vec<Vertex>, Vec<u32>) {
+        let width = size + 1;
+        let vertices = (0..width * width)
+            .map(|i| Vertex::new([(i % width) as f32, 0.0, (i / width) as f32], [0.0, 1.0, 0.0], [0.0, 0.0]))
+            .collect();
+        let mut indices = Vec::new();
+        for z in 0..size {
+            for x in 0..size {
+                let a = z * width + x;
+                let (b, c, d) = (a + 1, a + width + 1, a + width);
+                indices.extend_from_slice(&[a, b, c, c, d, a]);
+            }
+        }
+        (vertices, indices)
+    }
+
+    #[test]
+    fn optimize_vertex_cache_lowers_acmr() {
+        let (mut vertices, mut indices) = grid_mesh(64);
+        // Triangles are compared by their corners' positions in the original vertex list.
+        let triangles_before = canonical_triangles(indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect());
+        let positions_before: Vec<[f32; 4]> = vertices.iter().map(|v| v.pos).collect();
+        let before = acmr(&indices, 16);
+
+        optimize_vertex_cache(&mut vertices, &mut indices);
+        let after = acmr(&indices, 16);
+        assert!(after < before * 0.9, "ACMR went from {} to {}", before, after);
+
+        // The same triangles are drawn, with the same winding, only in another order.
+        let position_index = |v: &Vertex| positions_before.iter().position(|p| *p == v.pos).unwrap() as u32;
+        let triangles_after = canonical_triangles(
+            indices
+                .chunks_exact(3)
+                .map(|t| {
+                    [
+                        position_index(&vertices[t[0] as usize]),
+                        position_index(&vertices[t[1] as usize]),
+                        position_index(&vertices[t[2] as usize]),
+                    ]
+                })
+                .collect(),
+        );
+        assert_eq!(triangles_before, triangles_after);
+    }
+
+    #[test]
+    fn voxel_mesh_culls_hidden_faces() {
+        let triangles = |grid: &crate::voxel::VoxelGrid<bool>| {
+            create_vertices_from_voxel_grid(grid, cgmath::Point3::new(0.0, 0.0, 0.0)).1.len() / 3
+        };
+
+        // Only the outer shell of a solid 4×4×4 cube is drawn: 6 faces of 16 quads, rather than all
+        // 6 faces of each of the 64 voxels.
+        let mut cube = crate::voxel::VoxelGrid::new(4, 4, 4);
+        for z in 0..4 {
+            for y in 0..4 {
+                for x in 0..4 {
+                    cube.set(x, y, z, true);
+                }
+            }
+        }
+        assert_eq!(triangles(&cube), 6 * 16 * 2);
+
+        // Two voxels side by side hide the face they share from each other.
+        let mut pair = crate::voxel::VoxelGrid::new(2, 1, 1);
+        pair.set(0, 0, 0, true);
+        assert_eq!(triangles(&pair), 6 * 2);
+        pair.set(1, 0, 0, true);
+        assert_eq!(triangles(&pair), 10 * 2);
+    }
+
+    #[test]
+    fn flood_fill_stops_at_a_wall_between_chambers() {
+        // A wall across x = 2 splits the grid into a chamber of two voxels' width on either side.
+        let mut grid = crate::voxel::VoxelGrid::new(5, 3, 3);
+        for z in 0..3 {
+            for y in 0..3 {
+                grid.set(2, y, z, true);
+            }
+        }
+        assert_eq!(count_components(&grid), 2);
+
+        let left = voxel_flood_fill(&grid, (0, 0, 0));
+        assert_eq!(left.len(), 2 * 3 * 3);
+        assert!(left.iter().all(|&(x, _, _)| x < 2));
+        let right = voxel_flood_fill(&grid, (4, 2, 2));
+        assert_eq!(right.len(), 2 * 3 * 3);
+        assert!(right.iter().all(|&(x, _, _)| x > 2));
+        assert!(voxel_flood_fill(&grid, (2, 1, 1)).is_empty());
+
+        // A hole through the wall joins the chambers.
+        grid.set(2, 1, 1, false);
+        assert_eq!(count_components(&grid), 1);
+        assert_eq!(voxel_flood_fill(&grid, (0, 0, 0)).len(), 5 * 3 * 3 - 8);
+    }
+}
@@ -0,0 +1,217 @@
+//! Partitioning the terrain into irregular Voronoi regions, as an alternative to the uniform grid
+//! of chunks. Seams between regions wander across the terrain instead of lining up in straight
+//! rows, which makes them much harder to spot.
+
+use cgmath::{MetricSpace, Point2};
+use rayon::prelude::*;
+
+/// Assigns each cell of a `grid_width` by `grid_depth` grid of terrain columns to the region of its
+/// nearest seed point. The assignment is computed once, up front, into a lookup table.
+#[derive(Clone, Debug)]
+pub struct VoronoiChunkPartitioner {
+    /// The seed point of each region, in cells. A cell's center is at its coordinates plus one half.
+    seed_points: Vec<Point2<f32>>,
+    grid_width: u32,
+    grid_depth: u32,
+    /// The region of each cell, in row-major order.
+    regions: Vec<u32>,
+}
+
+impl VoronoiChunkPartitioner {
+    /// Create a new `VoronoiChunkPartitioner` splitting the grid among `seed_points`, which are
+    /// given in cells. Returns `None` if there are no seed points.
+    pub fn new(seed_points: Vec<Point2<f32>>, grid_width: u32, grid_depth: u32) -> Option<Self> {
+        if seed_points.is_empty() {
+            return None;
+        }
+        let regions = (0..grid_width as usize * grid_depth as usize)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i % grid_width as usize) as f32, (i / grid_width as usize) as f32);
+                let center = Point2::new(x + 0.5, y + 0.5);
+                nearest_seed(&seed_points, center)
+            })
+            .collect();
+        Some(Self {
+            seed_points,
+            grid_width,
+            grid_depth,
+            regions,
+        })
+    }
+
+    /// Like `new`, but with `count` seed points spread over the grid by `generate_seed_points`.
+    pub fn with_random_seeds(count: u32, seed: u64, grid_width: u32, grid_depth: u32) -> Option<Self> {
+        let seed_points = generate_seed_points(count, seed)
+            .into_iter()
+            .map(|p| Point2::new(p.x * grid_width as f32, p.y * grid_depth as f32))
+            .collect();
+        Self::new(seed_points, grid_width, grid_depth)
+    }
+
+    #[allow(dead_code)]
+    pub fn seed_points(&self) -> &[Point2<f32>] {
+        &self.seed_points
+    }
+
+    pub fn grid_width(&self) -> u32 {
+        self.grid_width
+    }
+
+    pub fn grid_depth(&self) -> u32 {
+        self.grid_depth
+    }
+
+    /// The number of regions, which is also the number of seed points. Regions no cell is nearest
+    /// to are counted, but empty.
+    pub fn region_count(&self) -> usize {
+        self.seed_points.len()
+    }
+
+    /// The region of the cell at `(x, y)`, or `None` if it lies outside of the grid.
+    #[allow(dead_code)]
+    pub fn region_at(&self, x: u32, y: u32) -> Option<u32> {
+        if x >= self.grid_width || y >= self.grid_depth {
+            return None;
+        }
+        Some(self.regions[(y * self.grid_width + x) as usize])
+    }
+
+    /// The cells of each region, as `(x, y)` pairs in row-major order, indexed by region.
+    pub fn region_cells(&self) -> Vec<Vec<(u32, u32)>> {
+        let mut cells = vec![Vec::new(); self.region_count()];
+        for (i, &region) in self.regions.iter().enumerate() {
+            let i = i as u32;
+            cells[region as usize].push((i % self.grid_width, i / self.grid_width));
+        }
+        cells
+    }
+}
+
+/// The index of the seed point nearest to `point`. Ties go to the earlier seed point, so the
+/// assignment does not depend on the order cells are visited in.
+fn nearest_seed(seed_points: &[Point2<f32>], point: Point2<f32>) -> u32 {
+    let mut nearest = 0;
+    let mut nearest_distance2 = std::f32::INFINITY;
+    for (i, seed_point) in seed_points.iter().enumerate() {
+        let distance2 = seed_point.distance2(point);
+        if distance2 < nearest_distance2 {
+            nearest = i;
+            nearest_distance2 = distance2;
+        }
+    }
+    nearest as u32
+}
+
+/// How many candidates are thrown for each seed point before the spacing is relaxed.
+const CANDIDATES_PER_POINT: u32 = 30;
+
+/// Generates `count` points in the unit square with a Poisson disk distribution: no two points lie
+/// closer than a minimum spacing, so they cover the square evenly without falling into a regular
+/// pattern. Points are placed by dart throwing; whenever too many candidates in a row are rejected,
+/// the spacing shrinks, so exactly `count` points are always returned. The same `seed` always gives
+/// the same points.
+pub fn generate_seed_points(count: u32, seed: u64) -> Vec<Point2<f32>> {
+    poisson_disk_points(count, seed).0
+}
+
+/// The points of `generate_seed_points`, along with the minimum spacing they ended up with.
+fn poisson_disk_points(count: u32, seed: u64) -> (Vec<Point2<f32>>, f32) {
+    let mut rng = XorShift64::new(seed);
+    let mut min_distance = initial_spacing(count);
+    let mut points: Vec<Point2<f32>> = Vec::with_capacity(count as usize);
+    let mut rejected = 0;
+    while points.len() < count as usize {
+        let candidate = Point2::new(rng.next_f32(), rng.next_f32());
+        let min_distance2 = min_distance * min_distance;
+        if points.iter().all(|p| p.distance2(candidate) >= min_distance2) {
+            points.push(candidate);
+            rejected = 0;
+        } else {
+            rejected += 1;
+            if rejected == CANDIDATES_PER_POINT {
+                min_distance *= 0.9;
+                rejected = 0;
+            }
+        }
+    }
+    (points, min_distance)
+}
+
+/// The spacing `generate_seed_points` first tries to keep between `count` points. Packing `count`
+/// disks of this diameter into the square fills a little under half of it, which dart throwing can
+/// usually reach.
+fn initial_spacing(count: u32) -> f32 {
+    (0.7 / count.max(1) as f32).sqrt()
+}
+
+/// A small xorshift generator. The seed points only need to look irregular, not be unpredictable.
+struct XorShift64 {
+    state: u64,
+}
+
+impl XorShift64 {
+    fn new(seed: u64) -> Self {
+        // A zero state would only ever produce zeros.
+        Self {
+            state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed },
+        }
+    }
+
+    /// A value uniformly distributed in `[0, 1)`.
+    fn next_f32(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_points() {
+        assert_eq!(generate_seed_points(40, 7), generate_seed_points(40, 7));
+        assert_ne!(generate_seed_points(40, 7), generate_seed_points(40, 8));
+    }
+
+    #[test]
+    fn requested_count_is_reached() {
+        for &count in &[0, 1, 2, 17, 100, 500] {
+            let points = generate_seed_points(count, 3);
+            assert_eq!(points.len(), count as usize);
+            assert!(points.iter().all(|p| (0.0..1.0).contains(&p.x) && (0.0..1.0).contains(&p.y)));
+        }
+    }
+
+    #[test]
+    fn minimum_spacing_is_respected() {
+        let (points, spacing) = poisson_disk_points(100, 3);
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                assert!(a.distance(*b) >= spacing, "{:?} and {:?} are closer than {}", a, b, spacing);
+            }
+        }
+        // The spacing is only relaxed a little from the one first tried.
+        assert!(spacing >= initial_spacing(100) * 0.5, "spacing relaxed to {}", spacing);
+    }
+
+    #[test]
+    fn region_cells_cover_every_cell_once() {
+        let partitioner = VoronoiChunkPartitioner::with_random_seeds(12, 5, 37, 23).unwrap();
+        let region_cells = partitioner.region_cells();
+        assert_eq!(region_cells.len(), 12);
+        let mut cells = Vec::new();
+        for (region, region_cells) in region_cells.iter().enumerate() {
+            for &(x, y) in region_cells {
+                assert_eq!(partitioner.region_at(x, y), Some(region as u32));
+                cells.push((y, x));
+            }
+        }
+        cells.sort_unstable();
+        let expected: Vec<(u32, u32)> = (0..23).flat_map(|y| (0..37).map(move |x| (y, x))).collect();
+        assert_eq!(cells, expected);
+    }
+}
@@ -0,0 +1,656 @@
+/// How a voxel grid is turned into a mesh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshExtractor {
+    /// A unit cube for every filled voxel; see `utils::create_vertices_from_voxel_grid`.
+    Cubes,
+    /// A smooth surface between filled and empty voxels; see `utils::dual_contour`.
+    #[allow(dead_code)]
+    DualContour,
+}
+
+impl Default for MeshExtractor {
+    fn default() -> Self {
+        MeshExtractor::Cubes
+    }
+}
+
+/// A dense 3D grid of voxels, for volumetric data a heightmap cannot represent, such as overhangs
+/// and tunnels. The grid spans `width` voxels along the x-axis, `height` along the y-axis, and
+/// `depth` along the z-axis, which is up. A voxel is filled if its value differs from
+/// `T::default()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelGrid<T: Copy + Default> {
+    /// Voxels ordered by z, then y, then x, so that x varies fastest.
+    data: Vec<T>,
+    width: u32,
+    height: u32,
+    depth: u32,
+}
+
+impl<T: Copy + Default> VoxelGrid<T> {
+    /// Create a new `VoxelGrid` of the given size with every voxel empty.
+    pub fn new(width: u32, height: u32, depth: u32) -> Self {
+        Self {
+            data: vec![T::default(); width as usize * height as usize * depth as usize],
+            width,
+            height,
+            depth,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn in_bounds(&self, x: u32, y: u32, z: u32) -> bool {
+        x < self.width && y < self.height && z < self.depth
+    }
+
+    fn index(&self, x: u32, y: u32, z: u32) -> usize {
+        // Checked in release builds too, since a coordinate past the end of a row would otherwise
+        // wrap around to a voxel of the next one rather than panic.
+        assert!(self.in_bounds(x, y, z), "voxel ({}, {}, {}) is out of bounds", x, y, z);
+        (z as usize * self.height as usize + y as usize) * self.width as usize + x as usize
+    }
+
+    /// Returns the voxel at `(x, y, z)`. Panics if it is out of bounds.
+    pub fn get(&self, x: u32, y: u32, z: u32) -> T {
+        self.data[self.index(x, y, z)]
+    }
+
+    /// Sets the voxel at `(x, y, z)` to `v`. Panics if it is out of bounds.
+    pub fn set(&mut self, x: u32, y: u32, z: u32, v: T) {
+        let i = self.index(x, y, z);
+        self.data[i] = v;
+    }
+
+    /// The voxels in storage order, by z, then y, then x.
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T: Copy + Default + PartialEq> VoxelGrid<T> {
+    /// Whether the voxel at `(x, y, z)` is filled. Voxels outside the grid are empty, so the given
+    /// coordinates may be one past either end of any axis.
+    pub fn is_filled(&self, x: i64, y: i64, z: i64) -> bool {
+        x >= 0 && y >= 0 && z >= 0
+            && self.in_bounds(x as u32, y as u32, z as u32)
+            && self.get(x as u32, y as u32, z as u32) != T::default()
+    }
+
+    /// The coordinates of every filled voxel, in storage order.
+    pub fn iter_filled(&self) -> impl Iterator<Item = (u32, u32, u32)> + '_ {
+        let (width, height) = (self.width as usize, self.height as usize);
+        self.data
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v != T::default())
+            .map(move |(i, _)| ((i % width) as u32, (i / width % height) as u32, (i / (width * height)) as u32))
+    }
+}
+
+/// The result of casting a ray into a `VoxelGrid`: the first filled voxel the ray hits, and the
+/// outward normal of the face it entered through. The voxel at `voxel + normal` is the empty one the
+/// ray passed through just before, where a new voxel would be placed against the hit one. The
+/// normal is zero if the ray starts inside the hit voxel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxelHit {
+    pub voxel: (u32, u32, u32),
+    pub normal: [i32; 3],
+}
+
+impl VoxelGrid<bool> {
+    /// Finds the first filled voxel along the ray from `origin` in direction `dir`, which need not
+    /// be normalized. Coordinates are in voxels, with the grid spanning from the origin to
+    /// `(width, height, depth)`. The ray is clipped to the grid, then walked one voxel at a time
+    /// with a 3D DDA (Amanatides and Woo's "A Fast Voxel Traversal Algorithm"), so every voxel it
+    /// passes through is visited exactly once.
+    pub fn raycast(&self, origin: cgmath::Point3<f32>, dir: cgmath::Vector3<f32>) -> Option<VoxelHit> {
+        let origin = [origin.x, origin.y, origin.z];
+        let dir = [dir.x, dir.y, dir.z];
+        let dims = [self.width, self.height, self.depth];
+
+        // Clip the ray to the grid's bounds, noting which side it enters through.
+        let mut t_enter = 0.0f32;
+        let mut t_exit = std::f32::INFINITY;
+        let mut entry_axis = None;
+        for axis in 0..3 {
+            if dir[axis] == 0.0 {
+                if origin[axis] < 0.0 || origin[axis] >= dims[axis] as f32 {
+                    return None;
+                }
+                continue;
+            }
+            let t_0 = -origin[axis] / dir[axis];
+            let t_1 = (dims[axis] as f32 - origin[axis]) / dir[axis];
+            let (near, far) = if t_0 < t_1 { (t_0, t_1) } else { (t_1, t_0) };
+            if near > t_enter {
+                t_enter = near;
+                entry_axis = Some(axis);
+            }
+            t_exit = t_exit.min(far);
+        }
+        if t_enter > t_exit || dims.contains(&0) {
+            return None;
+        }
+
+        let mut voxel = [0i64; 3];
+        let mut step = [0i64; 3];
+        let mut t_max = [std::f32::INFINITY; 3];
+        let mut t_delta = [std::f32::INFINITY; 3];
+        for axis in 0..3 {
+            let entry = origin[axis] + dir[axis] * t_enter;
+            voxel[axis] = (entry.floor() as i64).max(0).min(dims[axis] as i64 - 1);
+            if entry_axis == Some(axis) {
+                // Rounding could put the entry point just outside the grid, so take the boundary
+                // voxel directly.
+                voxel[axis] = if dir[axis] > 0.0 { 0 } else { dims[axis] as i64 - 1 };
+            }
+            if dir[axis] > 0.0 {
+                step[axis] = 1;
+                t_max[axis] = (voxel[axis] as f32 + 1.0 - origin[axis]) / dir[axis];
+                t_delta[axis] = 1.0 / dir[axis];
+            } else if dir[axis] < 0.0 {
+                step[axis] = -1;
+                t_max[axis] = (voxel[axis] as f32 - origin[axis]) / dir[axis];
+                t_delta[axis] = -1.0 / dir[axis];
+            }
+        }
+
+        let mut normal = [0i32; 3];
+        if let Some(axis) = entry_axis {
+            normal[axis] = -step[axis] as i32;
+        }
+        loop {
+            let (x, y, z) = (voxel[0] as u32, voxel[1] as u32, voxel[2] as u32);
+            if self.get(x, y, z) {
+                return Some(VoxelHit {
+                    voxel: (x, y, z),
+                    normal,
+                });
+            }
+
+            // Step into the neighbour across whichever voxel boundary the ray reaches first.
+            let axis = if t_max[0] < t_max[1] {
+                if t_max[0] < t_max[2] { 0 } else { 2 }
+            } else if t_max[1] < t_max[2] {
+                1
+            } else {
+                2
+            };
+            voxel[axis] += step[axis];
+            if voxel[axis] < 0 || voxel[axis] >= dims[axis] as i64 {
+                return None;
+            }
+            t_max[axis] += t_delta[axis];
+            normal = [0; 3];
+            normal[axis] = -step[axis] as i32;
+        }
+    }
+}
+
+/// The size of the header `VoxelGrid::encode_rle` writes before its runs: the width, height, and
+/// depth of the grid, each as a little-endian `u32`.
+const RLE_HEADER_SIZE: usize = 4 * 3;
+/// The size of each run written by `VoxelGrid::encode_rle`: a little-endian `u16` count followed by
+/// a `u8` value.
+const RLE_RUN_SIZE: usize = 2 + 1;
+
+#[derive(Debug)]
+pub enum RleDecodeError {
+    /// The data ends partway through the header or a run.
+    Truncated,
+    /// A run has a count of zero, or a value other than 0 or 1.
+    InvalidRun { offset: usize },
+    /// The runs cover a different number of voxels than the header's dimensions call for. Both
+    /// counts are given, expected first.
+    LengthMismatch(u64, u64),
+}
+
+impl std::fmt::Display for RleDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RleDecodeError::Truncated =>
+                write!(f, "The run-length encoded voxel data is truncated!"),
+            RleDecodeError::InvalidRun { offset } =>
+                write!(f, "Invalid run at byte {} of the run-length encoded voxel data!", offset),
+            RleDecodeError::LengthMismatch(expected, actual) =>
+                write!(f, "The runs cover {} voxels, but the grid has {}!", actual, expected),
+        }
+    }
+}
+
+impl std::error::Error for RleDecodeError {}
+
+impl VoxelGrid<bool> {
+    /// Encodes the grid as its dimensions followed by runs of equal voxels, in storage order (z,
+    /// then y, then x). Each run is a `u16` count and a `u8` value of 0 or 1; runs longer than
+    /// `u16::MAX` are split. Mostly solid or mostly empty grids shrink by orders of magnitude.
+    pub fn encode_rle(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RLE_HEADER_SIZE);
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.depth.to_le_bytes());
+
+        let mut voxels = self.data.iter().copied().peekable();
+        while let Some(value) = voxels.next() {
+            let mut count: u16 = 1;
+            while count < u16::MAX && voxels.peek() == Some(&value) {
+                voxels.next();
+                count += 1;
+            }
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.push(value as u8);
+        }
+        bytes
+    }
+
+    /// Reconstructs a grid written by `encode_rle`.
+    pub fn decode_rle(bytes: &[u8]) -> Result<VoxelGrid<bool>, RleDecodeError> {
+        if bytes.len() < RLE_HEADER_SIZE {
+            return Err(RleDecodeError::Truncated);
+        }
+        let read_u32 = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let (width, height, depth) = (read_u32(0), read_u32(4), read_u32(8));
+        let expected = width as u64 * height as u64 * depth as u64;
+
+        let runs = &bytes[RLE_HEADER_SIZE..];
+        if runs.len() % RLE_RUN_SIZE != 0 {
+            return Err(RleDecodeError::Truncated);
+        }
+        // Check the runs add up before allocating, so corrupt dimensions cannot allocate a huge
+        // grid.
+        let mut actual: u64 = 0;
+        for (i, run) in runs.chunks(RLE_RUN_SIZE).enumerate() {
+            let count = u16::from_le_bytes([run[0], run[1]]);
+            if count == 0 || run[2] > 1 {
+                return Err(RleDecodeError::InvalidRun { offset: RLE_HEADER_SIZE + i * RLE_RUN_SIZE });
+            }
+            actual += count as u64;
+        }
+        if actual != expected {
+            return Err(RleDecodeError::LengthMismatch(expected, actual));
+        }
+
+        let mut data = Vec::with_capacity(expected as usize);
+        for run in runs.chunks(RLE_RUN_SIZE) {
+            let count = u16::from_le_bytes([run[0], run[1]]);
+            data.extend(std::iter::repeat(run[2] == 1).take(count as usize));
+        }
+        Ok(VoxelGrid {
+            data,
+            width,
+            height,
+            depth,
+        })
+    }
+}
+
+// Voxel grids are saved run-length encoded, since a dense grid of booleans is mostly long runs.
+impl serde::Serialize for VoxelGrid<bool> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.encode_rle())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for VoxelGrid<bool> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: Vec<u8> = serde::Deserialize::deserialize(deserializer)?;
+        VoxelGrid::decode_rle(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A node of a `SparseVoxelOctree`, covering a cube of voxels.
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum OctreeNode {
+    /// Every voxel in the cube is filled, or every one is empty.
+    Leaf(bool),
+    /// The cube split into eight octants. Octant `i` is offset by half the cube along x if bit 0 of
+    /// `i` is set, along y if bit 1 is, and along z if bit 2 is.
+    Branch(Box<[OctreeNode; 8]>),
+}
+
+#[allow(dead_code)]
+impl OctreeNode {
+    fn child_index(x: u32, y: u32, z: u32, half: u32) -> usize {
+        ((x >= half) as usize) | ((y >= half) as usize) << 1 | ((z >= half) as usize) << 2
+    }
+
+    /// Replaces a branch whose octants are all equal leaves with a single leaf.
+    fn collapse(&mut self) {
+        if let OctreeNode::Branch(children) = self {
+            if let OctreeNode::Leaf(value) = children[0] {
+                if children.iter().all(|child| *child == OctreeNode::Leaf(value)) {
+                    *self = OctreeNode::Leaf(value);
+                }
+            }
+        }
+    }
+
+    fn count_nodes(&self) -> usize {
+        match self {
+            OctreeNode::Leaf(_) => 1,
+            OctreeNode::Branch(children) => 1 + children.iter().map(OctreeNode::count_nodes).sum::<usize>(),
+        }
+    }
+}
+
+/// A cube of `2^depth` voxels along each axis, stored as an octree in which any uniformly filled or
+/// empty octant is a single leaf. Unlike a `VoxelGrid`, memory grows with the amount of detail
+/// (the area of the boundary between filled and empty space) rather than with the volume, which
+/// suits large worlds that are mostly solid rock or open air. The API matches `VoxelGrid<bool>`.
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseVoxelOctree {
+    root: OctreeNode,
+    depth: u32,
+}
+
+#[allow(dead_code)]
+impl SparseVoxelOctree {
+    /// Create a new, empty `SparseVoxelOctree` spanning `2^depth` voxels along each axis.
+    pub fn new(depth: u32) -> Self {
+        Self {
+            root: OctreeNode::Leaf(false),
+            depth,
+        }
+    }
+
+    /// Builds the smallest octree containing `grid`. Voxels of the octree beyond the grid are
+    /// empty.
+    pub fn from_voxel_grid(grid: &VoxelGrid<bool>) -> Self {
+        fn build(grid: &VoxelGrid<bool>, x: u32, y: u32, z: u32, size: u32) -> OctreeNode {
+            if x >= grid.width() || y >= grid.height() || z >= grid.depth() {
+                return OctreeNode::Leaf(false);
+            }
+            if size == 1 {
+                return OctreeNode::Leaf(grid.get(x, y, z));
+            }
+            let half = size / 2;
+            let child = |i: usize| {
+                let offset = |bit: usize| if i & bit != 0 { half } else { 0 };
+                build(grid, x + offset(1), y + offset(2), z + offset(4), half)
+            };
+            let mut node = OctreeNode::Branch(Box::new([
+                child(0), child(1), child(2), child(3), child(4), child(5), child(6), child(7),
+            ]));
+            node.collapse();
+            node
+        }
+
+        let max_dim = grid.width().max(grid.height()).max(grid.depth()).max(1);
+        let depth = 32 - (max_dim - 1).leading_zeros();
+        Self {
+            root: build(grid, 0, 0, 0, 1 << depth),
+            depth,
+        }
+    }
+
+    /// The number of levels below the root; the octree spans `2^depth` voxels along each axis.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// The number of voxels along each axis.
+    pub fn size(&self) -> u32 {
+        1 << self.depth
+    }
+
+    pub fn in_bounds(&self, x: u32, y: u32, z: u32) -> bool {
+        x < self.size() && y < self.size() && z < self.size()
+    }
+
+    /// Returns the voxel at `(x, y, z)`. Panics if it is out of bounds.
+    pub fn get(&self, x: u32, y: u32, z: u32) -> bool {
+        assert!(self.in_bounds(x, y, z), "voxel ({}, {}, {}) is out of bounds", x, y, z);
+        let (mut x, mut y, mut z) = (x, y, z);
+        let mut half = self.size() / 2;
+        let mut node = &self.root;
+        loop {
+            match node {
+                OctreeNode::Leaf(value) => return *value,
+                OctreeNode::Branch(children) => {
+                    node = &children[OctreeNode::child_index(x, y, z, half)];
+                    x %= half;
+                    y %= half;
+                    z %= half;
+                    half /= 2;
+                },
+            }
+        }
+    }
+
+    /// Sets the voxel at `(x, y, z)` to `v`, splitting leaves on the way down as needed and merging
+    /// octants which become uniform on the way back up. Panics if it is out of bounds.
+    pub fn set(&mut self, x: u32, y: u32, z: u32, v: bool) {
+        fn set_in(node: &mut OctreeNode, x: u32, y: u32, z: u32, size: u32, v: bool) {
+            if size == 1 {
+                *node = OctreeNode::Leaf(v);
+                return;
+            }
+            if let OctreeNode::Leaf(value) = *node {
+                if value == v {
+                    return;
+                }
+                *node = OctreeNode::Branch(Box::new([
+                    OctreeNode::Leaf(value), OctreeNode::Leaf(value),
+                    OctreeNode::Leaf(value), OctreeNode::Leaf(value),
+                    OctreeNode::Leaf(value), OctreeNode::Leaf(value),
+                    OctreeNode::Leaf(value), OctreeNode::Leaf(value),
+                ]));
+            }
+            let half = size / 2;
+            if let OctreeNode::Branch(children) = node {
+                let i = OctreeNode::child_index(x, y, z, half);
+                set_in(&mut children[i], x % half, y % half, z % half, half, v);
+            }
+            node.collapse();
+        }
+
+        assert!(self.in_bounds(x, y, z), "voxel ({}, {}, {}) is out of bounds", x, y, z);
+        let size = self.size();
+        set_in(&mut self.root, x, y, z, size, v);
+    }
+
+    /// The coordinates of every filled voxel. Unlike `VoxelGrid::iter_filled`, these are given
+    /// octant by octant rather than in storage order.
+    pub fn iter_filled(&self) -> impl Iterator<Item = (u32, u32, u32)> {
+        fn collect(node: &OctreeNode, x: u32, y: u32, z: u32, size: u32, filled: &mut Vec<(u32, u32, u32)>) {
+            match node {
+                OctreeNode::Leaf(false) => {},
+                OctreeNode::Leaf(true) => {
+                    for z_i in z..z + size {
+                        for y_i in y..y + size {
+                            for x_i in x..x + size {
+                                filled.push((x_i, y_i, z_i));
+                            }
+                        }
+                    }
+                },
+                OctreeNode::Branch(children) => {
+                    let half = size / 2;
+                    for (i, child) in children.iter().enumerate() {
+                        let offset = |bit: usize| if i & bit != 0 { half } else { 0 };
+                        collect(child, x + offset(1), y + offset(2), z + offset(4), half, filled);
+                    }
+                },
+            }
+        }
+
+        let mut filled = Vec::new();
+        collect(&self.root, 0, 0, 0, self.size(), &mut filled);
+        filled.into_iter()
+    }
+
+    /// The number of nodes in the octree, leaves included.
+    pub fn node_count(&self) -> usize {
+        self.root.count_nodes()
+    }
+
+    /// An estimate of the memory used by the octree's nodes, in bytes. Compare with the length of
+    /// `VoxelGrid::data` to see what the octree saves.
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + (self.node_count() - 1) * std::mem::size_of::<OctreeNode>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rle_round_trips(grid: &VoxelGrid<bool>) {
+        let decoded = VoxelGrid::decode_rle(&grid.encode_rle()).expect("encoded grid failed to decode");
+        assert_eq!(&decoded, grid);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn get_panics_past_the_end_of_a_row() {
+        // (2, 0, 0) would index the first voxel of the next row if it were not checked.
+        VoxelGrid::<bool>::new(2, 2, 2).get(2, 0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn set_panics_out_of_bounds() {
+        VoxelGrid::new(2, 2, 2).set(0, 0, 2, true);
+    }
+
+    #[test]
+    fn rle_round_trips_random_grid() {
+        // A fixed linear congruential generator, so the grid is the same on every run.
+        let mut state: u32 = 0x2545_f491;
+        let mut grid = VoxelGrid::new(13, 7, 5);
+        for z in 0..grid.depth() {
+            for y in 0..grid.height() {
+                for x in 0..grid.width() {
+                    state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    grid.set(x, y, z, state >> 31 == 1);
+                }
+            }
+        }
+        assert_rle_round_trips(&grid);
+    }
+
+    #[test]
+    fn rle_round_trips_empty_grid() {
+        // One more voxel than a single run can count, so the run has to be split.
+        let grid = VoxelGrid::new(64, 64, 16);
+        let bytes = grid.encode_rle();
+        assert_eq!(bytes.len(), RLE_HEADER_SIZE + 2 * RLE_RUN_SIZE);
+        assert_rle_round_trips(&grid);
+    }
+
+    #[test]
+    fn rle_round_trips_alternating_grid() {
+        // Odd dimensions, so the parity also flips from the end of each row to the next.
+        let mut grid = VoxelGrid::new(5, 3, 3);
+        for z in 0..grid.depth() {
+            for y in 0..grid.height() {
+                for x in 0..grid.width() {
+                    grid.set(x, y, z, (x + y + z) % 2 == 0);
+                }
+            }
+        }
+        assert_eq!(grid.encode_rle().len(), RLE_HEADER_SIZE + 5 * 3 * 3 * RLE_RUN_SIZE);
+        assert_rle_round_trips(&grid);
+    }
+
+    #[test]
+    fn rle_round_trips_single_voxel() {
+        let mut grid = VoxelGrid::new(1, 1, 1);
+        assert_rle_round_trips(&grid);
+        grid.set(0, 0, 0, true);
+        assert_rle_round_trips(&grid);
+    }
+
+    #[test]
+    fn octree_matches_voxel_grid() {
+        let mut grid = VoxelGrid::new(5, 6, 7);
+        for (x, y, z) in [(0, 0, 0), (4, 5, 6), (2, 3, 1), (3, 3, 1)].iter().copied() {
+            grid.set(x, y, z, true);
+        }
+        let mut octree = SparseVoxelOctree::from_voxel_grid(&grid);
+        assert_eq!(octree.size(), 8);
+        for z in 0..octree.size() {
+            for y in 0..octree.size() {
+                for x in 0..octree.size() {
+                    assert_eq!(octree.get(x, y, z), grid.in_bounds(x, y, z) && grid.get(x, y, z));
+                }
+            }
+        }
+        let mut filled: Vec<_> = octree.iter_filled().collect();
+        filled.sort_by_key(|&(x, y, z)| (z, y, x));
+        assert_eq!(filled, grid.iter_filled().collect::<Vec<_>>());
+
+        // Clearing every voxel merges the octree back into a single leaf.
+        for (x, y, z) in grid.iter_filled() {
+            octree.set(x, y, z, false);
+        }
+        assert_eq!(octree, SparseVoxelOctree::new(3));
+    }
+
+    #[test]
+    fn octree_memory_usage_against_dense_grid() {
+        // A 50%-filled 256³ grid shaped like terrain: solid rock below half height, open air above,
+        // and a cave tunnel running through the rock.
+        let size = 256;
+        let mut grid = VoxelGrid::new(size, size, size);
+        for z in 0..size / 2 {
+            for y in 0..size {
+                for x in 0..size {
+                    let in_tunnel = (y as i32 - 128).abs() < 8 && (z as i32 - 64).abs() < 8;
+                    grid.set(x, y, z, !in_tunnel);
+                }
+            }
+        }
+        let dense = grid.data().len() * std::mem::size_of::<bool>();
+        let octree = SparseVoxelOctree::from_voxel_grid(&grid).memory_usage();
+        assert!(octree * 20 < dense, "256³ terrain: octree {} bytes, dense {} bytes", octree, dense);
+
+        // Noise with no structure to share is the worst case: every voxel ends up a leaf of its own,
+        // each larger than a `bool`.
+        let size = 32;
+        let mut state: u32 = 0x2545_f491;
+        let mut grid = VoxelGrid::new(size, size, size);
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    grid.set(x, y, z, state >> 31 == 1);
+                }
+            }
+        }
+        let dense = grid.data().len() * std::mem::size_of::<bool>();
+        let octree = SparseVoxelOctree::from_voxel_grid(&grid).memory_usage();
+        assert!(octree > dense, "32³ noise: octree {} bytes, dense {} bytes", octree, dense);
+    }
+
+    #[test]
+    fn decode_rle_rejects_corrupt_data() {
+        let bytes = VoxelGrid::<bool>::new(2, 2, 2).encode_rle();
+        assert!(matches!(VoxelGrid::decode_rle(&bytes[..RLE_HEADER_SIZE - 1]), Err(RleDecodeError::Truncated)));
+        assert!(matches!(VoxelGrid::decode_rle(&bytes[..bytes.len() - 1]), Err(RleDecodeError::Truncated)));
+
+        let mut wrong_size = bytes.clone();
+        wrong_size[0] = 3;
+        assert!(matches!(VoxelGrid::decode_rle(&wrong_size), Err(RleDecodeError::LengthMismatch(12, 8))));
+
+        let mut bad_value = bytes;
+        bad_value[RLE_HEADER_SIZE + 2] = 2;
+        assert!(matches!(
+            VoxelGrid::decode_rle(&bad_value),
+            Err(RleDecodeError::InvalidRun { offset: RLE_HEADER_SIZE })
+        ));
+    }
+}
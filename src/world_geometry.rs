@@ -1,9 +1,13 @@
 use cgmath::{Matrix4, Point3, Vector3};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::simplex;
+use crate::atlas;
 use crate::mesh::{CuboidFaces, IMeshAccumulator};
 use crate::utils;
+use crate::voronoi::VoronoiChunkPartitioner;
 
+#[derive(Clone, Copy)]
 pub struct ChunkIndex {
     pub vertex_offset: usize,
     pub index_offset: usize,
@@ -11,163 +15,826 @@ pub struct ChunkIndex {
     pub transform_index: usize,
 }
 
-#[allow(dead_code)]
-pub struct WorldGeometryManager {
+/// The generated mesh for the whole world, laid out chunk by chunk in shared vertex and index
+/// buffers. This lives entirely in CPU memory; see `TerrainMesh` for its GPU counterpart.
+pub struct WorldMesh {
     pub chunks: Vec<ChunkIndex>,
+    /// The transform for each chunk, indexed by `ChunkIndex::transform_index`.
+    pub chunk_transforms: Vec<utils::PaddedMatrix4>,
+    pub vertices: Vec<utils::IVertex>,
+    pub indices: Vec<u16>,
+}
 
-    /// This buffer holds the transforms for each of our chunks.
-    pub transforms_buf: crate::managed_buffer::ManagedBuffer<utils::PaddedMatrix4, Vec<utils::PaddedMatrix4>>,
-
+/// The parameters from which the terrain is generated. The same configuration always generates
+/// the same terrain.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TerrainConfig {
+    pub seed: u32,
+    /// Scales the height of the terrain.
+    pub amplitude: f32,
+    /// Scales how quickly the terrain varies across the xy plane.
+    pub frequency: f32,
     /// Chunks are cubes of world geometry, and this value is the size of the cube.
-    chunk_dim: usize,
-    noise: simplex::Simplex,
+    pub chunk_dim: usize,
+    /// The number of chunks generated along the x and y axes.
+    pub chunks_x: usize,
+    pub chunks_y: usize,
+    /// The number of levels of detail chunks are generated at. Level 0 is full detail, and each
+    /// level after it merges twice as many columns along each axis. 1 disables level of detail.
+    #[serde(default = "default_lod_levels")]
+    pub lod_levels: u32,
+    /// How far apart the levels of detail are, in chunk widths: chunks this far from the camera drop
+    /// to level 1, twice as far to level 2, and so on.
+    #[serde(default = "default_lod_distance_multiplier")]
+    pub lod_distance_multiplier: f32,
+    /// Whether chunks morph toward the next coarser level of detail as they near it, rather than
+    /// dropping to it all at once. See `ChunkLod::morph`.
+    #[serde(default)]
+    pub lod_transition: bool,
+}
+
+fn default_lod_levels() -> u32 {
+    4
+}
+
+fn default_lod_distance_multiplier() -> f32 {
+    4.0
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            amplitude: 1.0,
+            frequency: 1.0,
+            chunk_dim: 16,
+            chunks_x: 30,
+            chunks_y: 30,
+            lod_levels: default_lod_levels(),
+            lod_distance_multiplier: default_lod_distance_multiplier(),
+            lod_transition: false,
+        }
+    }
+}
+
+impl TerrainConfig {
+    /// The coarsest level of detail chunks are generated at. Columns are never merged past the
+    /// width of a chunk.
+    pub fn max_lod(&self) -> u32 {
+        let chunk_dim_log2 = (self.chunk_dim.max(1) as f32).log2().floor() as u32;
+        self.lod_levels.saturating_sub(1).min(chunk_dim_log2)
+    }
+
+    /// The level of detail of the chunk at `(chunk_x, chunk_y)` when viewed from `camera_pos`. `bias`
+    /// is added to the level before it is rounded down, so positive values coarsen the terrain and
+    /// negative ones refine it. With `lod_transition`, the chunk morphs toward the next level over
+    /// the last `LOD_MORPH_RANGE` of its level's distance band.
+    pub fn chunk_lod(&self, chunk_x: usize, chunk_y: usize, camera_pos: Point3<f32>, bias: f32) -> ChunkLod {
+        use cgmath::MetricSpace;
+
+        let chunk_dim = self.chunk_dim as f32;
+        let center = Point3::new((chunk_x as f32 + 0.5) * chunk_dim, (chunk_y as f32 + 0.5) * chunk_dim, 0.0);
+        // Only the horizontal distance counts, so climbing straight up does not coarsen the
+        // terrain underneath the camera.
+        let distance = center.distance(Point3::new(camera_pos.x, camera_pos.y, 0.0));
+        let spacing = (chunk_dim * self.lod_distance_multiplier).max(std::f32::EPSILON);
+        // Whole levels fall where each level's distance band starts.
+        let level = (distance / spacing + bias).max(0.0);
+        let whole = (level.floor() as u32).min(self.max_lod());
+        if !self.lod_transition || whole == self.max_lod() {
+            return ChunkLod::new(whole);
+        }
+        // The morph is complete just as the chunk drops to the next level, so it does not pop.
+        let t = ((level.fract() - (1.0 - LOD_MORPH_RANGE)) / LOD_MORPH_RANGE).max(0.0).min(1.0);
+        ChunkLod {
+            level: whole,
+            morph: (t * LOD_MORPH_STEPS as f32).round() as u32,
+        }
+    }
+}
+
+/// The fraction of each level's distance band, at its far end, over which chunks morph toward the
+/// next level when `TerrainConfig::lod_transition` is set.
+pub const LOD_MORPH_RANGE: f32 = 0.5;
 
-    /// This buffer holds the full mesh for the world geometry.
-    pub vertex_buf: crate::managed_buffer::ManagedBuffer<utils::IVertex, Vec<utils::IVertex>>,
-    pub index_buf: crate::managed_buffer::ManagedBuffer<u16, Vec<u16>>,
+/// The number of steps chunks morph toward the next level in. Each step regenerates the chunk, and
+/// heights are whole blocks, so finer steps would mostly regenerate chunks without changing them.
+pub const LOD_MORPH_STEPS: u32 = 4;
+
+/// The level of detail a chunk is generated at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkLod {
+    /// 0 is full detail, and each level after it merges twice as many columns along each axis.
+    pub level: u32,
+    /// How far the heights of the chunk's columns have morphed toward those of the columns they
+    /// merge into at `level + 1`, in steps out of `LOD_MORPH_STEPS`. After the last step the chunk
+    /// has the same shape as at the next level. This is geomorphing for blocky terrain: heights
+    /// are rounded to whole blocks, so each step raises or lowers some columns.
+    pub morph: u32,
+}
+
+impl ChunkLod {
+    /// The level of detail `level`, without any morph.
+    pub fn new(level: u32) -> Self {
+        Self { level, morph: 0 }
+    }
+}
+
+/// The level of detail of every chunk, in the same row-major order the chunks are generated in.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkLodMap {
+    lods: Vec<ChunkLod>,
+}
+
+impl ChunkLodMap {
+    /// Reassigns each chunk of the terrain generated from `config` its level of detail as seen from
+    /// `camera_pos`. Returns the indices of the chunks whose level changed, which are every chunk if
+    /// their number changed. Only these chunks must be regenerated for the new levels to take
+    /// effect; see `WorldGeometryManager::regenerate_chunks`.
+    pub fn update_lod(&mut self, config: &TerrainConfig, camera_pos: Point3<f32>, bias: f32) -> Vec<usize> {
+        let lods: Vec<ChunkLod> = (0..config.chunks_x * config.chunks_y)
+            .map(|i| config.chunk_lod(i % config.chunks_x, i / config.chunks_x, camera_pos, bias))
+            .collect();
+        let changed = if lods.len() == self.lods.len() {
+            (0..lods.len()).filter(|&i| lods[i] != self.lods[i]).collect()
+        } else {
+            (0..lods.len()).collect()
+        };
+        self.lods = lods;
+        changed
+    }
+
+    pub fn lods(&self) -> &[ChunkLod] {
+        &self.lods
+    }
+}
+
+/// Generates world geometry. This does not touch the GPU, so it can be used without a window.
+pub struct WorldGeometryManager {
+    config: TerrainConfig,
+    /// The height of the water surface. Terrain never dips more than one block below it.
+    sea_level: i32,
+    noise: utils::NoiseCompositor,
+    select_tile: atlas::TileSelector,
 }
 
 impl WorldGeometryManager {
-    // TODO: This shouldn't take a GpuContext. This needs another layer of abstraction around memory
-    // management.
-    pub fn new(gpu_context: &crate::gpu::GpuContext) -> Option<Self> {
-        let chunk_dim = 16;
-        let mut chunks = Vec::with_capacity(100);
-        let mut chunk_transforms: Vec<utils::PaddedMatrix4> = Vec::with_capacity(100);
-        let mut vertices = Vec::new();
-        let mut indices = Vec::new();
-        let noise = crate::simplex::Simplex::with_seed(0);
-
-        for y in 0..30 {
-            for x in 0..30 {
-                // Generate our transform matrix for this chunk.
-                let t = Matrix4::from_translation(Vector3::new((x * chunk_dim as i32) as f32, (y * chunk_dim as i32) as f32, 0.0));
-
-                // Now generate the actual mesh for the chunk.
-                let (vertices_n, indices_n) = generate_chunk_x_y(x, y, chunk_dim, &noise).report();
-                let chunk_index = ChunkIndex {
-                    vertex_offset: vertices.len(),
-                    index_offset: indices.len(),
-                    index_count: indices_n.len(),
-                    transform_index: chunk_transforms.len(),
-                };
-
-                // And update our local accumulators.
-                vertices.extend(vertices_n);
-                indices.extend(indices_n);
-                chunk_transforms.push(t.into());
-                chunks.push(chunk_index);
-            }
+    /// Create a new `WorldGeometryManager` generating terrain from `config`. Each face of the
+    /// terrain is textured with the atlas tile chosen by `select_tile`.
+    pub fn new(config: TerrainConfig, sea_level: i32, select_tile: atlas::TileSelector) -> Self {
+        Self {
+            config,
+            sea_level,
+            noise: utils::NoiseCompositor::terrain(config.seed),
+            select_tile,
         }
+    }
 
-        // Create the transforms buffer holding the transforms for each chunk.
-        let transforms_buf = crate::managed_buffer::ManagedBuffer::new_uniform_buf_with_data(
-            gpu_context,
-            chunk_transforms,
-        ).ok()?;
-
-        // Now we create the vertex buffer and index buffer on the GPU.
-        let vertex_buf = crate::managed_buffer::ManagedBuffer::new_vertex_buf_with_data(
-            gpu_context,
-            vertices,
-        ).ok()?;
-        let index_buf = crate::managed_buffer::ManagedBuffer::new_index_buf_with_data(
-            gpu_context,
-            indices,
-        ).ok()?;
-
-        // create the vertex and index buffers
-        // create the chunk transforms buffer
-        // create some initial chunks, centered around 0, 0
-        Some(Self {
-            chunks,
-            transforms_buf,
-            chunk_dim,
+    /// Like `new`, but the terrain's heights are sampled from `noise` rather than the default
+    /// `NoiseCompositor::terrain`. The seed of `noise` is used as is.
+    pub fn with_noise(
+        config: TerrainConfig,
+        sea_level: i32,
+        select_tile: atlas::TileSelector,
+        noise: utils::NoiseCompositor,
+    ) -> Self {
+        Self {
+            config,
+            sea_level,
             noise,
-            vertex_buf,
-            index_buf,
-        })
+            select_tile,
+        }
+    }
+
+    pub fn config(&self) -> TerrainConfig {
+        self.config
+    }
+
+    /// Generates terrain from `config` from now on. Returns whether `config` differs from the
+    /// previous configuration in any field, in which case previously generated meshes are stale.
+    pub fn set_config(&mut self, config: TerrainConfig) -> bool {
+        if config == self.config {
+            return false;
+        }
+        if config.seed != self.config.seed {
+            self.noise = self.noise.reseeded(config.seed);
+        }
+        self.config = config;
+        true
+    }
+
+    pub fn sea_level(&self) -> i32 {
+        self.sea_level
+    }
+
+    /// Generates the mesh for every chunk of the world at full detail. Chunks are generated in
+    /// parallel, then laid out in row-major order.
+    pub fn generate(&self) -> WorldMesh {
+        self.generate_with_lods(&[])
+    }
+
+    /// Like `generate`, but each chunk is generated at its level of detail in `lods`, which is in
+    /// the same order as the chunks. Chunks past the end of `lods` are generated at full detail.
+    pub fn generate_with_lods(&self, lods: &[ChunkLod]) -> WorldMesh {
+        let chunk_count = self.config.chunks_x * self.config.chunks_y;
+
+        // Every chunk depends only on its coordinates, so they can all be generated at once.
+        // `collect` keeps the results in index order.
+        let chunk_meshes: Vec<(Vec<utils::IVertex>, Vec<u16>)> =
+            (0..chunk_count).into_par_iter().map(|i| self.generate_chunk(i, lods)).collect();
+        lay_out_chunks(chunk_meshes, self.chunk_origins())
+    }
+
+    /// Generates the chunks at the indices `chunks` of `mesh`, which was generated with
+    /// `generate_with_lods`, again at their levels of detail in `lods`, leaving every other chunk
+    /// as it is. Returns `None`, leaving `mesh` untouched, if `mesh` is not a grid of chunks of the
+    /// current configuration, in which case it must be generated whole.
+    pub fn regenerate_chunks(&self, mesh: &mut WorldMesh, lods: &[ChunkLod], chunks: &[usize]) -> Option<()> {
+        let chunk_count = self.config.chunks_x * self.config.chunks_y;
+        if mesh.chunks.len() != chunk_count || chunks.iter().any(|&i| i >= chunk_count) {
+            return None;
+        }
+        let regenerated: Vec<(usize, (Vec<utils::IVertex>, Vec<u16>))> =
+            chunks.par_iter().map(|&i| (i, self.generate_chunk(i, lods))).collect();
+
+        // Chunks are laid out in order, so each chunk's vertices run up to the next chunk's.
+        let mut chunk_meshes: Vec<(Vec<utils::IVertex>, Vec<u16>)> = (0..chunk_count)
+            .map(|i| {
+                let chunk = &mesh.chunks[i];
+                let vertex_end = mesh.chunks.get(i + 1).map_or(mesh.vertices.len(), |next| next.vertex_offset);
+                (
+                    mesh.vertices[chunk.vertex_offset..vertex_end].to_vec(),
+                    mesh.indices[chunk.index_offset..chunk.index_offset + chunk.index_count].to_vec(),
+                )
+            })
+            .collect();
+        for (i, chunk_mesh) in regenerated {
+            chunk_meshes[i] = chunk_mesh;
+        }
+        *mesh = lay_out_chunks(chunk_meshes, self.chunk_origins());
+        Some(())
+    }
+
+    /// Generates the chunk at index `i` at its level of detail in `lods`, or at full detail if
+    /// `lods` has no level for it.
+    fn generate_chunk(&self, i: usize, lods: &[ChunkLod]) -> (Vec<utils::IVertex>, Vec<u16>) {
+        let chunks_x = self.config.chunks_x;
+        let (x, y) = ((i % chunks_x) as i32, (i / chunks_x) as i32);
+        let lod = lods.get(i).copied().unwrap_or_default();
+        generate_chunk_x_y(x, y, lod, &self.config, self.sea_level, &self.noise, self.select_tile).report()
+    }
+
+    /// The world position of the corner of each chunk, in row-major order.
+    fn chunk_origins(&self) -> Vec<(i32, i32)> {
+        let (chunk_dim, chunks_x) = (self.config.chunk_dim, self.config.chunks_x);
+        (0..chunks_x * self.config.chunks_y)
+            .map(|i| ((i % chunks_x * chunk_dim) as i32, (i / chunks_x * chunk_dim) as i32))
+            .collect()
+    }
+
+    /// Generates the mesh for the whole world with one chunk per region of `partitioner`, rather
+    /// than a grid of chunks. Every column is generated at full detail, and walled only down to its
+    /// lower neighbours, whichever region they belong to, so adjacent regions share the vertices
+    /// along their boundary exactly and leave no cracks or T-junctions between them. Each chunk is
+    /// placed at the corner of its region's bounding box; empty regions become empty chunks.
+    ///
+    /// Returns `None` if the partitioner's grid is not the size of the world in columns, or if a
+    /// region has too many vertices to be indexed with 16 bits.
+    pub fn generate_voronoi(&self, partitioner: &VoronoiChunkPartitioner) -> Option<WorldMesh> {
+        let extent = self.extent();
+        let (width, depth) = (extent.x as u32, extent.y as u32);
+        if partitioner.grid_width() != width || partitioner.grid_depth() != depth {
+            return None;
+        }
+        let heights = self.height_grid();
+        let height = |x: i64, y: i64| {
+            if x < 0 || y < 0 || x >= width as i64 || y >= depth as i64 {
+                None
+            } else {
+                Some(heights[(y * width as i64 + x) as usize])
+            }
+        };
+
+        let region_cells = partitioner.region_cells();
+        let regions: Vec<((Vec<utils::IVertex>, Vec<u16>), (i32, i32))> = region_cells
+            .par_iter()
+            .map(|cells| {
+                let min_x = cells.iter().map(|&(x, _)| x).min().unwrap_or(0) as i32;
+                let min_y = cells.iter().map(|&(_, y)| y).min().unwrap_or(0) as i32;
+                let mut m = IMeshAccumulator::new();
+                for &(x, y) in cells {
+                    let (x, y) = (x as i64, y as i64);
+                    let neighbours = ColumnNeighbours {
+                        left: height(x - 1, y),
+                        right: height(x + 1, y),
+                        back: height(x, y - 1),
+                        front: height(x, y + 1),
+                    };
+                    let (x, y) = (x as i32, y as i32);
+                    let top = Point3::new(x - min_x, y - min_y, height(x as i64, y as i64).unwrap_or(0));
+                    add_column(&mut m, top, (1, 1), neighbours, (x, y), self.sea_level, self.select_tile);
+                }
+                (m.report(), (min_x, min_y))
+            })
+            .collect();
+
+        if regions.iter().any(|((vertices, _), _)| vertices.len() > u16::max_value() as usize) {
+            return None;
+        }
+        let (chunk_meshes, origins) = regions.into_iter().unzip();
+        Some(lay_out_chunks(chunk_meshes, origins))
+    }
+
+    /// Returns the size of the generated world along the x and y axes, in world units. The world
+    /// starts at the origin and extends in the positive direction along both axes.
+    pub fn extent(&self) -> cgmath::Vector2<f32> {
+        cgmath::Vector2::new(
+            (self.config.chunks_x * self.config.chunk_dim) as f32,
+            (self.config.chunks_y * self.config.chunk_dim) as f32,
+        )
+    }
+
+    /// Returns the height of the terrain surface at world coordinates `(x, y)`, or `None` if the
+    /// point lies outside of the generated world. The height is sampled from the noise directly,
+    /// so it does not require the mesh.
+    pub fn height_at(&self, x: f32, y: f32) -> Option<f32> {
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let chunk_dim = self.config.chunk_dim;
+        let (x, y) = (x as usize, y as usize);
+        let (chunk_x, chunk_y) = (x / chunk_dim, y / chunk_dim);
+        if chunk_x >= self.config.chunks_x || chunk_y >= self.config.chunks_y {
+            return None;
+        }
+
+        let z = sample_height(
+            chunk_x as i32,
+            chunk_y as i32,
+            x % chunk_dim,
+            y % chunk_dim,
+            &self.config,
+            self.sea_level,
+            &self.noise,
+        );
+        // The topmost block in the column spans `z..z + 1`, so its top face is one unit higher.
+        Some((z + 1) as f32)
+    }
+
+    /// Samples the height of every column in the world, without building any geometry. The heights
+    /// are returned in row-major order, with `extent().x` columns per row.
+    pub fn height_grid(&self) -> Vec<i32> {
+        let chunk_dim = self.config.chunk_dim;
+        let width = self.config.chunks_x * chunk_dim;
+        let height = self.config.chunks_y * chunk_dim;
+        (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                sample_height(
+                    (x / chunk_dim) as i32,
+                    (y / chunk_dim) as i32,
+                    x % chunk_dim,
+                    y % chunk_dim,
+                    &self.config,
+                    self.sea_level,
+                    &self.noise,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Lays out the mesh of each chunk one after another in shared vertex and index buffers, along
+/// with a transform placing each chunk's local origin at its entry in `origins`, in world units.
+fn lay_out_chunks(chunk_meshes: Vec<(Vec<utils::IVertex>, Vec<u16>)>, origins: Vec<(i32, i32)>) -> WorldMesh {
+    let chunk_count = chunk_meshes.len();
+    let mut chunks = Vec::with_capacity(chunk_count);
+    let mut chunk_transforms: Vec<utils::PaddedMatrix4> = Vec::with_capacity(chunk_count);
+    let mut vertices = Vec::with_capacity(chunk_meshes.iter().map(|(v, _)| v.len()).sum());
+    let mut indices = Vec::with_capacity(chunk_meshes.iter().map(|(_, i)| i.len()).sum());
+
+    for ((vertices_n, indices_n), (x, y)) in chunk_meshes.into_iter().zip(origins) {
+        // Generate our transform matrix for this chunk.
+        let t = Matrix4::from_translation(Vector3::new(x as f32, y as f32, 0.0));
+
+        let chunk_index = ChunkIndex {
+            vertex_offset: vertices.len(),
+            index_offset: indices.len(),
+            index_count: indices_n.len(),
+            transform_index: chunk_transforms.len(),
+        };
+
+        // And update our local accumulators.
+        vertices.extend(vertices_n);
+        indices.extend(indices_n);
+        chunk_transforms.push(t.into());
+        chunks.push(chunk_index);
+    }
+
+    WorldMesh {
+        chunks,
+        chunk_transforms,
+        vertices,
+        indices,
     }
 }
 
-/// Generate a chunk of world geometry given: coordinates, the chunk dimensions, and a simplex noise
-/// instance.
+/// Sample the terrain height of the column at `(x_i, y_i)` within the chunk at `(x_off, y_off)`.
+/// The returned value is the z coordinate of the topmost block in the column.
+pub fn sample_height(
+    x_off: i32,
+    y_off: i32,
+    x_i: usize,
+    y_i: usize,
+    config: &TerrainConfig,
+    sea_level: i32,
+    noise: &utils::NoiseCompositor,
+) -> i32 {
+    let (x, y) = noise_coords(x_off, y_off, x_i, y_i, config);
+    height_from_noise(noise.sample(x, y), config, sea_level)
+}
+
+/// Samples the terrain heights of the `columns` within the chunk at `(x_off, y_off)`, as
+/// `sample_height` would, but four columns at a time.
+pub fn sample_heights(
+    x_off: i32,
+    y_off: i32,
+    columns: &[(usize, usize)],
+    config: &TerrainConfig,
+    sea_level: i32,
+    noise: &utils::NoiseCompositor,
+) -> Vec<i32> {
+    let mut heights = Vec::with_capacity(columns.len());
+    let batches = columns.chunks_exact(4);
+    let remainder = batches.remainder();
+    for batch in batches {
+        let mut xs = [0.0; 4];
+        let mut ys = [0.0; 4];
+        for (lane, &(x_i, y_i)) in batch.iter().enumerate() {
+            let (x, y) = noise_coords(x_off, y_off, x_i, y_i, config);
+            xs[lane] = x;
+            ys[lane] = y;
+        }
+        heights.extend(noise.sample_x4(xs, ys).iter().map(|&z| height_from_noise(z, config, sea_level)));
+    }
+    for &(x_i, y_i) in remainder {
+        heights.push(sample_height(x_off, y_off, x_i, y_i, config, sea_level, noise));
+    }
+    heights
+}
+
+/// The point at which the noise is sampled for the column at `(x_i, y_i)` within the chunk at
+/// `(x_off, y_off)`.
+fn noise_coords(x_off: i32, y_off: i32, x_i: usize, y_i: usize, config: &TerrainConfig) -> (f64, f64) {
+    let chunk_dim = config.chunk_dim;
+    let frequency = config.frequency as f64;
+    let x_off = x_off as f64 * frequency;
+    let y_off = y_off as f64 * frequency;
+    let x = x_i as f32 * config.frequency;// * 2.0;
+    let y = y_i as f32 * config.frequency;// * 2.0;
+    (
+        x_off as f64 + (x / chunk_dim as f32) as f64,
+        y_off as f64 + (y / chunk_dim as f32) as f64,
+    )
+}
+
+/// The z coordinate of the topmost block of a column whose noise sampled to `z`.
+fn height_from_noise(z: f64, config: &TerrainConfig, sea_level: i32) -> i32 {
+    ((z as f32 * config.amplitude) as i32).max(sea_level - 1)
+}
+
+/// Generate a chunk of world geometry given: coordinates, the level of detail, the terrain
+/// configuration, the sea level, the noise the heights are sampled from, and the selector choosing the texture
+/// atlas tile for each face.
+///
+/// At level of detail `lod`, only every `2^lod.level`th column along each axis is sampled, and each
+/// sampled column is widened to cover the ones skipped after it. The chunk's outer walls reach the
+/// same depth at every level, which hides the cracks between neighbouring chunks of different
+/// levels. See `chunk_heights` for how the chunk morphs toward the next level.
 pub fn generate_chunk_x_y(
     x_off: i32,
     y_off: i32,
-    chunk_dim: usize,
-    noise: &simplex::Simplex,
+    lod: ChunkLod,
+    config: &TerrainConfig,
+    sea_level: i32,
+    noise: &utils::NoiseCompositor,
+    select_tile: atlas::TileSelector,
 ) -> IMeshAccumulator {
+    let chunk_dim = config.chunk_dim;
+    let step = lod_step(lod, config);
+    let cells = (chunk_dim + step - 1) / step;
     let mut m = IMeshAccumulator::new();
 
     // Generate the height map for our current chunk of terrain.
-    let mut height_map = Vec::with_capacity(chunk_dim * chunk_dim);
-    for x_i in 0..chunk_dim {
-        for y_i in 0..chunk_dim {
-            let x = x_i as f32;// * 2.0;
-            let y = y_i as f32;// * 2.0;
-            let z1 = (noise.get2d(
-                (x_off as f64 + (x / chunk_dim as f32) as f64) / 2.0,
-                (y_off as f64 + (y / chunk_dim as f32) as f64) / 2.0,
-            ) * 20.0 as f64) as f32;
-
-            let mult = (noise.get2d(
-                x_off as f64 + (x / chunk_dim as f32) as f64,
-                y_off as f64 + (y / chunk_dim as f32) as f64,
-            ) * 2.0 as f64) as f32;
-
-            let extremes = (noise.get2d(
-                (x_off as f64 + (x / chunk_dim as f32) as f64) / 10.0,
-                (y_off as f64 + (y / chunk_dim as f32) as f64) / 10.0,
-            ) * 10.0 as f64) as f32;
-
-            height_map.push((z1 * mult * extremes).max(-1.0) as i32);
-        }
-    }
-
-    // Generate a mesh from the heightmap.
-    for x_i in 0..chunk_dim {
-        for y_i in 0..chunk_dim {
-            let z = height_map[x_i * chunk_dim + y_i];
-            let mut faces = CuboidFaces::TOP;
-            let mut z_max_delta: i32 = 1;
-            if x_i == 0 || height_map[(x_i - 1) * chunk_dim + y_i] < z {
-                faces |= CuboidFaces::LEFT;
-                if x_i != 0 {
-                    z_max_delta = z_max_delta.max(z - (height_map[(x_i - 1) * chunk_dim + y_i]));
-                } else {
-                    z_max_delta = z_max_delta.max(8);
-                }
-            }
-            if x_i == (chunk_dim - 1) || height_map[(x_i + 1) * chunk_dim + y_i] < z {
-                faces |= CuboidFaces::RIGHT;
-                if x_i != (chunk_dim - 1) {
-                    z_max_delta = z_max_delta.max(z - (height_map[(x_i + 1) * chunk_dim + y_i]));
-                } else {
-                    z_max_delta = z_max_delta.max(8);
-                }
-            }
-            if y_i == 0 || height_map[x_i * chunk_dim + (y_i - 1)] < z {
-                faces |= CuboidFaces::BACK;
-                if y_i != 0 {
-                    z_max_delta = z_max_delta.max(z - (height_map[x_i * chunk_dim + (y_i - 1)]));
-                } else {
-                    z_max_delta = z_max_delta.max(8);
-                }
-            }
-            if y_i == (chunk_dim - 1) || height_map[x_i * chunk_dim + (y_i + 1)] < z {
-                faces |= CuboidFaces::FRONT;
-                if y_i != (chunk_dim - 1) {
-                    z_max_delta = z_max_delta.max(z - (height_map[x_i * chunk_dim + (y_i + 1)]));
-                } else {
-                    z_max_delta = z_max_delta.max(8);
-                }
-            }
-            for i in 0..z_max_delta {
-                m.add_cuboid_faces(Point3::new(x_i as i32, y_i as i32, z - i), faces);
-            }
+    let height_map = chunk_heights(x_off, y_off, lod, config, sea_level, noise);
+
+    // Generate a mesh from the heightmap. Past the chunk's edges there are no neighbours, so the
+    // chunk is walled in.
+    for x_i in 0..cells {
+        for y_i in 0..cells {
+            let z = height_map[x_i * cells + y_i];
+            let neighbours = ColumnNeighbours {
+                left: if x_i != 0 { Some(height_map[(x_i - 1) * cells + y_i]) } else { None },
+                right: if x_i != cells - 1 { Some(height_map[(x_i + 1) * cells + y_i]) } else { None },
+                back: if y_i != 0 { Some(height_map[x_i * cells + (y_i - 1)]) } else { None },
+                front: if y_i != cells - 1 { Some(height_map[x_i * cells + (y_i + 1)]) } else { None },
+            };
+            let (block_x, block_y) = (x_i * step, y_i * step);
+            let size = ((chunk_dim - block_x).min(step) as i32, (chunk_dim - block_y).min(step) as i32);
+            let world_pos = (x_off * chunk_dim as i32 + block_x as i32, y_off * chunk_dim as i32 + block_y as i32);
+            add_column(
+                &mut m,
+                Point3::new(block_x as i32, block_y as i32, z),
+                size,
+                neighbours,
+                world_pos,
+                sea_level,
+                select_tile,
+            );
         }
     }
 
     m
 }
+
+/// The number of columns merged into one along each axis at level of detail `lod`.
+fn lod_step(lod: ChunkLod, config: &TerrainConfig) -> usize {
+    1 << lod.level.min(config.max_lod())
+}
+
+/// The heights of the merged columns of the chunk at `(x_off, y_off)` at level of detail `lod`,
+/// indexed by `x_i * cells + y_i`, where `cells` is the number of merged columns along each axis.
+/// The last column is narrower if the merged width does not divide the chunk.
+///
+/// Each height is moved `lod.morph` steps of the way toward the height of the column it merges into
+/// at the next level, which is the first of each pair of columns along each axis, and rounded to a
+/// whole block. Fully morphed, the chunk has the same shape as at the next level.
+pub fn chunk_heights(
+    x_off: i32,
+    y_off: i32,
+    lod: ChunkLod,
+    config: &TerrainConfig,
+    sea_level: i32,
+    noise: &utils::NoiseCompositor,
+) -> Vec<i32> {
+    let step = lod_step(lod, config);
+    let cells = (config.chunk_dim + step - 1) / step;
+    let columns: Vec<(usize, usize)> = (0..cells)
+        .flat_map(|x_i| (0..cells).map(move |y_i| (x_i * step, y_i * step)))
+        .collect();
+    let heights = sample_heights(x_off, y_off, &columns, config, sea_level, noise);
+    if lod.morph == 0 || lod.level >= config.max_lod() {
+        return heights;
+    }
+
+    // The columns of the next level are sampled where the first of each pair of columns is, so
+    // their heights are already at hand.
+    let t = lod.morph.min(LOD_MORPH_STEPS) as f32 / LOD_MORPH_STEPS as f32;
+    (0..cells * cells)
+        .map(|i| {
+            let (x_i, y_i) = (i / cells, i % cells);
+            let (z, parent_z) = (heights[i], heights[(x_i & !1) * cells + (y_i & !1)]);
+            z + ((parent_z - z) as f32 * t).round() as i32
+        })
+        .collect()
+}
+
+/// The heights of the columns bordering a column, or `None` where there is no column to border.
+struct ColumnNeighbours {
+    left: Option<i32>,
+    right: Option<i32>,
+    back: Option<i32>,
+    front: Option<i32>,
+}
+
+/// Walls facing a missing neighbour are this many blocks deep, so that the terrain looks solid
+/// from beside it.
+const EDGE_WALL_DEPTH: i32 = 8;
+
+/// Adds the faces of the column of blocks whose top block is at `top` to `m`. The column is `size`
+/// blocks wide along x and y, and its sides are walled down to the lowest neighbour below its top.
+/// Tiles are selected for the column's world position `(world_x, world_y)`, with heights given
+/// relative to `sea_level`.
+fn add_column(
+    m: &mut IMeshAccumulator,
+    top: Point3<i32>,
+    size: (i32, i32),
+    neighbours: ColumnNeighbours,
+    (world_x, world_y): (i32, i32),
+    sea_level: i32,
+    select_tile: atlas::TileSelector,
+) {
+    let z = top.z;
+    let mut faces = CuboidFaces::TOP;
+    let mut z_max_delta: i32 = 1;
+    let sides = [
+        (neighbours.left, CuboidFaces::LEFT),
+        (neighbours.right, CuboidFaces::RIGHT),
+        (neighbours.back, CuboidFaces::BACK),
+        (neighbours.front, CuboidFaces::FRONT),
+    ];
+    for &(neighbour, face) in sides.iter() {
+        match neighbour {
+            Some(neighbour_z) if neighbour_z < z => {
+                faces |= face;
+                z_max_delta = z_max_delta.max(z - neighbour_z);
+            },
+            Some(_) => {},
+            None => {
+                faces |= face;
+                z_max_delta = z_max_delta.max(EDGE_WALL_DEPTH);
+            },
+        }
+    }
+    for i in 0..z_max_delta {
+        let block_z = z - i;
+        m.add_cuboid_faces_sized(Point3::new(top.x, top.y, block_z), size, faces, |face| {
+            let (col, row) = select_tile(world_x, world_y, block_z - sea_level, face);
+            (col as u8, row as u8)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_config() -> TerrainConfig {
+        TerrainConfig {
+            chunk_dim: 4,
+            chunks_x: 2,
+            chunks_y: 2,
+            ..TerrainConfig::default()
+        }
+    }
+
+    #[test]
+    fn set_config_reports_only_changes() {
+        let mut manager = WorldGeometryManager::new(small_config(), 0, atlas::default_tile_selector);
+        let new_config = TerrainConfig {
+            amplitude: 2.0,
+            ..small_config()
+        };
+        assert!(manager.set_config(new_config));
+        assert!(!manager.set_config(new_config));
+        assert_eq!(manager.config(), new_config);
+    }
+
+    #[test]
+    fn set_config_reseeds_the_noise() {
+        let reseeded_config = TerrainConfig {
+            seed: 42,
+            ..small_config()
+        };
+        let mut manager = WorldGeometryManager::new(small_config(), 0, atlas::default_tile_selector);
+        assert!(manager.set_config(reseeded_config));
+        let fresh = WorldGeometryManager::new(reseeded_config, 0, atlas::default_tile_selector);
+        assert_eq!(manager.height_grid(), fresh.height_grid());
+    }
+
+    /// A row of chunks 16 columns wide whose levels of detail are four chunk widths apart, so that
+    /// chunk `x` is `x / 4` levels down as seen from the middle of chunk 0.
+    fn lod_config() -> TerrainConfig {
+        TerrainConfig {
+            chunk_dim: 16,
+            chunks_x: 20,
+            chunks_y: 1,
+            lod_levels: 4,
+            lod_distance_multiplier: 4.0,
+            ..TerrainConfig::default()
+        }
+    }
+
+    /// The middle of chunk `(0, 0)`, high above it.
+    fn lod_camera() -> Point3<f32> {
+        Point3::new(8.0, 8.0, 100.0)
+    }
+
+    #[test]
+    fn chunk_lod_drops_a_level_per_distance_band() {
+        let config = lod_config();
+        assert_eq!(config.max_lod(), 3);
+        for chunk_x in 0..config.chunks_x {
+            let expected = (chunk_x as u32 / 4).min(3);
+            assert_eq!(config.chunk_lod(chunk_x, 0, lod_camera(), 0.0), ChunkLod::new(expected), "chunk {}", chunk_x);
+        }
+    }
+
+    #[test]
+    fn chunk_lod_adds_the_bias() {
+        let config = lod_config();
+        assert_eq!(config.chunk_lod(4, 0, lod_camera(), -1.0).level, 0);
+        assert_eq!(config.chunk_lod(2, 0, lod_camera(), 0.5).level, 1);
+        assert_eq!(config.chunk_lod(0, 0, lod_camera(), 10.0).level, 3);
+    }
+
+    #[test]
+    fn chunk_lod_morphs_over_the_end_of_each_band() {
+        let config = TerrainConfig {
+            lod_transition: true,
+            ..lod_config()
+        };
+        // Chunks 4 to 7 are a quarter of a band apart, and only the second half of the band morphs.
+        let morphs: Vec<u32> = (4..8).map(|chunk_x| config.chunk_lod(chunk_x, 0, lod_camera(), 0.0).morph).collect();
+        assert_eq!(morphs, vec![0, 0, 0, LOD_MORPH_STEPS / 2]);
+        // Just short of the next band the morph is complete.
+        let camera = Point3::new(-7.9, 8.0, 100.0);
+        assert_eq!(config.chunk_lod(7, 0, camera, 0.0), ChunkLod { level: 1, morph: LOD_MORPH_STEPS });
+        // There is nothing to morph toward from the coarsest level.
+        assert_eq!(config.chunk_lod(15, 0, lod_camera(), 0.0), ChunkLod::new(3));
+        // Without transitions, chunks never morph.
+        assert_eq!(lod_config().chunk_lod(7, 0, lod_camera(), 0.0), ChunkLod::new(1));
+    }
+
+    #[test]
+    fn update_lod_reports_only_the_chunks_whose_level_changed() {
+        let config = lod_config();
+        let mut lods = ChunkLodMap::default();
+        assert_eq!(lods.update_lod(&config, lod_camera(), 0.0), (0..config.chunks_x).collect::<Vec<_>>());
+        assert!(lods.update_lod(&config, lod_camera(), 0.0).is_empty());
+
+        // Moving two chunks along shifts the band boundaries past some chunks but not others.
+        let camera = Point3::new(40.0, 8.0, 100.0);
+        let expected: Vec<usize> = (0..config.chunks_x)
+            .filter(|&x| config.chunk_lod(x, 0, camera, 0.0) != config.chunk_lod(x, 0, lod_camera(), 0.0))
+            .collect();
+        assert!(!expected.is_empty() && expected.len() < config.chunks_x);
+        assert_eq!(lods.update_lod(&config, camera, 0.0), expected);
+        assert_eq!(lods.lods()[expected[0]], config.chunk_lod(expected[0], 0, camera, 0.0));
+    }
+
+    /// Each chunk of `mesh` as its vertex bytes and its indices, so that meshes can be compared.
+    fn chunk_contents(mesh: &WorldMesh) -> Vec<(Vec<u8>, Vec<u16>)> {
+        (0..mesh.chunks.len())
+            .map(|i| {
+                let chunk = &mesh.chunks[i];
+                let vertex_end = mesh.chunks.get(i + 1).map_or(mesh.vertices.len(), |next| next.vertex_offset);
+                (
+                    bytemuck::cast_slice(&mesh.vertices[chunk.vertex_offset..vertex_end]).to_vec(),
+                    mesh.indices[chunk.index_offset..chunk.index_offset + chunk.index_count].to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn regenerate_chunks_only_remeshes_the_changed_chunks() {
+        let config = TerrainConfig {
+            lod_transition: true,
+            ..lod_config()
+        };
+        let manager = WorldGeometryManager::new(config, 0, atlas::default_tile_selector);
+        let mut lods = ChunkLodMap::default();
+        lods.update_lod(&config, lod_camera(), 0.0);
+        let mut mesh = manager.generate_with_lods(lods.lods());
+        let before = chunk_contents(&mesh);
+
+        let changed = lods.update_lod(&config, Point3::new(40.0, 8.0, 100.0), 0.0);
+        manager.regenerate_chunks(&mut mesh, lods.lods(), &changed).unwrap();
+        let after = chunk_contents(&mesh);
+        assert_eq!(after, chunk_contents(&manager.generate_with_lods(lods.lods())));
+        for i in (0..config.chunks_x).filter(|i| !changed.contains(i)) {
+            assert_eq!(after[i], before[i], "chunk {}", i);
+        }
+
+        // Chunks left out keep their old mesh even if their level differs.
+        let coarsest = vec![ChunkLod::new(3); config.chunks_x];
+        manager.regenerate_chunks(&mut mesh, &coarsest, &[0]).unwrap();
+        let regenerated = chunk_contents(&mesh);
+        assert_eq!(regenerated[0], chunk_contents(&manager.generate_with_lods(&coarsest))[0]);
+        assert_ne!(regenerated[0], after[0]);
+        assert_eq!(regenerated[1..], after[1..]);
+    }
+
+    #[test]
+    fn regenerate_chunks_rejects_a_mesh_of_another_layout() {
+        let manager = WorldGeometryManager::new(lod_config(), 0, atlas::default_tile_selector);
+        let mut mesh = WorldGeometryManager::new(small_config(), 0, atlas::default_tile_selector).generate();
+        assert!(manager.regenerate_chunks(&mut mesh, &[], &[0]).is_none());
+        assert_eq!(mesh.chunks.len(), 4);
+    }
+
+    #[test]
+    fn fully_morphed_heights_match_the_next_level() {
+        let config = lod_config();
+        let noise = utils::NoiseCompositor::terrain(config.seed);
+        let heights = |lod| chunk_heights(5, 3, lod, &config, 0, &noise);
+        let (fine, coarse) = (heights(ChunkLod::new(1)), heights(ChunkLod::new(2)));
+        let morphed = heights(ChunkLod { level: 1, morph: LOD_MORPH_STEPS });
+        let (fine_cells, coarse_cells) = (8, 4);
+        for x_i in 0..fine_cells {
+            for y_i in 0..fine_cells {
+                let i = x_i * fine_cells + y_i;
+                assert_eq!(morphed[i], coarse[x_i / 2 * coarse_cells + y_i / 2]);
+            }
+        }
+        assert_ne!(morphed, fine);
+        assert_eq!(heights(ChunkLod { level: 1, morph: 0 }), fine);
+        // Halfway, each height lies between the two levels'.
+        let halfway = heights(ChunkLod { level: 1, morph: LOD_MORPH_STEPS / 2 });
+        for i in 0..fine.len() {
+            assert!(halfway[i] >= fine[i].min(morphed[i]) && halfway[i] <= fine[i].max(morphed[i]));
+        }
+    }
+}